@@ -0,0 +1,244 @@
+//! Code actions: structural edits offered at the cursor.
+
+use std::collections::HashMap;
+
+use lsp_types::*;
+use regex::Regex;
+
+use crate::check::UNDEFINED_NONTERMINAL;
+use crate::commands;
+use crate::parse::{self, Document};
+
+/// The hom targets we offer to add templates for.
+const HOM_TARGETS: &[&str] = &["tex", "coq"];
+
+/// The most names offered in place of a misspelled one.
+const MAX_SUGGESTIONS: usize = 3;
+
+lazy_static::lazy_static! {
+    /// A name in quotes or backticks, as messages quote them.
+    static ref QUOTED: Regex = Regex::new(r#"["`']([A-Za-z][A-Za-z0-9_']*?)["`']"#).unwrap();
+}
+
+fn contains(range: &Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+// `Uri` is a false positive: its interior mutability never affects its hash.
+#[allow(clippy::mutable_key_type)]
+fn action(uri: &Uri, title: String, edit: TextEdit) -> CodeActionOrCommand {
+    let changes = HashMap::from([(uri.clone(), vec![edit])]);
+    CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit { changes: Some(changes), ..Default::default() }),
+        ..Default::default()
+    })
+}
+
+/// Offers to add a hom for each target the production under the cursor
+/// doesn't have one for yet, and to remove the hom under the cursor, if any.
+pub fn hom_actions(doc: &Document, uri: &Uri, range: Range) -> Vec<CodeActionOrCommand> {
+    let Some(production) = doc.grammars.iter()
+        .flat_map(|rule| &rule.productions)
+        .find(|p| contains(&p.range, range.start)) else { return vec![] };
+
+    let mut actions = vec![];
+    for target in HOM_TARGETS {
+        if !production.homs.iter().any(|hom| hom.target == *target) {
+            let edit = TextEdit::new(
+                Range::new(production.range.end, production.range.end),
+                format!(" {{{{ {target}  }}}}"),
+            );
+
+            actions.push(action(uri, format!("Add {target} hom"), edit));
+        }
+    }
+
+    if let Some(hom) = production.homs.iter().find(|h| contains(&h.range, range.start)) {
+        // Take the whitespace before the hom along with it.
+        let edit = TextEdit::new(Range::new(hom.preceding, hom.range.end), String::new());
+        actions.push(action(uri, format!("Remove {} hom", hom.target), edit));
+    }
+
+    actions
+}
+
+/// Offers to generate each of ott's backends, and any other target with a
+/// path template in `templates`, from the document `uri`.
+pub fn generate_actions(uri: &Uri, templates: &HashMap<String, String>) -> Vec<CodeActionOrCommand> {
+    let mut targets: Vec<_> = commands::TARGETS.iter().map(|&(target, name, _)| (target, name)).collect();
+    let mut others: Vec<_> = templates.keys()
+        .map(String::as_str)
+        .filter(|target| targets.iter().all(|(t, _)| t != target))
+        .collect();
+
+    others.sort();
+    targets.extend(others.into_iter().map(|target| (target, target)));
+    targets.into_iter()
+        .map(|(target, name)| {
+            let title = format!("Generate {name}");
+            let arguments = vec![serde_json::json!({ "uri": uri }), serde_json::json!(target)];
+            CodeActionOrCommand::CodeAction(CodeAction {
+                title: title.clone(),
+                kind: Some(CodeActionKind::SOURCE),
+                command: Some(Command::new(title, commands::GENERATE.into(), Some(arguments))),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// How many characters must be inserted, removed, or replaced to turn `a`
+/// into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The name the undefined-nonterminal `diagnostic` is about, and where it
+/// is in `text`: what the diagnostic's range covers, if that's a name, or
+/// else the first name its message quotes, where it's first written on the
+/// lines of its range.
+fn misspelled(text: &str, diagnostic: &Diagnostic) -> Option<(String, Range)> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    let lines: Vec<_> = text.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    let range = diagnostic.range;
+    let words = |line: u32| {
+        let text = *lines.get(line as usize).unwrap_or(&"");
+        text.match_indices(|c: char| !is_name(c))
+            .map(|(i, separator)| i + separator.len())
+            .chain([0])
+            .filter_map(move |start| {
+                let word = &text[start..];
+                let word = &word[..word.find(|c: char| !is_name(c)).unwrap_or(word.len())];
+                let start = parse::utf16_len(&text[..start]);
+                (!word.is_empty()).then(|| (word, Range::new(Position::new(line, start), Position::new(line, start + parse::utf16_len(word)))))
+            })
+    };
+
+    if let Some((word, _)) = words(range.start.line).find(|(_, r)| *r == range) {
+        return Some((word.to_string(), range));
+    }
+
+    let quoted = QUOTED.captures_iter(&diagnostic.message).filter_map(|caps| caps.get(1)).next()?.as_str();
+    (range.start.line..=range.end.line)
+        .flat_map(words)
+        .find(|(word, _)| *word == quoted)
+        .map(|(word, range)| (word.to_string(), range))
+}
+
+/// Offers, for each of `diagnostics` that's ott's complaint about a
+/// nonterminal or metavar it doesn't know, to replace the name with each of
+/// the few names `docs` declare that are closest to it, nearest first. An
+/// ott suffix, like the `1` of `exprr1`, is kept.
+#[allow(clippy::mutable_key_type)] // As in `action`.
+pub fn spelling_actions(
+    docs: &[&Document],
+    text: &str,
+    uri: &Uri,
+    diagnostics: &[Diagnostic],
+) -> Vec<CodeActionOrCommand> {
+    let declared: Vec<&str> = docs.iter()
+        .flat_map(|doc| {
+            let metavars = doc.metavars.iter().flat_map(|m| &m.names);
+            metavars.chain(doc.grammars.iter().flat_map(|rule| &rule.names))
+        })
+        .map(|name| name.text.as_str())
+        .collect();
+
+    let undefined = diagnostics.iter()
+        .filter(|d| d.code == Some(NumberOrString::String(UNDEFINED_NONTERMINAL.into())));
+
+    let mut actions = vec![];
+    for diagnostic in undefined {
+        let Some((word, range)) = misspelled(text, diagnostic) else { continue };
+        let root = word.trim_end_matches(|c: char| c.is_ascii_digit() || c == '\'' || c == '_');
+        let (root, suffix) = word.split_at(if root.is_empty() { word.len() } else { root.len() });
+        let threshold = (root.chars().count() / 3).max(1);
+
+        let mut closest: Vec<_> = declared.iter()
+            .map(|&name| (edit_distance(root, name), name))
+            .filter(|&(distance, _)| distance > 0 && distance <= threshold)
+            .collect();
+
+        closest.sort();
+        closest.dedup();
+        for (i, (_, name)) in closest.into_iter().take(MAX_SUGGESTIONS).enumerate() {
+            let replacement = format!("{name}{suffix}");
+            let changes = HashMap::from([(uri.clone(), vec![TextEdit::new(range, replacement.clone())])]);
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Change `{word}` to `{replacement}`"),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit { changes: Some(changes), ..Default::default() }),
+                is_preferred: Some(i == 0),
+                ..Default::default()
+            }));
+        }
+    }
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAMMAR: &str = "metavar termvar, x ::=\ngrammar\nexpr, e :: 'e_' ::=\n  | x :: :: var\n  | exprr1 exp :: :: app\n";
+
+    fn undefined(range: Range, message: &str) -> Diagnostic {
+        Diagnostic {
+            range,
+            code: Some(NumberOrString::String(UNDEFINED_NONTERMINAL.into())),
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The title, range, and replacement of each of `actions`.
+    fn fixes(actions: Vec<CodeActionOrCommand>, uri: &Uri) -> Vec<(String, Range, String)> {
+        actions.into_iter()
+            .map(|action| {
+                let CodeActionOrCommand::CodeAction(action) = action else { panic!("not an action") };
+                let edit = action.edit.unwrap();
+                let edits = |uri| &edit.changes.as_ref().unwrap()[uri];
+                let [TextEdit { range, new_text }] = edits(uri).as_slice() else { panic!("not one edit") };
+                (action.title, *range, new_text.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn misspelled_nonterminals_are_changed_to_the_closest_declared_ones() {
+        let doc = Document::parse(GRAMMAR);
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let range = |start, end| Range::new(Position::new(4, start), Position::new(4, end));
+
+        // The diagnostic covers the name, whose suffix is kept.
+        let diagnostics = [undefined(range(4, 10), "undefined nonterminal")];
+        let actions = spelling_actions(&[&doc], GRAMMAR, &uri, &diagnostics);
+        assert_eq!(fixes(actions, &uri), [("Change `exprr1` to `expr1`".into(), range(4, 10), "expr1".into())]);
+
+        // Or its message names one on the lines it covers.
+        let diagnostics = [undefined(range(0, 21), "undefined nonterminal `exp`")];
+        let actions = spelling_actions(&[&doc], GRAMMAR, &uri, &diagnostics);
+        assert_eq!(fixes(actions, &uri), [("Change `exp` to `expr`".into(), range(11, 14), "expr".into())]);
+
+        // Nothing declared is close to a name that's far off.
+        let diagnostics = [undefined(range(4, 10), "undefined nonterminal")];
+        let far = GRAMMAR.replace("exprr1", "typing");
+        assert!(spelling_actions(&[&Document::parse(&far)], &far, &uri, &diagnostics).is_empty());
+    }
+}
@@ -0,0 +1,2450 @@
+//! Running ott and turning what it prints into diagnostics.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::process::{Command, ExitStatus, Stdio};
+
+use parking_lot::{Mutex, RwLock};
+use regex::Regex;
+use lsp_types::*;
+
+use crate::config::{Config, LineFallback, MessageFormat, Overflow};
+use crate::parse::{self, Document};
+use crate::theory;
+
+/// The patterns for the locations in ott's output, as one run of its versions
+/// prints them, for `formatProfile` to choose between as the output drifts.
+///
+/// ott's lines are numbered from 1 everywhere. Its columns are numbered from
+/// 1 when it writes `column N`, as in `range1` and `range2`, but are offsets
+/// from 0 when it writes `(char N)`, as `col` matches.
+pub struct Profile {
+    /// The name `formatProfile` selects the profile by.
+    pub name: &'static str,
+    /// The oldest version of ott that prints this way, for `auto` to go by.
+    since: (u32, u32),
+    /// `line L, column C1 - C2`: one line, 1-based columns, both in the span.
+    range1: Regex,
+    /// `line L1, column C1 - line L2, column C2`: as `range1`, over lines.
+    range2: Regex,
+    /// `line L`, with no column.
+    range3: Regex,
+    file: Regex,
+    /// `(char C)`: a 0-based column, used as is.
+    col: Regex,
+    /// `line L, column C`: one 1-based column, with no end.
+    column: Regex,
+    /// `<message> at line L, column C`: a message carrying its own location.
+    inline: Regex,
+    /// Lines ott prints on moving from one pass to the next, with the pass
+    /// of what it reports after them.
+    passes: Vec<(Regex, &'static str)>,
+}
+
+lazy_static::lazy_static! {
+    /// Every profile, oldest first.
+    pub static ref PROFILES: Vec<Profile> = vec![
+        // Before 0.33, spans were given in `characters`, and the file named
+        // without an `on`.
+        Profile {
+            name: "ott-0.32",
+            since: (0, 0),
+            range1: Regex::new(r"line (\d+), characters (\d+)-(\d+)").unwrap(),
+            range2: Regex::new(r"line (\d+), character (\d+) - line (\d+), character (\d+)").unwrap(),
+            range3: Regex::new(r"line (\d+)").unwrap(),
+            file: Regex::new(r#"^File "?([^"]+?)"?,? line"#).unwrap(),
+            col: Regex::new(r"\(char (\d+)\)").unwrap(),
+            column: Regex::new(r"line \d+, character (\d+)").unwrap(),
+            inline: Regex::new(r"^(.*?),? at (line \d+(?:, character \d+)?)\.?$").unwrap(),
+            passes: vec![(Regex::new(r"^definition rules?:").unwrap(), "backend")],
+        },
+        Profile {
+            name: "ott-0.33",
+            since: (0, 33),
+            range1: Regex::new(r"line (\d+), column (\d+) - (\d+)").unwrap(),
+            range2: Regex::new(r"line (\d+), column (\d+) - line (\d+), column (\d+)").unwrap(),
+            range3: Regex::new(r"line (\d+)").unwrap(),
+            file: Regex::new(r#"^File "?([^"]+?)"?,? (on )?line"#).unwrap(),
+            col: Regex::new(r"\(char (\d+)\)").unwrap(),
+            column: Regex::new(r"line \d+, column (\d+)").unwrap(),
+            inline: Regex::new(r"^(.*?),? at (line \d+(?:, column \d+)?)\.?$").unwrap(),
+            // The tally of rules is printed once they've all been parsed
+            // and checked, before any output is written.
+            passes: vec![(Regex::new(r"^definition rules?( clauses)?:").unwrap(), "backend")],
+        },
+    ];
+
+    /// The version of each ott binary that `auto` has asked, by its path.
+    static ref VERSIONS: Mutex<HashMap<String, Option<(u32, u32)>>> = Mutex::default();
+    static ref VERSION: Regex = Regex::new(r"(\d+)\.(\d+)").unwrap();
+    static ref UNUSED: Regex = Regex::new(r"(?i)\b(unused|never used)\b").unwrap();
+    /// Complaints about a name declared more than once.
+    static ref DUPLICATE: Regex =
+        Regex::new(r"(?i)\b(multiple definitions?|defined more than once|duplicate|already (declared|defined))\b").unwrap();
+    static ref PARSE_PASS: Regex =
+        Regex::new(r"(?i)\b(no parses?|multiple parses|parse error|lex(ing|er) error|syntax error|unexpected)\b").unwrap();
+    static ref SORT_PASS: Regex = Regex::new(concat!(
+        r"(?i)\b(undefined|not defined|unknown|unbound|not bound|nonterminals?|metavars?|multiple definitions?",
+        r"|defined more than once|duplicate|already (declared|defined)|subrules?|bind(s|spec)?|substitutions?)\b",
+    )).unwrap();
+    /// Complaints about a nonterminal or metavar that isn't declared.
+    static ref UNDEFINED: Regex = Regex::new(concat!(
+        r"(?i)\b(undefined|not defined|unknown|undeclared|not declared)\b.*\b(nonterminal|metavar)s?\b",
+        r"|\b(nonterminal|metavar)s?\b.*\b(undefined|not defined|unknown|undeclared|not declared)\b",
+    )).unwrap();
+    static ref INFO: Regex = Regex::new(r"^(Ott version |definition rules?( clauses)?:)").unwrap();
+}
+
+/// The profile `config` selects: the one `formatProfile` names or, for
+/// `auto` or a name no profile has, the newest one for the version of ott
+/// that `ottPath` runs, or the newest of all if that can't be told.
+pub fn profile(config: &Config) -> &'static Profile {
+    let named = config.format_profile.as_deref().filter(|name| *name != "auto");
+    if let Some(profile) = named.and_then(|name| PROFILES.iter().find(|p| p.name == name)) {
+        return profile;
+    }
+
+    let version = ott_version(config);
+    PROFILES.iter().rev()
+        .find(|profile| version.is_none_or(|version| profile.since <= version))
+        .unwrap_or(&PROFILES[PROFILES.len() - 1])
+}
+
+/// How long ott has to say its version before it's taken not to know.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The most of what ott says when asked its version that's read.
+const VERSION_BYTES: usize = 4096;
+
+/// The `major.minor` version of the ott `config` runs, asked of it once.
+fn ott_version(config: &Config) -> Option<(u32, u32)> {
+    let path = config.ott_path();
+    if let Some(version) = VERSIONS.lock().get(path) {
+        return *version;
+    }
+
+    // If ott can't be run, it may be installed later: don't remember that.
+    let mut command = ott(config);
+    command.arg("-version").stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null());
+    let mut child = command.spawn().ok()?;
+    let (captured, done) = capture(child.stdout.take()?, VERSION_BYTES);
+
+    // An ott that hangs would hold up every check: give up on it in time.
+    let deadline = Instant::now() + VERSION_TIMEOUT;
+    while child.try_wait().ok()?.is_none() {
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            break;
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let _ = done.recv_timeout(Duration::from_millis(100));
+    let stdout = String::from_utf8_lossy(&captured.lock().bytes).into_owned();
+    let version = VERSION.captures(&stdout).and_then(|caps| Some((caps[1].parse().ok()?, caps[2].parse().ok()?)));
+    VERSIONS.lock().insert(path.to_string(), version);
+    version
+}
+
+/// How many times to try running ott before giving up.
+const SPAWN_ATTEMPTS: u32 = 3;
+
+/// The most diagnostics to report for one document, unless `maxDiagnostics`
+/// says otherwise.
+pub const MAX_DIAGNOSTICS: usize = 1000;
+
+/// The source of the diagnostics parsed out of what ott printed.
+const OTT: &str = "ott";
+
+/// The code of ott's complaints about a nonterminal or metavar it doesn't
+/// know, which are offered the declared names closest to it instead.
+pub const UNDEFINED_NONTERMINAL: &str = "undefined-nonterminal";
+
+/// The `data` of one of ott's diagnostics that has a column but no line, until
+/// [`place_lineless()`] gives it one.
+const LINELESS: &str = "ott-lsp/lineless";
+
+/// How ott exited and what it printed, up to `max_output_bytes` of it.
+pub struct Output {
+    pub status: ExitStatus,
+    /// What ott printed to stdout or, with `merge_stderr`, to either, in the
+    /// order it printed it.
+    pub stdout: Vec<u8>,
+    /// What ott printed to stderr, as when it dies of an exception. With
+    /// `merge_stderr`, that's everything it printed, as in `stdout`.
+    pub stderr: Vec<u8>,
+    /// Whether ott printed more than `stdout` holds. If so, `stdout` ends
+    /// with the last complete line that fit.
+    pub truncated: bool,
+    /// Whether ott was stopped for running past `checkTimeoutMs`.
+    pub timed_out: bool,
+}
+
+/// Runs ott over `files`, in order, and waits for it to exit. Failures to run
+/// ott at all, which can be transient, are retried with a short backoff,
+/// unless ott doesn't exist, each retry passed to `trace`.
+pub fn run(config: &Config, files: &[PathBuf], trace: &dyn Fn(String)) -> io::Result<Output> {
+    run_with(config, files, &[], None, trace)
+}
+
+/// The path of a process's stdin, for ott to read a buffer piped to it from
+/// in place of a file, where there's one.
+pub const STDIN: Option<&str> = if cfg!(unix) { Some("/dev/stdin") } else { None };
+
+/// Runs ott over `files` like [`run()`], with `input` piped to its stdin, for
+/// it to read as [`STDIN`].
+pub fn run_piped(config: &Config, files: &[PathBuf], input: &str, trace: &dyn Fn(String)) -> io::Result<Output> {
+    run_with(config, files, &[], Some(input.as_bytes()), trace)
+}
+
+/// Where what ott prints comes from, for a check to turn into diagnostics.
+pub trait OttRunner {
+    /// What ott prints when run over `files`, in order, with `stdin`, if
+    /// any, piped to it.
+    fn run(&self, config: &Config, files: &[PathBuf], stdin: Option<&str>) -> io::Result<Output>;
+}
+
+/// Runs the configured ott, as [`run()`] and [`run_piped()`] do, passing
+/// their retries to the function it holds.
+pub struct Subprocess<'a>(pub &'a dyn Fn(String));
+
+impl OttRunner for Subprocess<'_> {
+    fn run(&self, config: &Config, files: &[PathBuf], stdin: Option<&str>) -> io::Result<Output> {
+        match stdin {
+            Some(input) => run_piped(config, files, input, self.0),
+            None => run(config, files, self.0),
+        }
+    }
+}
+
+/// Output recorded from an earlier run of ott, or made up, replayed whatever
+/// it's run over, so that what's made of it can be seen without ott. Only
+/// in tests and builds with the `replay` feature.
+#[cfg(any(test, feature = "replay"))]
+pub struct Recorded {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub timed_out: bool,
+    /// The files each run was over, in order, and what was piped to it.
+    pub runs: Mutex<Vec<(Vec<PathBuf>, Option<String>)>>,
+}
+
+#[cfg(any(test, feature = "replay"))]
+impl Recorded {
+    /// A successful run that printed `stdout` and nothing else.
+    pub fn stdout(stdout: Vec<u8>) -> Recorded {
+        Recorded { status: ExitStatus::default(), stdout, stderr: vec![], timed_out: false, runs: Mutex::default() }
+    }
+}
+
+#[cfg(any(test, feature = "replay"))]
+impl OttRunner for Recorded {
+    fn run(&self, _: &Config, files: &[PathBuf], stdin: Option<&str>) -> io::Result<Output> {
+        self.runs.lock().push((files.to_vec(), stdin.map(String::from)));
+        let (stdout, stderr) = (self.stdout.clone(), self.stderr.clone());
+        Ok(Output { status: self.status, stdout, stderr, truncated: false, timed_out: self.timed_out })
+    }
+}
+
+/// Runs ott over `files` like [`run()`], with `args` as well, also having it
+/// write `output`, in the language the extension of `output` selects.
+pub fn generate(
+    config: &Config,
+    files: &[PathBuf],
+    output: &Path,
+    args: &[String],
+    trace: &dyn Fn(String),
+) -> io::Result<Output> {
+    let mut args: Vec<&OsStr> = args.iter().map(|arg| arg.as_ref()).collect();
+    args.extend(["-o".as_ref(), output.as_os_str()]);
+    run_with(config, files, &args, None, trace)
+}
+
+/// Runs ott over `files` like [`run()`], also having it parse `sample` as
+/// the nonterminal `nonterminal`, with its `-parse` option, and print what
+/// it made of it.
+pub fn parse_sample(
+    config: &Config,
+    files: &[PathBuf],
+    nonterminal: &str,
+    sample: &str,
+    trace: &dyn Fn(String),
+) -> io::Result<Output> {
+    let term = format!(":{nonterminal}: {sample}");
+    run_with(config, files, &["-parse".as_ref(), term.as_ref()], None, trace)
+}
+
+fn run_with(
+    config: &Config,
+    files: &[PathBuf],
+    args: &[&OsStr],
+    input: Option<&[u8]>,
+    trace: &dyn Fn(String),
+) -> io::Result<Output> {
+    let mut attempt = 1;
+    loop {
+        match run_once(config, files, args, input) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound && attempt < SPAWN_ATTEMPTS => {
+                let backoff = Duration::from_millis(50 << (attempt - 1));
+                trace(format!("failed to run {} (attempt {attempt}): {e}; retrying", config.ott_path()));
+                std::thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The flags every run of ott is passed, ahead of those particular to the run.
+fn flags(config: &Config) -> Vec<&str> {
+    let mut flags = vec!["-signal_parse_errors", "true", "-colour", "false"];
+    flags.extend(config.ott_flags.iter().map(String::as_str));
+    if let Some(merge) = config.merge {
+        flags.extend(["-merge", if merge { "true" } else { "false" }]);
+    }
+
+    if config.picky_multiple_parses {
+        flags.extend(["-picky_multiple_parses", "true"]);
+    }
+
+    flags
+}
+
+/// A command running the ott `config` names, in the environment it sets.
+fn ott(config: &Config) -> Command {
+    let mut command = Command::new(config.ott_path());
+    command.envs(&config.ott_env);
+    command
+}
+
+fn run_once(config: &Config, files: &[PathBuf], args: &[&OsStr], input: Option<&[u8]>) -> io::Result<Output> {
+    let mut command = ott(config);
+    command.args(flags(config)).args(args).args(files);
+    command.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() });
+
+    // Both streams through one pipe keep what ott prints to stderr where it
+    // printed it among the rest, which two pipes, read apart, can't.
+    let merged = match config.merge_stderr() {
+        true => {
+            let (reader, writer) = io::pipe()?;
+            command.stdout(writer.try_clone()?).stderr(writer);
+            Some(reader)
+        }
+        false => {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            None
+        }
+    };
+
+    // The command holds on to the pipe's writing ends: drop them, or it'd
+    // never be seen to close.
+    let mut child = command.spawn()?;
+    drop(command);
+
+    // Write the input alongside reading the output too, for the same
+    // reason, closing stdin once it's all written.
+    let writer = child.stdin.take().zip(input).map(|(mut stdin, input)| {
+        let input = input.to_vec();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        })
+    });
+
+    // Read stderr alongside stdout, or ott could block writing to it while
+    // we wait on stdout. Both are read on threads of their own, so that ott
+    // can be stopped if it runs for too long.
+    let limit = config.max_output_bytes();
+    let stderr = child.stderr.take().map(|errors| capture(errors, limit));
+    let stdout = match merged {
+        Some(reader) => capture(reader, limit),
+        None => capture(child.stdout.take().expect("stdout is piped"), limit),
+    };
+
+    let mut timed_out = false;
+    let status = match config.check_timeout() {
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                } else if Instant::now() >= deadline {
+                    timed_out = true;
+                    let _ = child.kill();
+                    break child.wait()?;
+                }
+
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        None => child.wait()?,
+    };
+
+    // A stopped ott may have left children of its own, as a wrapper script
+    // would, holding its pipes open. Take what they've said so far instead of
+    // waiting on them.
+    let finish = |(captured, done): Capture| {
+        match timed_out {
+            true => { let _ = done.recv_timeout(Duration::from_millis(100)); }
+            false => { let _ = done.recv(); }
+        }
+
+        std::mem::take(&mut *captured.lock())
+    };
+
+    let Captured { bytes: mut stdout, truncated } = finish(stdout);
+    if truncated {
+        let end = stdout.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        stdout.truncate(end);
+    }
+
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    let stderr = match stderr {
+        Some(stderr) => finish(stderr).bytes,
+        None => stdout.clone(),
+    };
+
+    Ok(Output { status, stdout, stderr, truncated, timed_out })
+}
+
+/// What's been read from one of ott's pipes.
+#[derive(Default)]
+struct Captured {
+    /// The first `maxOutputBytes` of it.
+    bytes: Vec<u8>,
+    /// Whether there was more than that.
+    truncated: bool,
+}
+
+/// What [`capture()`] has read so far, and a receiver that hears when it's
+/// read it all.
+type Capture = (std::sync::Arc<Mutex<Captured>>, mpsc::Receiver<()>);
+
+/// Reads `pipe` to its end on a thread of its own, keeping the first `limit`
+/// bytes. It keeps reading past them, without keeping anything, so that ott
+/// isn't left blocked on a full pipe.
+fn capture(mut pipe: impl Read + Send + 'static, limit: usize) -> Capture {
+    let captured = std::sync::Arc::new(Mutex::new(Captured::default()));
+    let (done, finished) = mpsc::channel();
+    let into = captured.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0; 8192];
+        while let Ok(n) = pipe.read(&mut buf) && n > 0 {
+            let mut captured = into.lock();
+            let room = limit.saturating_sub(captured.bytes.len());
+            captured.bytes.extend_from_slice(&buf[..n.min(room)]);
+            captured.truncated |= n > room;
+        }
+
+        let _ = done.send(());
+    });
+
+    (captured, finished)
+}
+
+/// Where `program` is found, the way running it would find it: in `PATH`,
+/// unless it's a path itself.
+fn resolve(program: &str) -> Option<PathBuf> {
+    if Path::new(program).components().count() > 1 {
+        return Some(PathBuf::from(program));
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// `arg` as a shell would need it written to pass it as is.
+pub fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c);
+    match !arg.is_empty() && arg.chars().all(plain) {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+/// The command line [`run()`] runs to check `files`, as it would be typed
+/// into a shell, with ott's path resolved if it can be.
+pub fn command_line(config: &Config, files: &[PathBuf]) -> String {
+    let program = resolve(config.ott_path())
+        .map_or_else(|| config.ott_path().to_string(), |path| path.display().to_string());
+
+    let files = files.iter().map(|file| file.display().to_string());
+    std::iter::once(program)
+        .chain(flags(config).into_iter().map(String::from))
+        .chain(files)
+        .map(|arg| quote(&arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs ott with nothing to check, only to have it loaded.
+pub fn prewarm(config: &Config) -> io::Result<ExitStatus> {
+    ott(config)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+}
+
+/// The diagnostic to report when ott couldn't be run at all.
+pub fn spawn_failed(config: &Config, error: &io::Error) -> Diagnostic {
+    let message = match error.kind() {
+        io::ErrorKind::NotFound if config.ott_path.is_none() => "`ott` not found on `PATH`: install ott or set `ottPath`".into(),
+        io::ErrorKind::NotFound => format!("`{}` not found: check `ottPath`", config.ott_path()),
+        _ => format!("failed to run `{}`: {error}", config.ott_path()),
+    };
+
+    Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::ERROR),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Runs the `preCheck` command, if there is one, in the directory of `path`,
+/// returning the diagnostic to report in place of checking `path` if it
+/// couldn't be run or failed.
+pub fn pre_check(config: &Config, path: &Path) -> Option<Diagnostic> {
+    let (program, args) = config.pre_check.split_first()?;
+    let mut command = Command::new(program);
+    command.args(args).stdin(Stdio::null()).stdout(Stdio::null());
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        command.current_dir(dir);
+    }
+
+    let message = match command.output() {
+        Ok(output) if output.status.success() => return None,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            format!("`preCheck` command `{program}` failed ({}): {}", output.status, stderr.trim_end())
+        }
+        Err(e) => format!("failed to run `preCheck` command `{program}`: {e}"),
+    };
+
+    Some(Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::ERROR),
+        message,
+        ..Default::default()
+    })
+}
+
+/// Runs the `postGenerate` command for `target`, if there is one, on the file
+/// ott generated at `output`, in its directory. Returns the command's name
+/// if it ran and succeeded, or `None` if there's none to run or, unless
+/// `postGenerateStrict` is set, it isn't installed.
+pub fn post_generate(config: &Config, target: &str, output: &Path) -> Result<Option<String>, String> {
+    let Some((program, args)) = config.post_generate.get(target).and_then(|command| command.split_first()) else {
+        return Ok(None);
+    };
+
+    let mut command = Command::new(program);
+    command.args(args).arg(output).stdin(Stdio::null()).stdout(Stdio::null());
+    if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        command.current_dir(dir);
+    }
+
+    match command.output() {
+        Ok(output) if output.status.success() => Ok(Some(program.clone())),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!("`postGenerate` command `{program}` failed ({}): {}", output.status, stderr.trim_end()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !config.post_generate_strict => Ok(None),
+        Err(e) => Err(format!("failed to run `postGenerate` command `{program}`: {e}")),
+    }
+}
+
+/// A copy of an unsaved buffer on disk for ott to read, removed on drop
+/// unless it's kept.
+pub struct TempFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempFile {
+    /// Writes `text`, the unsaved contents of `path`, to a new temp file in
+    /// `dir` with the same file name, ending in `.ott` so that ott reads it as
+    /// a source file. If `ensure_trailing_newline`, a missing newline at the
+    /// end of `text` is added, as most editors do on save.
+    pub fn write(dir: &Path, path: &Path, text: &str, ensure_trailing_newline: bool) -> io::Result<TempFile> {
+        let mut name = path.file_name().unwrap_or("buffer.ott".as_ref()).to_string_lossy().into_owned();
+        if path.extension().is_none_or(|extension| extension != "ott") {
+            name.push_str(".ott");
+        }
+
+        let mut contents = text.to_string();
+        if ensure_trailing_newline && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+
+        TempFile::create(dir, &name, &contents)
+    }
+
+    /// Creates a new empty temp file in `dir` named like `name`, extension
+    /// and all, for ott to write output to: ott picks the backend to generate
+    /// from the output's extension.
+    pub fn output(dir: &Path, name: &Path) -> io::Result<TempFile> {
+        TempFile::create(dir, &name.to_string_lossy(), "")
+    }
+
+    fn create(dir: &Path, name: &str, contents: &str) -> io::Result<TempFile> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let temp = dir.join(format!("ott-lsp-{}-{n}-{name}", std::process::id()));
+
+        // Don't leave half a copy behind if writing it fails.
+        let temp = TempFile { path: temp, keep: false };
+        std::fs::write(&temp.path, contents)?;
+        Ok(temp)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leaves the file on disk once this is dropped.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Returns the diagnostic to report in place of checking `path`, if `path`
+/// isn't something ott can check. Symlinks are followed, so a link to a file
+/// is a file.
+pub fn not_a_file(path: &Path) -> Option<Diagnostic> {
+    let message = match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => return None,
+        Ok(metadata) if metadata.is_dir() => format!("file path {} is a directory", path.display()),
+        // FIFOs, devices, and sockets: reading them could block, or see
+        // something different each time.
+        Ok(_) => format!("file path {} is not a regular file, and ott can only check those", path.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => format!("file path {} is not a file", path.display()),
+        Err(e) => format!("file path {} can't be read: {e}", path.display()),
+    };
+
+    Some(Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::INFORMATION),
+        message,
+        ..Default::default()
+    })
+}
+
+/// One diagnostic's worth of ott's output: a `File ...` location line and
+/// an `Error:` or `Warning:` line, in either order, plus any lines after them.
+#[derive(Default)]
+struct Block<'a> {
+    location: Option<&'a str>,
+    severity: Option<DiagnosticSeverity>,
+    /// The `(char N)` columns from the lines after the location, in order.
+    columns: Vec<u32>,
+    message: Vec<&'a str>,
+    /// Further `File ...` lines that ott printed as context for this one,
+    /// each with the lines after it but no severity.
+    related: Vec<Block<'a>>,
+}
+
+impl Block<'_> {
+    /// The file the location names, as ott printed it.
+    fn file(&self, profile: &Profile) -> Option<&str> {
+        profile.file.captures(self.location?)?.get(1).map(|m| m.as_str())
+    }
+
+    /// Whether the block reports that ott found several parses of something,
+    /// in which case the locations after it are those of the parses.
+    fn ambiguous(&self) -> bool {
+        self.message.first().is_some_and(|line| line.to_ascii_lowercase().contains("multiple parses"))
+    }
+
+    /// The lines of the block's message, if it has any, in `format`.
+    fn message(&self, format: MessageFormat) -> Option<String> {
+        let separator = match format {
+            MessageFormat::SingleLine => " ",
+            MessageFormat::MultiLine => "\n",
+        };
+
+        (!self.message.is_empty()).then(|| self.message.join(separator))
+    }
+
+    fn diagnostic(self, profile: &Profile, format: MessageFormat) -> Diagnostic {
+        let mut line_start = None;
+        let mut line_end = None;
+        let mut column_start = None;
+        let mut column_end = None;
+
+        // Parse line and column numbers using regex, making `column N`s
+        // 0-based like the `(char N)`s. A span's last column is the last
+        // character in it, so the 0-based end just past that is the same
+        // number.
+        let location = self.location.unwrap_or_default();
+        let number = |m: regex::Match<'_>| m.as_str().parse::<u32>().ok();
+        let column = |m: regex::Match<'_>| number(m).map(|c| c.saturating_sub(1));
+
+        // Every `(char N)` in the block, wherever ott printed it: the first
+        // is where the span starts and the last, if there are several, where
+        // it ends.
+        let chars: Vec<u32> = profile.col.captures_iter(location)
+            .filter_map(|caps| caps.get(1)?.as_str().parse().ok())
+            .chain(self.columns.iter().copied())
+            .collect();
+
+        if let Some(caps) = profile.range1.captures(location) {
+            line_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
+            column_start = caps.get(2).and_then(column);
+            column_end = caps.get(3).and_then(number);
+        } else if let Some(caps) = profile.range2.captures(location) {
+            line_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
+            column_start = caps.get(2).and_then(column);
+            line_end = caps.get(3).and_then(|m| m.as_str().parse::<u32>().ok());
+            column_end = caps.get(4).and_then(number);
+        } else if let Some(caps) = profile.range3.captures(location) {
+            // A `line N` with at most one `column N`. Otherwise, the
+            // columns, if any, come as `(char N)`s, either here or on later
+            // lines of the block.
+            line_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
+            column_start = profile.column.captures(location).and_then(|caps| caps.get(1)).and_then(column);
+        }
+
+        if let Some(&first) = chars.first().filter(|_| column_start.is_none() || profile.col.is_match(location)) {
+            column_start = Some(first);
+            column_end = chars.last().copied().filter(|&last| last > first);
+        }
+
+        let message = self.message(format).unwrap_or_else(|| "unknown ott diagnostic message".into());
+
+        // A column on no line in particular is left on the first until
+        // `place_lineless` finds a better one.
+        let data = (line_start.is_none() && column_start.is_some()).then(|| LINELESS.into());
+
+        // Create diagnostic range
+        let line_start = line_start.map(|l| l.saturating_sub(1)).unwrap_or(0);
+        let line_end = line_end.map(|l| l.saturating_sub(1)).unwrap_or(line_start);
+        let range = match (column_start, column_end) {
+            (Some(col_start), Some(col_end)) => Range::new(
+                Position::new(line_start, col_start),
+                Position::new(line_end, col_end),
+            ),
+            // Only the start is known: point at the character there
+            // rather than guessing at a span.
+            (Some(col), None) => Range::new(
+                Position::new(line_start, col),
+                Position::new(line_start, col + 1),
+            ),
+            (None, _) => Range::new(
+                Position::new(line_start, 0),
+                Position::new(line_end, 0),
+            ),
+        };
+
+        let code = UNDEFINED.is_match(&message).then(|| NumberOrString::String(UNDEFINED_NONTERMINAL.into()));
+        Diagnostic { range, severity: self.severity, code, source: Some(OTT.into()), message, data, ..Default::default() }
+    }
+
+    /// This block as context for another, in a check of `primary` that
+    /// reports its diagnostics against `uri`, with its message in `format`,
+    /// or `label` if it has none.
+    fn related(
+        self,
+        profile: &Profile,
+        format: MessageFormat,
+        label: &str,
+        files: &[PathBuf],
+        primary: &Path,
+        uri: &Uri,
+    ) -> DiagnosticRelatedInformation {
+        let uri = match self.file(profile).and_then(|file| named(file, files)) {
+            Some(path) if path != primary => crate::path_uri(path).unwrap_or_else(|| uri.clone()),
+            _ => uri.clone(),
+        };
+
+        let message = self.message(format).unwrap_or_else(|| label.into());
+        let range = self.diagnostic(profile, format).range;
+        DiagnosticRelatedInformation { location: Location::new(uri, range), message }
+    }
+}
+
+/// Where a diagnostic from a check of `primary` belongs.
+enum Route {
+    Primary,
+    /// Another of the files ott was run on, or a file elsewhere that ott
+    /// read on their behalf.
+    Other(PathBuf),
+    /// None of the files ott was run on, as when ott merged them.
+    Unknown,
+}
+
+/// The one of `files` that ott means by `file`.
+fn named<'f>(file: &str, files: &'f [PathBuf]) -> Option<&'f PathBuf> {
+    let file = Path::new(file);
+    files.iter().find(|candidate| {
+        theory::same_file(candidate, file) || (file.is_relative() && candidate.file_name() == file.file_name())
+    })
+}
+
+fn route(file: Option<&str>, files: &[PathBuf], primary: &Path, resolve: Resolve<'_>) -> Route {
+    let Some(file) = file else { return Route::Primary };
+    match named(file, files) {
+        Some(candidate) if candidate == primary => Route::Primary,
+        Some(other) => Route::Other(other.clone()),
+        None => resolve(file).map_or(Route::Unknown, Route::Other),
+    }
+}
+
+/// Finds the file ott means by a name that's none of the files it was run
+/// on, if it can.
+pub type Resolve<'a> = &'a dyn Fn(&str) -> Option<PathBuf>;
+
+/// The diagnostics from one ott run, by the file they're in.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// Those for the file being checked, including those in none of the
+    /// files ott was run on.
+    pub primary: Vec<Diagnostic>,
+    /// Those in each of the other files ott was run on, or read.
+    pub others: HashMap<PathBuf, Vec<Diagnostic>>,
+}
+
+/// Parses the diagnostics for `primary` out of the `output` of ott run on
+/// `files`. Diagnostics in the others are left to their own checks, and those
+/// that name none of them are reported against `primary`, whose document is
+/// `uri`.
+pub fn diagnostics(config: &Config, output: &Output, files: &[PathBuf], primary: &Path, uri: &Uri) -> Vec<Diagnostic> {
+    split_diagnostics(config, output, files, primary, uri, &|_| None).primary
+}
+
+/// Parses the diagnostics out of the `output` of ott run on `files` like
+/// [`diagnostics()`], but keeps those in the files other than `primary`,
+/// for reporting against those. So are those in files `resolve` finds, like
+/// one the files include; those in files it can't are `primary`'s.
+/// With `annotatePass`, each of ott's diagnostics has the pass that reported
+/// it added to its source, as ott marks it or, if it hasn't yet, as
+/// [`pass()`] tells it.
+pub fn split_diagnostics(
+    config: &Config,
+    output: &Output,
+    files: &[PathBuf],
+    primary: &Path,
+    uri: &Uri,
+    resolve: Resolve<'_>,
+) -> Diagnostics {
+    let profile = profile(config);
+    let mut parsed = false;
+    let mut diagnostics = Vec::new();
+    let mut others: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+    let mut push = |mut block: Block<'_>, pass: Option<&str>| {
+        parsed = true;
+        let route = route(block.file(profile), files, primary, resolve);
+        let file = block.file(profile).unwrap_or_default().to_string();
+        let label = if block.ambiguous() { "competing parse" } else { "related location" };
+        let related: Vec<_> = std::mem::take(&mut block.related).into_iter()
+            .map(|related| related.related(profile, config.message_format, label, files, primary, uri))
+            .collect();
+
+        let mut diagnostic = block.diagnostic(profile, config.message_format);
+        if !related.is_empty() {
+            diagnostic.related_information = Some(related);
+        }
+
+        if config.annotate_pass && let Some(pass) = pass.or_else(|| self::pass(&diagnostic.message)) {
+            diagnostic.source = Some(format!("{OTT}[{pass}]"));
+        }
+
+        match route {
+            Route::Primary => diagnostics.push(diagnostic),
+            Route::Other(file) => others.entry(file).or_default().push(diagnostic),
+            Route::Unknown => {
+                if config.merge == Some(true) {
+                    diagnostic.message.push_str(&format!(" (reported in {file}, merged from the checked files)"));
+                }
+
+                diagnostics.push(diagnostic)
+            }
+        }
+    };
+
+    let mut info = Vec::new();
+    let mut block: Option<Block<'_>> = None;
+    // The pass ott last said it moved on to, if any.
+    let mut pass = None;
+    let mut last = "";
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in stdout.lines() {
+        // `lines` drops the `\r` of a `\r\n`, but not one before a `\r\n`,
+        // as a doubly-translated Windows line ending has, or at the very end.
+        let line = line.trim_end_matches('\r');
+        let previous = std::mem::replace(&mut last, line);
+        let severity = if let Some(msg) = line.strip_prefix("Error:") {
+            Some((DiagnosticSeverity::ERROR, msg.trim()))
+        } else {
+            line.strip_prefix("Warning:").map(|msg| (DiagnosticSeverity::WARNING, msg.trim()))
+        };
+
+        // A severity can carry its own location, as in `Warning: <message>
+        // at line N, column C`, making one line a whole diagnostic, unless
+        // it's the severity a `File ...` line before it is waiting on.
+        let awaited = block.as_ref().is_some_and(|block| block.location.is_some() && block.severity.is_none());
+        if let Some((severity, msg)) = severity
+            && !awaited
+            && let Some(caps) = profile.inline.captures(msg)
+        {
+            if let Some(block) = block.take() {
+                push(block, pass);
+            }
+
+            push(Block {
+                location: caps.get(2).map(|m| m.as_str()),
+                severity: Some(severity),
+                message: vec![caps.get(1).map_or("", |m| m.as_str())],
+                ..Default::default()
+            }, pass);
+
+            continue;
+        }
+
+        let is_location = line.starts_with("File");
+
+        // A location right after a located diagnostic's message, rather
+        // than after a blank line, is context for it, unless a severity
+        // follows and it turns out to start the next diagnostic. After an
+        // ambiguity, each of a run of locations is one of the parses.
+        let chained = is_location
+            && !previous.trim().is_empty()
+            && block.as_ref().is_some_and(|block| {
+                block.location.is_some()
+                    && block.severity.is_some()
+                    && (!previous.starts_with("File") || block.ambiguous())
+            });
+
+        if let Some(block) = block.as_mut().filter(|_| chained) {
+            block.related.push(Block { location: Some(line), ..Default::default() });
+            continue;
+        }
+
+        let mut location = is_location.then_some(line);
+        if severity.is_some()
+            && let Some(block) = block.as_mut()
+            && block.related.last().is_some_and(|related| related.message.is_empty() && related.columns.is_empty())
+        {
+            location = block.related.pop().and_then(|related| related.location);
+        }
+
+        if location.is_some() || severity.is_some() {
+            // A second location or severity means this line starts the next
+            // diagnostic, whichever of the two ott printed first.
+            let repeated = block.as_ref().is_some_and(|block| {
+                (location.is_some() && block.location.is_some())
+                    || (severity.is_some() && block.severity.is_some())
+            });
+
+            if let Some(block) = block.take_if(|_| repeated) {
+                push(block, pass);
+            }
+
+            let block = block.get_or_insert_default();
+            if location.is_some() {
+                block.location = location;
+            }
+
+            if let Some((severity, msg)) = severity {
+                block.severity = Some(severity);
+                if !msg.is_empty() {
+                    block.message.push(msg);
+                }
+            }
+        } else if INFO.is_match(line) {
+            if let Some(block) = block.take() {
+                push(block, pass);
+            }
+
+            if let Some((_, next)) = profile.passes.iter().find(|(marker, _)| marker.is_match(line)) {
+                pass = Some(*next);
+            }
+
+            if config.show_info {
+                info.push(Diagnostic {
+                    range: Range::default(),
+                    severity: Some(DiagnosticSeverity::HINT),
+                    message: line.trim().to_string(),
+                    ..Default::default()
+                });
+            }
+        } else if let Some(block) = block.as_mut() {
+            // Lines after a context location belong to it.
+            let block = match block.related.last_mut() {
+                Some(related) => related,
+                None => block,
+            };
+
+            if profile.col.is_match(line) {
+                let columns = profile.col.captures_iter(line).filter_map(|caps| caps.get(1)?.as_str().parse::<u32>().ok());
+                block.columns.extend(columns);
+            } else if !line.trim().is_empty() && !line.starts_with("Definition rule") {
+                block.message.push(line.trim());
+            }
+        }
+    }
+
+    if let Some(block) = block {
+        push(block, pass);
+    }
+
+    // Whatever ott printed before it was stopped is as far as it got, and
+    // not necessarily why it failed.
+    let terminated = terminated(config, output);
+    if let Some(message) = &terminated {
+        diagnostics.push(Diagnostic {
+            range: Range::default(),
+            severity: Some(config.generic_failure_severity()),
+            message: message.clone(),
+            ..Default::default()
+        });
+    }
+
+    // emit a general error if no specific errors/warnings were found, with
+    // the last thing ott said on the way out, like a file it couldn't read
+    if !parsed && !output.status.success() && terminated.is_none() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = match stderr.lines().rfind(|line| !line.trim().is_empty()) {
+            Some(last) => format!("{FAILED}: {}", last.trim()),
+            None => FAILED.to_string(),
+        };
+
+        diagnostics.push(Diagnostic {
+            range: Range::default(),
+            severity: Some(config.generic_failure_severity()),
+            message,
+            ..Default::default()
+        });
+    }
+
+    if output.truncated {
+        let limit = config.max_output_bytes();
+        let message = format!("ott printed more than {limit} bytes; the rest was ignored (see `maxOutputBytes`)");
+        diagnostics.push(notice(message));
+    }
+
+    diagnostics.extend(info);
+    Diagnostics { primary: diagnostics, others }
+}
+
+/// The start of the message of the diagnostic reported when ott fails
+/// without saying where.
+const FAILED: &str = "ott processing failed";
+
+/// Why ott exited as it did in `output`, if it didn't exit of its own accord
+/// but was stopped: by us, for running past `checkTimeoutMs`, or by a signal.
+fn terminated(config: &Config, output: &Output) -> Option<String> {
+    if output.timed_out {
+        let ms = config.check_timeout_ms.unwrap_or_default();
+        return Some(format!("ott was stopped for running longer than {ms} ms (see `checkTimeoutMs`)"));
+    }
+
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&output.status);
+    #[cfg(not(unix))]
+    let signal: Option<i32> = None;
+
+    // SIGKILL is the OOM killer's.
+    signal.map(|signal| match signal {
+        9 => format!("ott was killed (signal {signal}), perhaps for running out of memory"),
+        _ => format!("ott was terminated by signal {signal}"),
+    })
+}
+
+/// Moves the diagnostic reported when ott fails without saying where, if it's
+/// among `diagnostics`, from the start of the file to `position`.
+pub fn place_unlocated(diagnostics: &mut [Diagnostic], position: Position) {
+    let unlocated = diagnostics.iter_mut().filter(|d| {
+        d.source.is_none() && d.range == Range::default() && d.message.starts_with(FAILED)
+    });
+
+    for diagnostic in unlocated {
+        diagnostic.range = Range::new(position, position);
+    }
+}
+
+/// Moves each of ott's diagnostics that it gave a column but no line for to
+/// the one line of `text`, if known, long enough to have that column, or if
+/// there are several, to the line of the last edit, `edit`, if known and
+/// `lineFallback` allows, and otherwise leaves it on the first line.
+pub fn place_lineless(config: &Config, diagnostics: &mut [Diagnostic], text: Option<&str>, edit: Option<Position>) {
+    let lineless = diagnostics.iter_mut().filter(|d| d.data.as_ref().and_then(|d| d.as_str()) == Some(LINELESS));
+    for diagnostic in lineless {
+        diagnostic.data = None;
+
+        // ott's columns count bytes.
+        let column = diagnostic.range.start.character as usize;
+        let mut fits = text.into_iter()
+            .flat_map(|text| text.split('\n'))
+            .enumerate()
+            .filter(|(_, line)| line.trim_end_matches('\r').len() > column);
+
+        let only = match (fits.next(), fits.next()) {
+            (Some((line, _)), None) => Some(line as u32),
+            _ => None,
+        };
+
+        let fallback = edit.filter(|_| config.line_fallback == LineFallback::Edit).map(|edit| edit.line);
+        let line = only.or(fallback).unwrap_or(0);
+        diagnostic.range.start.line = line;
+        diagnostic.range.end.line = line;
+    }
+}
+
+/// A warning about the diagnostics themselves, rather than the file.
+fn notice(message: String) -> Diagnostic {
+    Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::WARNING),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Puts the diagnostics from every source for a document whose text is
+/// `text`, if known, into the form they're reported in. In order:
+///
+///   1. ott's diagnostics with a column but no line that haven't been placed
+///      yet are, as [`place_lineless()`] does without an edit to go by, and
+///      ott's columns, which count bytes, are converted to UTF-16 code units
+///      like the rest, and every range is clamped to the text
+///   2. with `strict`, ott's warnings, and with `strictLints` too, the lints',
+///      are made errors, before any severity is left unpublished
+///   3. the diagnostics are sorted by position, and duplicates dropped
+///   4. those of severities that aren't published are dropped
+///   5. all but the first `maxDiagnostics` are dropped, with a notice or, if
+///      they're to be summarized, a summary of them for each section
+pub fn finalize_diagnostics(config: &Config, text: Option<&str>, mut diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    place_lineless(config, &mut diagnostics, text, None);
+    if let Some(text) = text {
+        let lines: Vec<_> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+        for diagnostic in &mut diagnostics {
+            let from_ott = from_ott(diagnostic);
+            let range = &mut diagnostic.range;
+            range.start = clamp(&lines, range.start, from_ott, false);
+            range.end = clamp(&lines, range.end, from_ott, true);
+
+            range.end = range.end.max(range.start);
+        }
+    }
+
+    if config.strict {
+        let promoted = |d: &Diagnostic| from_ott(d) || config.strict_lints;
+        for diagnostic in diagnostics.iter_mut().filter(|d| d.severity == Some(DiagnosticSeverity::WARNING)) {
+            if promoted(diagnostic) {
+                diagnostic.severity = Some(DiagnosticSeverity::ERROR);
+            }
+        }
+    }
+
+    // Clients may redraw diagnostics that only moved, so identical checks
+    // must publish them in an identical order.
+    diagnostics.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+
+    diagnostics.dedup();
+    diagnostics.retain(|d| config.publishes(d));
+    let max = config.max_diagnostics();
+    if diagnostics.len() > max {
+        let dropped = diagnostics.split_off(max);
+        match config.diagnostics_overflow {
+            Overflow::Truncate => diagnostics.push(notice(format!("{} more diagnostics were ignored", dropped.len()))),
+            Overflow::Summarize => diagnostics.extend(summarize(text, dropped)),
+        }
+    }
+
+    diagnostics
+}
+
+/// Whether `diagnostic` is one of ott's, whether or not its pass is named.
+fn from_ott(diagnostic: &Diagnostic) -> bool {
+    diagnostic.source.as_deref().is_some_and(|source| source.strip_prefix(OTT).is_some_and(|pass| {
+        pass.is_empty() || pass.starts_with('[')
+    }))
+}
+
+/// The pass of ott that reported a diagnostic with `message` before ott
+/// said it had moved on from checking the source, which ott doesn't mark:
+/// `parse` for terms that don't parse, and `sort` for the checks of the
+/// grammar and definitions, like of undefined or duplicate names.
+fn pass(message: &str) -> Option<&'static str> {
+    if PARSE_PASS.is_match(message) {
+        Some("parse")
+    } else if SORT_PASS.is_match(message) {
+        Some("sort")
+    } else {
+        None
+    }
+}
+
+/// One diagnostic for each section of `text`, if known, that any of the
+/// `dropped` diagnostics are in, where the first of them is, as severe as
+/// the most severe of them, and saying how many there were.
+fn summarize(text: Option<&str>, dropped: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    let blocks = text.map(parse::blocks).unwrap_or_default();
+    let mut summaries: Vec<(Option<usize>, Diagnostic, usize)> = vec![];
+    for diagnostic in dropped {
+        let line = diagnostic.range.start.line;
+        let block = blocks.iter().position(|b| b.lines.contains(&line));
+        match summaries.iter_mut().find(|(b, ..)| *b == block) {
+            Some((_, summary, count)) => {
+                let severity = |d: &Diagnostic| d.severity.unwrap_or(DiagnosticSeverity::ERROR);
+                summary.severity = Some(severity(summary).min(severity(&diagnostic)));
+                *count += 1;
+            }
+            None => summaries.push((block, diagnostic, 1)),
+        }
+    }
+
+    summaries.into_iter()
+        .map(|(block, first, count)| {
+            let section = match block {
+                Some(i) => format!(" in this `{}` section", blocks[i].keyword),
+                None => String::new(),
+            };
+
+            Diagnostic {
+                range: Range::new(first.range.start, first.range.start),
+                severity: first.severity,
+                message: format!("{count} more diagnostics{section} were ignored, starting with: {}", first.message),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// What diagnostics are published in order of: where they start, how severe
+/// they are, with a missing severity being an error, and what they say.
+fn sort_key(diagnostic: &Diagnostic) -> (Position, DiagnosticSeverity, &str, Position, Option<&str>) {
+    let severity = diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR);
+    let source = diagnostic.source.as_deref();
+    (diagnostic.range.start, severity, &diagnostic.message, diagnostic.range.end, source)
+}
+
+/// Moves `position` onto one of `lines`, converting its column from bytes to
+/// UTF-16 code units first if `bytes`. A column in the middle of a character
+/// is moved to its start, or if `end`, past it, so that a span ending there,
+/// like the one byte long span of a diagnostic ott gave only a start for,
+/// still covers the character.
+fn clamp(lines: &[&str], position: Position, bytes: bool, end: bool) -> Position {
+    let last = lines.len().saturating_sub(1) as u32;
+    if position.line > last {
+        let end = lines.last().map_or(0, |line| parse::utf16_len(line));
+        return Position::new(last, end);
+    }
+
+    let line = lines[position.line as usize];
+    let character = match bytes {
+        true => {
+            let mut column = (position.character as usize).min(line.len());
+            while !line.is_char_boundary(column) {
+                match end {
+                    true => column += 1,
+                    false => column -= 1,
+                }
+            }
+
+            parse::utf16_len(&line[..column])
+        }
+        false => position.character.min(parse::utf16_len(line)),
+    };
+
+    Position::new(position.line, character)
+}
+
+/// The diagnostics from the last ott run on a file, keyed by a hash of the
+/// file's contents and the settings it was checked with. Clean runs are
+/// cached too: an empty `diagnostics` is a result, not the absence of one.
+pub struct CachedCheck {
+    pub key: u64,
+    pub diagnostics: Diagnostics,
+}
+
+pub type CheckCache = RwLock<HashMap<Uri, CachedCheck>>;
+
+/// Checks the file at `file_path`, or its unsaved `buffer`, with the rest of
+/// `files`, the files of its theory, by ott as `ott` runs it, reusing what
+/// ott said last time if nothing it reads has changed since. Diagnostics
+/// are split between the files as [`split_diagnostics()`] splits them, but
+/// not finalized, and what's worth noting on the way is told to `log`.
+#[allow(clippy::too_many_arguments)]
+pub fn check_ott_file(
+    config: &Config,
+    cache: &CheckCache,
+    file_path: &Path,
+    files: &[PathBuf],
+    buffer: Option<&str>,
+    uri: &Uri,
+    ott: &dyn OttRunner,
+    resolve: Resolve<'_>,
+    log: &dyn Fn(MessageType, String),
+) -> Result<Diagnostics, Box<dyn Error + Sync + Send>> {
+    // A buffer that's never been saved can still be checked from a copy.
+    let only = |diagnostic| Diagnostics { primary: vec![diagnostic], ..Default::default() };
+    if let Some(warning) = not_a_file(file_path).filter(|_| buffer.is_none() || file_path.exists()) {
+        return Ok(only(warning));
+    }
+
+    // ott only reads files, so check a copy of the buffer if it's unsaved,
+    // or with `useStdin`, if it's checked alone, pipe it to ott to read as
+    // its stdin.
+    let saved = std::fs::read(file_path).ok();
+    let unsaved = buffer.filter(|text| saved.as_deref() != Some(text.as_bytes()));
+    let stdin = STDIN.filter(|_| config.use_stdin && files.len() == 1);
+    let piped = unsaved.filter(|_| stdin.is_some()).map(|text| {
+        let mut text = text.to_string();
+        if config.ensure_trailing_newline() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+
+        text
+    });
+
+    let mut temp = unsaved
+        .filter(|_| piped.is_none())
+        .map(|text| TempFile::write(&config.temp_dir(), file_path, text, config.ensure_trailing_newline()))
+        .transpose()?;
+
+    if config.keep_temp_files && let Some(temp) = &mut temp {
+        temp.keep();
+        log(MessageType::LOG, format!("kept copy of {} at {}", file_path.display(), temp.path().display()));
+    }
+
+    let files = match (&temp, stdin.filter(|_| piped.is_some())) {
+        (Some(temp), _) => theory::substitute(files, file_path, temp.path()),
+        (None, Some(stdin)) => vec![PathBuf::from(stdin)],
+        (None, None) => files.to_vec(),
+    };
+
+    // Neither does a failed `preCheck` nor not being able to run ott say
+    // anything about the file, so they're reported but not cached. `preCheck`
+    // runs first, since it may write the files ott reads.
+    if let Some(failed) = pre_check(config, file_path) {
+        return Ok(only(failed));
+    }
+
+    // If neither the files nor the config changed since the last run, ott
+    // would say the same thing again. Republish what it said last time.
+    let mut hasher = DefaultHasher::new();
+    match &piped {
+        Some(text) => text.hash(&mut hasher),
+        None => for file in &files {
+            std::fs::read(file).ok().hash(&mut hasher);
+        },
+    }
+
+    // Every setting that changes what's reported, whether how ott runs or
+    // how what it prints is read, like `messageFormat`.
+    config.diagnostics_fingerprint().hash(&mut hasher);
+    let key = hasher.finish();
+    if let Some(cached) = cache.read().get(uri).filter(|cached| cached.key == key) {
+        return Ok(cached.diagnostics.clone());
+    }
+
+    let output = match ott.run(config, &files, piped.as_deref()) {
+        Ok(output) => output,
+        Err(e) => return Ok(only(spawn_failed(config, &e))),
+    };
+
+    let primary = match (&temp, &piped) {
+        (Some(temp), _) => temp.path(),
+        (None, Some(_)) => &files[0],
+        (None, None) => file_path,
+    };
+
+    let diagnostics = split_diagnostics(config, &output, &files, primary, uri, resolve);
+
+    cache.write().insert(uri.clone(), CachedCheck { key, diagnostics: diagnostics.clone() });
+    Ok(diagnostics)
+}
+
+/// Checks `text` as the contents of the ott file at `path`, the way an open
+/// document is checked: by ott, along with the rest of its theory as it is on
+/// disk if `path` is part of one, then by the server's own passes. What ott
+/// prints comes from `ott`, which may replay recorded output instead of
+/// running it, so that the output can be checked without an ott binary.
+pub fn diagnostics_for(config: &Config, path: &Path, text: &str, ott: &dyn OttRunner) -> io::Result<Vec<Diagnostic>> {
+    let uri = crate::path_uri(&std::path::absolute(path)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path isn't valid UTF-8"))?;
+
+    let temp = TempFile::write(&config.temp_dir(), path, text, config.ensure_trailing_newline())?;
+    let theory = theory::resolve(&config.theory, None);
+    let files = theory::substitute(&theory::files_for(&theory, path), path, temp.path());
+    let output = ott.run(config, &files, None);
+    let mut diagnostics = match output {
+        Ok(output) => self::diagnostics(config, &output, &files, temp.path(), &uri),
+        Err(e) => vec![spawn_failed(config, &e)],
+    };
+
+    let doc = Document::parse(text);
+    relate_unused(&mut diagnostics, &doc, &uri);
+    diagnostics.extend(crate::lint::lint(config, &doc, text, &uri));
+    Ok(finalize_diagnostics(config, Some(text), diagnostics))
+}
+
+/// Points ott's warnings about unused productions at the declarations of the
+/// productions they name, which ott doesn't locate itself.
+pub fn relate_unused(diagnostics: &mut [Diagnostic], doc: &Document, uri: &Uri) {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    for diagnostic in diagnostics {
+        if diagnostic.severity != Some(DiagnosticSeverity::WARNING) || !UNUSED.is_match(&diagnostic.message) {
+            continue;
+        }
+
+        let mut related = vec![];
+        for word in diagnostic.message.split(|c| !is_name(c)) {
+            let productions = doc.grammars.iter().flat_map(|rule| &rule.productions);
+            for p in productions.filter(|p| p.id == word || p.label.text == word) {
+                if related.iter().all(|r: &DiagnosticRelatedInformation| r.location.range != p.label.range) {
+                    related.push(DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), p.label.range),
+                        message: format!("`{}` is declared here", p.id),
+                    });
+                }
+            }
+        }
+
+        if !related.is_empty() {
+            diagnostic.related_information = Some(related);
+        }
+    }
+}
+
+/// Whether any of `diagnostics` is ott's complaint about a name declared more
+/// than once, for [`relate_duplicates()`] to locate.
+pub fn has_duplicates(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| from_ott(d) && DUPLICATE.is_match(&d.message))
+}
+
+/// Points ott's complaints about metavars and nonterminals declared more than
+/// once at each of their declarations in `docs`, the files of the theory.
+pub fn relate_duplicates(diagnostics: &mut [Diagnostic], docs: &[(Uri, Document)]) {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    for diagnostic in diagnostics {
+        if !from_ott(diagnostic) || !DUPLICATE.is_match(&diagnostic.message) {
+            continue;
+        }
+
+        let mut related = vec![];
+        for word in diagnostic.message.split(|c| !is_name(c)).filter(|word| !word.is_empty()) {
+            let declarations: Vec<_> = docs.iter()
+                .flat_map(|(uri, doc)| {
+                    let metavars = doc.metavars.iter().flat_map(|m| &m.names);
+                    let nonterminals = doc.grammars.iter().flat_map(|rule| &rule.names);
+                    metavars.chain(nonterminals).filter(|name| name.text == word).map(move |name| (uri, name))
+                })
+                .collect();
+
+            if declarations.len() < 2 {
+                continue;
+            }
+
+            let total = declarations.len();
+            for (n, (uri, name)) in declarations.into_iter().enumerate() {
+                related.push(DiagnosticRelatedInformation {
+                    location: Location::new(uri.clone(), name.range),
+                    message: format!("`{word}` is declared here ({} of {total})", n + 1),
+                });
+            }
+        }
+
+        if !related.is_empty() {
+            diagnostic.related_information.get_or_insert_default().extend(related);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::ExitStatus;
+
+    use lsp_types::*;
+
+    use crate::config::Config;
+    use super::*;
+
+    /// The settings `settings` sets, with an ott that doesn't exist, so that
+    /// nothing is asked of a real one.
+    fn config(settings: serde_json::Value) -> Config {
+        let mut config: Config = serde_json::from_value(settings).unwrap();
+        config.ott_path.get_or_insert_with(|| "ott-lsp-test-no-ott".into());
+        config
+    }
+
+    #[cfg(unix)]
+    fn exit(code: i32) -> ExitStatus {
+        std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    const TEXT: &str = "metavar termvar, x ::=\n  {{ com term variables }}\n";
+
+    #[test]
+    fn diagnostics_for_locates_otts_errors() {
+        let ott = Recorded::stdout(b"File \"x.ott\" on line 2, column 3 - 5:\nError: no parses\n".to_vec());
+        let diagnostics = diagnostics_for(&config(serde_json::json!({})), Path::new("/x.ott"), TEXT, &ott).unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((1, 2), (1, 5)));
+        assert_eq!(diagnostics[0].message, "no parses");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].source.as_deref(), Some("ott"));
+    }
+
+    #[test]
+    fn diagnostics_for_adds_lints() {
+        let text = "metavar termvar, x ::=\n  {{ com term variables\n";
+        let ott = Recorded::stdout(vec![]);
+        let diagnostics = diagnostics_for(&config(serde_json::json!({})), Path::new("/x.ott"), text, &ott).unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((1, 2), (1, 4)));
+        assert_eq!(diagnostics[0].message, "`{{` is never closed by a `}}`");
+        assert_eq!(diagnostics[0].source.as_deref(), Some("ott-lsp"));
+    }
+
+    #[test]
+    fn diagnostics_for_is_empty_for_a_clean_run() {
+        let ott = Recorded::stdout(b"Ott version 0.33\n".to_vec());
+        let diagnostics = diagnostics_for(&config(serde_json::json!({})), Path::new("/x.ott"), TEXT, &ott).unwrap();
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn diagnostics_for_reports_silent_failures() {
+        let stderr = b"Fatal error: exception Not_found\n".to_vec();
+        let ott = Recorded { status: exit(2), stderr, ..Recorded::stdout(vec![]) };
+        let settings = serde_json::json!({ "genericFailureSeverity": "warning" });
+        let diagnostics = diagnostics_for(&config(settings), Path::new("/x.ott"), TEXT, &ott).unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range::default());
+        assert_eq!(diagnostics[0].message, "ott processing failed: Fatal error: exception Not_found");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn silent_failures_are_errors_unless_configured_otherwise() {
+        let ott = Recorded { status: exit(2), ..Recorded::stdout(vec![]) };
+        let severity = |settings| diagnostics_for(&config(settings), Path::new("/x.ott"), TEXT, &ott).unwrap()[0].severity;
+        assert_eq!(severity(serde_json::json!({})), Some(DiagnosticSeverity::ERROR));
+        assert_eq!(severity(serde_json::json!({ "genericFailureSeverity": "info" })), Some(DiagnosticSeverity::INFORMATION));
+
+        // Failures ott explains are as severe as ott says.
+        let ott = Recorded { status: exit(2), ..Recorded::stdout(b"Warning: unused production t_var at line 1\n".to_vec()) };
+        let config = config(serde_json::json!({ "genericFailureSeverity": "hint" }));
+        let diagnostics = diagnostics_for(&config, Path::new("/x.ott"), TEXT, &ott).unwrap();
+        let read: Vec<_> = diagnostics.iter().map(|d| (d.severity, d.message.as_str())).collect();
+        assert_eq!(read, [(Some(DiagnosticSeverity::WARNING), "unused production t_var")]);
+    }
+
+    /// Replays what ott printed in `tests/fixtures/<name>`.
+    fn fixture(name: &str) -> Recorded {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        Recorded::stdout(std::fs::read(path).unwrap())
+    }
+
+    const GRAMMAR: &str = "metavar termvar, x ::=\n  {{ com term variables }}\ngrammar\nt :: 't_' ::=\n  | x :: :: var\n";
+
+    /// Saves `text` as a file of its own, removed on drop.
+    fn saved(text: &str) -> TempFile {
+        TempFile::write(&std::env::temp_dir(), "x.ott".as_ref(), text, false).unwrap()
+    }
+
+    /// Checks the file at `path`, with `buffer` as its unsaved contents, if
+    /// any, as `ott` says, against `cache`.
+    fn check(config: &Config, cache: &CheckCache, path: &Path, buffer: Option<&str>, ott: &dyn OttRunner) -> Diagnostics {
+        let uri = crate::path_uri(path).unwrap();
+        let files = [path.to_path_buf()];
+        check_ott_file(config, cache, path, &files, buffer, &uri, ott, &|_| None, &|_, _| {}).unwrap()
+    }
+
+    fn checked(fixture: &str, settings: serde_json::Value) -> Vec<Diagnostic> {
+        check(&config(settings), &CheckCache::default(), saved(GRAMMAR).path(), None, &self::fixture(fixture)).primary
+    }
+
+    /// An ott that runs the shell script `body`, removed on drop.
+    #[cfg(unix)]
+    fn script(body: &str) -> TempFile {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = TempFile::write(&std::env::temp_dir(), "ott".as_ref(), &format!("#!/bin/sh\n{body}"), true).unwrap();
+        std::fs::set_permissions(script.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    #[test]
+    fn check_ott_file_reads_ranges_on_one_line() {
+        let diagnostics = checked("range.out", serde_json::json!({}));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((1, 2), (1, 5)));
+        assert_eq!(diagnostics[0].message, "no parses of the production");
+    }
+
+    #[test]
+    fn check_ott_file_reads_ranges_over_lines() {
+        let diagnostics = checked("span.out", serde_json::json!({}));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((0, 8), (2, 4)));
+        assert_eq!(diagnostics[0].message, "multiple definitions of termvar");
+    }
+
+    #[test]
+    fn check_ott_file_reads_char_columns() {
+        let diagnostics = checked("chars.out", serde_json::json!({}));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((2, 2), (2, 6)));
+        assert_eq!(diagnostics[0].message, "no parse of the premise");
+    }
+
+    #[test]
+    fn check_ott_file_reads_inline_locations_and_info() {
+        let diagnostics = checked("inline.out", serde_json::json!({}));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((2, 4), (2, 5)));
+        assert_eq!(diagnostics[0].message, "unused production t_var");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+
+        let diagnostics = checked("inline.out", serde_json::json!({ "showInfo": true }));
+        let info: Vec<_> = diagnostics.iter().filter(|d| d.severity == Some(DiagnosticSeverity::HINT)).collect();
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].message, "Ott version 0.33");
+        assert_eq!(info[1].message, "definition rules: 0 good 0 bad");
+    }
+
+    #[test]
+    fn check_ott_file_relates_competing_parses() {
+        let diagnostics = checked("parses.out", serde_json::json!({}));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((2, 2), (2, 8)));
+
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        let ranges: Vec<_> = related.iter().map(|r| r.location.range).collect();
+        assert_eq!(ranges, [range((0, 0), (0, 4)), range((1, 2), (1, 6))]);
+        assert!(related.iter().all(|r| r.message == "competing parse"));
+    }
+
+    #[test]
+    fn crlf_output_is_read_as_lf_output_is() {
+        let config = config(serde_json::json!({ "showInfo": true }));
+        for name in ["range.out", "span.out", "chars.out", "inline.out", "parses.out"] {
+            let lf = String::from_utf8(fixture(name).stdout).unwrap();
+            let crlf = lf.replace('\n', "\r\n");
+            let diagnostics = reported_with(&config, &crlf, GRAMMAR);
+            assert!(!diagnostics.is_empty(), "{name}");
+            assert_eq!(diagnostics, reported_with(&config, &lf, GRAMMAR), "{name}");
+            assert!(diagnostics.iter().all(|d| !d.message.contains('\r')), "{name}");
+        }
+    }
+
+    #[test]
+    fn unused_productions_are_related_to_their_declarations() {
+        let uri = crate::path_uri(&std::path::absolute("x.ott").unwrap()).unwrap();
+        let diagnostics = diagnostics_for(&config(serde_json::json!({})), Path::new("x.ott"), GRAMMAR, &fixture("inline.out")).unwrap();
+        let unused = diagnostics.iter().find(|d| d.message == "unused production t_var").unwrap();
+        assert_eq!(unused.related_information, Some(vec![DiagnosticRelatedInformation {
+            location: Location::new(uri, range((4, 12), (4, 15))),
+            message: "`t_var` is declared here".into(),
+        }]));
+
+        // By its label too, but only in a warning that it's unused.
+        let doc = Document::parse(GRAMMAR);
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let mut diagnostics = reported("Warning: production var is never used at line 5\nError: no parses of var at line 5\n", GRAMMAR);
+        relate_unused(&mut diagnostics, &doc, &uri);
+        let related = |message: &str| diagnostics.iter().find(|d| d.message == message).unwrap().related_information.clone();
+        assert_eq!(related("production var is never used").map(|r| r[0].location.range), Some(range((4, 12), (4, 15))));
+        assert_eq!(related("no parses of var"), None);
+    }
+
+    #[test]
+    fn names_declared_more_than_once_are_related_to_each_declaration() {
+        let (x, y): (Uri, Uri) = ("file:///x.ott".parse().unwrap(), "file:///y.ott".parse().unwrap());
+        let docs = [
+            (x.clone(), Document::parse(GRAMMAR)),
+            (y.clone(), Document::parse("grammar
+u :: 'u_' ::=
+  | u :: :: u
+
+metavar x, termvar ::=
+")),
+        ];
+
+        let stdout = "File \"x.ott\" on line 1, column 9 - 15:\nError: multiple definitions of termvar\n";
+        let mut diagnostics = reported(stdout, GRAMMAR);
+        assert!(has_duplicates(&diagnostics));
+        relate_duplicates(&mut diagnostics, &docs);
+
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        let related: Vec<_> = related.iter().map(|r| (&r.location.uri, r.location.range, r.message.as_str())).collect();
+        assert_eq!(related, [
+            (&x, range((0, 8), (0, 15)), "`termvar` is declared here (1 of 2)"),
+            (&y, range((4, 11), (4, 18)), "`termvar` is declared here (2 of 2)"),
+        ]);
+
+        // Only ott's complaints, and only of names declared more than once.
+        let mut diagnostics = reported("Error: multiple definitions of u at line 1\n", GRAMMAR);
+        diagnostics.push(Diagnostic { source: Some("ott-lsp".into()), ..diagnostics[0].clone() });
+        relate_duplicates(&mut diagnostics, &docs);
+        assert!(diagnostics.iter().all(|d| d.related_information.is_none()));
+        assert!(!has_duplicates(&diagnostics[1..]));
+    }
+
+    #[test]
+    fn check_ott_file_reuses_unchanged_results() {
+        let (config, cache, ott) = (config(serde_json::json!({})), CheckCache::default(), fixture("range.out"));
+        let file = saved(GRAMMAR);
+        let first = check(&config, &cache, file.path(), None, &ott);
+        let second = check(&config, &cache, file.path(), None, &ott);
+        assert_eq!(ott.runs.lock().len(), 1);
+        assert_eq!(first.primary, second.primary);
+    }
+
+    #[test]
+    fn check_ott_file_checks_a_copy_of_an_unsaved_buffer() {
+        let ott = fixture("range.out");
+        let edited = format!("{GRAMMAR}  | t t :: :: app\n");
+        let file = saved(GRAMMAR);
+        check(&config(serde_json::json!({})), &CheckCache::default(), file.path(), Some(&edited), &ott);
+
+        let runs = ott.runs.lock();
+        let (files, stdin) = &runs[0];
+        assert_eq!(stdin, &None);
+        assert_ne!(files[0], file.path());
+        assert!(files[0].to_string_lossy().ends_with("x.ott"));
+        assert!(!files[0].exists(), "the copy is removed after the check");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_pipes_an_unsaved_buffer_with_use_stdin() {
+        let ott = fixture("range.out");
+        let edited = format!("{GRAMMAR}  | t t :: :: app");
+        let config = config(serde_json::json!({ "useStdin": true }));
+        let file = saved(GRAMMAR);
+        let diagnostics = check(&config, &CheckCache::default(), file.path(), Some(&edited), &ott);
+
+        let runs = ott.runs.lock();
+        assert_eq!(runs[0], (vec![PathBuf::from("/dev/stdin")], Some(format!("{edited}\n"))));
+        assert_eq!(diagnostics.primary[0].range, range((1, 2), (1, 5)));
+        drop(runs);
+
+        // A file checked with the rest of its theory is copied as ever.
+        let other = saved("defns\nJop :: '' ::=\n");
+        let files = [file.path().to_path_buf(), other.path().to_path_buf()];
+        let uri = crate::path_uri(file.path()).unwrap();
+        check_ott_file(&config, &CheckCache::default(), file.path(), &files, Some(&edited), &uri, &ott, &|_| None, &|_, _| {})
+            .unwrap();
+        let runs = ott.runs.lock();
+        assert_eq!(runs[1].0.len(), 2);
+        assert!(runs[1].0[0].extension().is_some_and(|e| e == "ott") && runs[1].0[0] != file.path());
+        assert_eq!(runs[1].1, None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_has_ott_read_a_piped_buffer_from_its_stdin() {
+        // An ott that quotes the first line of what it's run on.
+        let ott = script(concat!(
+            "for file; do case \"$file\" in /dev/stdin)\n",
+            "  printf 'File \"%s\" on line 1, column 1 - 5:\\nError: %s\\n' \"$file\" \"$(head -n 1 \"$file\")\"\n",
+            "esac; done\n",
+        ));
+
+        let config = config(serde_json::json!({ "ottPath": ott.path(), "useStdin": true }));
+        let file = saved(GRAMMAR);
+        let diagnostics = check(&config, &CheckCache::default(), file.path(), Some("% edited\n"), &Subprocess(&|_| {}));
+        assert_eq!(diagnostics.primary.len(), 1);
+        assert_eq!(diagnostics.primary[0].message, "% edited");
+        assert_eq!(diagnostics.primary[0].range, range((0, 0), (0, 5)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_ends_an_unsaved_buffer_with_a_newline() {
+        // An ott that trips over a last line with no newline, as ott can.
+        let ott = script(concat!(
+            "for file; do :; done\n",
+            "[ -z \"$(tail -c 1 \"$file\")\" ] || printf 'File \"%s\" on line 5, column 1 - 1:\\nError: unexpected end of file\\n' \"$file\"\n",
+        ));
+
+        let file = saved(GRAMMAR);
+        let unsaved = GRAMMAR.trim_end();
+        let config = config(serde_json::json!({ "ottPath": ott.path() }));
+        let diagnostics = check(&config, &CheckCache::default(), file.path(), Some(unsaved), &Subprocess(&|_| {}));
+        assert_eq!(diagnostics.primary, vec![]);
+
+        let config = self::config(serde_json::json!({ "ottPath": ott.path(), "ensureTrailingNewline": false }));
+        let diagnostics = check(&config, &CheckCache::default(), file.path(), Some(unsaved), &Subprocess(&|_| {}));
+        assert_eq!(diagnostics.primary.len(), 1);
+        assert_eq!(diagnostics.primary[0].message, "unexpected end of file");
+        assert_eq!(diagnostics.primary[0].range, range((4, 0), (4, 1)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_checks_an_unsaved_file_of_a_theory_in_its_place() {
+        // An ott that quotes the first line of each file it's run on, in a
+        // diagnostic of its own.
+        let ott = script(concat!(
+            "for file; do case \"$file\" in *.ott)\n",
+            "  printf 'File \"%s\" on line 1, column 1 - 5:\\nError: %s\\n' \"$file\" \"$(head -n 1 \"$file\")\"\n",
+            "esac; done\n",
+        ));
+
+        let rules = "defns\nJop :: '' ::=\n";
+        let (grammar, saved_rules) = (saved(GRAMMAR), saved(rules));
+        let files = [grammar.path().to_path_buf(), saved_rules.path().to_path_buf()];
+        let (config, uri) = (config(serde_json::json!({ "ottPath": ott.path() })), crate::path_uri(saved_rules.path()).unwrap());
+        let unsaved = format!("% edited\n{rules}");
+        let check = |buffer| {
+            check_ott_file(&config, &CheckCache::default(), &files[1], &files, buffer, &uri, &Subprocess(&|_| {}), &|_| None, &|_, _| {}).unwrap()
+        };
+
+        // ott reads the buffer in place of the file, and the rest of the
+        // theory from disk, and what it says of the copy is of the file.
+        let diagnostics = check(Some(&unsaved));
+        let messages = |diagnostics: &[Diagnostic]| diagnostics.iter().map(|d| d.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(&diagnostics.primary), ["% edited"]);
+        assert_eq!(diagnostics.primary[0].range, range((0, 0), (0, 5)));
+        assert_eq!(diagnostics.others.len(), 1);
+        assert_eq!(messages(&diagnostics.others[&files[0]]), ["metavar termvar, x ::="]);
+
+        assert_eq!(messages(&check(None).primary), ["defns"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_reads_stderr_where_ott_printed_it() {
+        // An ott that prints its locations to stdout and its messages to
+        // stderr, in turn.
+        let ott = script(concat!(
+            "for file; do :; done\n",
+            "printf 'File \"%s\" on line 2, column 3 - 5:\\n' \"$file\"; printf 'Error: no parses\\n' >&2\n",
+            "printf 'File \"%s\" on line 1, column 9 - 15:\\n' \"$file\"; printf 'Warning: unused termvar\\n' >&2\n",
+        ));
+
+        let file = saved(GRAMMAR);
+        let read = |settings| {
+            let diagnostics = check(&config(settings), &CheckCache::default(), file.path(), None, &Subprocess(&|_| {})).primary;
+            diagnostics.into_iter().map(|d| (d.range, d.message)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(read(serde_json::json!({ "ottPath": ott.path() })), [
+            (range((1, 2), (1, 5)), "no parses".to_string()),
+            (range((0, 8), (0, 15)), "unused termvar".to_string()),
+        ]);
+
+        // Apart, the messages can't be told which location is theirs.
+        let apart = read(serde_json::json!({ "ottPath": ott.path(), "mergeStderr": false }));
+        assert!(!apart.contains(&(range((1, 2), (1, 5)), "no parses".to_string())), "{apart:?}");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_follows_symlinks_but_not_to_special_files() {
+        let (ott, file) = (fixture("range.out"), saved(GRAMMAR));
+        let link = file.path().with_extension("link.ott");
+        std::os::unix::fs::symlink(file.path(), &link).unwrap();
+        let diagnostics = check(&config(serde_json::json!({})), &CheckCache::default(), &link, None, &ott);
+        std::fs::remove_file(&link).unwrap();
+        assert_eq!(diagnostics.primary[0].message, "no parses of the production");
+        assert_eq!(ott.runs.lock()[0].0, std::slice::from_ref(&link));
+
+        let message = |path: &Path| not_a_file(path).map(|d| d.message);
+        let fifo = file.path().with_extension("fifo.ott");
+        assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+        std::os::unix::fs::symlink(&fifo, &link).unwrap();
+        let (to_fifo, fifo_message) = (message(&link), message(&fifo));
+        std::fs::remove_file(&link).unwrap();
+        std::fs::remove_file(&fifo).unwrap();
+        assert_eq!(to_fifo, Some(format!("file path {} is not a regular file, and ott can only check those", link.display())));
+        assert_eq!(fifo_message, Some(format!("file path {} is not a regular file, and ott can only check those", fifo.display())));
+
+        let dir = std::env::temp_dir();
+        assert_eq!(message(&dir), Some(format!("file path {} is a directory", dir.display())));
+        assert_eq!(message(&fifo), Some(format!("file path {} is not a file", fifo.display())));
+    }
+
+    #[test]
+    fn check_ott_file_rereads_output_when_message_format_changes() {
+        let (cache, file) = (CheckCache::default(), saved(GRAMMAR));
+        let ott = Recorded::stdout(b"File \"x.ott\" on line 2, column 3 - 5:\nError: no parses\nof the production\n".to_vec());
+
+        let single = check(&config(serde_json::json!({})), &cache, file.path(), None, &ott);
+        assert_eq!(single.primary[0].message, "no parses of the production");
+
+        let config = config(serde_json::json!({ "messageFormat": "multi-line" }));
+        let multi = check(&config, &cache, file.path(), None, &ott);
+        assert_eq!(multi.primary[0].message, "no parses\nof the production");
+        assert_eq!(ott.runs.lock().len(), 2);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_ott_file_runs_pre_check_before_reusing_results() {
+        let (cache, ott, file) = (CheckCache::default(), fixture("range.out"), saved(GRAMMAR));
+        let template = file.path().with_extension("in");
+        let generate = format!("cat '{}' > \"$0\"", template.display());
+        let config = config(serde_json::json!({ "preCheck": ["sh", "-c", generate, file.path()] }));
+
+        std::fs::write(&template, GRAMMAR).unwrap();
+        check(&config, &cache, file.path(), None, &ott);
+        check(&config, &cache, file.path(), None, &ott);
+        assert_eq!(ott.runs.lock().len(), 1);
+
+        std::fs::write(&template, format!("{GRAMMAR}  | t t :: :: app\n")).unwrap();
+        check(&config, &cache, file.path(), None, &ott);
+        assert_eq!(ott.runs.lock().len(), 2);
+        std::fs::remove_file(template).unwrap();
+    }
+
+    /// The diagnostics for `text` from ott's printing `stdout`, as they're
+    /// reported.
+    fn reported(stdout: &str, text: &str) -> Vec<Diagnostic> {
+        reported_with(&config(serde_json::json!({})), stdout, text)
+    }
+
+    fn reported_with(config: &Config, stdout: &str, text: &str) -> Vec<Diagnostic> {
+        let output = Output { status: ExitStatus::default(), stdout: stdout.into(), stderr: vec![], truncated: false, timed_out: false };
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let files = [PathBuf::from("/x.ott")];
+        let diagnostics = self::diagnostics(config, &output, &files, &files[0], &uri);
+        finalize_diagnostics(config, Some(text), diagnostics)
+    }
+
+    #[test]
+    fn strict_reports_warnings_as_errors() {
+        let stdout = "File \"x.ott\" on line 1, column 1 - 3:\nWarning: unused production\n";
+        let lint = Diagnostic {
+            range: range((1, 0), (1, 2)),
+            severity: Some(DiagnosticSeverity::WARNING),
+            source: Some("ott-lsp".into()),
+            message: "a lint".into(),
+            ..Default::default()
+        };
+
+        let severities = |settings| {
+            let config = config(settings);
+            let uri: Uri = "file:///x.ott".parse().unwrap();
+            let files = [PathBuf::from("/x.ott")];
+            let output = Output { status: ExitStatus::default(), stdout: stdout.into(), stderr: vec![], truncated: false, timed_out: false };
+            let mut diagnostics = self::diagnostics(&config, &output, &files, &files[0], &uri);
+            diagnostics.push(lint.clone());
+            finalize_diagnostics(&config, Some("grammar\nxx\n"), diagnostics).into_iter()
+                .map(|d| (d.message, d.severity.unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        let (warning, error) = (DiagnosticSeverity::WARNING, DiagnosticSeverity::ERROR);
+        assert_eq!(severities(serde_json::json!({})), [("unused production".into(), warning), ("a lint".into(), warning)]);
+        assert_eq!(severities(serde_json::json!({ "strict": true })), [
+            ("unused production".into(), error),
+            ("a lint".into(), warning),
+        ]);
+        assert_eq!(severities(serde_json::json!({ "strict": true, "strictLints": true })), [
+            ("unused production".into(), error),
+            ("a lint".into(), error),
+        ]);
+    }
+
+    #[test]
+    fn columns_without_lines_are_placed_on_a_line_that_has_them() {
+        let stdout = "File \"x.ott\"\nError: no parses\n  (char 20)\n";
+
+        // Only the third line is long enough.
+        let text = "grammar\nt :: 't_' ::=\n  | t t :: :: application\n";
+        assert_eq!(reported(stdout, text)[0].range, range((2, 20), (2, 21)));
+
+        // Of several that are, the last edited, if allowed.
+        let text = "metavar termvar, x ::=\ngrammar\nt :: 't_' ::=\n  | t t :: :: application\n";
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let files = [PathBuf::from("/x.ott")];
+        let output = Output { status: ExitStatus::default(), stdout: stdout.into(), stderr: vec![], truncated: false, timed_out: false };
+        let placed = |settings, edit| {
+            let config = config(settings);
+            let mut diagnostics = self::diagnostics(&config, &output, &files, &files[0], &uri);
+            place_lineless(&config, &mut diagnostics, Some(text), edit);
+            finalize_diagnostics(&config, Some(text), diagnostics)[0].range
+        };
+
+        let edit = Some(Position::new(3, 4));
+        assert_eq!(placed(serde_json::json!({}), edit), range((3, 20), (3, 21)));
+        assert_eq!(placed(serde_json::json!({ "lineFallback": "start" }), edit), range((0, 20), (0, 21)));
+        assert_eq!(placed(serde_json::json!({}), None), range((0, 20), (0, 21)));
+    }
+
+    #[test]
+    fn spawn_failures_say_where_ott_was_looked_for() {
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let message = |ott_path: Option<&str>, error: &io::Error| {
+            let config = Config { ott_path: ott_path.map(String::from), ..Default::default() };
+            spawn_failed(&config, error).message
+        };
+
+        assert_eq!(message(None, &missing), "`ott` not found on `PATH`: install ott or set `ottPath`");
+        assert_eq!(message(Some("/opt/ott"), &missing), "`/opt/ott` not found: check `ottPath`");
+
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "permission denied");
+        assert_eq!(message(None, &denied), "failed to run `ott`: permission denied");
+
+        let failed = spawn_failed(&Config::default(), &missing);
+        assert_eq!((failed.range, failed.severity), (Range::default(), Some(DiagnosticSeverity::ERROR)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn retries_of_failed_runs_are_traced() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Without execute permission, ott can't be run, but it does exist.
+        let ott = script("echo");
+        std::fs::set_permissions(ott.path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+        let traced = Mutex::new(vec![]);
+        let config = Config { ott_path: Some(ott.path().display().to_string()), ..Default::default() };
+        let Err(error) = run(&config, &[], &|message| traced.lock().push(message)) else { panic!("ott ran") };
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        let retries = std::mem::take(&mut *traced.lock());
+        assert_eq!(retries.len(), SPAWN_ATTEMPTS as usize - 1);
+        let retry = format!("failed to run {} (attempt 1): {error}; retrying", config.ott_path());
+        assert_eq!(retries[0], retry);
+
+        // A missing ott isn't retried.
+        let config = Config { ott_path: Some("ott-lsp-test-no-ott".into()), ..Default::default() };
+        assert!(run(&config, &[], &|message| traced.lock().push(message)).is_err());
+        assert!(traced.lock().is_empty());
+    }
+
+    #[test]
+    fn temp_copies_are_ott_files_but_outputs_keep_their_extensions() {
+        let dir = std::env::temp_dir();
+        let name = |temp: &TempFile| temp.path().file_name().unwrap().to_string_lossy().into_owned();
+
+        let copy = TempFile::write(&dir, "theory.mng".as_ref(), "grammar\n", false).unwrap();
+        assert!(name(&copy).ends_with("-theory.mng.ott"), "{}", name(&copy));
+        let copy = TempFile::write(&dir, "theory.ott".as_ref(), "grammar\n", false).unwrap();
+        assert!(name(&copy).ends_with("-theory.ott"), "{}", name(&copy));
+
+        for output in ["theory.tex", "Theory.v"] {
+            let temp = TempFile::output(&dir, output.as_ref()).unwrap();
+            assert!(name(&temp).ends_with(&format!("-{output}")), "{}", name(&temp));
+            assert_eq!(std::fs::read_to_string(temp.path()).unwrap(), "");
+        }
+    }
+
+    #[test]
+    fn each_profile_reads_the_output_of_its_versions() {
+        let text = "metavar termvar, x ::=\ngrammar\nt :: 't_' ::=\n";
+        let read = |profile: &str, stdout: &str| {
+            let config = config(serde_json::json!({ "formatProfile": profile }));
+            reported_with(&config, stdout, text).into_iter().map(|d| (d.range, d.message)).collect::<Vec<_>>()
+        };
+
+        let span = "File \"x.ott\" on line 2, column 3 - 5:\nError: no parses\n";
+        let lines = "File \"x.ott\" on line 1, column 9 - line 3, column 2:\nError: no parses\n";
+        let inline = "Warning: unused production at line 3, column 6\n";
+        assert_eq!(read("ott-0.33", span), [(range((1, 2), (1, 5)), "no parses".into())]);
+        assert_eq!(read("ott-0.33", lines), [(range((0, 8), (2, 2)), "no parses".into())]);
+        assert_eq!(read("ott-0.33", inline), [(range((2, 5), (2, 6)), "unused production".into())]);
+
+        let span = "File \"x.ott\" line 2, characters 3-5:\nError: no parses\n";
+        let lines = "File \"x.ott\" line 1, character 9 - line 3, character 2:\nError: no parses\n";
+        let inline = "Warning: unused production at line 3, character 6\n";
+        assert_eq!(read("ott-0.32", span), [(range((1, 2), (1, 5)), "no parses".into())]);
+        assert_eq!(read("ott-0.32", lines), [(range((0, 8), (2, 2)), "no parses".into())]);
+        assert_eq!(read("ott-0.32", inline), [(range((2, 5), (2, 6)), "unused production".into())]);
+
+        // Read with the other's patterns, only the line is found.
+        assert_eq!(read("ott-0.33", span), [(range((1, 0), (1, 0)), "no parses".into())]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn auto_chooses_the_profile_by_otts_version() {
+        let profile = |version: &str| {
+            let ott = script(&format!("[ \"$1\" = -version ] && echo 'Ott version {version}'\n"));
+            let config = config(serde_json::json!({ "ottPath": ott.path(), "formatProfile": "auto" }));
+            profile(&config).name
+        };
+
+        assert_eq!(profile("0.31"), "ott-0.32");
+        assert_eq!(profile("0.32.1"), "ott-0.32");
+        assert_eq!(profile("0.33"), "ott-0.33");
+        assert_eq!(profile("0.34"), "ott-0.33");
+
+        // An ott that can't be run, or doesn't say, is read as the newest.
+        assert_eq!(self::profile(&config(serde_json::json!({}))).name, "ott-0.33");
+        assert_eq!(profile("unknown"), "ott-0.33");
+
+        // A name pins its profile; an unknown one is `auto`.
+        assert_eq!(self::profile(&config(serde_json::json!({ "formatProfile": "ott-0.32" }))).name, "ott-0.32");
+        assert_eq!(self::profile(&config(serde_json::json!({ "formatProfile": "ott-9" }))).name, "ott-0.33");
+    }
+
+    #[test]
+    fn line_zero_is_the_first_line() {
+        let diagnostics = reported("File \"x.ott\" on line 0, column 1 - 3:\nError: no parses\n", "grammar\n");
+        assert_eq!(diagnostics[0].range, range((0, 0), (0, 3)));
+
+        let diagnostics = reported("File \"x.ott\" on line 0, column 1 - line 0, column 2:\nError: no parses\n", "grammar\n");
+        assert_eq!(diagnostics[0].range, range((0, 0), (0, 2)));
+    }
+
+    #[test]
+    fn spans_cover_their_last_column() {
+        let diagnostics = reported("File \"x.ott\" on line 1, column 1 - 7:\nError: no parses\n", "grammar\n");
+        assert_eq!(diagnostics[0].range, range((0, 0), (0, 7)));
+
+        let diagnostics = reported("File \"x.ott\" on line 1, column 7 - line 2, column 1:\nError: no parses\n", "grammar\nt\n");
+        assert_eq!(diagnostics[0].range, range((0, 6), (1, 1)));
+    }
+
+    #[test]
+    fn diagnostics_are_published_in_one_order_whatever_order_they_come_in() {
+        let text = "grammar\nt :: 't_' ::=\n  | x :: :: var\n";
+        let diagnostic = |start, end, severity, message: &str| Diagnostic {
+            range: range(start, end),
+            severity: Some(severity),
+            source: Some("ott-lsp".into()),
+            message: message.into(),
+            ..Default::default()
+        };
+
+        let diagnostics = vec![
+            diagnostic((2, 4), (2, 5), DiagnosticSeverity::WARNING, "b"),
+            diagnostic((1, 0), (1, 1), DiagnosticSeverity::HINT, "a"),
+            diagnostic((2, 4), (2, 5), DiagnosticSeverity::ERROR, "c"),
+            diagnostic((2, 4), (2, 9), DiagnosticSeverity::WARNING, "a"),
+            diagnostic((2, 4), (2, 5), DiagnosticSeverity::WARNING, "a"),
+            diagnostic((1, 0), (1, 1), DiagnosticSeverity::HINT, "a"),
+        ];
+
+        let config = config(serde_json::json!({ "publishedSeverities": ["error", "warning", "hint"] }));
+        let published = finalize_diagnostics(&config, Some(text), diagnostics.clone());
+        let order: Vec<_> = published.iter().map(|d| (d.range, d.severity.unwrap(), d.message.as_str())).collect();
+        assert_eq!(order, [
+            (range((1, 0), (1, 1)), DiagnosticSeverity::HINT, "a"),
+            (range((2, 4), (2, 5)), DiagnosticSeverity::ERROR, "c"),
+            (range((2, 4), (2, 5)), DiagnosticSeverity::WARNING, "a"),
+            (range((2, 4), (2, 9)), DiagnosticSeverity::WARNING, "a"),
+            (range((2, 4), (2, 5)), DiagnosticSeverity::WARNING, "b"),
+        ]);
+
+        for i in 0..diagnostics.len() {
+            let mut shuffled = diagnostics.clone();
+            shuffled.rotate_left(i);
+            shuffled.swap(0, i / 2);
+            let json = |diagnostics: &[Diagnostic]| serde_json::to_string(diagnostics).unwrap();
+            assert_eq!(json(&finalize_diagnostics(&config, Some(text), shuffled)), json(&published));
+        }
+    }
+
+    /// Five diagnostics: two in `GRAMMAR`'s `metavar` section, the last a
+    /// warning, and three in its `grammar` section, the first a warning.
+    const OVERFLOWING: &str = concat!(
+        "Error: no parses at line 1, column 1\nWarning: unused x at line 2, column 3\n",
+        "Warning: unused t at line 4, column 1\nError: undefined nonterminal u at line 5, column 5\n",
+        "Error: no parses of u at line 5, column 7\n",
+    );
+
+    #[test]
+    fn diagnostics_past_the_most_are_dropped_with_a_notice() {
+        let config = config(serde_json::json!({ "maxDiagnostics": 2 }));
+        let diagnostics = reported_with(&config, OVERFLOWING, GRAMMAR);
+        let read: Vec<_> = diagnostics.iter().map(|d| (d.range, d.severity, d.message.as_str())).collect();
+        assert_eq!(read, [
+            (range((0, 0), (0, 1)), Some(DiagnosticSeverity::ERROR), "no parses"),
+            (range((1, 2), (1, 3)), Some(DiagnosticSeverity::WARNING), "unused x"),
+            (range((0, 0), (0, 0)), Some(DiagnosticSeverity::WARNING), "3 more diagnostics were ignored"),
+        ]);
+    }
+
+    #[test]
+    fn diagnostics_past_the_most_can_be_summarized_by_section() {
+        let config = config(serde_json::json!({ "maxDiagnostics": 1, "diagnosticsOverflow": "summarize" }));
+        let diagnostics = reported_with(&config, OVERFLOWING, GRAMMAR);
+        let read: Vec<_> = diagnostics.iter().map(|d| (d.range, d.severity, d.message.as_str())).collect();
+        assert_eq!(read, [
+            (range((0, 0), (0, 1)), Some(DiagnosticSeverity::ERROR), "no parses"),
+            (
+                range((1, 2), (1, 2)),
+                Some(DiagnosticSeverity::WARNING),
+                "1 more diagnostics in this `metavar` section were ignored, starting with: unused x",
+            ),
+            (
+                range((3, 0), (3, 0)),
+                Some(DiagnosticSeverity::ERROR),
+                "3 more diagnostics in this `grammar` section were ignored, starting with: unused t",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn column_and_char_locations_land_on_the_same_characters() {
+        // `column N`s count from 1, `(char N)`s from 0.
+        let text = "grammar\n  | x y :: :: var\n";
+        let columns = reported("File \"x.ott\" on line 2, column 5 - 7:\nError: no parses\n", text);
+        let chars = reported("File \"x.ott\" on line 2\nError: no parses\n  (char 4)\n  (char 7)\n", text);
+        assert_eq!(columns[0].range, range((1, 4), (1, 7)));
+        assert_eq!(chars[0].range, columns[0].range);
+
+        let column = reported("File \"x.ott\" on line 2, column 5:\nError: no parses\n", text);
+        let char = reported("File \"x.ott\" on line 2 (char 4)\nError: no parses\n", text);
+        assert_eq!(column[0].range.start, Position::new(1, 4));
+        assert_eq!(char[0].range, column[0].range);
+    }
+
+    #[test]
+    fn spans_past_the_end_of_a_line_end_there() {
+        let diagnostics = reported("File \"x.ott\" on line 1, column 5 - 12:\nError: no parses\n", "grammar\n");
+        assert_eq!(diagnostics[0].range, range((0, 4), (0, 7)));
+
+        let diagnostics = reported("File \"x.ott\" on line 1, column 8:\nError: no parses\n", "grammar\r\n");
+        assert_eq!(diagnostics[0].range, range((0, 7), (0, 7)));
+    }
+
+    #[test]
+    fn spans_ending_inside_a_character_cover_it_in_utf16() {
+        // `∀` is 3 bytes and 1 UTF-16 code unit, `𝔸` 4 bytes and 2 units.
+        let text = "x ∀ 𝔸 y\n";
+        let diagnostics = reported("File \"x.ott\" on line 1, column 3 - 3:\nError: no parses\n", text);
+        assert_eq!(diagnostics[0].range, range((0, 2), (0, 3)));
+
+        let diagnostics = reported("File \"x.ott\" on line 1, column 3 - 4:\nError: no parses\n", text);
+        assert_eq!(diagnostics[0].range, range((0, 2), (0, 3)));
+
+        // A start inside a character starts at it.
+        let diagnostics = reported("File \"x.ott\" on line 1, column 4 - 8:\nError: no parses\n", text);
+        assert_eq!(diagnostics[0].range, range((0, 2), (0, 6)));
+
+        let diagnostics = reported("File \"x.ott\" on line 1, column 7 - 7:\nError: no parses\n", text);
+        assert_eq!(diagnostics[0].range, range((0, 4), (0, 6)));
+
+        // Only a start: the one character there.
+        let diagnostics = reported("File \"x.ott\" on line 1\nError: no parses\n  (char 6)\n", text);
+        assert_eq!(diagnostics[0].range, range((0, 4), (0, 6)));
+    }
+
+    #[test]
+    fn bare_lines_take_their_columns_from_the_chars_after_them() {
+        let text = "metavar termvar, x ::=\n  {{ com term variables }}\n";
+        let diagnostics = reported("File \"x.ott\" on line 2\nError: no parses\n  (char 5)\n", text);
+        assert_eq!(diagnostics[0].range, range((1, 5), (1, 6)));
+        assert_eq!(diagnostics[0].message, "no parses");
+
+        let diagnostics = reported("File \"x.ott\" on line 2\nError: no parses\n  (char 5)\n  (char 8)\n", text);
+        assert_eq!(diagnostics[0].range, range((1, 5), (1, 8)));
+
+        // With no column at all, the start of the line.
+        let diagnostics = reported("File \"x.ott\" on line 2\nError: no parses\n", text);
+        assert_eq!(diagnostics[0].range, range((1, 0), (1, 0)));
+    }
+
+    #[test]
+    fn chars_in_a_block_span_from_the_first_to_the_last() {
+        let text = "metavar termvar, x ::=\n  {{ com term variables }}\n";
+        let span = |stdout: &str| reported(stdout, text)[0].range;
+        assert_eq!(span("File \"x.ott\" on line 2\nError: no parses\n  (char 3)\n  (char 5)\n  (char 9)\n"), range((1, 3), (1, 9)));
+        assert_eq!(span("File \"x.ott\" on line 2\nError: no parses\n  (char 3) to (char 9)\n"), range((1, 3), (1, 9)));
+        assert_eq!(span("File \"x.ott\" on line 2 (char 3)\nError: no parses\n  (char 9)\n"), range((1, 3), (1, 9)));
+
+        // A last that's before the first isn't an end.
+        assert_eq!(span("File \"x.ott\" on line 2\nError: no parses\n  (char 9)\n  (char 3)\n"), range((1, 9), (1, 10)));
+    }
+
+    #[test]
+    fn messages_are_read_before_or_after_their_locations() {
+        let located = reported("File \"x.ott\" on line 2, column 3 - 5:\nError: no parses\n", TEXT);
+        let messaged = reported("Error: no parses\nFile \"x.ott\" on line 2, column 3 - 5:\n", TEXT);
+        assert_eq!(located, messaged);
+        assert_eq!(located.len(), 1);
+        assert_eq!(located[0].range, range((1, 2), (1, 5)));
+        assert_eq!(located[0].message, "no parses");
+        assert_eq!(located[0].severity, Some(DiagnosticSeverity::ERROR));
+
+        // Another severity starts the next, whichever comes first.
+        let stdout = concat!(
+            "Warning: unused production\nFile \"x.ott\" on line 1, column 9 - 15:\n",
+            "Error: no parses\nFile \"x.ott\" on line 2, column 3 - 5:\n",
+        );
+
+        let diagnostics = reported(stdout, TEXT);
+        let read: Vec<_> = diagnostics.iter().map(|d| (d.severity, d.message.as_str(), d.range)).collect();
+        assert_eq!(read, [
+            (Some(DiagnosticSeverity::WARNING), "unused production", range((0, 8), (0, 15))),
+            (Some(DiagnosticSeverity::ERROR), "no parses", range((1, 2), (1, 5))),
+        ]);
+
+        // Blank lines between them are in neither.
+        let stdout = "File \"x.ott\" on line 1, column 9 - 15:\nWarning: unused\n\nproduction\n\nError: no parses at line 2\n";
+        let messages: Vec<_> = reported(stdout, TEXT).into_iter().map(|d| d.message).collect();
+        assert_eq!(messages, ["unused production", "no parses"]);
+        let config = config(serde_json::json!({ "messageFormat": "multi-line" }));
+        let messages: Vec<_> = reported_with(&config, stdout, TEXT).into_iter().map(|d| d.message).collect();
+        assert_eq!(messages, ["unused\nproduction", "no parses"]);
+    }
+
+    #[test]
+    fn inline_errors_and_warnings_carry_their_own_locations() {
+        let stdout = "Error: no parses of x, at line 2, column 3.\nWarning: unused production t_var at line 5\n";
+        let diagnostics = reported(stdout, GRAMMAR);
+        let read: Vec<_> = diagnostics.iter().map(|d| (d.severity, d.message.as_str(), d.range)).collect();
+        assert_eq!(read, [
+            (Some(DiagnosticSeverity::ERROR), "no parses of x", range((1, 2), (1, 3))),
+            (Some(DiagnosticSeverity::WARNING), "unused production t_var", range((4, 0), (4, 0))),
+        ]);
+
+        // A location of its own isn't taken for one.
+        let diagnostics = reported("File \"x.ott\" on line 2, column 3 - 5:\nError: no parses at line 1\n", GRAMMAR);
+        assert_eq!(diagnostics[0].range, range((1, 2), (1, 5)));
+        assert_eq!(diagnostics[0].message, "no parses at line 1");
+    }
+
+    #[test]
+    fn only_the_published_severities_are_reported() {
+        let stdout = "Warning: unused production t_var at line 5\nError: no parses at line 4\n";
+        let config = config(serde_json::json!({ "publishedSeverities": ["error"] }));
+        let diagnostics = reported_with(&config, stdout, GRAMMAR);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["no parses"]);
+
+        // Warnings made errors by `strict` are errors by then.
+        let config = self::config(serde_json::json!({ "publishedSeverities": ["error"], "strict": true }));
+        assert_eq!(reported_with(&config, stdout, GRAMMAR).len(), 2);
+
+        let config = self::config(serde_json::json!({ "publishedSeverities": ["warning", "hint"] }));
+        let diagnostics = reported_with(&config, stdout, GRAMMAR);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["unused production t_var"]);
+    }
+
+    #[test]
+    fn chained_locations_are_related_to_the_diagnostic_before_them() {
+        let stdout = concat!(
+            "File \"x.ott\" on line 5, column 5 - 5:\nError: undefined nonterminal u\n",
+            "File \"x.ott\" on line 4, column 1 - 1:\n  in the rule\n",
+            "File \"x.ott\" on line 1, column 9 - 15:\n",
+        );
+
+        let diagnostics = reported(stdout, GRAMMAR);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((4, 4), (4, 5)));
+        assert_eq!(diagnostics[0].message, "undefined nonterminal u");
+
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        assert_eq!(diagnostics[0].related_information, Some(vec![
+            DiagnosticRelatedInformation {
+                location: Location::new(uri.clone(), range((3, 0), (3, 1))),
+                message: "in the rule".into(),
+            },
+            DiagnosticRelatedInformation {
+                location: Location::new(uri, range((0, 8), (0, 15))),
+                message: "related location".into(),
+            },
+        ]));
+
+        // After a blank line, it's the next diagnostic's.
+        let stdout = concat!(
+            "File \"x.ott\" on line 5, column 5 - 5:\nError: undefined nonterminal u\n\n",
+            "File \"x.ott\" on line 4, column 1 - 1:\nError: no parses\n",
+        );
+
+        let diagnostics = reported(stdout, GRAMMAR);
+        let read: Vec<_> = diagnostics.iter().map(|d| (d.range, d.related_information.is_some())).collect();
+        assert_eq!(read, [(range((3, 0), (3, 1)), false), (range((4, 4), (4, 5)), false)]);
+    }
+
+    /// The sources of the diagnostics from ott's printing `stdout`, with
+    /// `annotatePass` as given.
+    fn sources(stdout: &str, annotate_pass: bool) -> Vec<String> {
+        let config = config(serde_json::json!({ "annotatePass": annotate_pass }));
+        reported_with(&config, stdout, GRAMMAR).into_iter().filter_map(|d| d.source).collect()
+    }
+
+    const PASSES: &str = concat!(
+        "File \"x.ott\" on line 1, column 1 - 3:\nError: no parses of the premise\n\n",
+        "File \"x.ott\" on line 2, column 1 - 3:\nError: undefined nonterminal u\n\n",
+        "definition rules: 0 good 1 bad\n",
+        "File \"x.ott\" on line 3, column 1 - 3:\nWarning: undefined nonterminal v in the coq output\n",
+    );
+
+    #[test]
+    fn annotate_pass_names_the_pass_ott_marks() {
+        assert_eq!(sources(PASSES, true), ["ott[parse]", "ott[sort]", "ott[backend]"]);
+    }
+
+    #[test]
+    fn annotate_pass_leaves_sources_alone_when_off() {
+        assert_eq!(sources(PASSES, false), ["ott", "ott", "ott"]);
+    }
+
+    #[test]
+    fn annotated_diagnostics_are_still_otts() {
+        let config = config(serde_json::json!({ "annotatePass": true, "strict": true }));
+        let diagnostics = reported_with(&config, PASSES, GRAMMAR);
+        assert!(diagnostics.iter().all(|d| d.severity == Some(DiagnosticSeverity::ERROR)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn ott_running_too_long_is_stopped_and_says_so() {
+        let ott = script("echo 'Ott version 0.33'\nsleep 10\n");
+        let config = config(serde_json::json!({ "ottPath": ott.path(), "checkTimeoutMs": 100 }));
+
+        let started = std::time::Instant::now();
+        let output = Subprocess(&|_| {}).run(&config, &[], None).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(output.timed_out);
+        assert_eq!(output.stdout, b"Ott version 0.33\n");
+
+        let diagnostics = diagnostics(&config, &output, &[], Path::new("/x.ott"), &"file:///x.ott".parse().unwrap());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "ott was stopped for running longer than 100 ms (see `checkTimeoutMs`)");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn ott_killed_by_a_signal_says_so() {
+        let status = std::os::unix::process::ExitStatusExt::from_raw(9);
+        let ott = Recorded { status, ..Recorded::stdout(b"Ott version 0.33\n".to_vec()) };
+        let diagnostics = diagnostics_for(&config(serde_json::json!({})), Path::new("/x.ott"), TEXT, &ott).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "ott was killed (signal 9), perhaps for running out of memory");
+
+        // A timeout ott enforces itself, as `timeout` does, is just an exit.
+        let ott = Recorded { status: exit(124), ..Recorded::stdout(vec![]) };
+        let diagnostics = diagnostics_for(&config(serde_json::json!({})), Path::new("/x.ott"), TEXT, &ott).unwrap();
+        assert_eq!(diagnostics[0].message, "ott processing failed");
+    }
+}
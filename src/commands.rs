@@ -0,0 +1,659 @@
+//! Commands run via `workspace/executeCommand`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use lsp_types::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::ProductionOrder;
+use crate::enclosing;
+use crate::navigation::is_use;
+use crate::parse::{self, Name};
+
+pub const MATCH_DELIMITER: &str = "ott-lsp.matchDelimiter";
+pub const CHECK_BLOCK: &str = "ott-lsp.checkBlock";
+pub const DUMP_SYMBOLS: &str = "ott-lsp.dumpSymbols";
+pub const CHECK_ALL: &str = "ott-lsp.checkAll";
+pub const GENERATE: &str = "ott-lsp.generate";
+pub const GENERATE_ALL: &str = "ott-lsp.generateAll";
+pub const TOGGLE_TEX_WRAP: &str = "ott-lsp.toggleTexWrap";
+pub const TOGGLE_TEX_SHOW_META: &str = "ott-lsp.toggleTexShowMeta";
+pub const STATUS: &str = "ott-lsp.status";
+pub const CHECK_REFERENCES: &str = "ott-lsp.checkReferences";
+pub const SHOW_COMMAND: &str = "ott-lsp.showCommand";
+pub const PREVIEW_BLOCK: &str = "ott-lsp.previewBlock";
+pub const PROJECT_DIAGNOSTICS: &str = "ott-lsp.projectDiagnostics";
+pub const NEW_RULE: &str = "ott-lsp.newRule";
+pub const LIST_RULES: &str = "ott-lsp.listRules";
+pub const SORT_PRODUCTIONS: &str = "ott-lsp.sortProductions";
+pub const EXTRACT_EMBEDS: &str = "ott-lsp.extractEmbeds";
+pub const NEXT_DIAGNOSTIC: &str = "ott-lsp.nextDiagnostic";
+pub const PREV_DIAGNOSTIC: &str = "ott-lsp.prevDiagnostic";
+pub const PREVIEW_BACKEND: &str = "ott-lsp.previewBackend";
+pub const REVEAL_IN_OUTPUT: &str = "ott-lsp.revealInOutput";
+pub const NORMALIZE_NAMES: &str = "ott-lsp.normalizeNames";
+pub const TEST_PRODUCTION: &str = "ott-lsp.testProduction";
+pub const CHECK_HOM_COVERAGE: &str = "ott-lsp.checkHomCoverage";
+pub const WHICH_PRODUCTION: &str = "ott-lsp.whichProduction";
+pub const BUILD_THEORY: &str = "ott-lsp.buildTheory";
+
+/// Every command the server advertises.
+pub const ALL: &[&str] = &[
+    MATCH_DELIMITER,
+    CHECK_BLOCK,
+    DUMP_SYMBOLS,
+    CHECK_ALL,
+    GENERATE,
+    GENERATE_ALL,
+    TOGGLE_TEX_WRAP,
+    TOGGLE_TEX_SHOW_META,
+    STATUS,
+    CHECK_REFERENCES,
+    SHOW_COMMAND,
+    PREVIEW_BLOCK,
+    PROJECT_DIAGNOSTICS,
+    NEW_RULE,
+    LIST_RULES,
+    SORT_PRODUCTIONS,
+    EXTRACT_EMBEDS,
+    NEXT_DIAGNOSTIC,
+    PREV_DIAGNOSTIC,
+    PREVIEW_BACKEND,
+    REVEAL_IN_OUTPUT,
+    NORMALIZE_NAMES,
+    TEST_PRODUCTION,
+    CHECK_HOM_COVERAGE,
+    WHICH_PRODUCTION,
+    BUILD_THEORY,
+];
+
+/// Deserializes the `n`th argument of a command invocation.
+pub fn argument<T: DeserializeOwned>(params: &ExecuteCommandParams, n: usize) -> Result<T, String> {
+    let value = params.arguments.get(n)
+        .ok_or_else(|| format!("{}: missing argument {n}", params.command))?;
+
+    serde_json::from_value(value.clone())
+        .map_err(|e| format!("{}: invalid argument {n}: {e}", params.command))
+}
+
+/// The comment that keeps a production where it is when [`SORT_PRODUCTIONS`]
+/// sorts the others around it, as in `| x :: :: var % ott-lsp: pin`.
+pub const PIN: &str = "ott-lsp: pin";
+
+/// Sorts the productions of the grammar rule at `position` in `uri` by `order`,
+/// stably, so that sorting again changes nothing. Each production moves with
+/// the `%` comments on the lines just above it and on its own lines, and
+/// with its homs, even those spanning lines; blank lines stay where they
+/// are, as do productions with a [`PIN`] comment. `None` if the cursor isn't
+/// in a grammar rule, or something other than productions and comments is
+/// between its productions.
+// `Uri` is a false positive: its interior mutability never affects its hash.
+#[allow(clippy::mutable_key_type)]
+pub fn sort_productions(
+    doc: &parse::Document,
+    text: &str,
+    uri: &Uri,
+    position: Position,
+    order: ProductionOrder,
+) -> Option<WorkspaceEdit> {
+    let rule = doc.grammars.iter().rev()
+        .find(|rule| rule.productions.first().is_some_and(|p| p.range.start.line <= position.line + 1))
+        .filter(|rule| position.line <= rule.productions[rule.productions.len() - 1].range.end.line)?;
+
+    let lines: Vec<_> = text.split('\n').collect();
+    let comment = |line: usize| lines[line].trim_start().starts_with('%');
+    let blank = |line: usize| lines[line].trim().is_empty();
+
+    // Each production's lines, with the comments just above it.
+    let mut chunks = vec![];
+    let mut previous_end = None;
+    for production in &rule.productions {
+        let (start, end) = (production.range.start.line as usize, production.range.end.line as usize);
+        let mut first = start;
+        while first > 0 && Some(first - 1) != previous_end && comment(first - 1) {
+            first -= 1;
+        }
+
+        // Anything else between productions would be left behind.
+        if let Some(previous) = previous_end && !(previous + 1..first).all(blank) {
+            return None;
+        }
+
+        chunks.push((first, end, production));
+        previous_end = Some(end);
+    }
+
+    let key = |production: &parse::Production| match order {
+        ProductionOrder::Label => production.label.text.clone(),
+        ProductionOrder::Body => {
+            let body: Vec<_> = production.body.iter().map(|word| word.text.as_str()).collect();
+            body.join(" ")
+        }
+    };
+
+    let pinned = |&(first, end, _): &(usize, usize, _)| lines[first..=end].iter().any(|line| line.contains(PIN));
+    let mut sorted: Vec<_> = chunks.iter().filter(|chunk| !pinned(chunk)).collect();
+    sorted.sort_by_key(|(_, _, production)| key(production));
+
+    let mut sorted = sorted.into_iter();
+    let mut replacement: Vec<&str> = vec![];
+    for (i, chunk) in chunks.iter().enumerate() {
+        if i > 0 {
+            replacement.extend(&lines[chunks[i - 1].1 + 1..chunk.0]);
+        }
+
+        let (first, end, _) = if pinned(chunk) { chunk } else { sorted.next()? };
+        replacement.extend(&lines[*first..=*end]);
+    }
+
+    let (first, last) = (chunks[0].0, chunks[chunks.len() - 1].1);
+    let range = Range::new(Position::new(first as u32, 0), Position::new(last as u32, parse::utf16_len(lines[last])));
+    let edit = TextEdit::new(range, replacement.join("\n"));
+    Some(WorkspaceEdit { changes: Some(HashMap::from([(uri.clone(), vec![edit])])), ..Default::default() })
+}
+
+/// An inference rule of the judgement [`LIST_RULES`] was asked about.
+#[derive(Debug, Serialize)]
+pub struct RuleItem {
+    pub name: String,
+    pub id: String,
+    /// The rule's conclusion, to show alongside its name.
+    pub label: String,
+    pub location: Location,
+}
+
+/// The rules of the judgement named `word` in `docs`, or of the judgement
+/// with a rule named `word`, or of every judgement of the `defns` block named
+/// `word`, in the order they're written. `None` if there's no such judgement
+/// or block; empty if it has no rules yet.
+pub fn list_rules(docs: &[(Uri, parse::Document)], word: &str) -> Option<Vec<RuleItem>> {
+    let blocks = || docs.iter()
+        .flat_map(|(uri, doc)| doc.defns.iter().map(move |block| (uri, block)));
+    let defns = || blocks().flat_map(|(uri, block)| block.defns.iter().map(move |defn| (uri, defn)));
+
+    let judgements: Vec<_> = match defns().find(|(_, defn)| defn.name.text == word)
+        .or_else(|| defns().find(|(_, defn)| defn.rules.iter().any(|r| r.name.text == word || r.id == word)))
+    {
+        Some(judgement) => vec![judgement],
+        None => blocks().filter(|(_, block)| block.name.text == word)
+            .flat_map(|(uri, block)| block.defns.iter().map(move |defn| (uri, defn)))
+            .collect(),
+    };
+
+    if judgements.is_empty() && !blocks().any(|(_, block)| block.name.text == word) {
+        return None;
+    }
+
+    let rules = judgements.iter()
+        .flat_map(|(uri, defn)| defn.rules.iter().map(move |rule| RuleItem {
+            name: rule.name.text.clone(),
+            id: rule.id.clone(),
+            label: rule.conclusion.clone(),
+            location: Location::new((*uri).clone(), rule.name.range),
+        }))
+        .collect();
+
+    Some(rules)
+}
+
+/// The ways the rule, or failing that the production, at `position` in
+/// `doc` may be written in ott's LaTeX, most telling first: the rule's macro,
+/// like `\ottdruleGtTXXvar`, then its id as typeset, like `GtT\_var`, or the
+/// production's label. `None` if there's neither at `position`.
+pub fn tex_anchors(doc: &parse::Document, text: &str, position: Position) -> Option<Vec<String>> {
+    let item = enclosing::enclosing_block(doc, text, position)?.item?;
+    let rule = doc.defns.iter().flat_map(|d| &d.defns).flat_map(|defn| &defn.rules)
+        .find(|rule| item.start.line <= rule.name.range.start.line && rule.name.range.end.line <= item.end.line);
+
+    if let Some(rule) = rule {
+        return Some(vec![format!("\\ottdrule{}", rule.id.replace('_', "XX")), rule.id.replace('_', "\\_")]);
+    }
+
+    let production = doc.grammars.iter().flat_map(|rule| &rule.productions).find(|p| p.range == item)?;
+    Some(vec![production.label.text.replace('_', "\\_")])
+}
+
+/// Where the first of `anchors` that `output` has is, trying each in turn.
+pub fn find_anchor(output: &str, anchors: &[String]) -> Option<Range> {
+    anchors.iter().find_map(|anchor| {
+        output.split('\n').enumerate().find_map(|(line, text)| {
+            let (line, start) = (line as u32, parse::utf16_len(&text[..text.find(anchor.as_str())?]));
+            Some(Range::new(Position::new(line, start), Position::new(line, start + parse::utf16_len(anchor))))
+        })
+    })
+}
+
+/// The start of the first of `diagnostics` after `position` or, going
+/// backwards, the last before it, wrapping around the ends of the document.
+/// `None` if there are no diagnostics.
+pub fn adjacent_diagnostic(diagnostics: &[Diagnostic], position: Position, backwards: bool) -> Option<Position> {
+    let mut starts: Vec<_> = diagnostics.iter().map(|d| d.range.start).collect();
+    starts.sort();
+    match backwards {
+        false => starts.iter().find(|&&start| start > position).or(starts.first()).copied(),
+        true => starts.iter().rfind(|&&start| start < position).or(starts.last()).copied(),
+    }
+}
+
+/// Returns the range of the `{{` or `}}` matching the delimiter at
+/// `position`, if there is one.
+pub fn match_delimiter(text: &str, position: Position) -> Option<Range> {
+    let contains = |range: &Option<Range>| {
+        range.is_some_and(|r| r.start <= position && position <= r.end)
+    };
+
+    parse::delimiter_pairs(text).into_iter()
+        .find_map(|pair| {
+            if contains(&pair.open) {
+                pair.close
+            } else if contains(&pair.close) {
+                pair.open
+            } else {
+                None
+            }
+        })
+}
+
+/// Inserts a rule of the judgement whose `defn` the cursor at `position` is
+/// in, after the rule or header at the cursor: a comment standing in for its
+/// premises, a line of dashes with a name no rule of its `defns` has, and the
+/// judgement's form as its conclusion.
+pub fn new_rule(doc: &parse::Document, text: &str, position: Position) -> Option<TextEdit> {
+    let blocks = parse::blocks(text);
+    let block = blocks.iter().find(|b| b.keyword == "defns" && b.lines.contains(&position.line))?;
+    let in_block = |name: &Name| block.lines.contains(&name.range.start.line);
+    let defns = doc.defns.iter().rev().find(|d| in_block(&d.name) && d.name.range.start.line <= position.line)?;
+    let defn = defns.defns.iter().rev().find(|d| d.name.range.start.line <= position.line)?;
+
+    let taken = |name: &str| defns.defns.iter().flat_map(|d| &d.rules).any(|rule| rule.name.text == name);
+    let name = (1..)
+        .map(|n| if n == 1 { "NewRule".to_string() } else { format!("NewRule{n}") })
+        .find(|name| !taken(name))?;
+
+    let form: Vec<_> = defn.form.iter().map(|word| word.text.as_str()).collect();
+    let conclusion = form.join(" ");
+    let dashes = "-".repeat(conclusion.chars().count().max(10));
+    let rule = format!("% premises\n{dashes} :: {name}\n{conclusion}\n");
+
+    // After the paragraph at the cursor: rules are separated by blank lines.
+    let lines: Vec<_> = text.split('\n').collect();
+    let last = (block.lines.end as usize).min(lines.len()) - 1;
+    let edit = match (position.line as usize..=last).find(|&i| lines[i].trim().is_empty()) {
+        Some(blank) => {
+            let start = Position::new(blank as u32, 0);
+            TextEdit::new(Range::new(start, start), format!("\n{rule}"))
+        }
+        None => {
+            let end = Position::new(last as u32, parse::utf16_len(lines[last]));
+            TextEdit::new(Range::new(end, end), format!("\n\n{}", rule.trim_end()))
+        }
+    };
+
+    Some(edit)
+}
+
+/// The sections every other section may refer to.
+const DECLARATIONS: &[&str] = &["metavar", "indexvar", "grammar"];
+
+/// What to check to check one block of a file on its own.
+pub struct BlockExtract {
+    /// The block and the declarations it depends on, with every other line
+    /// blanked out so that line numbers match the original.
+    pub text: String,
+    /// Whether everything the block might depend on was kept.
+    pub complete: bool,
+}
+
+/// Extracts the top-level block of `text` containing `position` along with
+/// the metavars and grammar it might refer to. A `defns` or `funs` block can
+/// also use judgements and functions from others, which aren't kept.
+pub fn extract_block(text: &str, position: Position) -> Option<BlockExtract> {
+    let blocks = parse::blocks(text);
+    let block = blocks.iter().find(|b| b.lines.contains(&position.line))?;
+    let relational = |keyword| matches!(keyword, "defns" | "funs");
+    let complete = !relational(block.keyword)
+        || !blocks.iter().any(|b| b.lines != block.lines && relational(b.keyword));
+
+    let kept = |line| blocks.iter()
+        .filter(|b| b.lines == block.lines || DECLARATIONS.contains(&b.keyword))
+        .any(|b| b.lines.contains(&line));
+
+    let text = text.split('\n')
+        .enumerate()
+        .map(|(i, line)| if kept(i as u32) { line } else { "" })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Some(BlockExtract { text, complete })
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckBlockResult {
+    pub diagnostics: Vec<Diagnostic>,
+    /// `false` if some of what the block depends on wasn't checked with it,
+    /// in which case some of the diagnostics may be spurious.
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewBlockResult {
+    /// What ott generated from the block, empty if it generated nothing.
+    pub output: String,
+    /// Whether ott exited successfully without reporting any errors.
+    pub success: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    /// As in [`CheckBlockResult`].
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewBackendResult {
+    /// What ott generated from the theory, empty if it generated nothing.
+    pub output: String,
+    /// Whether ott exited successfully without reporting any errors.
+    pub success: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestProductionResult {
+    /// Whether ott parsed the sample as the nonterminal without reporting
+    /// any errors.
+    pub parses: bool,
+    /// What ott printed of the parse: the derivation it found, or where it
+    /// gave up.
+    pub output: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct HomCoverageResult {
+    /// How many productions have a hom for the target.
+    pub annotated: usize,
+    /// How many productions there are.
+    pub total: usize,
+    /// The labels of the productions that don't.
+    pub missing: Vec<Location>,
+}
+
+/// The totals over the documents checked by [`CHECK_ALL`].
+#[derive(Debug, Default, Serialize)]
+pub struct CheckAllResult {
+    pub files: usize,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+/// A diagnostic in one of the files [`PROJECT_DIAGNOSTICS`] checked.
+#[derive(Debug, Serialize)]
+pub struct ProjectDiagnostic {
+    pub uri: Uri,
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ProjectDiagnosticsResult {
+    /// Every file's diagnostics, file by file in the theory's order.
+    pub diagnostics: Vec<ProjectDiagnostic>,
+    pub summary: CheckAllResult,
+}
+
+/// One run of ott in a [`BUILD_THEORY`].
+#[derive(Debug, Serialize)]
+pub struct BuildStep {
+    /// The file built.
+    pub uri: Uri,
+    /// What ott was run on: the files `uri` depends on, in order, then `uri`.
+    pub files: Vec<Uri>,
+    pub success: bool,
+    /// The diagnostics in `uri` itself; those in the files before it are
+    /// their own steps'.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BuildTheoryResult {
+    /// The theory's files, each after those it depends on.
+    pub order: Vec<Uri>,
+    pub steps: Vec<BuildStep>,
+    /// Whether every step succeeded.
+    pub success: bool,
+}
+
+/// The settings in effect, as [`STATUS`] reports them.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusResult {
+    pub ott_path: String,
+    pub tex_wrap: bool,
+    pub tex_show_meta: bool,
+}
+
+/// The version of the JSON [`DUMP_SYMBOLS`] returns. Bump it when a field is
+/// renamed or removed; adding fields doesn't need it.
+pub const SYMBOLS_SCHEMA: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct DumpSymbolsResult {
+    pub schema: u32,
+    pub document: parse::Document,
+}
+
+/// The backends ott generates, with their names for people and the path
+/// templates [`GENERATE`] writes them to by default.
+pub const TARGETS: &[(&str, &str, &str)] = &[
+    ("coq", "Coq", "{dir}/{stem}.v"),
+    ("isa", "Isabelle", "{dir}/{stem}.thy"),
+    ("hol", "HOL", "{dir}/{stem}Script.sml"),
+    ("lem", "Lem", "{dir}/{stem}.lem"),
+    ("ocaml", "OCaml", "{dir}/{stem}.ml"),
+    ("tex", "LaTeX", "{dir}/{stem}.tex"),
+];
+
+/// Where generating `target` from `source` writes: the template for `target`
+/// in `templates`, or else its default, with `{dir}` and `{stem}` replaced by
+/// the directory and file stem of `source`. A relative result is relative to
+/// the directory of `source`.
+pub fn output_path(templates: &HashMap<String, String>, target: &str, source: &Path) -> Option<PathBuf> {
+    let default = TARGETS.iter().find(|(t, ..)| *t == target).map(|(.., template)| *template);
+    let template = templates.get(target).map(String::as_str).or(default)?;
+    let dir = source.parent().unwrap_or(Path::new(""));
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let path = template.replace("{dir}", &dir.to_string_lossy()).replace("{stem}", &stem);
+    Some(dir.join(path))
+}
+
+/// `line` as a comment in `target`'s language, if we know how to write one.
+fn comment(target: &str, line: &str) -> Option<String> {
+    match target.split('-').next()? {
+        "tex" | "twf" => Some(format!("% {line}")),
+        "coq" | "isa" | "hol" | "lem" | "ocaml" => Some(format!("(* {line} *)")),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtractEmbedsResult {
+    /// The code of the embeds, in the order they're written.
+    pub text: String,
+    /// How many embeds of the target there were.
+    pub count: usize,
+    /// The file the code was written to, if `extractPaths` names one.
+    pub output: Option<PathBuf>,
+}
+
+/// The code of the `embed` blocks for `target` in `doc`, in the order they're
+/// written, separated by blank lines. Given the `source` file's name, each is
+/// preceded by a comment saying where in it it's from, for targets whose
+/// comments we know. `None` if there are none.
+pub fn extract_embeds(doc: &parse::Document, target: &str, source: Option<&str>) -> Option<ExtractEmbedsResult> {
+    let embeds: Vec<_> = doc.embeds.iter().filter(|embed| embed.target == target).collect();
+    if embeds.is_empty() {
+        return None;
+    }
+
+    let blocks: Vec<_> = embeds.iter()
+        .map(|embed| {
+            let line = embed.range.start.line + 1;
+            match source.and_then(|source| comment(target, &format!("from {source}, line {line}"))) {
+                Some(comment) => format!("{comment}\n{}", embed.body),
+                None => embed.body.clone(),
+            }
+        })
+        .collect();
+
+    Some(ExtractEmbedsResult { text: blocks.join("\n\n") + "\n", count: embeds.len(), output: None })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateResult {
+    /// The file ott was asked to write.
+    pub output: PathBuf,
+    /// Whether ott exited successfully without reporting any errors.
+    pub success: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The `postGenerate` command that was run on the output, if one was.
+    pub formatted_by: Option<String>,
+    /// Why the `postGenerate` command failed, if it did. The output is as
+    /// ott generated it, or as the command left it.
+    pub post_generate_error: Option<String>,
+    /// Whether the client was asked to open the output.
+    pub shown: bool,
+}
+
+impl GenerateResult {
+    /// What to tell the user about generating `target`.
+    pub fn message(&self, target: &str) -> (MessageType, String) {
+        let output = self.output.display();
+        match (self.success, &self.post_generate_error, &self.formatted_by) {
+            (false, ..) => (MessageType::ERROR, format!("failed to generate {target} in {output}")),
+            (true, Some(e), _) => (MessageType::ERROR, format!("generated {target} in {output}, but {e}")),
+            (true, None, Some(program)) => {
+                (MessageType::INFO, format!("generated {target} in {output}, formatted with `{program}`"))
+            }
+            (true, None, None) => (MessageType::INFO, format!("generated {target} in {output}")),
+        }
+    }
+}
+
+/// The targets [`GENERATE_ALL`] generates: those `templates` gives paths,
+/// known ones first in the order of [`TARGETS`], or all of [`TARGETS`] if it
+/// gives none.
+pub fn configured_targets(templates: &HashMap<String, String>) -> Vec<String> {
+    if templates.is_empty() {
+        return TARGETS.iter().map(|(target, ..)| target.to_string()).collect();
+    }
+
+    let known = |target: &str| TARGETS.iter().position(|(t, ..)| *t == target).unwrap_or(TARGETS.len());
+    let mut targets: Vec<_> = templates.keys().cloned().collect();
+    targets.sort_by_key(|target| (known(target), target.clone()));
+    targets
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GenerateAllResult {
+    /// What came of generating each target, by target.
+    pub targets: BTreeMap<String, GenerateResult>,
+    /// The targets that failed, in the order they were generated.
+    pub failed: Vec<String>,
+}
+
+/// A reference, in one of the files searched, to something none declares.
+#[derive(Debug, Serialize)]
+pub struct UnresolvedReference {
+    pub uri: Uri,
+    pub range: Range,
+    pub name: String,
+    pub severity: DiagnosticSeverity,
+}
+
+/// Finds the references in `docs` that none of them declares: productions
+/// named in `parsing` sections, which ott rejects outright, and the
+/// nonterminals and metavars substitutions are for.
+pub fn unresolved_references(docs: &[(Uri, parse::Document)]) -> Vec<UnresolvedReference> {
+    let all = || docs.iter().map(|(_, doc)| doc);
+    let productions: Vec<_> = all().flat_map(|doc| &doc.grammars).flat_map(|rule| &rule.productions).collect();
+    let metavars: Vec<_> = all().flat_map(|doc| &doc.metavars).flat_map(|m| &m.names).collect();
+    let nonterminals: Vec<_> = all().flat_map(|doc| &doc.grammars).flat_map(|rule| &rule.names).collect();
+    let uses = |roots: &[&Name], name: &Name| roots.iter().any(|root| is_use(&name.text, &root.text));
+
+    let mut references = vec![];
+    for (uri, doc) in docs {
+        let mut unresolved = |name: &Name, severity| references.push(UnresolvedReference {
+            uri: uri.clone(),
+            range: name.range,
+            name: name.text.clone(),
+            severity,
+        });
+
+        for name in doc.precedences.iter().flat_map(|p| [&p.left, &p.right]) {
+            if !productions.iter().any(|p| p.id == name.text) {
+                unresolved(name, DiagnosticSeverity::ERROR);
+            }
+        }
+
+        for subst in &doc.substitutions {
+            if !uses(&metavars, &subst.metavar) {
+                unresolved(&subst.metavar, DiagnosticSeverity::WARNING);
+            }
+
+            if !uses(&nonterminals, &subst.nonterminal) {
+                unresolved(&subst.nonterminal, DiagnosticSeverity::WARNING);
+            }
+        }
+    }
+
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEORY: &str = "metavar x ::=\n\ngrammar\nt :: 't_' ::=\n  | x :: :: var\n\nembed {{ tex \\newcommand{\\x}{x} }}\n\ndefns\nJop :: '' ::=\n\ndefn\nt --> t :: :: reduce :: E_ by\n\ndefns\nJtype :: '' ::=\n";
+
+    /// The lines of `extract` that were kept, by number.
+    fn kept(extract: &BlockExtract) -> Vec<(usize, &str)> {
+        extract.text.split('\n').enumerate().filter(|(_, line)| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn blocks_are_extracted_with_the_declarations_they_may_use() {
+        let extract = extract_block(THEORY, Position::new(12, 3)).unwrap();
+        assert_eq!(extract.text.split('\n').count(), THEORY.split('\n').count());
+        assert_eq!(kept(&extract), [
+            (0, "metavar x ::="),
+            (2, "grammar"),
+            (3, "t :: 't_' ::="),
+            (4, "  | x :: :: var"),
+            (8, "defns"),
+            (9, "Jop :: '' ::="),
+            (11, "defn"),
+            (12, "t --> t :: :: reduce :: E_ by"),
+        ]);
+
+        // The other `defns` block might have had judgements it uses.
+        assert!(!extract.complete);
+
+        // The grammar needs only the declarations.
+        let extract = extract_block(THEORY, Position::new(3, 0)).unwrap();
+        assert_eq!(kept(&extract), [(0, "metavar x ::="), (2, "grammar"), (3, "t :: 't_' ::="), (4, "  | x :: :: var")]);
+        assert!(extract.complete);
+
+        let extract = extract_block(THEORY, Position::new(6, 4)).unwrap();
+        assert!(kept(&extract).contains(&(6, "embed {{ tex \\newcommand{\\x}{x} }}")));
+        assert!(extract.complete);
+    }
+
+    #[test]
+    fn a_lone_defns_block_is_extracted_whole() {
+        let text = "metavar x ::=\n\ndefns\nJop :: '' ::=\n";
+        let extract = extract_block(text, Position::new(3, 0)).unwrap();
+        assert_eq!(extract.text, text);
+        assert!(extract.complete);
+    }
+}
@@ -0,0 +1,345 @@
+//! Completion of names from the parsed document model.
+
+use lsp_types::*;
+
+use crate::lint::{BACKENDS, OTHER_TARGETS};
+use crate::parse::{self, Document, GrammarRule, InferenceRule, Name, TokenKind};
+
+/// Whether `position` in `text` is where a rule's name goes: after the `::`
+/// on a line of dashes in a `defns` section.
+fn at_rule_name(text: &str, position: Position) -> bool {
+    let in_defns = parse::blocks(text).iter()
+        .any(|block| block.keyword == "defns" && block.lines.contains(&position.line));
+
+    let tokens = parse::tokenize(text);
+    let mut line = tokens.iter().filter(|t| t.range.start.line == position.line);
+    let dashes = line.next().is_some_and(|t| t.text.starts_with("---") && t.text.chars().all(|c| c == '-'));
+    let separator = line.next().is_some_and(|t| t.is("::") && t.range.end <= position);
+    in_defns && dashes && separator
+}
+
+/// The grammar rule a production on `position`'s line would belong to, if
+/// the line is blank but for a `|` before `position`, along with the column
+/// the production starts at: that of the `|`, if there is one.
+fn at_production<'d>(doc: &'d Document, text: &str, position: Position) -> Option<(&'d GrammarRule, u32)> {
+    let line = text.lines().nth(position.line as usize)?;
+    let (mut column, mut bar) = (0, None);
+    for c in line.chars() {
+        match c {
+            c if c.is_whitespace() => {}
+            '|' if bar.is_none() && column < position.character => bar = Some(column),
+            _ => return None,
+        }
+
+        column += c.len_utf16() as u32;
+    }
+
+    let blocks = parse::blocks(text);
+    let grammar = blocks.iter().find(|b| b.keyword == "grammar" && b.lines.contains(&position.line))?;
+    let rule = doc.grammars.iter().rev().find(|rule| {
+        rule.names.first().is_some_and(|name| {
+            let line = name.range.start.line;
+            grammar.lines.contains(&line) && line < position.line
+        })
+    })?;
+
+    Some((rule, bar.unwrap_or(position.character)))
+}
+
+/// What a hom for `target` is for, for those ott knows.
+fn target_description(target: &str) -> Option<&'static str> {
+    Some(match target {
+        "tex" => "LaTeX",
+        "coq" => "Coq",
+        "isa" => "Isabelle",
+        "hol" => "HOL",
+        "lem" => "Lem",
+        "ocaml" => "OCaml",
+        "twelf" => "Twelf",
+        "lex" => "the ocamllex lexer",
+        "menhir" => "the Menhir parser",
+        "com" => "a comment, typeset in the LaTeX",
+        "ich" => "Isabelle, Coq, and HOL",
+        "ic" => "Isabelle and Coq",
+        "ch" => "Coq and HOL",
+        "ih" => "Isabelle and HOL",
+        "icho" => "Isabelle, Coq, HOL, and OCaml",
+        "ichl" => "Isabelle, Coq, HOL, and Lem",
+        "ichlo" => "Isabelle, Coq, HOL, Lem, and OCaml",
+        _ => return None,
+    })
+}
+
+/// The hom targets ott knows, and those in `known`, to replace the part of
+/// a target typed in `range` with.
+fn targets(range: Range, known: &[String]) -> Vec<CompletionItem> {
+    let mut targets: Vec<&str> = vec![];
+    for target in BACKENDS.iter().chain(OTHER_TARGETS).copied().chain(known.iter().map(String::as_str)) {
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    targets.into_iter()
+        .enumerate()
+        .map(|(i, target)| CompletionItem {
+            label: target.into(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            detail: target_description(target).map(String::from),
+            sort_text: Some(format!("{i:03}")),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, target.into()))),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// `text` escaped for use in a snippet's placeholder.
+fn escape_snippet(text: &str) -> String {
+    text.replace('\\', r"\\").replace('$', r"\$").replace('}', r"\}")
+}
+
+/// A snippet for a new production of `rule`, replacing what's between
+/// `start` and `position` on the line, with tab stops at the body, its
+/// flags, and its label.
+fn production_snippet(rule: &GrammarRule, start: u32, position: Position) -> CompletionItem {
+    let nonterminal = &rule.names[0].text;
+    let snippet = format!("| ${{1:{}}} :: $2 :: ${{3:Name}}", escape_snippet(nonterminal));
+    CompletionItem {
+        label: format!("| {nonterminal} :: :: Name"),
+        kind: Some(CompletionItemKind::SNIPPET),
+        detail: Some(format!("a production of `{nonterminal}`, named `{}Name`", rule.prefix)),
+        filter_text: Some("|".into()),
+        insert_text_format: Some(InsertTextFormat::SNIPPET),
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+            range: Range::new(Position::new(position.line, start), position),
+            new_text: snippet,
+        })),
+        ..Default::default()
+    }
+}
+
+/// The documentation for `rule` in `text`: the `%` comment above it, or
+/// failing that, the rule as written, from its first premise to its
+/// conclusion.
+fn rule_documentation(rule: &InferenceRule, text: &str) -> Documentation {
+    let value = rule.doc.clone().unwrap_or_else(|| {
+        let first = rule.premises.first().and_then(|p| p.first()).unwrap_or(&rule.name);
+        let (start, end) = (first.range.start.line as usize, rule.name.range.start.line as usize + 1);
+        let lines: Vec<_> = text.lines().skip(start).take(end + 1 - start).map(str::trim_end).collect();
+        format!("```ott\n{}\n```", lines.join("\n"))
+    });
+
+    Documentation::MarkupContent(MarkupContent { kind: MarkupKind::Markdown, value })
+}
+
+/// The documentation for a metavar or nonterminal declared as `name` in
+/// `text`: its `%` comment `doc`, or failing that, the line declaring it.
+fn declaration_documentation(doc: Option<&str>, name: &Name, text: &str) -> Documentation {
+    let value = doc.map(String::from).unwrap_or_else(|| {
+        let line = text.lines().nth(name.range.start.line as usize).unwrap_or_default();
+        format!("```ott\n{}\n```", line.trim_end())
+    });
+
+    Documentation::MarkupContent(MarkupContent { kind: MarkupKind::Markdown, value })
+}
+
+/// The metavars and nonterminals `doc` declares, with where and their
+/// documentation, and ott's
+/// keywords, those that begin a section only at the start of a line, for
+/// the word at `position` in `text`. None in a comment, or in a quoted
+/// prefix like `'t_'`.
+fn names(doc: &Document, text: &str, position: Position) -> Vec<CompletionItem> {
+    let tokens = parse::tokenize(text);
+    let token = tokens.iter().find(|t| t.range.start < position && position <= t.range.end);
+    if token.is_some_and(|t| t.kind == TokenKind::Comment || t.text.starts_with('\'')) {
+        return vec![];
+    }
+
+    // Only a name being typed is completed, not the likes of the `|` or `{`
+    // that trigger completion elsewhere.
+    if let Some(token) = token {
+        let mut column = token.range.start.character;
+        let typed = token.text.chars()
+            .take_while(|c| {
+                column += c.len_utf16() as u32;
+                column <= position.character
+            })
+            .last();
+
+        if typed.is_some_and(|c| !(c.is_alphanumeric() || c == '_' || c == '\'')) {
+            return vec![];
+        }
+    }
+
+    let line_start = match token {
+        Some(token) => token.line_start,
+        None => !tokens.iter().any(|t| t.range.start.line == position.line && t.range.end <= position),
+    };
+
+    let item = |label: &str, kind| CompletionItem { label: label.into(), kind: Some(kind), ..Default::default() };
+    let declared = |kind: &str, name: &Name, doc: Option<&str>, item_kind| CompletionItem {
+        detail: Some(format!("{kind}, declared on line {}", name.range.start.line + 1)),
+        documentation: Some(declaration_documentation(doc, name, text)),
+        ..item(&name.text, item_kind)
+    };
+
+    let metavars = doc.metavars.iter()
+        .flat_map(|m| m.names.iter().map(|name| (name, m.doc.as_deref())))
+        .map(|(name, doc)| declared("metavar", name, doc, CompletionItemKind::VARIABLE));
+
+    let nonterminals = doc.grammars.iter()
+        .flat_map(|rule| rule.names.iter().map(|name| (name, rule.doc.as_deref())))
+        .map(|(name, doc)| declared("nonterminal", name, doc, CompletionItemKind::CLASS));
+
+    let sections = parse::SECTION_KEYWORDS.iter().filter(|_| line_start);
+    let keywords = sections.chain(parse::KEYWORDS)
+        .map(|keyword| item(keyword, CompletionItemKind::KEYWORD));
+
+    let mut items: Vec<CompletionItem> = vec![];
+    for candidate in metavars.chain(nonterminals).chain(keywords) {
+        if !items.iter().any(|item| item.label == candidate.label) {
+            items.push(candidate);
+        }
+    }
+
+    items
+}
+
+/// The completions at `position` in `doc`, whose text is `text`. After a
+/// rule's `::`, these are the names of the document's other rules, each
+/// offered once, with its conclusion as detail and its documentation as
+/// documentation. At the start of a line of a
+/// grammar rule, if the client takes `snippets`, it's a production of the
+/// rule. At a hom's target, they're the targets ott knows and those in
+/// `known_targets`. Anywhere else, they're the document's metavars and
+/// nonterminals and ott's keywords.
+pub fn completions(
+    doc: &Document,
+    text: &str,
+    position: Position,
+    snippets: bool,
+    known_targets: &[String],
+) -> Vec<CompletionItem> {
+    // A hom's body is another language, but for its interpolations, and
+    // for its target, which is ott's.
+    if let Some(embed) = parse::embed_context_at(text, position).filter(|embed| embed.interpolation.is_none()) {
+        return embed.typed_target.map(|range| targets(range, known_targets)).unwrap_or_default();
+    }
+
+    if snippets && let Some((rule, start)) = at_production(doc, text, position) {
+        return vec![production_snippet(rule, start, position)];
+    }
+
+    if !at_rule_name(text, position) {
+        return names(doc, text, position);
+    }
+
+    let mut items: Vec<CompletionItem> = vec![];
+    for rule in doc.defns.iter().flat_map(|d| &d.defns).flat_map(|d| &d.rules) {
+        // The name being typed is parsed as a rule of its own.
+        let typing = rule.name.range.start <= position && position <= rule.name.range.end;
+        if typing || items.iter().any(|item| item.label == rule.name.text) {
+            continue;
+        }
+
+        items.push(CompletionItem {
+            label: rule.name.text.clone(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: (!rule.conclusion.is_empty()).then(|| rule.conclusion.clone()),
+            documentation: Some(rule_documentation(rule, text)),
+            ..Default::default()
+        });
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A theory whose metavar and one rule have `%` comments above them.
+    const THEORY: &str = r"% Term variables.
+metavar termvar, x ::=
+grammar
+t :: 't_' ::=
+  | x :: :: var
+
+defns
+Jop :: '' ::=
+
+defn
+t --> t :: :: reduce :: E_ by
+
+% Steps to itself.
+----- :: refl
+x --> x
+
+t --> t
+----- :: twice
+t --> t
+
+----- :: 
+";
+
+    fn documentation(item: &CompletionItem) -> Option<&str> {
+        match item.documentation.as_ref()? {
+            Documentation::MarkupContent(content) => Some(&content.value),
+            Documentation::String(value) => Some(value),
+        }
+    }
+
+    /// The label, detail, and documentation of each of `items`.
+    fn described(items: &[CompletionItem]) -> Vec<(&str, Option<&str>, Option<&str>)> {
+        items.iter().map(|item| (item.label.as_str(), item.detail.as_deref(), documentation(item))).collect()
+    }
+
+    #[test]
+    fn names_are_documented_by_their_comments_or_declarations() {
+        let doc = Document::parse(THEORY);
+        let items = completions(&doc, THEORY, Position::new(4, 4), false, &[]);
+        let declared: Vec<_> = items.iter().filter(|item| item.kind != Some(CompletionItemKind::KEYWORD)).cloned().collect();
+        assert_eq!(described(&declared), [
+            ("termvar", Some("metavar, declared on line 2"), Some("Term variables.")),
+            ("x", Some("metavar, declared on line 2"), Some("Term variables.")),
+            ("t", Some("nonterminal, declared on line 4"), Some("```ott\nt :: 't_' ::=\n```")),
+        ]);
+    }
+
+    #[test]
+    fn rules_are_documented_by_their_comments_or_themselves() {
+        let doc = Document::parse(THEORY);
+        let items = completions(&doc, THEORY, Position::new(20, 9), false, &[]);
+        assert_eq!(described(&items), [
+            ("refl", Some("x --> x"), Some("Steps to itself.")),
+            ("twice", Some("t --> t"), Some("```ott\nt --> t\n----- :: twice\nt --> t\n```")),
+        ]);
+    }
+
+    #[test]
+    fn hom_targets_are_offered_where_the_target_goes() {
+        let text = "metavar x ::=\ngrammar\nt :: 't_' ::=\n  | x :: :: var {{ co }} {{ tex x }}\n";
+        let doc = Document::parse(text);
+        let known = ["tex".to_string(), "mine".to_string()];
+
+        let items = completions(&doc, text, Position::new(3, 21), false, &known);
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels[..4], ["tex", "coq", "isa", "hol"]);
+        assert_eq!(labels.iter().filter(|label| **label == "tex").count(), 1);
+        assert_eq!(labels.last(), Some(&"mine"));
+        assert!(items.iter().all(|item| item.kind == Some(CompletionItemKind::KEYWORD)));
+
+        let coq = &items[1];
+        assert_eq!(coq.detail.as_deref(), Some("Coq"));
+        let typed = Range::new(Position::new(3, 19), Position::new(3, 21));
+        assert_eq!(coq.text_edit, Some(CompletionTextEdit::Edit(TextEdit::new(typed, "coq".into()))));
+
+        // Right after the `{{`, nothing's been typed yet.
+        let items = completions(&doc, text, Position::new(3, 18), false, &[]);
+        let empty = Range::new(Position::new(3, 18), Position::new(3, 18));
+        assert_eq!(items[0].text_edit, Some(CompletionTextEdit::Edit(TextEdit::new(empty, "tex".into()))));
+
+        // Past the target, the body is LaTeX.
+        assert_eq!(completions(&doc, text, Position::new(3, 32), false, &known), []);
+    }
+}
@@ -0,0 +1,518 @@
+//! The server's settings, as the client sends them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use regex::Regex;
+use serde::Deserialize;
+use lsp_types::*;
+
+use crate::{check, parse, theory};
+
+#[derive(Default, Debug, Clone, Deserialize)]
+pub struct Config {
+    /// The ott binary to run. Defaults to `ott`, looked up in `PATH`.
+    #[serde(default, alias = "ottPath")]
+    pub ott_path: Option<String>,
+    /// Environment variables to set for each ott process, on top of the
+    /// server's own.
+    #[serde(default, alias = "ottEnv")]
+    pub ott_env: HashMap<String, String>,
+    #[serde(default, alias = "ottFlags")]
+    pub ott_flags: Vec<String>,
+    /// The most ott processes to run at once. Defaults to the CPU count.
+    #[serde(default, alias = "maxParallelChecks")]
+    pub max_parallel_checks: Option<usize>,
+    /// Whether to report ott's informational output as hints.
+    #[serde(default, alias = "showInfo")]
+    pub show_info: bool,
+    /// The files, relative to the workspace root, that make up a multi-file
+    /// theory. Checking any one of them passes all of them to ott, in order.
+    #[serde(default)]
+    pub theory: Vec<String>,
+    /// Whether to check that binding specifications and substitutions refer
+    /// to variables that exist.
+    #[serde(default, alias = "lintBindings")]
+    pub lint_bindings: bool,
+    /// Whether to warn about metavars whose names overlap, like `x` and `x1`.
+    #[serde(default, alias = "lintMetavars")]
+    pub lint_metavars: bool,
+    /// Whether to point out metavars that no grammar production uses.
+    #[serde(default, alias = "lintUnused")]
+    pub lint_unused: bool,
+    /// Whether to check that rules' premises have the form of a judgement.
+    #[serde(default, alias = "lintJudgements")]
+    pub lint_judgements: bool,
+    /// Whether to check that rules' conclusions have the form of a judgement
+    /// of their `defns` block.
+    #[serde(default, alias = "lintConclusions")]
+    pub lint_conclusions: bool,
+    /// Whether to hint at metavars in rules' conclusions that none of their
+    /// premises mention.
+    #[serde(default, alias = "lintUnbound")]
+    pub lint_unbound: bool,
+    /// The judgements whose rules' conclusions may introduce metavars of
+    /// their own, which the `unbound` lint leaves alone.
+    #[serde(default, alias = "freshJudgements")]
+    pub fresh_judgements: Vec<String>,
+    /// Whether to warn about homs and embeds for targets ott doesn't know.
+    #[serde(default, alias = "lintTargets")]
+    pub lint_targets: bool,
+    /// Whether to warn about rules whose lines mix tabs and spaces in their
+    /// indentation.
+    #[serde(default, alias = "lintWhitespace")]
+    pub lint_whitespace: bool,
+    /// Whether to run each lint, by its id in `lint::LINTS`, and at what
+    /// severity, over its `lint_*` flag and its own severity.
+    #[serde(default)]
+    pub lints: HashMap<String, LintSetting>,
+    /// Whether to point ott's complaints about metavars and nonterminals
+    /// declared more than once at each of their declarations in the theory.
+    #[serde(default, alias = "relateDuplicates")]
+    pub relate_duplicates: bool,
+    /// Targets for which to point out each production without a hom.
+    #[serde(default, alias = "homCoverage")]
+    pub hom_coverage: Vec<String>,
+    /// Targets to accept besides those ott knows, when linting targets.
+    #[serde(default, alias = "knownTargets")]
+    pub known_targets: Vec<String>,
+    /// How long, in milliseconds, the server must be idle before it rescans
+    /// and reparses every ott file in the workspace. Unset disables this.
+    #[serde(default, alias = "idleReindexMs")]
+    pub idle_reindex_ms: Option<u64>,
+    /// How long, in milliseconds, checks wait after startup for settings to
+    /// arrive, if none were passed at initialization. Passing this at
+    /// initialization waits for more.
+    #[serde(default, alias = "configGraceMs")]
+    pub config_grace_ms: Option<u64>,
+    /// How long, in milliseconds, a document must go without changing before
+    /// it's checked as typed.
+    #[serde(default, alias = "debounceMs")]
+    pub debounce_ms: Option<u64>,
+    /// Whether scans of the workspace for ott files skip what `.gitignore`
+    /// and `.ignore` files ignore.
+    #[serde(default, alias = "respectGitignore")]
+    pub respect_gitignore: Option<bool>,
+    /// Whether to add a missing newline at the end of an unsaved buffer when
+    /// checking it. Defaults to `true`.
+    #[serde(default, alias = "ensureTrailingNewline")]
+    pub ensure_trailing_newline: Option<bool>,
+    /// Whether to lengthen a rule's line of dashes to span the rule as it's
+    /// typed. Defaults to `true`.
+    #[serde(default, alias = "alignSeparators")]
+    pub align_separators: Option<bool>,
+    /// Whether to show each rule's full name as an inlay hint at its line
+    /// of dashes. Defaults to `true`.
+    #[serde(default, alias = "inlayHints")]
+    pub inlay_hints: Option<bool>,
+    /// Whether to pass ott `-merge true` or `-merge false`. Unset passes
+    /// neither, leaving it to ott's default.
+    #[serde(default)]
+    pub merge: Option<bool>,
+    /// The most of ott's output to read, in bytes. Defaults to 8 MiB.
+    #[serde(default, alias = "maxOutputBytes")]
+    pub max_output_bytes: Option<usize>,
+    /// How long ott may run for, in milliseconds, before it's stopped.
+    /// Defaults to no limit.
+    #[serde(default, alias = "checkTimeoutMs")]
+    pub check_timeout_ms: Option<u64>,
+    /// Whether to read what ott prints to stdout and stderr as one stream,
+    /// in the order it printed it. Defaults to `true`.
+    #[serde(default, alias = "mergeStderr")]
+    pub merge_stderr: Option<bool>,
+    /// The severity of the diagnostic reported when ott fails without saying
+    /// why. Defaults to `error`.
+    #[serde(default, alias = "genericFailureSeverity")]
+    pub generic_failure_severity: Option<Severity>,
+    /// Whether to report ott's warnings as errors, for theories that are to
+    /// have none.
+    #[serde(default)]
+    pub strict: bool,
+    /// Whether `strict` reports the lints' warnings as errors too.
+    #[serde(default, alias = "strictLints")]
+    pub strict_lints: bool,
+    /// The name of the `check::Profile` to read ott's output with, or `auto`
+    /// to choose by ott's version. Defaults to `auto`.
+    #[serde(default, alias = "formatProfile")]
+    pub format_profile: Option<String>,
+    /// Whether to send `ott-lsp/embedRegions` notifications.
+    #[serde(default, alias = "embedRegions")]
+    pub embed_regions: bool,
+    /// The severities of the diagnostics to report. Defaults to all of them.
+    #[serde(default, alias = "publishedSeverities")]
+    pub published_severities: Option<Vec<Severity>>,
+    /// The language ids of the open documents to check. Defaults to `ott`.
+    #[serde(default, alias = "languageIds")]
+    pub language_ids: Option<Vec<String>>,
+    /// Where to write each target `ott-lsp.generate` generates, as a path
+    /// template with `{dir}` and `{stem}` placeholders.
+    #[serde(default, alias = "outputPaths")]
+    pub output_paths: HashMap<String, String>,
+    /// Where `ott-lsp.extractEmbeds` writes each target's embeds, as a path
+    /// template like those of `output_paths`. Unset targets aren't written.
+    #[serde(default, alias = "extractPaths")]
+    pub extract_paths: HashMap<String, String>,
+    /// A command, and its arguments, to run on each file `ott-lsp.generate`
+    /// generates, by target, as to format it. The file's path is passed last.
+    #[serde(default, alias = "postGenerate")]
+    pub post_generate: HashMap<String, Vec<String>>,
+    /// Whether a `post_generate` command that isn't installed is a failure,
+    /// rather than skipped.
+    #[serde(default, alias = "postGenerateStrict")]
+    pub post_generate_strict: bool,
+    /// Whether to have the client open what `ott-lsp.generate` generates,
+    /// beside the source, if it can.
+    #[serde(default, alias = "showGenerated")]
+    pub show_generated: bool,
+    /// Whether generated LaTeX is wrapped in a document preamble and
+    /// postamble. Defaults to ott's default, `true`.
+    #[serde(default, alias = "texWrap")]
+    pub tex_wrap: Option<bool>,
+    /// Whether generated LaTeX includes meta productions and rules. Defaults
+    /// to ott's default, `true`.
+    #[serde(default, alias = "texShowMeta")]
+    pub tex_show_meta: Option<bool>,
+    /// How to render ott's messages that span several lines.
+    #[serde(default, alias = "messageFormat")]
+    pub message_format: MessageFormat,
+    /// Whether the sources of ott's diagnostics name the pass of ott that
+    /// reported them, like `ott[parse]`, when it can be told.
+    #[serde(default, alias = "annotatePass")]
+    pub annotate_pass: bool,
+    /// Whether to put the version of the document and the number of the
+    /// publish in the `data` of every diagnostic published, to tell which
+    /// publish put a diagnostic there when debugging.
+    #[serde(default, alias = "debugDiagnosticData")]
+    pub debug_diagnostic_data: bool,
+    /// A command, and its arguments, to run in a document's directory before
+    /// each ott run on it, as when the document is generated.
+    #[serde(default, alias = "preCheck")]
+    pub pre_check: Vec<String>,
+    /// Whether to have ott report every term with more than one parse.
+    #[serde(default, alias = "pickyMultipleParses")]
+    pub picky_multiple_parses: bool,
+    /// Whether to run ott once at startup, so that the first real check
+    /// doesn't pay for loading it.
+    #[serde(default)]
+    pub prewarm: bool,
+    /// Whether to check every ott file in the workspace at startup and
+    /// publish what's found, open or not.
+    #[serde(default, alias = "checkOnInitialize")]
+    pub check_on_initialize: bool,
+    /// Whether a save runs ott again even if nothing it's given changed, as
+    /// what it reads besides, or ott itself, may have. Defaults to `true`.
+    #[serde(default, alias = "forceCheckOnSave")]
+    pub force_check_on_save: Option<bool>,
+    /// Whether to pipe an unsaved buffer checked on its own to ott, for it to
+    /// read as its stdin, rather than writing a copy of it to disk.
+    #[serde(default, alias = "useStdin")]
+    pub use_stdin: bool,
+    /// Whether to leave the copies of unsaved buffers handed to ott on disk,
+    /// logging where they are, rather than removing them after the check.
+    #[serde(default, alias = "keepTempFiles")]
+    pub keep_temp_files: bool,
+    /// The directory to write those copies to. Defaults to the system's.
+    #[serde(default, alias = "tempDir")]
+    pub temp_dir: Option<PathBuf>,
+    /// A regex for what counts as an identifier when looking for uses of
+    /// metavars and nonterminals. Defaults to `parse::IDENTIFIER`.
+    #[serde(default, alias = "identifierPattern")]
+    pub identifier_pattern: Option<String>,
+    /// Whether to check documents outside every workspace folder. Defaults
+    /// to `true`.
+    #[serde(default, alias = "checkOutsideWorkspace")]
+    pub check_outside_workspace: Option<bool>,
+    /// The most diagnostics to report for one document. Defaults to
+    /// `check::MAX_DIAGNOSTICS`.
+    #[serde(default, alias = "maxDiagnostics")]
+    pub max_diagnostics: Option<usize>,
+    /// What to do with the diagnostics past `max_diagnostics`.
+    #[serde(default, alias = "diagnosticsOverflow")]
+    pub diagnostics_overflow: Overflow,
+    /// Where to put a diagnostic ott gave a column but no line for, when the
+    /// column doesn't pick out a line itself.
+    #[serde(default, alias = "lineFallback")]
+    pub line_fallback: LineFallback,
+    /// What `ott-lsp.sortProductions` sorts productions by.
+    #[serde(default, alias = "productionOrder")]
+    pub production_order: ProductionOrder,
+    /// The conventions `ott-lsp.normalizeNames` renames declarations to.
+    #[serde(default)]
+    pub naming: Naming,
+    /// Whether an open document of the theory is reported on from a check of
+    /// another of its files that's already running, rather than checked on
+    /// its own. Defaults to `true` when a `theory` is set.
+    #[serde(default, alias = "batchTheoryOpens")]
+    pub batch_theory_opens: Option<bool>,
+    /// Any settings we don't know, like misspellings of those we do.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// A diagnostic severity, as named in settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    #[serde(alias = "info")]
+    Information,
+    Hint,
+}
+
+/// A lint's entry in the `lints` setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+pub struct LintSetting {
+    pub enabled: Option<bool>,
+    pub severity: Option<Severity>,
+}
+
+/// The conventions `ott-lsp.normalizeNames` renames declarations to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Naming {
+    pub metavars: Option<NameCase>,
+    pub nonterminals: Option<NameCase>,
+}
+
+/// A convention for the case of a name, as named in settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum NameCase {
+    /// `typvar`
+    #[serde(rename = "lowercase")]
+    Lower,
+    /// `TYPVAR`
+    #[serde(rename = "UPPERCASE")]
+    Upper,
+    /// `typ_var`
+    #[serde(rename = "snake_case")]
+    Snake,
+    /// `TypVar`
+    #[serde(rename = "CamelCase")]
+    Camel,
+}
+
+/// How to render ott's messages that span several lines, as named in settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageFormat {
+    /// Joined into one line.
+    #[default]
+    SingleLine,
+    /// With their line breaks.
+    MultiLine,
+}
+
+/// What to do with a document's diagnostics past the most to report, as named
+/// in settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Drop them, saying how many there were.
+    #[default]
+    Truncate,
+    /// Replace them with one diagnostic for each section they're in.
+    Summarize,
+}
+
+/// Where to put a diagnostic that has a column but no line, as named in
+/// settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineFallback {
+    /// On the line last edited, if known, as the likeliest culprit.
+    #[default]
+    Edit,
+    /// On the first line.
+    Start,
+}
+
+/// What to sort a grammar rule's productions by, as named in settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductionOrder {
+    /// The label after the second `::`.
+    #[default]
+    Label,
+    /// The words before the first `::`.
+    Body,
+}
+
+impl Severity {
+    /// The name for `severity`. Clients treat a missing one as an error.
+    pub fn of(severity: Option<DiagnosticSeverity>) -> Severity {
+        match severity {
+            Some(DiagnosticSeverity::WARNING) => Severity::Warning,
+            Some(DiagnosticSeverity::INFORMATION) => Severity::Information,
+            Some(DiagnosticSeverity::HINT) => Severity::Hint,
+            _ => Severity::Error,
+        }
+    }
+
+    pub fn diagnostic_severity(self) -> DiagnosticSeverity {
+        match self {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Information => DiagnosticSeverity::INFORMATION,
+            Severity::Hint => DiagnosticSeverity::HINT,
+        }
+    }
+}
+
+impl Config {
+    pub fn ott_path(&self) -> &str {
+        self.ott_path.as_deref().unwrap_or("ott")
+    }
+
+    pub fn max_parallel_checks(&self) -> usize {
+        self.max_parallel_checks
+            .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1)
+    }
+
+    /// This config with the files `ottFlags` includes with `-i` resolved
+    /// against the workspace `root`.
+    pub fn resolve_includes(mut self, root: Option<&Path>) -> Config {
+        self.ott_flags = theory::resolve_includes(&self.ott_flags, root);
+        self
+    }
+
+    /// Whether ott runs differently under `other`, so that results from
+    /// before the change from `self` can't be trusted.
+    pub fn runs_ott_differently(&self, other: &Config) -> bool {
+        self.ott_path != other.ott_path || self.ott_env != other.ott_env
+    }
+
+    /// A hash of the settings that change what a check reports, so that
+    /// documents are rechecked when one changes and only then.
+    pub fn diagnostics_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (&self.ott_path, &self.ott_flags, self.show_info, self.merge, &self.pre_check).hash(&mut hasher);
+        (self.picky_multiple_parses, self.max_output_bytes, &self.theory, self.ensure_trailing_newline).hash(&mut hasher);
+        self.check_timeout_ms.hash(&mut hasher);
+        (self.generic_failure_severity, &self.format_profile, self.merge_stderr, self.use_stdin).hash(&mut hasher);
+        (self.strict, self.strict_lints).hash(&mut hasher);
+        (self.lint_bindings, self.lint_metavars, self.lint_judgements, self.lint_targets, self.lint_whitespace).hash(&mut hasher);
+        (self.lint_conclusions, self.lint_unused, self.lint_unbound, &self.fresh_judgements).hash(&mut hasher);
+        self.lints.iter().collect::<BTreeMap<_, _>>().hash(&mut hasher);
+        self.ott_env.iter().collect::<BTreeMap<_, _>>().hash(&mut hasher);
+        (&self.known_targets, &self.published_severities, &self.language_ids, self.message_format).hash(&mut hasher);
+        (self.check_outside_workspace, self.max_diagnostics, self.diagnostics_overflow, self.relate_duplicates).hash(&mut hasher);
+        (self.annotate_pass, &self.hom_coverage, self.line_fallback, self.debug_diagnostic_data).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether `diagnostic` is one of the severities to report.
+    pub fn publishes(&self, diagnostic: &Diagnostic) -> bool {
+        self.published_severities.as_ref()
+            .is_none_or(|severities| severities.contains(&Severity::of(diagnostic.severity)))
+    }
+
+    /// Whether to check open documents with the language id `id`.
+    pub fn checks_language(&self, id: &str) -> bool {
+        self.language_ids.as_ref().map_or(id == "ott", |ids| ids.iter().any(|i| i == id))
+    }
+
+    /// Whether to check the document at `path`, given the workspace's `roots`.
+    pub fn checks_path(&self, path: &Path, roots: &[PathBuf]) -> bool {
+        self.check_outside_workspace.unwrap_or(true) || roots.iter().any(|root| path.starts_with(root))
+    }
+
+    pub fn batches_theory_opens(&self) -> bool {
+        self.batch_theory_opens.unwrap_or(!self.theory.is_empty())
+    }
+
+    pub fn max_diagnostics(&self) -> usize {
+        self.max_diagnostics.unwrap_or(check::MAX_DIAGNOSTICS)
+    }
+
+    pub fn max_output_bytes(&self) -> usize {
+        self.max_output_bytes.unwrap_or(8 << 20)
+    }
+
+    pub fn check_timeout(&self) -> Option<Duration> {
+        self.check_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn generic_failure_severity(&self) -> DiagnosticSeverity {
+        self.generic_failure_severity.map_or(DiagnosticSeverity::ERROR, Severity::diagnostic_severity)
+    }
+
+    pub fn merge_stderr(&self) -> bool {
+        self.merge_stderr.unwrap_or(true)
+    }
+
+    pub fn ensure_trailing_newline(&self) -> bool {
+        self.ensure_trailing_newline.unwrap_or(true)
+    }
+
+    pub fn align_separators(&self) -> bool {
+        self.align_separators.unwrap_or(true)
+    }
+
+    pub fn inlay_hints(&self) -> bool {
+        self.inlay_hints.unwrap_or(true)
+    }
+
+    pub fn force_check_on_save(&self) -> bool {
+        self.force_check_on_save.unwrap_or(true)
+    }
+
+    pub fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// The configured identifier pattern or, if it isn't valid, the default.
+    pub fn identifier_pattern(&self) -> Regex {
+        self.identifier_pattern.as_deref()
+            .and_then(|pattern| Regex::new(pattern).ok())
+            .unwrap_or_else(|| Regex::new(parse::IDENTIFIER).expect("default identifier pattern is valid"))
+    }
+
+    /// The flags to pass ott when generating `target`, on top of the usual.
+    pub fn generation_args(&self, target: &str) -> Vec<String> {
+        let options = [("-tex_wrap", self.tex_wrap), ("-tex_show_meta", self.tex_show_meta)];
+        options.into_iter()
+            .filter(|_| target == "tex")
+            .filter_map(|(flag, value)| Some([flag.to_string(), value?.to_string()]))
+            .flatten()
+            .collect()
+    }
+
+    pub fn respect_gitignore(&self) -> bool {
+        self.respect_gitignore.unwrap_or(true)
+    }
+
+    pub fn config_grace(&self) -> Duration {
+        Duration::from_millis(self.config_grace_ms.unwrap_or(200))
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms.unwrap_or(300))
+    }
+
+    pub fn idle_reindex(&self) -> Option<Duration> {
+        self.idle_reindex_ms.filter(|&ms| ms > 0).map(Duration::from_millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn unknown_settings_are_kept_aside_but_aliases_arent_unknown() {
+        let settings = json!({ "ottPath": "ott2", "ott_flags": ["-merge"], "ottFlag": ["-tex_wrap"], "showinfo": true });
+        let config: Config = serde_json::from_value(settings).unwrap();
+
+        assert_eq!(config.ott_path.as_deref(), Some("ott2"));
+        assert_eq!(config.ott_flags, ["-merge"]);
+        assert!(!config.show_info);
+        let unknown: Vec<_> = config.unknown.keys().map(String::as_str).collect();
+        assert_eq!(unknown, ["ottFlag", "showinfo"]);
+        assert_eq!(config.unknown["ottFlag"], json!(["-tex_wrap"]));
+    }
+}
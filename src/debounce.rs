@@ -0,0 +1,86 @@
+//! Jobs held back until they've gone a while without being pushed again.
+//!
+//! Like [`crate::pool::Pool`], this doesn't own a thread. [`Debounce::push()`]
+//! tells the caller when to spawn a timer, which takes jobs with
+//! [`Debounce::next()`] as they come due until it returns `None`. There's
+//! only ever one timer.
+
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+pub struct Debounce<T> {
+    state: Mutex<State<T>>,
+    wake: Condvar,
+}
+
+struct State<T> {
+    /// Each job, with when it comes due.
+    pending: Vec<(T, Instant)>,
+    timer: bool,
+    shutdown: bool,
+}
+
+impl<T: PartialEq> Debounce<T> {
+    pub fn new() -> Self {
+        Debounce {
+            state: Mutex::new(State { pending: vec![], timer: false, shutdown: false }),
+            wake: Condvar::new(),
+        }
+    }
+
+    /// Holds `job` until `delay` from now, putting it off that long again if
+    /// it's already held. Returns `true` if there's no timer, in which case
+    /// the caller must spawn one that takes jobs via [`Debounce::next()`].
+    pub fn push(&self, job: T, delay: Duration) -> bool {
+        let mut state = self.state.lock();
+        if state.shutdown {
+            return false;
+        }
+
+        let due = Instant::now() + delay;
+        state.pending.retain(|(pending, _)| *pending != job);
+        state.pending.push((job, due));
+        self.wake.notify_all();
+
+        let spawn = !state.timer;
+        state.timer = true;
+        spawn
+    }
+
+    /// Drops `job` if it's held, as when it's been done some other way.
+    pub fn cancel(&self, job: &T) {
+        self.state.lock().pending.retain(|(pending, _)| pending != job);
+    }
+
+    /// Waits for the next job to come due and returns it. A `None` means
+    /// there's nothing left to wait for, and the timer must exit.
+    pub fn next(&self) -> Option<T> {
+        let mut state = self.state.lock();
+        loop {
+            let next = state.pending.iter()
+                .enumerate()
+                .min_by_key(|(_, (_, due))| *due)
+                .map(|(i, (_, due))| (i, *due));
+
+            match next {
+                Some((i, due)) if !state.shutdown && due <= Instant::now() => return Some(state.pending.remove(i).0),
+                Some((_, due)) if !state.shutdown => {
+                    self.wake.wait_until(&mut state, due);
+                }
+                _ => {
+                    state.timer = false;
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Drops every held job, refuses new ones, and retires the timer.
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock();
+        state.shutdown = true;
+        state.pending.clear();
+        self.wake.notify_all();
+    }
+}
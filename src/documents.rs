@@ -0,0 +1,167 @@
+//! The documents the client has open, shared by every handler.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use lsp_types::{Position, Uri};
+use parking_lot::RwLock;
+
+use ott_lsp::parse::{self, Document, Parse};
+
+/// An open document, as the client last sent it.
+#[derive(Clone)]
+pub struct DocumentState {
+    pub text: String,
+    pub version: i32,
+    pub language_id: String,
+    /// Where the last change that changed anything first changed the text,
+    /// if there's been one.
+    pub last_edit: Option<Position>,
+    /// The parse of `text`, made the first time anything asks for it, or
+    /// from the parse of the previous version, if there was one, when only
+    /// one section of the document changed.
+    parsed: OnceLock<Parse>,
+}
+
+impl DocumentState {
+    fn new(text: String, version: i32, language_id: String) -> Self {
+        DocumentState { text, version, language_id, last_edit: None, parsed: OnceLock::new() }
+    }
+
+    /// The parse of this version of the document.
+    pub fn parsed(&self) -> Arc<Document> {
+        self.parsed.get_or_init(|| Parse::new(&self.text)).document.clone()
+    }
+}
+
+/// Every open document. Each version is parsed at most once, however many
+/// requests ask about it.
+#[derive(Default)]
+pub struct Documents(RwLock<HashMap<Uri, DocumentState>>);
+
+impl Documents {
+    pub fn open(&self, uri: Uri, text: String, version: i32, language_id: String) {
+        self.0.write().insert(uri, DocumentState::new(text, version, language_id));
+    }
+
+    /// Replaces the text of `uri`, if it's open, with its `version`.
+    pub fn change(&self, uri: &Uri, text: String, version: i32) {
+        if let Some(doc) = self.0.write().get_mut(uri) {
+            let last_edit = first_difference(&doc.text, &text).or(doc.last_edit);
+            let parsed = doc.parsed.get().and_then(|parse| parse.edit(&doc.text, &text));
+            *doc = DocumentState::new(text, version, std::mem::take(&mut doc.language_id));
+            doc.last_edit = last_edit;
+            if let Some(parsed) = parsed {
+                let _ = doc.parsed.set(parsed);
+            }
+        }
+    }
+
+    pub fn close(&self, uri: &Uri) {
+        self.0.write().remove(uri);
+    }
+
+    /// What `f` makes of `uri`, if it's open.
+    pub fn with<T>(&self, uri: &Uri, f: impl FnOnce(&DocumentState) -> T) -> Option<T> {
+        self.0.read().get(uri).map(f)
+    }
+
+    /// A copy of `uri` as it is now, already parsed, if it's open.
+    pub fn get(&self, uri: &Uri) -> Option<DocumentState> {
+        self.with(uri, |doc| {
+            doc.parsed();
+            doc.clone()
+        })
+    }
+
+    /// The parse of the current text of `uri`, if it's open.
+    pub fn parsed(&self, uri: &Uri) -> Option<Arc<Document>> {
+        self.with(uri, |doc| doc.parsed())
+    }
+
+    /// The current text of `uri` and its parse, both empty if it isn't open.
+    pub fn snapshot(&self, uri: &Uri) -> (String, Arc<Document>) {
+        self.with(uri, |doc| (doc.text.clone(), doc.parsed())).unwrap_or_default()
+    }
+
+    pub fn uris(&self) -> Vec<Uri> {
+        self.0.read().keys().cloned().collect()
+    }
+
+    /// Calls `f` with every open document. `f` mustn't open, change, or close
+    /// any.
+    pub fn for_each(&self, mut f: impl FnMut(&Uri, &DocumentState)) {
+        for (uri, doc) in self.0.read().iter() {
+            f(uri, doc);
+        }
+    }
+}
+
+/// Where in `new` it first differs from `old`, if it does.
+fn first_difference(old: &str, new: &str) -> Option<Position> {
+    let offset = old.char_indices()
+        .zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        .or_else(|| (old.len() != new.len()).then(|| old.len().min(new.len())))?;
+
+    let before = &new[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some(Position::new(before.matches('\n').count() as u32, parse::utf16_len(&before[line_start..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAMMAR: &str = "metavar termvar, x ::=\ngrammar\nt :: 't_' ::=\n  | x :: :: var\n";
+
+    fn uri() -> Uri {
+        "file:///x.ott".parse().unwrap()
+    }
+
+    #[test]
+    fn each_version_is_parsed_once() {
+        let documents = Documents::default();
+        documents.open(uri(), GRAMMAR.into(), 1, "ott".into());
+        let parsed = documents.parsed(&uri()).unwrap();
+        assert!(Arc::ptr_eq(&parsed, &documents.parsed(&uri()).unwrap()));
+        assert!(Arc::ptr_eq(&parsed, &documents.snapshot(&uri()).1));
+        assert_eq!(parsed.grammars[0].productions[0].id, "t_var");
+
+        let changed = GRAMMAR.replace(":: var", ":: variable");
+        documents.change(&uri(), changed.clone(), 2);
+        let reparsed = documents.parsed(&uri()).unwrap();
+        assert!(!Arc::ptr_eq(&parsed, &reparsed));
+        assert_eq!(reparsed.grammars[0].productions[0].id, "t_variable");
+        assert_eq!(documents.snapshot(&uri()).0, changed);
+    }
+
+    #[test]
+    fn changes_keep_the_language_and_where_the_text_last_changed() {
+        let documents = Documents::default();
+        documents.open(uri(), GRAMMAR.into(), 1, "ott".into());
+        assert_eq!(documents.with(&uri(), |doc| doc.last_edit), Some(None));
+
+        documents.change(&uri(), GRAMMAR.replace("| x", "| 𝔸 x"), 2);
+        let (version, language_id, last_edit) = documents.with(&uri(), |doc| (doc.version, doc.language_id.clone(), doc.last_edit)).unwrap();
+        assert_eq!((version, language_id.as_str(), last_edit), (2, "ott", Some(Position::new(3, 4))));
+
+        // A change that changes nothing leaves it where it was.
+        documents.change(&uri(), GRAMMAR.replace("| x", "| 𝔸 x"), 3);
+        assert_eq!(documents.with(&uri(), |doc| doc.last_edit), Some(Some(Position::new(3, 4))));
+
+        documents.close(&uri());
+        assert!(documents.get(&uri()).is_none());
+        assert_eq!(documents.snapshot(&uri()).0, "");
+        documents.change(&uri(), GRAMMAR.into(), 4);
+        assert!(documents.uris().is_empty());
+    }
+
+    #[test]
+    fn first_difference_is_in_utf16() {
+        assert_eq!(first_difference("𝔸 x\n", "𝔸 y\n"), Some(Position::new(0, 3)));
+        assert_eq!(first_difference("a\nb", "a\nbc"), Some(Position::new(1, 1)));
+        assert_eq!(first_difference("a\nb", "a\nb"), None);
+    }
+}
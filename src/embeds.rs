@@ -0,0 +1,39 @@
+//! The `ott-lsp/embedRegions` notification, which tells the client where the
+//! code in each `embed` block is and what language it's in, so it can
+//! highlight the block as that language.
+
+use lsp_types::{Range, Uri};
+use serde::Serialize;
+
+use crate::parse::Document;
+
+pub const EMBED_REGIONS: &str = "ott-lsp/embedRegions";
+
+#[derive(Debug, Serialize)]
+pub struct EmbedRegionsParams {
+    pub uri: Uri,
+    pub regions: Vec<EmbedRegion>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbedRegion {
+    /// The embed's target: `tex`, `coq`, `isa`, `hol`, `lem`, and so on.
+    pub target: String,
+    /// The whole `{{ ... }}` block.
+    pub range: Range,
+    /// Just the embedded code, after the target and before the `}}`.
+    pub content_range: Range,
+}
+
+pub fn embed_regions(doc: &Document, uri: Uri) -> EmbedRegionsParams {
+    let regions = doc.embeds.iter()
+        .map(|embed| EmbedRegion {
+            target: embed.target.clone(),
+            range: embed.range,
+            content_range: embed.content,
+        })
+        .collect();
+
+    EmbedRegionsParams { uri, regions }
+}
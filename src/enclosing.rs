@@ -0,0 +1,127 @@
+//! The top-level block a position is in, for clients' "select block"
+//! commands and breadcrumbs.
+
+use lsp_types::*;
+use serde::Serialize;
+
+use crate::parse::{self, Document};
+
+/// The method of the request for the enclosing block.
+pub const ENCLOSING_BLOCK: &str = "ott-lsp/enclosingBlock";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnclosingBlock {
+    /// The keyword the block begins with: `metavar`, `grammar`, `defns`,
+    /// `embed`, `funs`, `subrules`, and so on.
+    pub kind: String,
+    /// From the block's keyword to the end of its last line that isn't
+    /// blank.
+    pub range: Range,
+    /// The production of a `grammar` block, or the rule of a `defns` block,
+    /// that the position is in, if any.
+    pub item: Option<Range>,
+}
+
+/// The block of `text` that `position` is in, or `None` if it's before the
+/// first block or in the blank lines after one.
+pub fn enclosing_block(doc: &Document, text: &str, position: Position) -> Option<EnclosingBlock> {
+    let lines: Vec<_> = text.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    let blank = |line: u32| lines.get(line as usize).is_none_or(|line| line.trim().is_empty());
+    let blocks = parse::blocks(text);
+    let block = blocks.iter().find(|block| block.lines.contains(&position.line))?;
+    let end = (block.lines.start..block.lines.end.min(lines.len() as u32)).rev().find(|&line| !blank(line))?;
+    if position.line > end {
+        return None;
+    }
+
+    let line_end = |line: u32| Position::new(line, parse::utf16_len(lines[line as usize]));
+    let range = Range::new(Position::new(block.lines.start, 0), line_end(end));
+    let within = |range: &Range| range.start.line <= position.line && position.line <= range.end.line;
+    let item = match block.keyword {
+        "grammar" => doc.grammars.iter().flat_map(|rule| &rule.productions)
+            .map(|production| production.range)
+            .find(within),
+        // A rule is the paragraph around its line of dashes: its premises
+        // above, up to a blank line, and its conclusion below.
+        "defns" => doc.defns.iter().flat_map(|d| &d.defns).flat_map(|defn| &defn.rules)
+            .map(|rule| {
+                let dashes = rule.name.range.start.line;
+                let first = (0..dashes).rev().take_while(|&line| !blank(line)).last().unwrap_or(dashes);
+                let last = if blank(dashes + 1) { dashes } else { dashes + 1 };
+                Range::new(Position::new(first, 0), line_end(last))
+            })
+            .find(within),
+        _ => None,
+    };
+
+    Some(EnclosingBlock { kind: block.keyword.to_string(), range, item })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEORY: &str = r"% Variables.
+metavar x ::=
+
+grammar
+t :: 't_' ::=
+  | x :: :: var
+  | t t :: :: app
+
+
+defns
+Jop :: '' ::=
+
+defn
+t done :: :: done :: D_ by
+
+t done
+----- :: twice
+t done
+
+----- :: ax
+x done
+
+embed {{ tex \foo }}
+";
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    /// The kind, range, and item of the block at `line` and `character`.
+    fn enclosing(line: u32, character: u32) -> Option<(String, Range, Option<Range>)> {
+        let block = enclosing_block(&Document::parse(THEORY), THEORY, Position::new(line, character))?;
+        Some((block.kind, block.range, block.item))
+    }
+
+    #[test]
+    fn blocks_run_from_their_keyword_to_their_last_line() {
+        assert_eq!(enclosing(1, 3), Some(("metavar".into(), range((1, 0), (1, 13)), None)));
+        let grammar = range((3, 0), (6, 17));
+        assert_eq!(enclosing(3, 0), Some(("grammar".into(), grammar, None)));
+        assert_eq!(enclosing(6, 17), Some(("grammar".into(), grammar, Some(range((6, 2), (6, 17))))));
+        assert_eq!(enclosing(22, 5), Some(("embed".into(), range((22, 0), (22, 20)), None)));
+
+        // Comments before the first block, and blank lines after each, are
+        // in none.
+        for line in [0, 2, 7, 8, 21, 23] {
+            assert_eq!(enclosing(line, 0), None, "line {line}");
+        }
+    }
+
+    #[test]
+    fn items_are_the_production_or_rule_the_position_is_in() {
+        let defns = range((9, 0), (20, 6));
+        assert_eq!(enclosing(5, 4), Some(("grammar".into(), range((3, 0), (6, 17)), Some(range((5, 2), (5, 15))))));
+        assert_eq!(enclosing(15, 0), Some(("defns".into(), defns, Some(range((15, 0), (17, 6))))));
+        assert_eq!(enclosing(17, 6), Some(("defns".into(), defns, Some(range((15, 0), (17, 6))))));
+        assert_eq!(enclosing(19, 0), Some(("defns".into(), defns, Some(range((19, 0), (20, 6))))));
+
+        // Between rules, and in a judgement's header, is in the block alone.
+        assert_eq!(enclosing(18, 0), Some(("defns".into(), defns, None)));
+        assert_eq!(enclosing(13, 3), Some(("defns".into(), defns, None)));
+    }
+}
@@ -0,0 +1,55 @@
+//! Folding ranges: runs of `%` comment lines, like banners between sections,
+//! that are long enough to be worth collapsing.
+
+use lsp_types::*;
+
+use crate::parse::{self, TokenKind};
+
+/// The fewest lines a run of comments must span to be folded: shorter ones
+/// take up barely more room than the fold would.
+const MIN_COMMENT_LINES: u32 = 3;
+
+/// A range for each run of consecutive lines of `text` that are comments
+/// alone, as opposed to comments after something else on their line, of at
+/// least `MIN_COMMENT_LINES` lines.
+pub fn comment_ranges(text: &str) -> Vec<FoldingRange> {
+    let lines: Vec<_> = parse::tokenize(text).into_iter()
+        .filter(|token| token.kind == TokenKind::Comment && token.line_start)
+        .map(|token| token.range.start.line)
+        .collect();
+
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for line in lines {
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => runs.push((line, line)),
+        }
+    }
+
+    runs.into_iter()
+        .filter(|(start, end)| end - start + 1 >= MIN_COMMENT_LINES)
+        .map(|(start_line, end_line)| FoldingRange {
+            start_line,
+            end_line,
+            kind: Some(FoldingRangeKind::Comment),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_runs_of_comment_lines_fold() {
+        let text = "%%%%%%%%%%%%\n% Syntax\n%%%%%%%%%%%%\n\n% Term variables.\n% Just the one.\nmetavar x ::= % a\n  % b\n  % c\n";
+        let folds: Vec<_> = comment_ranges(text).into_iter().map(|f| (f.start_line, f.end_line, f.kind)).collect();
+        assert_eq!(folds, [(0, 2, Some(FoldingRangeKind::Comment))]);
+
+        // A comment after something else on its line isn't one alone.
+        let text = "metavar x ::= % a\n% b\n% c\n  % d\n";
+        let folds: Vec<_> = comment_ranges(text).into_iter().map(|f| (f.start_line, f.end_line)).collect();
+        assert_eq!(folds, [(1, 3)]);
+    }
+}
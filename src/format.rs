@@ -0,0 +1,54 @@
+//! Formatting as the user types: lengthening a rule's line of dashes to span
+//! its premises and conclusion.
+
+use lsp_types::*;
+
+use crate::parse;
+
+/// The character that triggers formatting.
+pub const TRIGGER: &str = "-";
+
+/// The fewest dashes a line can start with to be taken for a rule's line of
+/// dashes rather than, say, an arrow being typed.
+const MIN_DASHES: usize = 3;
+
+/// Lengthens the line of dashes at `position` in a `defns` block to span the
+/// widest of the rule's premises and its conclusion. Dashes are only ever
+/// added: shortening them under the user's cursor would fight their typing.
+pub fn align_separator(text: &str, position: Position) -> Option<Vec<TextEdit>> {
+    let blocks = parse::blocks(text);
+    blocks.iter().find(|b| b.keyword == "defns" && b.lines.contains(&position.line))?;
+
+    // Dashes in a hom are code for some other language.
+    if parse::embed_context_at(text, position).is_some() {
+        return None;
+    }
+
+    let lines: Vec<_> = text.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    let line = *lines.get(position.line as usize)?;
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+    let dashes = trimmed.len() - trimmed.trim_start_matches('-').len();
+    let rest = &trimmed[dashes..];
+    if dashes < MIN_DASHES || !(rest.is_empty() || rest.starts_with([' ', '\t', ':'])) {
+        return None;
+    }
+
+    // The premises are the lines just above, up to a blank one; the
+    // conclusion, the line just below. Comments don't count.
+    let is_rule_line = |line: &&&str| !line.trim().is_empty();
+    let above = lines[..position.line as usize].iter().rev().take_while(is_rule_line);
+    let below = lines.get(position.line as usize + 1).into_iter().take_while(is_rule_line);
+    let width = above.chain(below)
+        .filter(|line| !line.trim_start().starts_with('%'))
+        .map(|line| parse::utf16_len(line.trim_end()))
+        .max()?;
+
+    let have = parse::utf16_len(indent) + dashes as u32;
+    if width <= have {
+        return None;
+    }
+
+    let end = Position::new(position.line, have);
+    Some(vec![TextEdit::new(Range::new(end, end), "-".repeat((width - have) as usize))])
+}
@@ -0,0 +1,150 @@
+//! The dependency graph of a theory's nonterminals and judgements, for
+//! clients to draw.
+
+use lsp_types::*;
+use serde::{Deserialize, Serialize};
+
+use crate::navigation::is_use;
+use crate::parse::{Document, Name};
+
+/// The method of the request for the graph.
+pub const DEPENDENCY_GRAPH: &str = "ott-lsp/dependencyGraph";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Nonterminal,
+    Judgement,
+}
+
+/// A nonterminal or judgement, identified by its kind and name, like
+/// `nonterminal:t` or `judgement:GtT`.
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: NodeKind,
+    pub name: String,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// A nonterminal's productions, or a judgement's form, use `to`.
+    Uses,
+    /// A rule of judgement `from` has a premise of judgement `to`.
+    Premise,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyGraphParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Whether to graph every file of the document's theory rather than the
+    /// document alone.
+    #[serde(default)]
+    pub whole_theory: bool,
+}
+
+fn id(kind: NodeKind, name: &str) -> String {
+    match kind {
+        NodeKind::Nonterminal => format!("nonterminal:{name}"),
+        NodeKind::Judgement => format!("judgement:{name}"),
+    }
+}
+
+/// The graph of the nonterminals and judgements declared in `docs`. Edges
+/// only join nodes in the graph: uses of nonterminals declared elsewhere, and
+/// premises of no known judgement, are left out.
+pub fn dependency_graph(docs: &[(Uri, Document)]) -> DependencyGraph {
+    let all = || docs.iter().map(|(_, doc)| doc);
+    let nonterminals: Vec<_> = all().flat_map(|doc| &doc.grammars).flat_map(|rule| &rule.names).collect();
+    let metavars: Vec<_> = all().flat_map(|doc| &doc.metavars).flat_map(|m| &m.names).collect();
+    let judgements: Vec<_> = all().flat_map(|doc| &doc.defns).flat_map(|d| &d.defns).collect();
+
+    // The nonterminal a word is a use of, by its first name, as in
+    // `navigation::declaration`, unless a metavar declares it.
+    let nonterminal_of = |word: &Name| -> Option<String> {
+        let rule = all().flat_map(|doc| &doc.grammars)
+            .flat_map(|rule| rule.names.iter().map(move |name| (rule, name)))
+            .filter(|(_, name)| is_use(&word.text, &name.text))
+            .max_by_key(|(_, name)| name.text.len())?;
+
+        let metavar = metavars.iter().any(|m| is_use(&word.text, &m.text) && m.text.len() > rule.1.text.len());
+        (!metavar).then(|| rule.0.names[0].text.clone())
+    };
+
+    // Forms are compared by their terminals alone, as the judgement lint does.
+    let terminals = |words: &[Name]| -> Vec<String> {
+        words.iter()
+            .filter(|word| {
+                let declared = |names: &[&Name]| names.iter().any(|n| is_use(&word.text, &n.text));
+                !declared(&nonterminals) && !declared(&metavars)
+            })
+            .map(|word| word.text.clone())
+            .collect()
+    };
+
+    let forms: Vec<_> = judgements.iter().map(|defn| (terminals(&defn.form), &defn.name.text)).collect();
+
+    let (mut nodes, mut edges) = (vec![], vec![]);
+    let mut edge = |from: String, to: String, kind| {
+        let edge = Edge { from, to, kind };
+        if !edges.contains(&edge) {
+            edges.push(edge);
+        }
+    };
+
+    for (uri, doc) in docs {
+        for rule in doc.grammars.iter().filter(|rule| !rule.names.is_empty()) {
+            let from = id(NodeKind::Nonterminal, &rule.names[0].text);
+            for word in rule.productions.iter().flat_map(|p| &p.body) {
+                if let Some(to) = nonterminal_of(word) {
+                    edge(from.clone(), id(NodeKind::Nonterminal, &to), EdgeKind::Uses);
+                }
+            }
+        }
+
+        for defn in doc.defns.iter().flat_map(|d| &d.defns) {
+            let from = id(NodeKind::Judgement, &defn.name.text);
+            for word in &defn.form {
+                if let Some(to) = nonterminal_of(word) {
+                    edge(from.clone(), id(NodeKind::Nonterminal, &to), EdgeKind::Uses);
+                }
+            }
+
+            for premise in defn.rules.iter().flat_map(|rule| &rule.premises) {
+                let used = terminals(premise);
+                if let Some((_, to)) = forms.iter().find(|(form, _)| *form == used) {
+                    edge(from.clone(), id(NodeKind::Judgement, to), EdgeKind::Premise);
+                }
+            }
+        }
+
+        let location = |name: &Name| Location::new(uri.clone(), name.range);
+        for name in doc.grammars.iter().filter_map(|rule| rule.names.first()) {
+            let (kind, name, location) = (NodeKind::Nonterminal, name.text.clone(), location(name));
+            nodes.push(Node { id: id(kind, &name), kind, name, location });
+        }
+
+        for defn in doc.defns.iter().flat_map(|d| &d.defns) {
+            let (kind, name, location) = (NodeKind::Judgement, defn.name.text.clone(), location(&defn.name));
+            nodes.push(Node { id: id(kind, &name), kind, name, location });
+        }
+    }
+
+    DependencyGraph { nodes, edges }
+}
@@ -0,0 +1,147 @@
+//! The type hierarchy of a document's nonterminals, as ordered by its
+//! `subrules`: a nonterminal's supertypes are the nonterminals it is a
+//! subgrammar of, and its subtypes, those that are subgrammars of it.
+
+use lsp_types::*;
+use regex::Regex;
+
+use crate::navigation::declaration;
+use crate::parse::{self, Document, GrammarRule};
+
+/// The index in `doc.grammars` of the nonterminal `word` is a use of.
+fn rule_of(doc: &Document, word: &str) -> Option<usize> {
+    let name = declaration(doc, word)?;
+    doc.grammars.iter().position(|rule| rule.names.iter().any(|n| n.range == name.range))
+}
+
+/// The nonterminals reachable from `start` by one or more `subrules`, going
+/// up to supertypes or down to subtypes, not counting `start` itself.
+fn closure(doc: &Document, start: usize, up: bool) -> Vec<usize> {
+    let edges: Vec<_> = doc.subrules.iter()
+        .filter_map(|s| Some((rule_of(doc, &s.sub.text)?, rule_of(doc, &s.sup.text)?)))
+        .map(|(sub, sup)| if up { (sub, sup) } else { (sup, sub) })
+        .collect();
+
+    let mut reached = vec![start];
+    let mut i = 0;
+    while let Some(&from) = reached.get(i) {
+        for &(_, to) in edges.iter().filter(|(f, _)| *f == from) {
+            if !reached.contains(&to) {
+                reached.push(to);
+            }
+        }
+
+        i += 1;
+    }
+
+    reached.remove(0);
+    reached
+}
+
+/// The nonterminals just above or below `start`: those in its closure that
+/// aren't reachable through another in it, so that a client expanding the
+/// hierarchy a level at a time sees each nonterminal where it belongs, even
+/// when a `subrules` section spells out a relation that follows from others.
+fn adjacent(doc: &Document, start: usize, up: bool) -> Vec<usize> {
+    let all = closure(doc, start, up);
+    let beyond: Vec<_> = all.iter().flat_map(|&other| closure(doc, other, up)).collect();
+    all.iter().copied().filter(|rule| !beyond.contains(rule)).collect()
+}
+
+fn item(rule: &GrammarRule, uri: &Uri) -> TypeHierarchyItem {
+    let name = &rule.names[0];
+    let end = rule.productions.iter().map(|p| p.label.range.end).fold(name.range.end, Position::max);
+    let synonyms: Vec<_> = rule.names[1..].iter().map(|name| name.text.as_str()).collect();
+    TypeHierarchyItem {
+        name: name.text.clone(),
+        kind: SymbolKind::CLASS,
+        tags: None,
+        detail: (!synonyms.is_empty()).then(|| synonyms.join(", ")),
+        uri: uri.clone(),
+        range: Range::new(name.range.start, end),
+        selection_range: name.range,
+        data: None,
+    }
+}
+
+/// The nonterminal at `position` in `doc`, whose text is `text` and whose
+/// identifiers match `identifier`, as an item of the hierarchy.
+pub fn prepare(
+    doc: &Document,
+    text: &str,
+    uri: &Uri,
+    position: Position,
+    identifier: &Regex,
+) -> Option<Vec<TypeHierarchyItem>> {
+    let name = parse::name_at(text, position, identifier)?;
+    let rule = rule_of(doc, &name.text)?;
+    Some(vec![item(&doc.grammars[rule], uri)])
+}
+
+/// The nonterminals `of` is a subgrammar of, or with `up` false, those that
+/// are subgrammars of it, a level at a time.
+pub fn related(doc: &Document, of: &TypeHierarchyItem, up: bool) -> Vec<TypeHierarchyItem> {
+    let Some(start) = doc.grammars.iter().position(|rule| rule.names.first().is_some_and(|n| n.text == of.name)) else {
+        return vec![];
+    };
+
+    adjacent(doc, start, up).into_iter()
+        .map(|rule| item(&doc.grammars[rule], &of.uri))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chain of subrules, `v <:: e <:: t`, with what follows from it
+    /// spelled out too.
+    const CHAIN: &str = r"metavar x ::=
+grammar
+t :: 't_' ::=
+  | x :: :: var
+e , f :: 'e_' ::=
+  | x :: :: var
+v :: 'v_' ::=
+  | x :: :: var
+subrules
+v <:: e
+e <:: t
+v <:: t
+";
+
+    fn prepared(line: u32, character: u32) -> Option<TypeHierarchyItem> {
+        let (identifier, uri) = (Regex::new(parse::IDENTIFIER).unwrap(), "file:///x.ott".parse().unwrap());
+        let items = prepare(&Document::parse(CHAIN), CHAIN, &uri, Position::new(line, character), &identifier)?;
+        items.into_iter().next()
+    }
+
+    fn names(items: Vec<TypeHierarchyItem>) -> Vec<String> {
+        items.into_iter().map(|item| item.name).collect()
+    }
+
+    #[test]
+    fn nonterminals_are_prepared_wherever_theyre_named() {
+        let v = prepared(9, 0).unwrap();
+        assert_eq!((v.name.as_str(), v.detail), ("v", None));
+        assert_eq!(v.range, Range::new(Position::new(6, 0), Position::new(7, 15)));
+        assert_eq!(v.selection_range, Range::new(Position::new(6, 0), Position::new(6, 1)));
+
+        // By a synonym, as the rule it's a name of.
+        let e = prepared(4, 4).unwrap();
+        assert_eq!((e.name.as_str(), e.detail.as_deref()), ("e", Some("f")));
+        assert_eq!(prepared(3, 4), None);
+    }
+
+    #[test]
+    fn supertypes_and_subtypes_are_a_level_at_a_time() {
+        let doc = Document::parse(CHAIN);
+        let [t, e, v] = [(2, 0), (4, 0), (6, 0)].map(|(line, character)| prepared(line, character).unwrap());
+        assert_eq!(names(related(&doc, &v, true)), ["e"]);
+        assert_eq!(names(related(&doc, &e, true)), ["t"]);
+        assert_eq!(names(related(&doc, &t, true)), Vec::<String>::new());
+        assert_eq!(names(related(&doc, &t, false)), ["e"]);
+        assert_eq!(names(related(&doc, &e, false)), ["v"]);
+        assert_eq!(names(related(&doc, &v, false)), Vec::<String>::new());
+    }
+}
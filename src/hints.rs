@@ -0,0 +1,85 @@
+//! Inlay hints: each rule's full name at the start of its line of dashes, so
+//! that long derivations can be scanned by rule.
+
+use lsp_types::*;
+
+use crate::parse::{self, Document};
+
+/// A hint with the name of each rule whose line of dashes is in `range`, the
+/// name prefixed with its judgement's prefix, as ott and its backends call it.
+pub fn rule_names(doc: &Document, text: &str, range: Range) -> Vec<InlayHint> {
+    let lines: Vec<_> = text.split('\n').collect();
+    doc.defns.iter().flat_map(|d| &d.defns).flat_map(|defn| &defn.rules)
+        .filter(|rule| range.start.line <= rule.name.range.start.line && rule.name.range.start.line <= range.end.line)
+        .filter_map(|rule| {
+            let line = lines.get(rule.name.range.start.line as usize)?;
+            let indent = &line[..line.len() - line.trim_start().len()];
+            Some(InlayHint {
+                position: Position::new(rule.name.range.start.line, parse::utf16_len(indent)),
+                label: InlayHintLabel::String(rule.id.clone()),
+                kind: None,
+                text_edits: None,
+                tooltip: rule.doc.clone().map(InlayHintTooltip::String),
+                padding_left: None,
+                padding_right: Some(true),
+                data: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r"defns
+Jop :: '' ::=
+
+defn
+t --> t' :: :: reduce :: E_ by
+
+% Steps.
+  ------ :: step
+  t --> t
+
+----- :: refl
+t --> t
+
+defn
+t value :: :: value :: V_ by
+
+	----- :: unit
+unit value
+";
+
+    /// The position and label of each hint on `range`'s lines.
+    fn hinted(start: u32, end: u32) -> Vec<(Position, String, Option<String>)> {
+        let range = Range::new(Position::new(start, 0), Position::new(end, 0));
+        rule_names(&Document::parse(RULES), RULES, range).into_iter()
+            .map(|hint| {
+                let InlayHintLabel::String(label) = hint.label else { panic!("a string label") };
+                let tooltip = hint.tooltip.map(|tooltip| match tooltip {
+                    InlayHintTooltip::String(tooltip) => tooltip,
+                    InlayHintTooltip::MarkupContent(content) => content.value,
+                });
+
+                (hint.position, label, tooltip)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_rules_name_is_hinted_at_its_line_of_dashes() {
+        assert_eq!(hinted(0, 20), [
+            (Position::new(7, 2), "E_step".into(), Some("Steps.".into())),
+            (Position::new(10, 0), "E_refl".into(), None),
+            (Position::new(16, 1), "V_unit".into(), None),
+        ]);
+    }
+
+    #[test]
+    fn only_the_rules_in_range_are_hinted() {
+        assert_eq!(hinted(8, 10), [(Position::new(10, 0), "E_refl".into(), None)]);
+        assert_eq!(hinted(11, 15), []);
+    }
+}
@@ -0,0 +1,95 @@
+//! Code lenses above each `defns` block and judgement counting its rules,
+//! for a sense of the size of a semantics at a glance.
+
+use lsp_types::*;
+use serde::{Deserialize, Serialize};
+
+use crate::commands;
+use crate::parse::Document;
+
+/// What a lens is about, kept in its `data` until it's resolved.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LensData {
+    pub uri: Uri,
+    /// The name of the `defns` block or judgement.
+    pub name: String,
+}
+
+/// A lens, without its command, over the name of each `defns` block and each
+/// judgement in `doc`. The counts are left to [`resolve()`].
+pub fn lenses(doc: &Document, uri: &Uri) -> Vec<CodeLens> {
+    let blocks = doc.defns.iter().map(|block| &block.name);
+    let judgements = doc.defns.iter().flat_map(|block| &block.defns).map(|defn| &defn.name);
+    blocks.chain(judgements)
+        .map(|name| CodeLens {
+            range: name.range,
+            command: None,
+            data: serde_json::to_value(LensData { uri: uri.clone(), name: name.text.clone() }).ok(),
+        })
+        .collect()
+}
+
+/// `lens` with its command: the number of rules of its block or judgement
+/// in `doc`, which lists them with `ott-lsp.listRules`.
+pub fn resolve(doc: &Document, mut lens: CodeLens, data: LensData) -> CodeLens {
+    let blocks = doc.defns.iter().filter(|block| block.name.text == data.name);
+    let judgements = doc.defns.iter().flat_map(|block| &block.defns).filter(|defn| defn.name.text == data.name);
+    let count: usize = blocks.flat_map(|block| &block.defns).chain(judgements).map(|defn| defn.rules.len()).sum();
+    let title = match count {
+        1 => "1 rule".to_string(),
+        n => format!("{n} rules"),
+    };
+
+    lens.command = Some(Command::new(title, commands::LIST_RULES.into(), Some(vec![data.name.into()])));
+    lens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r"defns
+Jop :: '' ::=
+
+defn
+t --> t :: :: reduce :: E_ by
+
+----- :: refl
+t --> t
+
+t --> t
+----- :: twice
+t --> t
+
+defn
+|- t :: :: typing :: T_ by
+
+----- :: any
+|- t
+";
+
+    /// The range and title of each lens on `text`, once resolved.
+    fn resolved(text: &str) -> Vec<(Range, String)> {
+        let doc = Document::parse(text);
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        lenses(&doc, &uri).into_iter()
+            .map(|lens| {
+                let data = serde_json::from_value(lens.data.clone().unwrap()).unwrap();
+                let lens = resolve(&doc, lens, data);
+                let command = lens.command.unwrap();
+                assert_eq!(command.command, commands::LIST_RULES);
+                (lens.range, command.title)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lenses_count_the_rules_of_blocks_and_judgements() {
+        let range = |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        assert_eq!(resolved(RULES), [
+            (range(1, 0, 3), "3 rules".to_string()),
+            (range(4, 14, 20), "2 rules".to_string()),
+            (range(14, 11, 17), "1 rule".to_string()),
+        ]);
+    }
+}
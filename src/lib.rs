@@ -0,0 +1,101 @@
+//! The parts of the server that don't need a language client, for reuse by
+//! other tools.
+
+pub mod parse;
+pub mod config;
+pub mod navigation;
+pub mod theory;
+pub mod check;
+pub mod lint;
+
+use std::path::{Path, PathBuf};
+
+use lsp_types::Uri;
+
+/// The filesystem path `uri` refers to. On Windows, `file:///C:/x.ott` is
+/// `C:\x.ott` and `file://server/share/x.ott` is `\\server\share\x.ott`.
+pub fn uri_path(uri: &Uri) -> PathBuf {
+    let path = uri.path().as_estr().decode().into_string_lossy();
+    if !cfg!(windows) {
+        return PathBuf::from(path.as_ref());
+    }
+
+    let host = uri.authority().map_or("", |authority| authority.host().as_str());
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ if !host.is_empty() => &format!("//{host}{path}"),
+        _ => &path,
+    };
+
+    PathBuf::from(path.replace('/', "\\"))
+}
+
+/// The `file` URI for `path`, which must be absolute.
+pub fn path_uri(path: &Path) -> Option<Uri> {
+    let mut path = path.to_str()?.to_string();
+    let mut uri = String::from("file://");
+    if cfg!(windows) {
+        // Canonical paths are verbatim: `\\?\C:\x` rather than `C:\x`.
+        path = path.strip_prefix(r"\\?\").unwrap_or(&path).replace('\\', "/");
+        if let [drive, b':', ..] = path.as_bytes() && drive.is_ascii_alphabetic() {
+            uri.push('/');
+            uri.push_str(&path[..2]);
+            path.replace_range(..2, "");
+        } else if let Some((host, rest)) = path.strip_prefix("//").and_then(|p| p.split_once('/')) {
+            uri.push_str(host);
+            path = format!("/{rest}");
+        }
+    }
+
+    for byte in path.bytes() {
+        match byte {
+            b'/' | b'-' | b'.' | b'_' | b'~' => uri.push(byte as char),
+            _ if byte.is_ascii_alphanumeric() => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    uri.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(uri: &str) -> Uri {
+        uri.parse().unwrap()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn uris_are_percent_decoded_into_paths() {
+        assert_eq!(uri_path(&uri("file:///home/x/theory.ott")), Path::new("/home/x/theory.ott"));
+        assert_eq!(uri_path(&uri("file:///home/my%20x/%CE%BB.ott")), Path::new("/home/my x/λ.ott"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn paths_are_percent_encoded_into_uris() {
+        assert_eq!(path_uri(Path::new("/home/x/theory.ott")), Some(uri("file:///home/x/theory.ott")));
+        assert_eq!(path_uri(Path::new("/home/my x/λ#1.ott")), Some(uri("file:///home/my%20x/%CE%BB%231.ott")));
+
+        let path = PathBuf::from("/a b/c%d/[e].ott");
+        assert_eq!(uri_path(&path_uri(&path).unwrap()), path);
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn windows_uris_are_drive_and_share_paths() {
+        assert_eq!(uri_path(&uri("file:///C:/x/theory.ott")), Path::new(r"C:\x\theory.ott"));
+        assert_eq!(uri_path(&uri("file:///c%3A/my%20x/theory.ott")), Path::new(r"c:\my x\theory.ott"));
+        assert_eq!(uri_path(&uri("file://server/share/theory.ott")), Path::new(r"\\server\share\theory.ott"));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn windows_paths_are_drive_and_share_uris() {
+        assert_eq!(path_uri(Path::new(r"C:\x\theory.ott")), Some(uri("file:///C:/x/theory.ott")));
+        assert_eq!(path_uri(Path::new(r"\\?\C:\my x\theory.ott")), Some(uri("file:///C:/my%20x/theory.ott")));
+        assert_eq!(path_uri(Path::new(r"\\server\share\theory.ott")), Some(uri("file://server/share/theory.ott")));
+    }
+}
@@ -0,0 +1,733 @@
+//! Checks of our own, run over the parsed document alongside ott's.
+
+use lsp_types::*;
+
+use crate::config::Config;
+use crate::navigation::{self, is_use};
+use crate::parse::{self, Defn, Document, Name, Production};
+
+fn warning(range: Range, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::WARNING),
+        source: Some("ott-lsp".into()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// The ids of the lints, as the `lints` setting names them and as the codes
+/// of their diagnostics.
+pub const LINTS: &[&str] = &[
+    "delimiters",
+    "bindings",
+    "metavars",
+    "unused",
+    "judgements",
+    "conclusions",
+    "unbound",
+    "targets",
+    "whitespace",
+    "coverage",
+];
+
+/// A lint's diagnostics, found when it's called.
+type Lint<'a> = &'a dyn Fn() -> Vec<Diagnostic>;
+
+/// Runs every enabled lint over `doc`, whose text is `text`. A lint is
+/// enabled by its `lints` setting or, failing that, its `lint_*` flag, and
+/// its diagnostics take the severity of its `lints` setting, if any.
+pub fn lint(config: &Config, doc: &Document, text: &str, uri: &Uri) -> Vec<Diagnostic> {
+    let lints: [(&str, bool, Lint<'_>); 10] = [
+        ("delimiters", true, &|| delimiters(text).into_iter().collect()),
+        ("bindings", config.lint_bindings, &|| bindings(doc)),
+        ("metavars", config.lint_metavars, &|| metavars(doc, uri)),
+        ("unused", config.lint_unused, &|| unused_metavars(doc)),
+        ("judgements", config.lint_judgements, &|| premises(doc)),
+        ("conclusions", config.lint_conclusions, &|| conclusions(doc, text)),
+        ("unbound", config.lint_unbound, &|| unbound(doc, text, &config.fresh_judgements)),
+        ("targets", config.lint_targets, &|| targets(text, &config.known_targets)),
+        ("whitespace", config.lint_whitespace, &|| whitespace(doc, text)),
+        ("coverage", !config.hom_coverage.is_empty(), &|| coverage(doc, &config.hom_coverage)),
+    ];
+
+    let mut diagnostics = vec![];
+    for (id, enabled, lint) in lints {
+        let setting = config.lints.get(id);
+        if !setting.and_then(|s| s.enabled).unwrap_or(enabled) {
+            continue;
+        }
+
+        for mut diagnostic in lint() {
+            if let Some(severity) = setting.and_then(|s| s.severity) {
+                diagnostic.severity = Some(severity.diagnostic_severity());
+            }
+
+            diagnostic.code = Some(NumberOrString::String(id.into()));
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics
+}
+
+/// Points at the first `{{` or `}}` that isn't matched, if any. Unlike ott's
+/// own error, this is always where the problem is, not where ott gave up.
+fn delimiters(text: &str) -> Option<Diagnostic> {
+    let (range, message) = parse::delimiter_pairs(text).into_iter()
+        .filter_map(|pair| match (pair.open, pair.close) {
+            (Some(open), None) => Some((open, "`{{` is never closed by a `}}`")),
+            (None, Some(close)) => Some((close, "`}}` closes no `{{`")),
+            _ => None,
+        })
+        .min_by_key(|(range, _)| range.start)?;
+
+    Some(Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("ott-lsp".into()),
+        message: message.into(),
+        ..Default::default()
+    })
+}
+
+/// Checks that each `bind x in t` names variables of its own production and
+/// that each substitution substitutes for a declared metavar.
+fn bindings(doc: &Document) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for production in doc.grammars.iter().flat_map(|rule| &rule.productions) {
+        let in_body = |name: &Name| production.body.iter().any(|n| n.text == name.text);
+        for bind in &production.binds {
+            // Auxiliaries like `binders(p)` aren't variables of the production.
+            if !bind.binder.text.contains('(') && !in_body(&bind.binder) {
+                let message = format!("bound variable `{}` does not appear in production `{}`",
+                    bind.binder.text, production.label.text);
+
+                diagnostics.push(warning(bind.binder.range, message));
+            }
+
+            if !in_body(&bind.scope) {
+                let message = format!("binding scope `{}` does not appear in production `{}`",
+                    bind.scope.text, production.label.text);
+
+                diagnostics.push(warning(bind.scope.range, message));
+            }
+        }
+    }
+
+    for subst in &doc.substitutions {
+        if !doc.is_metavar(&subst.metavar.text) {
+            let message = format!("substitution `{}` of `{}` references unknown metavar `{}`",
+                subst.function.text, subst.nonterminal.text, subst.metavar.text);
+
+            diagnostics.push(warning(subst.metavar.range, message));
+        }
+    }
+
+    diagnostics
+}
+
+/// Checks that no two metavar declarations declare the same name, or names
+/// one of which reads as the other with a suffix, like `x` and `x1`. Names
+/// within one declaration are deliberate synonyms and aren't compared.
+fn metavars(doc: &Document, uri: &Uri) -> Vec<Diagnostic> {
+    let related = |name: &Name| DiagnosticRelatedInformation {
+        location: Location::new(uri.clone(), name.range),
+        message: format!("`{}` is declared here", name.text),
+    };
+
+    let mut diagnostics = vec![];
+    for (i, metavar) in doc.metavars.iter().enumerate() {
+        for name in &metavar.names {
+            let mut earlier = doc.metavars[..i].iter().flat_map(|m| &m.names);
+            let overlaps = |other: &&Name| is_use(&name.text, &other.text) || is_use(&other.text, &name.text);
+            let Some(other) = earlier.find(overlaps) else { continue };
+
+            let message = match name.text == other.text {
+                true => format!("metavar `{}` is declared more than once", name.text),
+                false => format!("metavar `{}` overlaps metavar `{}`: one reads as the other with a suffix",
+                    name.text, other.text),
+            };
+
+            let mut diagnostic = warning(name.range, message);
+            diagnostic.related_information = Some(vec![related(other), related(name)]);
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics
+}
+
+/// Points out each metavar that no grammar production uses, under any of its
+/// names, whether alone, like `x1`, or indexed, like `x_i`. Rules are left
+/// out: a metavar only ever used there still never makes it into a term.
+fn unused_metavars(doc: &Document) -> Vec<Diagnostic> {
+    let words: Vec<_> = doc.grammars.iter()
+        .flat_map(|rule| &rule.productions)
+        .flat_map(|p| &p.body)
+        .flat_map(|word| word.text.split('_'))
+        .collect();
+
+    doc.metavars.iter()
+        .filter(|m| !m.names.iter().any(|name| words.iter().any(|word| is_use(word, &name.text))))
+        .filter_map(|m| m.names.first())
+        .map(|name| Diagnostic {
+            range: name.range,
+            severity: Some(DiagnosticSeverity::HINT),
+            source: Some("ott-lsp".into()),
+            message: format!("metavar `{}` is used by no grammar production", name.text),
+            tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The backends ott generates for. A hom for one of them may also be for its
+/// variables, like `texvar`, or for one of its parts, like `coq-equality` or
+/// `isa-import`.
+pub const BACKENDS: &[&str] = &["tex", "coq", "isa", "hol", "lem", "ocaml", "twelf", "lex", "menhir"];
+
+/// The targets that aren't for one backend: comments, the shorthands for
+/// several of the theorem provers at once, and the like.
+pub const OTHER_TARGETS: &[&str] = &[
+    "com", "order", "aux", "auxparam", "isasyn", "isaprec", "texlong", "phantom", "repr-locally-nameless",
+    "ich", "ic", "ch", "ih", "icho", "ichl", "ichlo",
+];
+
+/// The backends each letter of a shorthand target like `ich` stands for.
+const SHORTHANDS: &[(char, &str)] = &[('i', "isa"), ('c', "coq"), ('h', "hol"), ('l', "lem"), ('o', "ocaml")];
+
+/// Whether a hom for `hom` is one for `target`, as a hom for `ich`, short
+/// for Isabelle, Coq, and HOL, is one for `coq`.
+pub fn covers(hom: &str, target: &str) -> bool {
+    let shorthand = OTHER_TARGETS.contains(&hom) && hom.chars().all(|c| SHORTHANDS.iter().any(|(s, _)| *s == c));
+    hom == target || (shorthand && hom.chars().any(|c| SHORTHANDS.contains(&(c, target))))
+}
+
+/// The productions of `doc` with no hom for `target`, for which ott makes
+/// up output of its own.
+pub fn uncovered<'d>(doc: &'d Document, target: &str) -> Vec<&'d Production> {
+    doc.grammars.iter()
+        .flat_map(|rule| &rule.productions)
+        .filter(|production| !production.homs.iter().any(|hom| covers(&hom.target, target)))
+        .collect()
+}
+
+/// Points out each production with no hom for one of `targets`.
+fn coverage(doc: &Document, targets: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for target in targets {
+        for production in uncovered(doc, target) {
+            diagnostics.push(Diagnostic {
+                range: production.label.range,
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                source: Some("ott-lsp".into()),
+                message: format!("production `{}` has no `{target}` hom, so ott makes one up", production.id),
+                ..Default::default()
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Whether ott, or the user by way of `known`, knows the hom target `target`.
+fn is_known_target(target: &str, known: &[String]) -> bool {
+    let for_backend = |backend: &&str| target.strip_prefix(backend)
+        .is_some_and(|rest| rest.is_empty() || rest == "var" || rest.starts_with('-'));
+
+    BACKENDS.iter().any(for_backend)
+        || OTHER_TARGETS.contains(&target)
+        || known.iter().any(|k| k == target)
+}
+
+/// Checks that each hom and embed in `text`, whatever it's attached to, is
+/// for a target ott knows, or one of the `known` targets. ott silently
+/// ignores any other, so a misspelled target only shows up as missing
+/// output.
+fn targets(text: &str, known: &[String]) -> Vec<Diagnostic> {
+    parse::all_homs(text).into_iter()
+        .filter(|hom| !is_known_target(&hom.target, known))
+        .map(|hom| warning(hom.target_range, format!("unknown target `{}`: ott ignores this", hom.target)))
+        .collect()
+}
+
+/// Checks that each rule's premises have the form of one of the document's
+/// judgements, or of one of its productions, as formulas like `x : t in G`
+/// do. Forms are compared by their terminals alone, since the metavars and
+/// nonterminals between them vary from use to use.
+fn premises(doc: &Document) -> Vec<Diagnostic> {
+    let terminals = |words: &[Name]| -> Vec<String> {
+        words.iter()
+            .filter(|word| navigation::declaration(doc, &word.text).is_none())
+            .map(|word| word.text.clone())
+            .collect()
+    };
+
+    let judgements: Vec<_> = doc.defns.iter().flat_map(|d| &d.defns).collect();
+    let forms: Vec<_> = judgements.iter().map(|defn| terminals(&defn.form))
+        .chain(doc.grammars.iter().flat_map(|rule| &rule.productions).map(|p| terminals(&p.body)))
+        .collect();
+
+    let mut diagnostics = vec![];
+    for premise in judgements.iter().flat_map(|defn| &defn.rules).flat_map(|rule| &rule.premises) {
+        // Dot forms, like `</ G |- ei : ti // i IN 1 .. n />`, aren't checked.
+        let dots = |name: &Name| ["</", "/>", ".."].iter().any(|dots| name.text.contains(dots));
+        let used = terminals(premise);
+        if premise.iter().any(dots) || forms.contains(&used) {
+            continue;
+        }
+
+        let text: Vec<_> = premise.iter().map(|word| word.text.as_str()).collect();
+        let mut message = format!("premise `{}` has the form of no judgement or formula", text.join(" "));
+        let shared = |defn: &&Defn| terminals(&defn.form).iter().filter(|t| used.contains(t)).count();
+        if let Some(closest) = judgements.iter().copied().filter(|defn| shared(defn) > 0).max_by_key(shared) {
+            let form: Vec<_> = closest.form.iter().map(|word| word.text.as_str()).collect();
+            message.push_str(&format!("; did you mean judgement `{}` (`{}`)?", closest.name.text, form.join(" ")));
+        }
+
+        let range = Range::new(premise[0].range.start, premise[premise.len() - 1].range.end);
+        diagnostics.push(Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("ott-lsp".into()),
+            message,
+            ..Default::default()
+        });
+    }
+
+    diagnostics
+}
+
+/// Checks that each rule's conclusion has the form of one of the judgements
+/// of its `defns` block, as forms are compared in [`premises()`]. A rule
+/// concluding some other judgement was likely copied from another block.
+fn conclusions(doc: &Document, text: &str) -> Vec<Diagnostic> {
+    let terminals = |words: Vec<&str>| -> Vec<String> {
+        words.into_iter().filter(|word| navigation::declaration(doc, word).is_none()).map(String::from).collect()
+    };
+
+    let lines: Vec<_> = text.split('\n').collect();
+    let mut diagnostics = vec![];
+    for defns in &doc.defns {
+        let forms: Vec<_> = defns.defns.iter()
+            .map(|defn| terminals(defn.form.iter().map(|word| word.text.as_str()).collect()))
+            .collect();
+
+        for rule in defns.defns.iter().flat_map(|defn| &defn.rules) {
+            let dots = ["</", "/>", ".."].iter().any(|dots| rule.conclusion.contains(dots));
+            if rule.conclusion.is_empty() || dots || forms.contains(&terminals(rule.conclusion.split_whitespace().collect())) {
+                continue;
+            }
+
+            let line = rule.name.range.start.line + 1;
+            let Some(text) = lines.get(line as usize).map(|line| line.trim_end()) else { continue };
+            let start = parse::utf16_len(&text[..text.len() - text.trim_start().len()]);
+            let message = format!("conclusion `{}` has the form of no judgement of `defns {}`",
+                rule.conclusion, defns.name.text);
+
+            diagnostics.push(Diagnostic {
+                range: Range::new(Position::new(line, start), Position::new(line, parse::utf16_len(text))),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("ott-lsp".into()),
+                message,
+                ..Default::default()
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Points out each metavar in a rule's conclusion that none of its premises
+/// mentions, which in a mode-correct relation is likely a typo. Being a hint
+/// and no more, it stays out of the way where it's wrong: rules without
+/// premises, like axioms, aren't checked, nor are rules with dot forms, nor
+/// those of the judgements in `fresh`, whose conclusions may introduce
+/// variables of their own.
+fn unbound(doc: &Document, text: &str, fresh: &[String]) -> Vec<Diagnostic> {
+    let metavars: Vec<_> = doc.metavars.iter().flat_map(|m| &m.names).collect();
+    let is_metavar = |word: &str| navigation::declaration(doc, word)
+        .is_some_and(|name| metavars.iter().any(|m| m.range == name.range));
+
+    let lines: Vec<_> = text.split('\n').collect();
+    let mut diagnostics = vec![];
+    for defn in doc.defns.iter().flat_map(|d| &d.defns).filter(|defn| !fresh.contains(&defn.name.text)) {
+        for rule in defn.rules.iter().filter(|rule| !rule.premises.is_empty()) {
+            let dots = |text: &str| ["</", "/>", ".."].iter().any(|dots| text.contains(dots));
+            let premises: Vec<_> = rule.premises.iter().flatten().collect();
+            if dots(&rule.conclusion) || premises.iter().any(|word| dots(&word.text)) {
+                continue;
+            }
+
+            let line = rule.name.range.start.line + 1;
+            let Some(text) = lines.get(line as usize) else { continue };
+            let words = parse::tokenize(text).into_iter().filter(|t| t.kind == parse::TokenKind::Word);
+            let mut seen = vec![];
+            for word in words.filter(|word| is_metavar(word.text)) {
+                if seen.contains(&word.text) || premises.iter().any(|p| p.text == word.text) {
+                    continue;
+                }
+
+                seen.push(word.text);
+                let (start, end) = (word.range.start.character, word.range.end.character);
+                diagnostics.push(Diagnostic {
+                    range: Range::new(Position::new(line, start), Position::new(line, end)),
+                    severity: Some(DiagnosticSeverity::HINT),
+                    source: Some("ott-lsp".into()),
+                    message: format!("`{}` in the conclusion of `{}` is in none of its premises", word.text, rule.name.text),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indent {
+    None,
+    Tabs,
+    Spaces,
+    Mixed,
+}
+
+impl Indent {
+    fn of(line: &str) -> (Indent, usize) {
+        let leading = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        let indent = match (leading.contains('\t'), leading.contains(' ')) {
+            (false, false) => Indent::None,
+            (true, false) => Indent::Tabs,
+            (false, true) => Indent::Spaces,
+            (true, true) => Indent::Mixed,
+        };
+
+        (indent, leading.len())
+    }
+}
+
+/// Checks that the lines of each rule, from its premises through its line of
+/// dashes to its conclusion, don't mix tabs and spaces in their indentation,
+/// pointing at each line that's indented differently from the rest.
+fn whitespace(doc: &Document, text: &str) -> Vec<Diagnostic> {
+    let lines: Vec<_> = text.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    let blank = |i: usize| lines[i].trim().is_empty();
+
+    let mut diagnostics = vec![];
+    for rule in doc.defns.iter().flat_map(|d| &d.defns).flat_map(|defn| &defn.rules) {
+        // Rules are separated by blank lines, so a rule is the paragraph
+        // around its line of dashes.
+        let dashes = rule.name.range.start.line as usize;
+        if dashes >= lines.len() {
+            continue;
+        }
+
+        let first = (0..dashes).rev().take_while(|&i| !blank(i)).last().unwrap_or(dashes);
+        let last = (dashes + 1..lines.len()).take_while(|&i| !blank(i)).last().unwrap_or(dashes);
+        let indents: Vec<_> = (first..=last).map(|i| (i, Indent::of(lines[i]))).collect();
+
+        // Lines are held to the indentation of the line of dashes, or, if it
+        // has none or both, to that of the first line indented with one.
+        let single = |indent: &Indent| matches!(indent, Indent::Tabs | Indent::Spaces);
+        let expected = Some(Indent::of(lines[dashes]).0).filter(single)
+            .or_else(|| indents.iter().map(|(_, (indent, _))| *indent).find(single));
+
+        for &(i, (indent, len)) in &indents {
+            let unlike = |with| format!("indented with {with}, unlike the rest of rule `{}`", rule.id);
+            let message = match (indent, expected) {
+                (Indent::Mixed, _) => format!("indentation mixes tabs and spaces in rule `{}`", rule.id),
+                (Indent::Tabs, Some(Indent::Spaces)) => unlike("tabs"),
+                (Indent::Spaces, Some(Indent::Tabs)) => unlike("spaces"),
+                _ => continue,
+            };
+
+            let range = Range::new(Position::new(i as u32, 0), Position::new(i as u32, len as u32));
+            diagnostics.push(warning(range, message));
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    /// The diagnostics of the lint `id`, enabled with `settings` besides, for
+    /// `text`, and its range and message.
+    fn linted_with(id: &str, mut settings: serde_json::Value, text: &str) -> Vec<(Range, String)> {
+        settings["lints"][id] = serde_json::json!({ "enabled": true });
+        let config: Config = serde_json::from_value(settings).unwrap();
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        lint(&config, &Document::parse(text), text, &uri).into_iter()
+            .filter(|d| d.code == Some(NumberOrString::String(id.into())))
+            .map(|d| (d.range, d.message))
+            .collect()
+    }
+
+    fn linted(id: &str, text: &str) -> Vec<(Range, String)> {
+        linted_with(id, serde_json::json!({}), text)
+    }
+
+    #[test]
+    fn delimiters_points_at_the_first_unmatched_delimiter() {
+        let unclosed = "metavar x ::= {{ com a {{ tex x }}\ngrammar\n";
+        assert_eq!(linted("delimiters", unclosed), [(range((0, 14), (0, 16)), "`{{` is never closed by a `}}`".into())]);
+
+        let extra = "metavar x ::= {{ com a }} }}\n  {{ tex x }} }}\n";
+        assert_eq!(linted("delimiters", extra), [(range((0, 26), (0, 28)), "`}}` closes no `{{`".into())]);
+    }
+
+    #[test]
+    fn delimiters_skips_escaped_braces_and_comments() {
+        let text = "% {{ not a hom\nmetavar x ::= {{ tex \\{{x\\}} }} {{ com [[ x }} ]] }}\n";
+        assert_eq!(linted("delimiters", text), []);
+
+        // It's on unless it's turned off.
+        let config: Config = serde_json::from_value(serde_json::json!({})).unwrap();
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let text = "metavar x ::= {{ com a\n";
+        assert_eq!(lint(&config, &Document::parse(text), text, &uri).len(), 1);
+
+        let config: Config = serde_json::from_value(serde_json::json!({ "lints": { "delimiters": { "enabled": false } } })).unwrap();
+        assert_eq!(lint(&config, &Document::parse(text), text, &uri), []);
+    }
+
+    #[test]
+    fn lints_are_turned_on_and_off_and_given_severities_one_by_one() {
+        let text = "metavar x ::= {{ coqq nat }}\n  {{ com a\n";
+        let linted = |lints| {
+            let config: Config = serde_json::from_value(serde_json::json!({ "lints": lints })).unwrap();
+            let uri: Uri = "file:///x.ott".parse().unwrap();
+            lint(&config, &Document::parse(text), text, &uri).into_iter()
+                .map(|d| (d.code, d.severity))
+                .collect::<Vec<_>>()
+        };
+
+        let code = |id: &str| Some(NumberOrString::String(id.into()));
+        assert_eq!(linted(serde_json::json!({})), [(code("delimiters"), Some(DiagnosticSeverity::ERROR))]);
+
+        // Turning one on leaves the others as they were.
+        let lints = serde_json::json!({ "targets": { "enabled": true, "severity": "hint" } });
+        assert_eq!(linted(lints), [
+            (code("delimiters"), Some(DiagnosticSeverity::ERROR)),
+            (code("targets"), Some(DiagnosticSeverity::HINT)),
+        ]);
+
+        // As does turning one off, or only changing its severity.
+        let lints = serde_json::json!({ "delimiters": { "enabled": false }, "targets": { "enabled": true } });
+        assert_eq!(linted(lints), [(code("targets"), Some(DiagnosticSeverity::WARNING))]);
+        let lints = serde_json::json!({ "delimiters": { "severity": "info" } });
+        assert_eq!(linted(lints), [(code("delimiters"), Some(DiagnosticSeverity::INFORMATION))]);
+    }
+
+    #[test]
+    fn lint_flags_turn_their_lints_on_unless_lints_says_otherwise() {
+        let text = "metavar x ::= {{ coqq nat }}\n\tgrammar\n";
+        let linted = |settings| {
+            let config: Config = serde_json::from_value(settings).unwrap();
+            assert!(config.unknown.is_empty(), "{:?}", config.unknown);
+            let uri: Uri = "file:///x.ott".parse().unwrap();
+            lint(&config, &Document::parse(text), text, &uri).into_iter()
+                .filter_map(|d| match d.code {
+                    Some(NumberOrString::String(code)) => Some(code),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(linted(serde_json::json!({})), Vec::<String>::new());
+        assert_eq!(linted(serde_json::json!({ "lintTargets": true })), ["targets"]);
+        assert_eq!(linted(serde_json::json!({ "lint_targets": true, "lintUnused": true })), ["unused", "targets"]);
+        let settings = serde_json::json!({ "lintTargets": true, "lints": { "targets": { "enabled": false } } });
+        assert_eq!(linted(settings), Vec::<String>::new());
+
+        // Every flag stands for its lint, here each finding something.
+        let text = TYPING
+            .replacen("metavar x ::=", "metavar x ::= {{ coqq nat }}\nmetavar y, x1 ::=", 1)
+            .replacen("  | x :: :: var", "  | x :: :: var\n  | \\ x . e :: :: lam (+ bind z in e +)", 1)
+            .replacen("G , x : t\n------------ :: formula", "  G , x : t\n\t------------ :: formula", 1)
+            .replacen("G |- e : t\n------------ :: ok\nG |- e : t", "G |- e : t\n------------ :: ok\ne --> e", 1)
+            + "\nG |- e : t\n------------ :: fresh\nG |- x1 : t\n"
+            + "\ndefns\nJred :: '' ::=\n\ndefn\ne --> e :: :: reduce :: E_ by\n";
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let linted = |settings| {
+            let config: Config = serde_json::from_value(settings).unwrap();
+            lint(&config, &Document::parse(&text), &text, &uri).into_iter()
+                .filter(|d| d.code != Some(NumberOrString::String("delimiters".into())))
+                .map(|d| (d.range, d.message))
+                .collect::<Vec<_>>()
+        };
+
+        let flags = [
+            ("lintBindings", "bindings"), ("lintMetavars", "metavars"), ("lintUnused", "unused"),
+            ("lintJudgements", "judgements"), ("lintConclusions", "conclusions"), ("lintUnbound", "unbound"),
+            ("lintTargets", "targets"), ("lintWhitespace", "whitespace"),
+        ];
+
+        assert_eq!(flags.len() + 2, LINTS.len());
+        for (flag, id) in flags {
+            let flagged = linted(serde_json::json!({ flag: true }));
+            assert!(!flagged.is_empty(), "{flag}");
+            assert_eq!(flagged, linted(serde_json::json!({ "lints": { id: { "enabled": true } } })), "{flag}");
+        }
+    }
+
+    const TYPING: &str = r"metavar x ::=
+grammar
+e :: 'e_' ::=
+  | x :: :: var
+t :: 't_' ::=
+  | unit :: :: unit
+G :: 'G_' ::=
+  | empty :: :: empty
+  | G , x : t :: :: cons
+
+defns
+Jtype :: '' ::=
+
+defn
+G |- e : t :: :: typing :: T_ by
+
+G |- e : t
+------------ :: ok
+G |- e : t
+
+G |= e : t
+------------ :: typo
+G |- e : t
+
+G , x : t
+------------ :: formula
+G |- x : t
+";
+
+    #[test]
+    fn judgements_points_out_premises_of_no_judgement() {
+        assert_eq!(linted("judgements", TYPING), [(
+            range((20, 0), (20, 10)),
+            "premise `G |= e : t` has the form of no judgement or formula; did you mean judgement `typing` (`G |- e : t`)?".into(),
+        )]);
+
+        // Nothing's close enough to suggest.
+        let text = TYPING.replace("G |= e : t", "e ~~> e");
+        assert_eq!(linted("judgements", &text), [(
+            range((20, 0), (20, 7)),
+            "premise `e ~~> e` has the form of no judgement or formula".into(),
+        )]);
+
+        // It's off unless it's turned on.
+        let config: Config = serde_json::from_value(serde_json::json!({})).unwrap();
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        assert_eq!(lint(&config, &Document::parse(TYPING), TYPING, &uri), []);
+    }
+
+    #[test]
+    fn conclusions_points_out_rules_concluding_another_blocks_judgement() {
+        // The rule `ok` concludes the judgement of a block of its own.
+        let reduce = "\ndefns\nJred :: '' ::=\n\ndefn\ne --> e :: :: reduce :: E_ by\n";
+        let text = TYPING.replacen("------------ :: ok\nG |- e : t", "------------ :: ok\ne --> e", 1) + reduce;
+        let message = "conclusion `e --> e` has the form of no judgement of `defns Jtype`";
+        assert_eq!(linted("conclusions", &text), [(range((18, 0), (18, 7)), message.into())]);
+
+        let config: Config = serde_json::from_value(serde_json::json!({ "lints": { "conclusions": { "enabled": true } } })).unwrap();
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let diagnostics = lint(&config, &Document::parse(&text), &text, &uri);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+
+        // It's off unless it's turned on.
+        let config: Config = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(lint(&config, &Document::parse(&text), &text, &uri), []);
+        assert_eq!(linted("conclusions", TYPING), []);
+    }
+
+    #[test]
+    fn unbound_points_out_conclusion_metavars_no_premise_mentions() {
+        // The premise of `fresh` doesn't mention the `x1` of its conclusion.
+        // The axiom `ax` has no premises to, nor does it need them.
+        let text = format!("{TYPING}\nG |- e : t\n------------ :: fresh\nG |- x1 : t\n\n------------ :: ax\nG |- x : t\n");
+        let message = "`x1` in the conclusion of `fresh` is in none of its premises";
+        assert_eq!(linted("unbound", &text), [(range((30, 5), (30, 7)), message.into())]);
+
+        // Unless its judgement may introduce variables of its own.
+        let settings = serde_json::json!({ "freshJudgements": ["typing"] });
+        assert_eq!(linted_with("unbound", settings, &text), []);
+        assert_eq!(linted("unbound", TYPING), []);
+    }
+
+    #[test]
+    fn whitespace_points_out_lines_indented_unlike_their_rule() {
+        let text = TYPING.replace("G |- e : t\n------------ :: ok\nG |- e : t", "  G |- e : t\n\t------------ :: ok\n \tG |- e : t");
+        assert_eq!(linted("whitespace", &text), [
+            (range((16, 0), (16, 2)), "indented with spaces, unlike the rest of rule `T_ok`".into()),
+            (range((18, 0), (18, 2)), "indentation mixes tabs and spaces in rule `T_ok`".into()),
+        ]);
+
+        // Without a line of dashes to go by, the first indented line sets
+        // the rule's indentation.
+        let text = TYPING.replace("G |- e : t\n------------ :: ok\nG |- e : t", "\tG |- e : t\n------------ :: ok\n  G |- e : t");
+        assert_eq!(linted("whitespace", &text), [(range((18, 0), (18, 2)), "indented with spaces, unlike the rest of rule `T_ok`".into())]);
+
+        // It's off unless it's turned on.
+        let config: Config = serde_json::from_value(serde_json::json!({})).unwrap();
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        assert_eq!(lint(&config, &Document::parse(&text), &text, &uri), []);
+    }
+
+    #[test]
+    fn unused_points_out_metavars_no_production_uses() {
+        // `dead` is used, but only in a rule.
+        let text = r"metavar termvar, x ::=
+metavar label, l ::=
+metavar dead, d ::=
+indexvar n ::=
+grammar
+t :: 't_' ::=
+  | x1 :: :: var
+  | { l_n = t } :: :: record
+
+defns
+Jop :: '' ::=
+
+defn
+t done :: :: done :: D_ by
+
+----- :: var
+d done
+";
+        assert_eq!(linted("unused", text), [(range((2, 8), (2, 12)), "metavar `dead` is used by no grammar production".into())]);
+
+        let config: Config = serde_json::from_value(serde_json::json!({ "lints": { "unused": { "enabled": true } } })).unwrap();
+        let uri: Uri = "file:///x.ott".parse().unwrap();
+        let diagnostics = lint(&config, &Document::parse(text), text, &uri);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::HINT));
+        assert_eq!(diagnostics[0].tags, Some(vec![DiagnosticTag::UNNECESSARY]));
+    }
+
+    #[test]
+    fn targets_points_out_misspelled_hom_and_embed_targets() {
+        let text = "grammar\nt :: 't_' ::=\n  | x :: :: var {{ coqq x }} {{ coq-equality }} {{ ich x }} {{ texvar x }}\n\nembed\n{{ tex-preamble \\usepackage{x} }}\n{{ lemm foo }}\n";
+        assert_eq!(linted("targets", text), [
+            (range((2, 19), (2, 23)), "unknown target `coqq`: ott ignores this".into()),
+            (range((6, 3), (6, 7)), "unknown target `lemm`: ott ignores this".into()),
+        ]);
+
+        // Targets of the user's own are known too.
+        let settings = serde_json::json!({ "knownTargets": ["lemm"] });
+        assert_eq!(linted_with("targets", settings, text), [(range((2, 19), (2, 23)), "unknown target `coqq`: ott ignores this".into())]);
+
+        // As are the homs of metavars and judgements, but not those in
+        // comments.
+        let text = "metavar x ::= {{ isa nat }} {{ coqq nat }}\n% {{ comm }}\ndefns\nJop :: '' ::= {{ cmo typing }}\n";
+        assert_eq!(linted("targets", text), [
+            (range((0, 31), (0, 35)), "unknown target `coqq`: ott ignores this".into()),
+            (range((3, 17), (3, 20)), "unknown target `cmo`: ott ignores this".into()),
+        ]);
+    }
+}
@@ -0,0 +1,76 @@
+//! Structured logs, written to a file given on the command line, for finding
+//! out after the fact what the server did and how long it took. Nothing is
+//! sent anywhere.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde_json::{Map, Value, json};
+
+/// How big the log grows before it's moved aside to `<path>.1`, replacing
+/// whatever was there, and started afresh.
+const MAX_BYTES: u64 = 8 << 20;
+
+/// A log of one JSON object per line, or nowhere at all.
+#[derive(Default)]
+pub struct Log(Option<Mutex<LogFile>>);
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+}
+
+fn append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Log {
+    /// A log appending to the file at `path`.
+    pub fn open(path: PathBuf) -> std::io::Result<Log> {
+        let file = append(&path)?;
+        Ok(Log(Some(Mutex::new(LogFile { path, file }))))
+    }
+
+    /// Writes a line with the time, `level`, `event`, and `fields`, which
+    /// should be a JSON object. Failures to write are ignored: the log is
+    /// for diagnosing problems, not a reason to have them.
+    pub fn write(&self, level: &str, event: &str, fields: Value) {
+        let Some(log) = &self.0 else { return };
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let mut record = Map::from_iter([
+            ("time".to_string(), json!(time)),
+            ("level".to_string(), json!(level)),
+            ("event".to_string(), json!(event)),
+        ]);
+
+        if let Value::Object(fields) = fields {
+            record.extend(fields);
+        }
+
+        let mut log = log.lock();
+        let _ = writeln!(log.file, "{}", Value::Object(record));
+        if log.file.metadata().is_ok_and(|m| m.len() > MAX_BYTES) {
+            let mut old = log.path.clone().into_os_string();
+            old.push(".1");
+            let _ = std::fs::rename(&log.path, old);
+            if let Ok(file) = append(&log.path) {
+                log.file = file;
+            }
+        }
+    }
+
+    /// Writes an `info` line for `event` that took `elapsed`, as `ms`, along
+    /// with `fields`.
+    pub fn timed(&self, event: &str, elapsed: Duration, fields: Value) {
+        let mut fields = match fields {
+            Value::Object(fields) => fields,
+            _ => Map::new(),
+        };
+
+        fields.insert("ms".into(), json!(elapsed.as_secs_f64() * 1000.0));
+        self.write("info", event, Value::Object(fields));
+    }
+}
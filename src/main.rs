@@ -1,69 +1,658 @@
-use std::path::Path;
+mod pool;
+mod debounce;
+mod commands;
+mod actions;
+mod workspace;
+mod embeds;
+mod rename;
+mod completion;
+mod documents;
+mod graph;
+mod log;
+mod format;
+mod hints;
+mod enclosing;
+mod hierarchy;
+mod summary;
+mod terms;
+mod lens;
+mod folding;
+
+use std::path::{Path, PathBuf};
 use std::error::Error;
-use std::process::Command;
+use std::process::ExitCode;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::thread::Scope;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use parking_lot::RwLock;
-use serde_json::from_value;
-use serde::Deserialize;
+use parking_lot::{Mutex, RwLock};
 use regex::Regex;
-use lsp_server::{Connection, Message, Notification, Response};
+use serde_json::from_value;
+use serde::Serialize;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
 use lsp_types::*;
 
-lazy_static::lazy_static! {
-    static ref RANGE1: Regex = Regex::new(r"line (\d+), column (\d+) - (\d+)").unwrap();
-    static ref RANGE2: Regex = Regex::new(r"line (\d+), column (\d+) - line (\d+), column (\d+)").unwrap();
-    static ref RANGE3: Regex = Regex::new(r"line (\d+)").unwrap();
-    static ref COL: Regex = Regex::new(r"\(char (\d+)\)").unwrap();
+use ott_lsp::{check, lint, navigation, parse, theory, path_uri, uri_path};
+use ott_lsp::config::{Config, Naming, NameCase, ProductionOrder, Severity};
+
+use crate::documents::Documents;
+use crate::pool::Pool;
+use crate::debounce::Debounce;
+
+
+/// The `textDocument/diagnostic` requests waiting on a check of each
+/// document, with the result id the client already has, if any.
+type Pulls = Mutex<HashMap<Uri, Vec<(RequestId, Option<String>)>>>;
+
+/// The requests being answered on worker threads, and whether the client has
+/// cancelled each since.
+type InFlight = Mutex<HashMap<RequestId, bool>>;
+
+/// A `checkAll` command waiting on the checks of the documents in
+/// `remaining`, with the totals of those that have finished.
+struct CheckAll {
+    id: RequestId,
+    remaining: HashSet<Uri>,
+    summary: commands::CheckAllResult,
 }
 
-#[derive(Default, Debug, Deserialize)]
-struct Config {
-    #[serde(default, alias = "ottFlags")]
-    ott_flags: Vec<String>,
+/// Work for the workers, at most `maxParallelChecks` of which run at once:
+/// a check of a document, or a command that runs ott.
+#[derive(PartialEq)]
+enum Job {
+    Check(Uri),
+    Command(RequestId, ExecuteCommandParams),
 }
 
-fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    if let Some(arg) = std::env::args().nth(1) {
-        if arg == "--version" {
-            println!(env!("CARGO_PKG_VERSION"));
-            return Ok(());
-        }
+/// The commands that run ott, which can take a while on a big theory.
+const SLOW_COMMANDS: [&str; 8] = [
+    commands::GENERATE,
+    commands::GENERATE_ALL,
+    commands::PREVIEW_BLOCK,
+    commands::PREVIEW_BACKEND,
+    commands::PROJECT_DIAGNOSTICS,
+    commands::TEST_PRODUCTION,
+    commands::BUILD_THEORY,
+    commands::CHECK_BLOCK,
+];
+
+/// The registration id of the watcher on the files open documents depend on.
+const DEPENDENCY_WATCHER: &str = "ott-lsp/dependencies";
+
+/// The state shared between the message loop and the check workers.
+struct Server {
+    connection: Connection,
+    /// The workspace folders, in the order the client listed them.
+    roots: Vec<PathBuf>,
+    /// Whether the client lets us register file watchers.
+    can_watch_files: bool,
+    /// Whether the client pulls diagnostics rather than having them pushed.
+    pull_diagnostics: bool,
+    /// Whether the client can be asked to pull diagnostics again.
+    can_refresh_diagnostics: bool,
+    /// Whether the client can show progress on tokens we create.
+    can_report_progress: bool,
+    /// Whether the client can insert completions as snippets.
+    can_complete_snippets: bool,
+    /// Whether the client can be asked to open a document.
+    can_show_documents: bool,
+    pulls: Pulls,
+    check_alls: Mutex<Vec<CheckAll>>,
+    in_flight: InFlight,
+    /// The id of the next request we send to the client.
+    next_request_id: AtomicI32,
+    dependents: RwLock<theory::Dependents>,
+    config: RwLock<Config>,
+    /// Bumped when the way ott is run changes. Checks started before then
+    /// are stale, and their results are dropped.
+    ott_generation: AtomicU64,
+    cache: check::CheckCache,
+    documents: Documents,
+    /// Checks, and commands that run ott, waiting for a worker.
+    jobs: Pool<Job>,
+    /// The open documents to check once they've stopped changing.
+    changes: Debounce<Uri>,
+    /// Every ott file in the workspace, for workspace-wide symbol search.
+    index: workspace::Index,
+    idle: workspace::Idle,
+    /// The files that aren't open that diagnostics were last published for
+    /// by the check of another file of their theory, to clear once they're
+    /// gone.
+    theory_published: Mutex<HashSet<Uri>>,
+    /// The open documents to report on from the results of a running check
+    /// of another file of their theory, keyed by the document being checked.
+    /// Each check that can take them has an entry for as long as it runs.
+    riders: Mutex<HashMap<Uri, Vec<Uri>>>,
+    /// The files outside the theory, like ones it includes, that the last
+    /// check of each file found diagnostics in, keyed by the file checked.
+    elsewhere_published: Mutex<HashMap<PathBuf, Vec<PathBuf>>>,
+    /// The diagnostics last reported for each open document, for hovers.
+    reported: RwLock<HashMap<Uri, Vec<Diagnostic>>>,
+    /// How many diagnostics were last reported for every file, open or not.
+    tally: summary::Tally,
+    /// How many times diagnostics have been published, for
+    /// `debug_diagnostic_data`.
+    publishes: AtomicU64,
+    /// How much the client wants `$/logTrace` notifications.
+    trace: RwLock<TraceValue>,
+    /// Where `--log-file` says to log to, if anywhere.
+    log: log::Log,
+    /// When the server started, for the settings' grace period.
+    started: Instant,
+    /// Whether the client has given us settings, at initialization or since.
+    configured: AtomicBool,
+}
+
+fn main() -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|s| s.as_str()) == Some("--version") {
+        println!(env!("CARGO_PKG_VERSION"));
+        return Ok(ExitCode::SUCCESS);
+    } else if args.iter().any(|arg| arg == "--check") {
+        return batch_check(args);
     }
 
+    let log = match args.iter().position(|arg| arg == "--log-file") {
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("missing value for --log-file")?;
+            log::Log::open(PathBuf::from(path))?
+        }
+        None => log::Log::default(),
+    };
+
+    log.write("info", "start", serde_json::json!({ "version": env!("CARGO_PKG_VERSION") }));
+
     // Create the transport, run the server
     let (connection, io_threads) = Connection::stdio();
-    let server_capabilities = serde_json::to_value(ServerCapabilities {
-        text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
-            open_close: Some(true),
-            change: Some(TextDocumentSyncKind::FULL),
-            save: Some(SaveOptions::default().into()),
+
+    // Panics that are caught, as the parser's are, would otherwise only be
+    // visible on stderr. Tell the client about them too.
+    let sender = connection.sender.clone();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let message = format!("ott-lsp internal error, results may be incomplete: {info}");
+        let params = LogMessageParams { typ: MessageType::ERROR, message };
+        let _ = sender.send(Message::Notification(Notification::new("window/logMessage".into(), params)));
+    }));
+
+    let server = Server::start(connection, log)?;
+    server.warn_about_settings(None)?;
+    std::thread::scope(|scope| server.run(scope))?;
+
+    // Dropping the server and the panic hook drops every sender on the
+    // connection, letting the IO threads exit.
+    drop(server);
+    drop(std::panic::take_hook());
+    io_threads.join()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Handles `--check <file> [--ott-path <path>] [--flags <flags>]
+/// [--theory <file>]... [--ott-output <recorded>] [--strict]
+/// [--strict-lints]`: checks `file` once, prints every diagnostic as
+/// `file:line:col: severity: message`, and fails if any of them is an error.
+/// With `--ott-output`, what ott printed is read from `recorded` instead, in
+/// builds with the `replay` feature.
+/// `--strict` and `--strict-lints` are the settings of those names.
+fn batch_check(args: Vec<String>) -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let mut config = Config::default();
+    let mut file = None;
+    let mut recorded = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--check" => file = Some(PathBuf::from(value()?)),
+            "--ott-path" => config.ott_path = Some(value()?),
+            "--flags" => config.ott_flags.extend(value()?.split_whitespace().map(String::from)),
+            "--theory" => config.theory.push(value()?),
+            "--ott-output" => recorded = Some(PathBuf::from(value()?)),
+            "--strict" => config.strict = true,
+            "--strict-lints" => (config.strict, config.strict_lints) = (true, true),
+            _ => return Err(format!("unknown argument: {arg}").into()),
+        }
+    }
+
+    let file = file.ok_or("missing value for --check")?;
+    if let Some(diagnostic) = check::not_a_file(&file) {
+        return Err(diagnostic.message.into());
+    }
+
+    let text = std::fs::read_to_string(&file)?;
+    let diagnostics = match recorded {
+        #[cfg(feature = "replay")]
+        Some(recorded) => check::diagnostics_for(&config, &file, &text, &check::Recorded::stdout(std::fs::read(recorded)?))?,
+        #[cfg(not(feature = "replay"))]
+        Some(_) => return Err("--ott-output needs ott-lsp built with the `replay` feature".into()),
+        None => check::diagnostics_for(&config, &file, &text, &check::Subprocess(&|message| eprintln!("{message}")))?,
+    };
+
+    for d in &diagnostics {
+        let severity = match d.severity {
+            Some(DiagnosticSeverity::ERROR) => "error",
+            Some(DiagnosticSeverity::WARNING) => "warning",
+            Some(DiagnosticSeverity::INFORMATION) => "info",
+            _ => "hint",
+        };
+
+        let (line, col) = (d.range.start.line + 1, d.range.start.character + 1);
+        println!("{}:{line}:{col}: {severity}: {}", file.display(), d.message);
+        for related in d.related_information.iter().flatten() {
+            let start = related.location.range.start;
+            let path = uri_path(&related.location.uri);
+            println!("{}:{}:{}: note: {}", path.display(), start.line + 1, start.character + 1, related.message);
+        }
+    }
+
+    match diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR)) {
+        true => Ok(ExitCode::FAILURE),
+        false => Ok(ExitCode::SUCCESS),
+    }
+}
+
+impl Server {
+    /// Initializes the server over `connection`, going by the capabilities,
+    /// workspace folders, and settings of the client's `initialize` request.
+    fn start(connection: Connection, log: log::Log) -> Result<Server, Box<dyn Error + Send + Sync>> {
+        let (initialize_id, params) = connection.initialize_start()?;
+        let params: InitializeParams = from_value(params)?;
+
+        // Clients that can pull diagnostics get them only when they ask.
+        let capabilities = &params.capabilities;
+        let pull_diagnostics = capabilities.text_document.as_ref()
+            .is_some_and(|t| t.diagnostic.is_some());
+
+        let can_refresh_diagnostics = capabilities.workspace.as_ref()
+            .and_then(|w| w.diagnostic.as_ref())
+            .and_then(|d| d.refresh_support)
+            .unwrap_or(false);
+
+        let can_report_progress = capabilities.window.as_ref()
+            .and_then(|w| w.work_done_progress)
+            .unwrap_or(false);
+
+        let can_complete_snippets = capabilities.text_document.as_ref()
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.completion_item.as_ref())
+            .and_then(|i| i.snippet_support)
+            .unwrap_or(false);
+
+        let can_show_documents = capabilities.window.as_ref()
+            .and_then(|w| w.show_document.as_ref())
+            .is_some_and(|s| s.support);
+
+        let can_watch_files = capabilities.workspace.as_ref()
+            .and_then(|w| w.did_change_watched_files.as_ref())
+            .and_then(|w| w.dynamic_registration)
+            .unwrap_or(false);
+
+        let mut server_capabilities = serde_json::to_value(ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(SaveOptions::default().into()),
+                ..Default::default()
+            })),
+            document_symbol_provider: Some(OneOf::Left(true)),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            workspace_symbol_provider: Some(OneOf::Left(true)),
+            completion_provider: Some(CompletionOptions {
+                trigger_characters: Some(vec!["|".into(), "{".into()]),
+                ..Default::default()
+            }),
+            rename_provider: Some(OneOf::Right(RenameOptions {
+                prepare_provider: Some(true),
+                work_done_progress_options: Default::default(),
+            })),
+            code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+            inlay_hint_provider: Some(OneOf::Left(true)),
+            code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(true) }),
+            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                first_trigger_character: format::TRIGGER.into(),
+                more_trigger_character: None,
+            }),
+            execute_command_provider: Some(ExecuteCommandOptions {
+                commands: commands::ALL.iter().map(|c| c.to_string()).collect(),
+                ..Default::default()
+            }),
+            workspace: Some(WorkspaceServerCapabilities {
+                workspace_folders: None,
+                file_operations: None,
+            }),
+            diagnostic_provider: pull_diagnostics.then(|| {
+                DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                    identifier: Some("ott".into()),
+                    inter_file_dependencies: true,
+                    workspace_diagnostics: true,
+                    ..Default::default()
+                })
+            }),
             ..Default::default()
-        })),
-        document_symbol_provider: Some(OneOf::Left(true)),
-        workspace: Some(WorkspaceServerCapabilities {
-            workspace_folders: None,
-            file_operations: None,
-        }),
-        ..Default::default()
-    })?;
-
-    let config = RwLock::new(Config::default());
-    connection.initialize(server_capabilities)?;
-
-    for msg in &connection.receiver {
+        })?;
+
+        // `lsp-types` has no field for it.
+        server_capabilities["typeHierarchyProvider"] = true.into();
+        let result = serde_json::json!({ "capabilities": server_capabilities });
+        connection.initialize_finish(initialize_id, result)?;
+
+        #[allow(deprecated)]
+        let roots: Vec<PathBuf> = match params.workspace_folders {
+            Some(folders) => folders.into_iter().map(|f| uri_path(&f.uri)).collect(),
+            None => params.root_uri.iter().map(uri_path).collect(),
+        };
+
+        // Settings passed up front apply from the first check, which doesn't
+        // wait for more unless they say to; later `didChangeConfiguration`
+        // notifications replace them.
+        let config: Option<Config> = params.initialization_options.clone()
+            .and_then(|options| serde_json::from_value::<Config>(options).ok())
+            .map(|config| config.resolve_includes(roots.first().map(PathBuf::as_path)));
+        let configured = AtomicBool::new(config.as_ref().is_some_and(|c| c.config_grace_ms.is_none()));
+
+        Ok(Server {
+            connection,
+            roots,
+            can_watch_files,
+            pull_diagnostics,
+            can_refresh_diagnostics,
+            can_report_progress,
+            can_complete_snippets,
+            can_show_documents,
+            pulls: Pulls::default(),
+            check_alls: Mutex::default(),
+            in_flight: InFlight::default(),
+            next_request_id: AtomicI32::new(0),
+            dependents: Default::default(),
+            config: RwLock::new(config.unwrap_or_default()),
+            ott_generation: AtomicU64::new(0),
+            cache: check::CheckCache::default(),
+            documents: Documents::default(),
+            jobs: Pool::new(),
+            changes: Debounce::new(),
+            index: workspace::Index::default(),
+            idle: workspace::Idle::new(),
+            theory_published: Mutex::default(),
+            riders: Mutex::default(),
+            elsewhere_published: Mutex::default(),
+            reported: RwLock::default(),
+            tally: summary::Tally::default(),
+            publishes: AtomicU64::new(0),
+            trace: RwLock::new(params.trace.unwrap_or(TraceValue::Off)),
+            log,
+            started: Instant::now(),
+            configured,
+        })
+    }
+
+    fn run<'s>(&'s self, scope: &'s Scope<'s, '_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        scope.spawn(|| self.idle.run(|| self.config.read().idle_reindex(), |interrupted| {
+            self.index.rebuild(&self.roots, self.config.read().respect_gitignore(), interrupted)
+        }));
+
+        if self.config.read().prewarm {
+            scope.spawn(|| self.prewarm());
+        }
+
+        // Whether to is only known once the settings have arrived.
+        if !self.pull_diagnostics {
+            scope.spawn(|| self.check_workspace(scope));
+        }
+
+        // Stop the background threads however the loop ends, or the scope
+        // would wait on them forever.
+        let result = self.handle_messages(scope);
+        self.idle.shutdown();
+        self.changes.shutdown();
+        self.jobs.shutdown();
+        result
+    }
+
+    fn handle_messages<'s>(&'s self, scope: &'s Scope<'s, '_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for msg in &self.connection.receiver {
+            self.idle.touch();
+            let started = Instant::now();
+            let method = match &msg {
+                Message::Request(req) => Some(("request", req.method.clone())),
+                Message::Notification(not) => Some(("notification", not.method.clone())),
+                Message::Response(_) => None,
+            };
+
+            let shutdown = self.handle_message(scope, msg)?;
+            if let Some((kind, method)) = method {
+                self.log.timed(kind, started.elapsed(), serde_json::json!({ "method": method }));
+            }
+
+            if shutdown {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles one message from the client. Returns whether it asked the
+    /// server to shut down.
+    fn handle_message<'s>(
+        &'s self,
+        scope: &'s Scope<'s, '_>,
+        msg: Message,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let connection = &self.connection;
         match msg {
             Message::Request(req) => {
                 if connection.handle_shutdown(&req)? {
-                    return Ok(());
+                    return Ok(true);
                 }
 
                 match req.method.as_str() {
                     "textDocument/documentSymbol" => {
-                        connection.sender.send(Message::Response( Response {
-                            id: req.id,
-                            result: Some(serde_json::Value::Array(vec![])),
-                            error: None,
-                        }))?;
+                        let params: DocumentSymbolParams = from_value(req.params)?;
+                        self.spawn_request(scope, req.id, move |cancelled| {
+                            // Parsing a big document is the slow part.
+                            let parsed = self.documents.parsed(&params.text_document.uri);
+                            if cancelled() {
+                                return None;
+                            }
+
+                            let symbols = parsed.map(|parsed| navigation::document_symbols(&parsed)).unwrap_or_default();
+                            Some(DocumentSymbolResponse::Nested(symbols))
+                        });
+                    }
+                    "textDocument/completion" => {
+                        let params: CompletionParams = from_value(req.params)?;
+                        let doc = &params.text_document_position;
+                        let uri = &doc.text_document.uri;
+                        let (text, parsed) = self.documents.snapshot(uri);
+                        let known = self.config.read().known_targets.clone();
+                        let items = completion::completions(&parsed, &text, doc.position, self.can_complete_snippets, &known);
+                        respond(connection, req.id, CompletionResponse::Array(items))?;
+                    }
+                    "textDocument/hover" => {
+                        let params: HoverParams = from_value(req.params)?;
+                        let doc = &params.text_document_position_params;
+                        let uri = &doc.text_document.uri;
+                        let hover = self.reported.read().get(uri)
+                            .and_then(|diagnostics| navigation::diagnostic_hover(diagnostics, doc.position))
+                            .or_else(|| {
+                                let (text, parsed) = self.documents.snapshot(uri);
+                                let identifier = self.config.read().identifier_pattern();
+                                navigation::hover(&parsed, doc.position)
+                                    .or_else(|| navigation::function_hover(&parsed, &text, doc.position, &identifier))
+                            });
+
+                        respond(connection, req.id, hover)?;
+                    }
+                    "textDocument/inlayHint" => {
+                        let params: InlayHintParams = from_value(req.params)?;
+                        let hints = match self.config.read().inlay_hints() {
+                            true => {
+                                let (text, parsed) = self.documents.snapshot(&params.text_document.uri);
+                                hints::rule_names(&parsed, &text, params.range)
+                            }
+                            false => vec![],
+                        };
+
+                        respond(connection, req.id, hints)?;
+                    }
+                    "textDocument/foldingRange" => {
+                        let params: FoldingRangeParams = from_value(req.params)?;
+                        let (text, _) = self.documents.snapshot(&params.text_document.uri);
+                        respond(connection, req.id, folding::comment_ranges(&text))?;
+                    }
+                    "textDocument/codeLens" => {
+                        let params: CodeLensParams = from_value(req.params)?;
+                        let uri = &params.text_document.uri;
+                        let (_, parsed) = self.documents.snapshot(uri);
+                        respond(connection, req.id, lens::lenses(&parsed, uri))?;
+                    }
+                    "codeLens/resolve" => {
+                        let lens: CodeLens = from_value(req.params)?;
+                        let data: lens::LensData = from_value(lens.data.clone().unwrap_or_default())?;
+                        let (_, parsed) = self.documents.snapshot(&data.uri);
+                        respond(connection, req.id, lens::resolve(&parsed, lens, data))?;
+                    }
+                    "textDocument/onTypeFormatting" => {
+                        let params: DocumentOnTypeFormattingParams = from_value(req.params)?;
+                        let doc = &params.text_document_position;
+                        let edits = match self.config.read().align_separators() && params.ch == format::TRIGGER {
+                            true => self.documents.with(&doc.text_document.uri, |open| {
+                                format::align_separator(&open.text, doc.position)
+                            }).flatten(),
+                            false => None,
+                        };
+
+                        respond(connection, req.id, edits)?;
+                    }
+                    "textDocument/definition" => {
+                        let params: GotoDefinitionParams = from_value(req.params)?;
+                        let doc = &params.text_document_position_params;
+                        let uri = &doc.text_document.uri;
+                        let (text, parsed) = self.documents.snapshot(uri);
+                        let identifier = self.config.read().identifier_pattern();
+                        let definition = navigation::definition(&parsed, &text, uri, doc.position, &identifier)
+                            .or_else(|| {
+                                let name = parse::name_at(&text, doc.position, &identifier)?;
+                                self.declaration_elsewhere(uri, &name.text)
+                            })
+                            .or_else(|| navigation::premise_judgement(&parsed, uri, doc.position));
+
+                        respond(connection, req.id, definition)?;
+                    }
+                    "textDocument/diagnostic" => {
+                        let params: DocumentDiagnosticParams = from_value(req.params)?;
+                        let uri = params.text_document.uri;
+                        let pull = (req.id, params.previous_result_id);
+                        self.pulls.lock().entry(uri.clone()).or_default().push(pull);
+                        self.schedule_check(scope, uri);
+                    }
+                    "workspace/diagnostic" => {
+                        let params: WorkspaceDiagnosticParams = from_value(req.params)?;
+                        self.spawn_request(scope, req.id, move |cancelled| {
+                            self.workspace_diagnostics(&params, cancelled)
+                        });
+                    }
+                    "workspace/symbol" => {
+                        let params: WorkspaceSymbolParams = from_value(req.params)?;
+                        self.spawn_request(scope, req.id, move |cancelled| {
+                            let symbols = self.workspace_symbols(&params.query, cancelled)?;
+                            Some(WorkspaceSymbolResponse::Nested(symbols))
+                        });
+                    }
+                    "textDocument/prepareRename" => {
+                        let params: TextDocumentPositionParams = from_value(req.params)?;
+                        let files = self.rename_files(&params.text_document.uri);
+                        let identifier = self.config.read().identifier_pattern();
+                        match rename::prepare_rename(&files, params.position, &identifier) {
+                            Ok(response) => respond(connection, req.id, response)?,
+                            Err(e) => respond_err(connection, req.id, ErrorCode::RequestFailed, e)?,
+                        }
+                    }
+                    "textDocument/rename" => {
+                        let params: RenameParams = from_value(req.params)?;
+                        let doc = &params.text_document_position;
+                        let files = self.rename_files(&doc.text_document.uri);
+                        let identifier = self.config.read().identifier_pattern();
+                        match rename::rename(&files, doc.position, &params.new_name, &identifier) {
+                            Ok(edit) => respond(connection, req.id, edit)?,
+                            Err(e) => respond_err(connection, req.id, ErrorCode::RequestFailed, e)?,
+                        }
+                    }
+                    "textDocument/codeAction" => {
+                        let params: CodeActionParams = from_value(req.params)?;
+                        let uri = &params.text_document.uri;
+                        let mut actions = self.documents.parsed(uri)
+                            .map(|parsed| actions::hom_actions(&parsed, uri, params.range))
+                            .unwrap_or_default();
+
+                        if !params.context.diagnostics.is_empty() && let Some(text) = self.documents.with(uri, |open| open.text.clone()) {
+                            let files = self.rename_files(uri);
+                            let docs: Vec<_> = files.iter().map(|(_, _, doc)| &**doc).collect();
+                            actions.extend(actions::spelling_actions(&docs, &text, uri, &params.context.diagnostics));
+                        }
+
+                        actions.extend(actions::generate_actions(uri, &self.config.read().output_paths));
+                        respond(connection, req.id, actions)?;
+                    }
+                    graph::DEPENDENCY_GRAPH => {
+                        let params: graph::DependencyGraphParams = from_value(req.params)?;
+                        let uri = &params.text_document.uri;
+                        let docs: Vec<_> = match params.whole_theory {
+                            true => self.rename_files(uri).into_iter()
+                                .map(|(uri, _, doc)| (uri, (*doc).clone()))
+                                .collect(),
+                            false => vec![(uri.clone(), (*self.documents.snapshot(uri).1).clone())],
+                        };
+
+                        respond(connection, req.id, graph::dependency_graph(&docs))?;
+                    }
+                    summary::SUMMARY => respond(connection, req.id, self.tally.summary())?,
+                    "textDocument/prepareTypeHierarchy" => {
+                        let params: TypeHierarchyPrepareParams = from_value(req.params)?;
+                        let doc = &params.text_document_position_params;
+                        let uri = &doc.text_document.uri;
+                        let (text, parsed) = self.documents.snapshot(uri);
+                        let identifier = self.config.read().identifier_pattern();
+                        respond(connection, req.id, hierarchy::prepare(&parsed, &text, uri, doc.position, &identifier))?;
+                    }
+                    "typeHierarchy/supertypes" => {
+                        let params: TypeHierarchySupertypesParams = from_value(req.params)?;
+                        let (_, parsed) = self.documents.snapshot(&params.item.uri);
+                        respond(connection, req.id, hierarchy::related(&parsed, &params.item, true))?;
+                    }
+                    "typeHierarchy/subtypes" => {
+                        let params: TypeHierarchySubtypesParams = from_value(req.params)?;
+                        let (_, parsed) = self.documents.snapshot(&params.item.uri);
+                        respond(connection, req.id, hierarchy::related(&parsed, &params.item, false))?;
+                    }
+                    enclosing::ENCLOSING_BLOCK => {
+                        let params: TextDocumentPositionParams = from_value(req.params)?;
+                        let (text, parsed) = self.documents.snapshot(&params.text_document.uri);
+                        respond(connection, req.id, enclosing::enclosing_block(&parsed, &text, params.position))?;
+                    }
+                    "workspace/executeCommand" => {
+                        let params: ExecuteCommandParams = from_value(req.params)?;
+                        if params.command == commands::CHECK_ALL {
+                            self.check_all(scope, req.id)?;
+                            return Ok(false);
+                        }
+
+                        // Generating from, or checking, a big theory can
+                        // take a while, so these run ott on the workers.
+                        if SLOW_COMMANDS.contains(&params.command.as_str()) {
+                            self.schedule(scope, Job::Command(req.id, params));
+                            return Ok(false);
+                        }
+
+                        match self.execute_command(&params) {
+                            Ok(result) => respond(connection, req.id, result)?,
+                            Err(e) => respond_err(connection, req.id, ErrorCode::InvalidParams, e)?,
+                        }
                     }
                     _ => {}
                 }
@@ -71,160 +660,2328 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             Message::Response(_resp) => {}
             Message::Notification(not) => {
                 match not.method.as_str() {
+                    "$/cancelRequest" => {
+                        let params: CancelParams = from_value(not.params)?;
+                        let id: RequestId = match params.id {
+                            NumberOrString::Number(n) => n.into(),
+                            NumberOrString::String(s) => s.into(),
+                        };
+
+                        if let Some(cancelled) = self.in_flight.lock().get_mut(&id) {
+                            *cancelled = true;
+                        }
+                    }
+                    "$/setTrace" => {
+                        let params: SetTraceParams = from_value(not.params)?;
+                        *self.trace.write() = params.value;
+                    }
                     "workspace/didChangeConfiguration" => {
                         let params: DidChangeConfigurationParams = from_value(not.params)?;
-                        if let Ok(new_config) = serde_json::from_value(params.settings) {
-                            *config.write() = new_config;
+                        if let Ok(new_config) = serde_json::from_value::<Config>(params.settings) {
+                            let new_config = new_config.resolve_includes(self.roots.first().map(PathBuf::as_path));
+                            let (restart, fingerprint) = {
+                                let config = self.config.read();
+                                (config.runs_ott_differently(&new_config), config.diagnostics_fingerprint())
+                            };
+
+                            let recheck = new_config.diagnostics_fingerprint() != fingerprint;
+                            let previous = std::mem::replace(&mut *self.config.write(), new_config);
+                            self.configured.store(true, Ordering::SeqCst);
+                            self.warn_about_settings(Some(&previous))?;
+                            self.update_dependencies()?;
+                            if restart {
+                                self.cache.write().clear();
+                            }
+
+                            if restart || recheck {
+                                self.restart_checks(scope);
+                            }
                         }
                     }
                     "textDocument/didOpen" => {
                         let params: DidOpenTextDocumentParams = from_value(not.params)?;
-                        let uri = &params.text_document.uri;
-                        check_ott_file(&*config.read(), uri.path().as_str(), uri, &connection)?;
+                        let TextDocumentItem { uri, language_id, version, text } = params.text_document;
+                        self.documents.open(uri.clone(), text, version, language_id);
+                        self.update_dependencies()?;
+                        self.send_embed_regions(&uri)?;
+                        // Clients that pull diagnostics ask for them on
+                        // open and save themselves.
+                        if !self.pull_diagnostics {
+                            self.schedule_check(scope, uri);
+                        }
                     }
                     "textDocument/didSave" => {
                         let params: DidSaveTextDocumentParams = from_value(not.params)?;
-                        let uri = &params.text_document.uri;
-                        check_ott_file(&*config.read(), uri.path().as_str(), uri, &connection)?;
+
+                        // Saving is asking for a fresh look: what ott said
+                        // last time may be out of date for reasons the cache
+                        // can't see, like a changed include or ott binary.
+                        if self.config.read().force_check_on_save() {
+                            self.cache.write().remove(&params.text_document.uri);
+                        }
+
+                        // Saving checks right away, not once typing stops.
+                        if !self.pull_diagnostics {
+                            self.changes.cancel(&params.text_document.uri);
+                            self.schedule_check(scope, params.text_document.uri);
+                        }
+                    }
+                    "textDocument/didChange" => {
+                        let mut params: DidChangeTextDocumentParams = from_value(not.params)?;
+                        if let Some(change) = params.content_changes.pop() {
+                            let uri = params.text_document.uri;
+                            self.documents.change(&uri, change.text, params.text_document.version);
+
+                            self.send_embed_regions(&uri)?;
+                            if !self.pull_diagnostics {
+                                self.debounce_check(scope, uri);
+                            }
+                        }
+                    }
+                    "textDocument/didClose" => {
+                        let params: DidCloseTextDocumentParams = from_value(not.params)?;
+                        self.changes.cancel(&params.text_document.uri);
+                        self.documents.close(&params.text_document.uri);
+                        self.reported.write().remove(&params.text_document.uri);
+                        self.update_dependencies()?;
+                    }
+                    "workspace/didChangeWatchedFiles" => {
+                        let params: DidChangeWatchedFilesParams = from_value(not.params)?;
+                        for change in params.changes {
+                            let dependents = self.dependents.read().of(&uri_path(&change.uri)).to_vec();
+                            for uri in dependents {
+                                self.schedule_check(scope, uri);
+                            }
+                        }
                     }
                     _ => {}
                 }
             }
         }
+
+        Ok(false)
     }
 
-    io_threads.join()?;
-    Ok(())
-}
+    fn execute_command(&self, params: &ExecuteCommandParams) -> Result<serde_json::Value, String> {
+        match params.command.as_str() {
+            commands::MATCH_DELIMITER => {
+                let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+                let range = self.documents.with(&doc.text_document.uri, |open| {
+                    commands::match_delimiter(&open.text, doc.position)
+                });
 
-fn publish_diagnostics(
-    uri: Uri,
-    diagnostics: Vec<Diagnostic>,
-    connection: &Connection,
-) -> Result<(), Box<dyn Error + Sync + Send>> {
-    let params = PublishDiagnosticsParams { uri, diagnostics, version: None, };
-    let notification = Notification::new("textDocument/publishDiagnostics".to_string(), params);
-    connection.sender.send(Message::Notification(notification))?;
-    Ok(())
-}
+                let range = range.ok_or_else(|| format!("{} is not open", doc.text_document.uri.as_str()))?;
+                Ok(serde_json::to_value(range).expect("range serializes"))
+            }
+            commands::DUMP_SYMBOLS => {
+                let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+                let document = self.documents.parsed(&doc.uri)
+                    .ok_or_else(|| format!("{} is not open", doc.uri.as_str()))?;
 
-fn check_ott_file(
-    config: &Config,
-    file_path: &str,
-    uri: &Uri,
-    connection: &Connection,
-) -> Result<(), Box<dyn Error + Sync + Send>> {
-    if !Path::new(file_path).is_file() {
-        let warning = Diagnostic {
-            range: Range::default(),
-            severity: Some(DiagnosticSeverity::INFORMATION),
-            message: format!("file path {file_path} is not a file"),
-            ..Default::default()
+                let result = commands::DumpSymbolsResult { schema: commands::SYMBOLS_SCHEMA, document: (*document).clone() };
+                Ok(serde_json::to_value(result).expect("document serializes"))
+            }
+            commands::TOGGLE_TEX_WRAP | commands::TOGGLE_TEX_SHOW_META => {
+                let mut config = self.config.write();
+                let (flag, option) = match params.command.as_str() {
+                    commands::TOGGLE_TEX_WRAP => ("-tex_wrap", &mut config.tex_wrap),
+                    _ => ("-tex_show_meta", &mut config.tex_show_meta),
+                };
+
+                let value = !option.unwrap_or(true);
+                *option = Some(value);
+                drop(config);
+
+                let message = format!("generating LaTeX with `{flag} {value}`");
+                if let Err(e) = self.show_message(MessageType::INFO, message) {
+                    eprintln!("failed to show message: {e}");
+                }
+
+                Ok(serde_json::Value::Bool(value))
+            }
+            commands::STATUS => {
+                let config = self.config.read();
+                let status = commands::StatusResult {
+                    ott_path: config.ott_path().to_string(),
+                    tex_wrap: config.tex_wrap.unwrap_or(true),
+                    tex_show_meta: config.tex_show_meta.unwrap_or(true),
+                };
+
+                Ok(serde_json::to_value(status).expect("status serializes"))
+            }
+            commands::NEW_RULE => {
+                let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+                let (text, parsed) = self.documents.snapshot(&doc.text_document.uri);
+                let edit = commands::new_rule(&parsed, &text, doc.position)
+                    .ok_or_else(|| "the cursor isn't in a judgement of a `defns` block".to_string())?;
+
+                Ok(serde_json::to_value(edit).expect("edit serializes"))
+            }
+            commands::NEXT_DIAGNOSTIC | commands::PREV_DIAGNOSTIC => {
+                let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+                let backwards = params.command == commands::PREV_DIAGNOSTIC;
+                let position = self.reported.read().get(&doc.text_document.uri)
+                    .and_then(|diagnostics| commands::adjacent_diagnostic(diagnostics, doc.position, backwards));
+
+                Ok(serde_json::to_value(position).expect("position serializes"))
+            }
+            commands::REVEAL_IN_OUTPUT => {
+                let location = self.reveal_in_output(params)?;
+                Ok(serde_json::to_value(location).expect("location serializes"))
+            }
+            commands::EXTRACT_EMBEDS => {
+                let result = self.extract_embeds(params)?;
+                Ok(serde_json::to_value(result).expect("result serializes"))
+            }
+            commands::SORT_PRODUCTIONS => {
+                let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+                let uri = &doc.text_document.uri;
+                let (text, parsed) = self.documents.snapshot(uri);
+                let order = self.config.read().production_order;
+                let edit = commands::sort_productions(&parsed, &text, uri, doc.position, order)
+                    .ok_or_else(|| "the cursor isn't in a grammar rule whose productions can be sorted".to_string())?;
+
+                Ok(serde_json::to_value(edit).expect("edit serializes"))
+            }
+            commands::NORMALIZE_NAMES => {
+                let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+                let naming = self.config.read().naming;
+                if naming == Naming::default() {
+                    return Err("`naming` sets no convention to normalize names to".into());
+                }
+
+                let identifier = self.config.read().identifier_pattern();
+                let result = rename::normalize(&self.rename_files(&doc.uri), naming, &identifier);
+                Ok(serde_json::to_value(result).expect("result serializes"))
+            }
+            commands::CHECK_HOM_COVERAGE => {
+                let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+                let target: String = commands::argument(params, 1)?;
+                if self.documents.parsed(&doc.uri).is_none() {
+                    return Err(format!("{} is not open", doc.uri.as_str()));
+                }
+
+                let mut result = commands::HomCoverageResult::default();
+                for (uri, _, doc) in self.rename_files(&doc.uri) {
+                    let missing = lint::uncovered(&doc, &target);
+                    result.total += doc.grammars.iter().map(|rule| rule.productions.len()).sum::<usize>();
+                    result.missing.extend(missing.iter().map(|p| Location::new(uri.clone(), p.label.range)));
+                }
+
+                result.annotated = result.total - result.missing.len();
+                Ok(serde_json::to_value(result).expect("result serializes"))
+            }
+            commands::WHICH_PRODUCTION => {
+                let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+                let term: String = commands::argument(params, 1)?;
+                let nonterminal: Option<String> = commands::argument(params, 2).ok().flatten();
+                if self.documents.parsed(&doc.uri).is_none() {
+                    return Err(format!("{} is not open", doc.uri.as_str()));
+                }
+
+                let files = self.rename_files(&doc.uri);
+                let docs: Vec<_> = files.iter().map(|(uri, _, doc)| (uri, &**doc)).collect();
+                let candidates = terms::which_production(&docs, &term, nonterminal.as_deref())?;
+                Ok(serde_json::to_value(candidates).expect("candidates serialize"))
+            }
+            commands::LIST_RULES => {
+                let docs = self.theory_documents();
+                let name = match commands::argument::<String>(params, 0) {
+                    Ok(name) => name,
+                    Err(_) => {
+                        let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+                        let text = self.documents.with(&doc.text_document.uri, |open| open.text.clone())
+                            .ok_or_else(|| format!("{} is not open", doc.text_document.uri.as_str()))?;
+
+                        let identifier = self.config.read().identifier_pattern();
+                        parse::name_at(&text, doc.position, &identifier)
+                            .ok_or_else(|| "there's no name under the cursor".to_string())?
+                            .text
+                    }
+                };
+
+                let rules = commands::list_rules(&docs, &name)
+                    .ok_or_else(|| format!("`{name}` isn't a judgement, rule, or `defns` block"))?;
+
+                Ok(serde_json::to_value(rules).expect("rules serialize"))
+            }
+            commands::SHOW_COMMAND => {
+                let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+                Ok(serde_json::Value::String(self.show_command(&doc.uri)))
+            }
+            commands::CHECK_REFERENCES => {
+                let severities: Option<Vec<Severity>> = match params.arguments.is_empty() {
+                    true => None,
+                    false => Some(commands::argument(params, 0)?),
+                };
+
+                let references: Vec<_> = commands::unresolved_references(&self.theory_documents()).into_iter()
+                    .filter(|r| severities.as_ref().is_none_or(|s| s.contains(&Severity::of(Some(r.severity)))))
+                    .collect();
+
+                Ok(serde_json::to_value(references).expect("references serialize"))
+            }
+            command => Err(format!("unknown command: {command}")),
+        }
+    }
+
+    /// Checks the saved files of the theory with one run of ott, returning
+    /// every diagnostic in any of them, along with the server's own, and
+    /// their totals.
+    fn project_diagnostics(&self) -> Result<commands::ProjectDiagnosticsResult, String> {
+        let config = self.config.read().clone();
+        let files = self.theory();
+        let primary = files.first().ok_or("no `theory` is configured")?;
+        let uri = path_uri(primary).ok_or_else(|| format!("{} isn't a valid URI", primary.display()))?;
+
+        let mut diagnostics = match check::pre_check(&config, primary) {
+            Some(failed) => check::Diagnostics { primary: vec![failed], ..Default::default() },
+            None => match check::run(&config, &files, &|message| self.trace(message)) {
+                Ok(output) => check::split_diagnostics(&config, &output, &files, primary, &uri, &|_| None),
+                Err(e) => check::Diagnostics { primary: vec![check::spawn_failed(&config, &e)], ..Default::default() },
+            },
         };
 
-        return publish_diagnostics(uri.clone(), vec![warning], &connection);
-    }
-
-    let output = Command::new("ott")
-        .arg("-signal_parse_errors")
-        .arg("true")
-        .arg("-colour")
-        .arg("false")
-        .args(&config.ott_flags)
-        .arg(file_path)
-        .output()?;
-
-    let mut diagnostics = Vec::new();
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut lines = stdout.lines().peekable();
-    while let Some(line) = lines.next() {
-        if line.starts_with("File") {
-            // Start of an error or warning block
-            let mut line_start = None;
-            let mut line_end = None;
-            let mut column_start = None;
-            let mut column_end = None;
-            let mut message = Vec::new();
-            let mut severity = None;
-
-            // Parse line and column numbers using regex
-            if let Some(caps) = RANGE1.captures(line) {
-                line_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
-                column_start = caps.get(2).and_then(|m| m.as_str().parse::<u32>().ok());
-                column_end = caps.get(3).and_then(|m| m.as_str().parse::<u32>().ok());
-            } else if let Some(caps) = RANGE2.captures(line) {
-                line_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
-                column_start = caps.get(2).and_then(|m| m.as_str().parse::<u32>().ok());
-                line_end = caps.get(3).and_then(|m| m.as_str().parse::<u32>().ok());
-                column_end = caps.get(4).and_then(|m| m.as_str().parse::<u32>().ok());
-            } else if let Some(caps) = RANGE3.captures(line) {
-                line_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
-            }
-
-            // Collect message until we hit a blank line or next "File" line
-            while let Some(current_line) = lines.peek() {
-                if current_line.starts_with("File") {
-                    break;
-                }
-
-                if let Some(msg) = current_line.strip_prefix("Error:") {
-                    severity = Some(DiagnosticSeverity::ERROR);
-                    let trimmed = msg.trim();
-                    if !trimmed.is_empty() {
-                        message.push(trimmed);
-                    }
-                } else if let Some(msg) = current_line.strip_prefix("Warning:") {
-                    severity = Some(DiagnosticSeverity::WARNING);
-                    let trimmed = msg.trim();
-                    if !trimmed.is_empty() {
-                        message.push(trimmed);
-                    }
-                } else if let Some(caps) = COL.captures(current_line) {
-                    if column_start.is_none() {
-                        column_start = caps.get(1).and_then(|m| m.as_str().parse::<u32>().ok());
-                    }
-                } else if !current_line.starts_with("Definition rule") {
-                    message.push(current_line.trim());
-                }
-
-                lines.next();
-            }
-
-            let message = message.is_empty()
-                .then(|| "unknown ott diagnostic message".into())
-                .unwrap_or(message.join(" "));
-
-            // Create diagnostic range
-            let line_start = line_start.map(|l| l - 1).unwrap_or(0);
-            let line_end = line_end.map(|l| l - 1).unwrap_or(line_start);
-            let range = match (column_start, column_end) {
-                (Some(col_start), Some(col_end)) => Range::new(
-                    Position::new(line_start, col_start),
-                    Position::new(line_end, col_end),
-                ),
-                (Some(col), None) => Range::new(
-                    Position::new(line_start, col),
-                    Position::new(line_end, col + message.len() as u32),
-                ),
-                (None, _) => Range::new(
-                    Position::new(line_start, 0),
-                    Position::new(line_end, 0),
-                ),
+        let mut result = commands::ProjectDiagnosticsResult::default();
+        for file in &files {
+            let Some(uri) = path_uri(file) else { continue };
+            let mut found = match file == primary {
+                true => std::mem::take(&mut diagnostics.primary),
+                false => diagnostics.others.remove(file).unwrap_or_default(),
             };
 
-            diagnostics.push(Diagnostic { range, severity, message, ..Default::default() });
+            let text = std::fs::read_to_string(file).ok();
+            if let Some(text) = &text {
+                let doc = parse::Document::parse(text);
+                check::relate_unused(&mut found, &doc, &uri);
+                self.relate_duplicates(&config, &mut found);
+                found.extend(lint::lint(&config, &doc, text, &uri));
+            }
+
+            let found = check::finalize_diagnostics(&config, text.as_deref(), found);
+            let count = |severity| found.iter().filter(|d| d.severity == Some(severity)).count();
+            result.summary.files += 1;
+            result.summary.errors += count(DiagnosticSeverity::ERROR);
+            result.summary.warnings += count(DiagnosticSeverity::WARNING);
+            result.diagnostics.extend(found.into_iter().map(|diagnostic| {
+                commands::ProjectDiagnostic { uri: uri.clone(), diagnostic }
+            }));
         }
+
+        Ok(result)
     }
 
-    // emit a general error if no specific errors/warnings were found
-    if diagnostics.is_empty() && !output.status.success() {
-        diagnostics.push(Diagnostic {
-            range: Range::default(),
-            severity: Some(DiagnosticSeverity::ERROR),
-            message: "ott processing failed".to_string(),
+    /// Runs ott on each saved file of `theory` in turn, after the files it
+    /// depends on, by what they declare, and along with them, as one would
+    /// by hand to build a theory whose files must come in order.
+    fn build_theory(&self) -> Result<commands::BuildTheoryResult, String> {
+        let config = self.config.read().clone();
+        let files = self.theory();
+        if files.is_empty() {
+            return Err("no `theory` is configured".into());
+        }
+
+        let uris = files.iter()
+            .map(|file| path_uri(file).ok_or_else(|| format!("{} isn't a valid URI", file.display())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let texts: Vec<_> = files.iter().map(|file| std::fs::read_to_string(file).ok()).collect();
+        let docs: Vec<_> = texts.iter().map(|text| parse::Document::parse(text.as_deref().unwrap_or_default())).collect();
+        let order = theory::build_order(&docs).map_err(|cycle| {
+            let names: Vec<_> = cycle.iter().map(|&i| files[i].display().to_string()).collect();
+            format!("the theory's files depend on each other in a cycle: {}", names.join(" -> "))
+        })?;
+
+        let mut result = commands::BuildTheoryResult {
+            order: order.iter().map(|(i, _)| uris[*i].clone()).collect(),
+            success: true,
             ..Default::default()
-        });
+        };
+
+        for (i, needed) in order {
+            let (file, uri) = (&files[i], &uris[i]);
+            let run: Vec<_> = needed.iter().chain([&i]).map(|&j| files[j].clone()).collect();
+            let (success, diagnostics) = match check::pre_check(&config, file) {
+                Some(failed) => (false, vec![failed]),
+                None => match check::run(&config, &run, &|message| self.trace(message)) {
+                    Ok(output) => {
+                        let diagnostics = check::split_diagnostics(&config, &output, &run, file, uri, &|_| None).primary;
+                        let errors = diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+                        (output.status.success() && !errors, diagnostics)
+                    }
+                    Err(e) => (false, vec![check::spawn_failed(&config, &e)]),
+                },
+            };
+
+            result.success &= success;
+            result.steps.push(commands::BuildStep {
+                uri: uri.clone(),
+                files: needed.iter().chain([&i]).map(|&j| uris[j].clone()).collect(),
+                success,
+                diagnostics: check::finalize_diagnostics(&config, texts[i].as_deref(), diagnostics),
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Generates one target from the block at a position on its own, as
+    /// `ott-lsp.checkBlock` checks it, returning what ott generated rather
+    /// than writing it anywhere.
+    fn preview_block(&self, params: &ExecuteCommandParams) -> Result<commands::PreviewBlockResult, String> {
+        let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+        let target: String = commands::argument(params, 1)?;
+        let uri = &doc.text_document.uri;
+        let extract = self.documents.with(uri, |open| commands::extract_block(&open.text, doc.position))
+            .ok_or_else(|| format!("{} is not open", uri.as_str()))?
+            .ok_or("position is not in a block")?;
+
+        // ott picks the backend from the output's extension, so the output
+        // is named as it would be if it were generated for real.
+        let config = self.config.read().clone();
+        let source = uri_path(uri);
+        let name = commands::output_path(&config.output_paths, &target, &source)
+            .and_then(|output| output.file_name().map(PathBuf::from))
+            .ok_or_else(|| format!("unknown target `{target}`: give it a path in `outputPaths`"))?;
+
+        let mut temp = check::TempFile::write(&config.temp_dir(), &source, &extract.text, true)
+            .map_err(|e| format!("failed to write block: {e}"))?;
+
+        let output = check::TempFile::output(&config.temp_dir(), &name)
+            .map_err(|e| format!("failed to create output file: {e}"))?;
+
+        if config.keep_temp_files {
+            temp.keep();
+            self.log_message(MessageType::LOG, format!("kept block at {}", temp.path().display()));
+        }
+
+        let files = [temp.path().to_path_buf()];
+        let args = config.generation_args(&target);
+        let (success, diagnostics) = match check::generate(&config, &files, output.path(), &args, &|message| self.trace(message)) {
+            Ok(run) => {
+                let diagnostics = check::diagnostics(&config, &run, &files, temp.path(), uri);
+                let errors = diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+                (run.status.success() && !errors, diagnostics)
+            }
+            Err(e) => (false, vec![check::spawn_failed(&config, &e)]),
+        };
+
+        let diagnostics = check::finalize_diagnostics(&config, Some(&extract.text), diagnostics);
+        let output = std::fs::read_to_string(output.path()).unwrap_or_default();
+        Ok(commands::PreviewBlockResult { output, success, diagnostics, complete: extract.complete })
+    }
+
+    /// Checks the block of a document around a position on its own, from a
+    /// copy of just that block, as `ott-lsp.checkBlock` does.
+    fn check_block(&self, params: &ExecuteCommandParams) -> Result<commands::CheckBlockResult, String> {
+        let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+        let uri = &doc.text_document.uri;
+        let extract = self.documents.with(uri, |open| commands::extract_block(&open.text, doc.position))
+            .ok_or_else(|| format!("{} is not open", uri.as_str()))?
+            .ok_or("position is not in a block")?;
+
+        let config = self.config.read().clone();
+        let mut temp = check::TempFile::write(&config.temp_dir(), &uri_path(uri), &extract.text, true)
+            .map_err(|e| format!("failed to write block: {e}"))?;
+
+        if config.keep_temp_files {
+            temp.keep();
+            self.log_message(MessageType::LOG, format!("kept block at {}", temp.path().display()));
+        }
+
+        let files = [temp.path().to_path_buf()];
+        let diagnostics = match check::run(&config, &files, &|message| self.trace(message)) {
+            Ok(output) => check::diagnostics(&config, &output, &files, temp.path(), uri),
+            Err(e) => vec![check::spawn_failed(&config, &e)],
+        };
+
+        let diagnostics = check::finalize_diagnostics(&config, Some(&extract.text), diagnostics);
+
+        Ok(commands::CheckBlockResult { diagnostics, complete: extract.complete })
+    }
+
+    /// Generates one target from the saved theory of a document, as
+    /// `ott-lsp.generate` does, returning what ott generated rather than
+    /// writing it anywhere.
+    fn preview_backend(&self, params: &ExecuteCommandParams) -> Result<commands::PreviewBackendResult, String> {
+        let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+        let target: String = commands::argument(params, 1)?;
+        let config = self.config.read().clone();
+        let source = uri_path(&doc.uri);
+        let name = commands::output_path(&config.output_paths, &target, &source)
+            .and_then(|output| output.file_name().map(PathBuf::from))
+            .ok_or_else(|| format!("unknown target `{target}`: give it a path in `outputPaths`"))?;
+
+        let output = check::TempFile::output(&config.temp_dir(), &name)
+            .map_err(|e| format!("failed to create output file: {e}"))?;
+
+        let files = theory::files_for(&self.theory(), &source);
+        let args = config.generation_args(&target);
+        let (success, diagnostics) = match check::generate(&config, &files, output.path(), &args, &|message| self.trace(message)) {
+            Ok(run) => {
+                let diagnostics = check::diagnostics(&config, &run, &files, &source, &doc.uri);
+                let errors = diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+                (run.status.success() && !errors, diagnostics)
+            }
+            Err(e) => (false, vec![check::spawn_failed(&config, &e)]),
+        };
+
+        let text = std::fs::read_to_string(&source).ok();
+        let diagnostics = check::finalize_diagnostics(&config, text.as_deref(), diagnostics);
+        let output = std::fs::read_to_string(output.path()).unwrap_or_default();
+        Ok(commands::PreviewBackendResult { output, success, diagnostics })
+    }
+
+    /// Has ott parse a sample as a nonterminal of the saved theory of a
+    /// document, to test the nonterminal's productions on their own.
+    fn test_production(&self, params: &ExecuteCommandParams) -> Result<commands::TestProductionResult, String> {
+        let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+        let nonterminal: String = commands::argument(params, 1)?;
+        let sample: String = commands::argument(params, 2)?;
+        let config = self.config.read().clone();
+        let source = uri_path(&doc.uri);
+        let files = theory::files_for(&self.theory(), &source);
+
+        // ott only takes a nonterminal's own names, not words using them.
+        let declared = files.iter()
+            .filter_map(|file| std::fs::read_to_string(file).ok())
+            .any(|text| parse::Document::parse(&text).grammars.iter()
+                .flat_map(|rule| &rule.names)
+                .any(|name| name.text == nonterminal));
+
+        if !declared {
+            return Err(format!("no nonterminal `{nonterminal}` is declared in the saved theory"));
+        }
+
+        let (parses, output, diagnostics) = match check::parse_sample(&config, &files, &nonterminal, &sample, &|message| self.trace(message)) {
+            Ok(run) => {
+                let diagnostics = check::diagnostics(&config, &run, &files, &source, &doc.uri);
+                let errors = diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+                let output = String::from_utf8_lossy(&run.stdout).trim().to_string();
+                (run.status.success() && !errors, output, diagnostics)
+            }
+            Err(e) => (false, String::new(), vec![check::spawn_failed(&config, &e)]),
+        };
+
+        let text = std::fs::read_to_string(&source).ok();
+        let diagnostics = check::finalize_diagnostics(&config, text.as_deref(), diagnostics);
+        Ok(commands::TestProductionResult { parses, output, diagnostics })
+    }
+
+    /// The command line a check of `uri` would run, as Markdown, along with
+    /// whatever else about the check isn't apparent from it.
+    fn show_command(&self, uri: &Uri) -> String {
+        let config = self.config.read().clone();
+        let path = uri_path(uri);
+        let files = theory::files_for(&self.theory(), &path);
+        let mut markdown = format!("```sh\n{}\n```", check::command_line(&config, &files));
+
+        let saved = std::fs::read(&path).ok();
+        let unsaved = self.documents.with(uri, |open| saved.as_deref() != Some(open.text.as_bytes()));
+        if unsaved == Some(true) {
+            let temp = config.temp_dir();
+            let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+            markdown += &match check::STDIN.filter(|_| config.use_stdin && files.len() == 1) {
+                Some(stdin) => format!("\n\n`{name}` has unsaved changes, so they're piped to `ott` and checked \
+                    as `{stdin}` in its place."),
+                None => format!("\n\n`{name}` has unsaved changes, so a copy of them in `{}` is checked in its place.",
+                    temp.display()),
+            };
+        }
+
+        if !config.pre_check.is_empty() {
+            let command: Vec<_> = config.pre_check.iter().map(|arg| check::quote(arg)).collect();
+            markdown += &format!("\n\n`preCheck` runs `{}` first.", command.join(" "));
+        }
+
+        markdown
+    }
+
+    /// Collects a document's embeds for a target, from its buffer if it's
+    /// open, and writes them where `extractPaths` says, if it says.
+    fn extract_embeds(&self, params: &ExecuteCommandParams) -> Result<commands::ExtractEmbedsResult, String> {
+        let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+        let target: String = commands::argument(params, 1)?;
+        let comments: bool = match params.arguments.len() > 2 {
+            true => commands::argument(params, 2)?,
+            false => false,
+        };
+
+        let source = uri_path(&doc.uri);
+        let parsed = match self.documents.parsed(&doc.uri) {
+            Some(parsed) => parsed,
+            None => {
+                let text = std::fs::read_to_string(&source).map_err(|e| format!("{}: {e}", source.display()))?;
+                Arc::new(parse::Document::parse(&text))
+            }
+        };
+
+        let name = source.file_name().map(|name| name.to_string_lossy());
+        let mut result = commands::extract_embeds(&parsed, &target, name.as_deref().filter(|_| comments))
+            .ok_or_else(|| format!("{} has no `{target}` embeds", source.display()))?;
+
+        let config = self.config.read().clone();
+        if config.extract_paths.contains_key(&target)
+            && let Some(output) = commands::output_path(&config.extract_paths, &target, &source)
+        {
+            if let Some(dir) = output.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+            }
+
+            std::fs::write(&output, &result.text).map_err(|e| format!("{}: {e}", output.display()))?;
+            result.output = Some(output);
+        }
+
+        Ok(result)
     }
 
-    publish_diagnostics(uri.clone(), diagnostics, &connection)
+    /// Finds the rule or production at a position in the LaTeX generated from
+    /// its document, where `outputPaths` says that is, by its name.
+    fn reveal_in_output(&self, params: &ExecuteCommandParams) -> Result<Location, String> {
+        let doc: TextDocumentPositionParams = commands::argument(params, 0)?;
+        let uri = &doc.text_document.uri;
+        let (text, parsed) = self.documents.snapshot(uri);
+        let anchors = commands::tex_anchors(&parsed, &text, doc.position)
+            .ok_or("position is not in a rule or production")?;
+
+        let config = self.config.read().clone();
+        let output = commands::output_path(&config.output_paths, "tex", &uri_path(uri))
+            .ok_or("no path for `tex` in `outputPaths`")?;
+
+        let generated = std::fs::read_to_string(&output).map_err(|e| format!("{}: {e}", output.display()))?;
+        let range = commands::find_anchor(&generated, &anchors)
+            .ok_or_else(|| format!("`{}` isn't in {}: is it up to date?", anchors[0], output.display()))?;
+
+        let output = path_uri(&output).ok_or_else(|| format!("{} isn't a valid URI", output.display()))?;
+        Ok(Location::new(output, range))
+    }
+
+    /// Runs ott over the saved theory of a document to generate one of its
+    /// targets, telling the user how it went.
+    fn generate(&self, params: &ExecuteCommandParams) -> Result<commands::GenerateResult, String> {
+        let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+        let target: String = commands::argument(params, 1)?;
+        let config = self.config.read().clone();
+        let mut result = self.generate_target(&config, &doc.uri, &target)?;
+        if result.success && config.show_generated && self.can_show_documents {
+            result.shown = self.show_generated(&result.output);
+        }
+
+        let (typ, message) = result.message(&target);
+        if let Err(e) = self.show_message(typ, message) {
+            eprintln!("failed to show message: {e}");
+        }
+
+        Ok(result)
+    }
+
+    /// Asks the client to open `output` without taking focus from the source,
+    /// returning whether it was asked.
+    fn show_generated(&self, output: &Path) -> bool {
+        let Some(uri) = path_uri(output) else { return false };
+        let params = ShowDocumentParams { uri, external: Some(false), take_focus: Some(false), selection: None };
+        match self.send_request("window/showDocument", params) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("failed to show generated output: {e}");
+                false
+            }
+        }
+    }
+
+    /// Generates each of the configured targets from the theory of the
+    /// document, one after the other or, if the second argument is `true`, up
+    /// to `maxParallelChecks` at a time. Each step is logged, and reported as
+    /// progress when the client can show it.
+    fn generate_all(&self, params: &ExecuteCommandParams) -> Result<commands::GenerateAllResult, String> {
+        let doc: TextDocumentIdentifier = commands::argument(params, 0)?;
+        let parallel: bool = match params.arguments.len() > 1 {
+            true => commands::argument(params, 1)?,
+            false => false,
+        };
+
+        let config = self.config.read().clone();
+        let targets = commands::configured_targets(&config.output_paths);
+        let token = params.work_done_progress_params.work_done_token.clone();
+        let progress = self.begin_progress(token, "Generating");
+        let done = AtomicUsize::new(0);
+        let step = |target: &String| {
+            self.log_message(MessageType::LOG, format!("generating {target}"));
+            let result = self.generate_target(&config, &doc.uri, target);
+            let (typ, message) = match &result {
+                Ok(result) => result.message(target),
+                Err(e) => (MessageType::ERROR, format!("failed to generate {target}: {e}")),
+            };
+
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            if let Some(token) = &progress {
+                self.progress(token, WorkDoneProgress::Report(WorkDoneProgressReport {
+                    message: Some(message.clone()),
+                    percentage: Some((done * 100 / targets.len()) as u32),
+                    cancellable: None,
+                }));
+            }
+
+            self.log_message(typ, message);
+            (target.clone(), result)
+        };
+
+        let results: Vec<_> = match parallel {
+            true => std::thread::scope(|scope| {
+                targets.chunks(config.max_parallel_checks())
+                    .flat_map(|chunk| {
+                        let steps: Vec<_> = chunk.iter().map(|target| scope.spawn(|| step(target))).collect();
+                        steps.into_iter().map(|step| step.join().expect("generation doesn't panic")).collect::<Vec<_>>()
+                    })
+                    .collect()
+            }),
+            false => targets.iter().map(step).collect(),
+        };
+
+        let mut summary = commands::GenerateAllResult::default();
+        for (target, result) in results {
+            match result {
+                Ok(result) => {
+                    if !result.success {
+                        summary.failed.push(target.clone());
+                    }
+
+                    summary.targets.insert(target, result);
+                }
+                Err(_) => summary.failed.push(target),
+            }
+        }
+
+        let generated = targets.len() - summary.failed.len();
+        let (typ, message) = match summary.failed.is_empty() {
+            true => (MessageType::INFO, format!("generated all {generated} targets")),
+            false => (MessageType::ERROR, format!("generated {generated} of {} targets; failed: {}",
+                targets.len(), summary.failed.join(", "))),
+        };
+
+        if let Some(token) = &progress {
+            let end = WorkDoneProgressEnd { message: Some(message.clone()) };
+            self.progress(token, WorkDoneProgress::End(end));
+        }
+
+        if let Err(e) = self.show_message(typ, message) {
+            eprintln!("failed to show message: {e}");
+        }
+
+        Ok(summary)
+    }
+
+    /// Runs ott over the saved theory of `uri` to generate `target` at the
+    /// path `outputPaths` gives it.
+    fn generate_target(&self, config: &Config, uri: &Uri, target: &str) -> Result<commands::GenerateResult, String> {
+        let source = uri_path(uri);
+        let output = commands::output_path(&config.output_paths, target, &source)
+            .ok_or_else(|| format!("unknown target `{target}`: give it a path in `outputPaths`"))?;
+
+        if let Some(dir) = output.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+        }
+
+        let files = theory::files_for(&self.theory(), &source);
+        let args = config.generation_args(target);
+        let (success, diagnostics) = match check::generate(config, &files, &output, &args, &|message| self.trace(message)) {
+            Ok(run) => {
+                let diagnostics = check::diagnostics(config, &run, &files, &source, uri);
+                let errors = diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+                (run.status.success() && !errors, diagnostics)
+            }
+            Err(e) => (false, vec![check::spawn_failed(config, &e)]),
+        };
+
+        let (formatted_by, post_generate_error) = match success {
+            true => match check::post_generate(config, target, &output) {
+                Ok(program) => (program, None),
+                Err(e) => (None, Some(e)),
+            },
+            false => (None, None),
+        };
+
+        let text = std::fs::read_to_string(&source).ok();
+        let diagnostics = check::finalize_diagnostics(config, text.as_deref(), diagnostics);
+        Ok(commands::GenerateResult { output, success, diagnostics, formatted_by, post_generate_error, shown: false })
+    }
+
+    /// Searches the open documents and, for files that aren't open, the
+    /// workspace index.
+    fn workspace_symbols(&self, query: &str, cancelled: &dyn Fn() -> bool) -> Option<Vec<WorkspaceSymbol>> {
+        let mut symbols = vec![];
+        let mut open = vec![];
+        self.documents.for_each(|uri, doc| {
+            if !cancelled() {
+                symbols.extend(navigation::workspace_symbols(&doc.parsed(), uri, query));
+            }
+
+            open.push(uri_path(uri));
+        });
+
+        self.index.for_each(|path, doc| {
+            if open.iter().any(|o| theory::same_file(o, path)) || cancelled() {
+                return;
+            }
+
+            if let Some(uri) = path_uri(path) {
+                symbols.extend(navigation::workspace_symbols(doc, &uri, query));
+            }
+        });
+
+        (!cancelled()).then_some(symbols)
+    }
+
+    /// Finds the declaration of the metavar or nonterminal `word` uses in the
+    /// files other than `uri` in its theory or, failing that, the workspace.
+    fn declaration_elsewhere(&self, uri: &Uri, word: &str) -> Option<GotoDefinitionResponse> {
+        let path = uri_path(uri);
+        let found = |file: &Path, doc: &parse::Document| {
+            let name = navigation::declaration(doc, word)?;
+            Some(GotoDefinitionResponse::Scalar(Location::new(path_uri(file)?, name.range)))
+        };
+
+        for file in theory::files_for(&self.theory(), &path) {
+            if theory::same_file(&file, &path) {
+                continue;
+            }
+
+            // Prefer what's in the editor to what's on disk.
+            let mut open = None;
+            self.documents.for_each(|uri, doc| {
+                if open.is_none() && theory::same_file(&uri_path(uri), &file) {
+                    open = Some(doc.parsed());
+                }
+            });
+
+            let doc = open.unwrap_or_else(|| {
+                Arc::new(parse::Document::parse(&std::fs::read_to_string(&file).unwrap_or_default()))
+            });
+
+            if let Some(definition) = found(&file, &doc) {
+                return Some(definition);
+            }
+        }
+
+        let mut definition = None;
+        self.index.for_each(|file, doc| {
+            if definition.is_none() && !theory::same_file(file, &path) {
+                definition = found(file, doc);
+            }
+        });
+
+        definition
+    }
+
+    /// The files a rename in `uri` renames in: `uri` itself, then the other
+    /// files of its theory, preferring what's open to what's on disk.
+    fn rename_files(&self, uri: &Uri) -> Vec<rename::File> {
+        let path = uri_path(uri);
+        let (text, doc) = self.documents.snapshot(uri);
+        let mut files = vec![(uri.clone(), text, doc)];
+        for file in theory::files_for(&self.theory(), &path) {
+            if theory::same_file(&file, &path) {
+                continue;
+            }
+
+            let mut open = None;
+            self.documents.for_each(|uri, doc| {
+                if open.is_none() && theory::same_file(&uri_path(uri), &file) {
+                    open = Some((uri.clone(), doc.text.clone(), doc.parsed()));
+                }
+            });
+
+            files.extend(open.or_else(|| {
+                let text = std::fs::read_to_string(&file).ok()?;
+                let doc = Arc::new(parse::Document::parse(&text));
+                Some((path_uri(&file)?, text, doc))
+            }));
+        }
+
+        files
+    }
+
+    /// Points ott's complaints about names declared more than once at their
+    /// declarations in every file we know of, if `relateDuplicates` is set.
+    fn relate_duplicates(&self, config: &Config, diagnostics: &mut [Diagnostic]) {
+        if config.relate_duplicates && check::has_duplicates(diagnostics) {
+            check::relate_duplicates(diagnostics, &self.theory_documents());
+        }
+    }
+
+    /// Every ott file the server knows of: the open documents, the files of
+    /// the theory, and the workspace index, preferring what's open to what's
+    /// on disk.
+    fn theory_documents(&self) -> Vec<(Uri, parse::Document)> {
+        let mut docs = vec![];
+        self.documents.for_each(|uri, open| docs.push((uri.clone(), uri_path(uri), (*open.parsed()).clone())));
+
+        let known = |docs: &[(Uri, PathBuf, parse::Document)], path: &Path| {
+            docs.iter().any(|(_, p, _)| theory::same_file(p, path))
+        };
+
+        for path in self.theory() {
+            if !known(&docs, &path) && let Some(uri) = path_uri(&path) {
+                let text = std::fs::read_to_string(&path).unwrap_or_default();
+                docs.push((uri, path, parse::Document::parse(&text)));
+            }
+        }
+
+        self.index.for_each(|path, doc| {
+            if !known(&docs, path) && let Some(uri) = path_uri(path) {
+                docs.push((uri, path.to_path_buf(), doc.clone()));
+            }
+        });
+
+        docs.into_iter().map(|(uri, _, doc)| (uri, doc)).collect()
+    }
+
+    /// The file in the workspace that ott means by `file`, which isn't one
+    /// of the files it was run on, as when one of them includes it: `file`
+    /// itself, if it's absolute, or the one ott file in the workspace whose
+    /// path ends with it. `None` if there's no such file, or several.
+    fn workspace_file(&self, file: &str) -> Option<PathBuf> {
+        let file = Path::new(file);
+        if file.is_absolute() {
+            return file.is_file().then(|| file.to_path_buf());
+        }
+
+        let respect_ignores = self.config.read().respect_gitignore();
+        let mut found: Vec<_> = self.roots.iter()
+            .flat_map(|root| workspace::ott_files(root, respect_ignores))
+            .filter(|path| path.ends_with(file))
+            .collect();
+
+        match found.len() {
+            1 => found.pop(),
+            _ => None,
+        }
+    }
+
+    fn theory(&self) -> Vec<PathBuf> {
+        theory::resolve(&self.config.read().theory, self.roots.first().map(|r| r.as_path()))
+    }
+
+    /// Recomputes which files the open documents depend on and, if that set
+    /// changed, asks the client to watch the new set instead.
+    fn update_dependencies(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let theory = self.theory();
+        let includes = theory::includes(&self.config.read().ott_flags);
+        let uris = self.documents.uris();
+        let open = uris.iter().map(|uri| (uri, uri_path(uri)));
+        let dependents = theory::Dependents::build(&theory, &includes, open);
+
+        let unchanged = dependents.files() == self.dependents.read().files();
+        let watched = self.dependents.read().files().len();
+        let watchers: Vec<_> = dependents.files().iter()
+            .map(|path| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(path.display().to_string()),
+                kind: None,
+            })
+            .collect();
+
+        *self.dependents.write() = dependents;
+        if unchanged || !self.can_watch_files {
+            return Ok(());
+        }
+
+        if watched > 0 {
+            let unregistration = Unregistration {
+                id: DEPENDENCY_WATCHER.into(),
+                method: "workspace/didChangeWatchedFiles".into(),
+            };
+
+            self.send_request("client/unregisterCapability", UnregistrationParams {
+                unregisterations: vec![unregistration],
+            })?;
+        }
+
+        if !watchers.is_empty() {
+            let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+            let registration = Registration {
+                id: DEPENDENCY_WATCHER.into(),
+                method: "workspace/didChangeWatchedFiles".into(),
+                register_options: Some(serde_json::to_value(options)?),
+            };
+
+            self.send_request("client/registerCapability", RegistrationParams {
+                registrations: vec![registration],
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Tells the client where the embeds in `uri` are, if it asked to know.
+    fn send_embed_regions(&self, uri: &Uri) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.config.read().embed_regions {
+            return Ok(());
+        }
+
+        if let Some(doc) = self.documents.parsed(uri) {
+            let params = embeds::embed_regions(&doc, uri.clone());
+            let notification = Notification::new(embeds::EMBED_REGIONS.into(), params);
+            self.connection.sender.send(Message::Notification(notification))?;
+        }
+
+        Ok(())
+    }
+
+    fn show_message(&self, typ: MessageType, message: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let notification = Notification::new("window/showMessage".into(), ShowMessageParams { typ, message });
+        self.connection.sender.send(Message::Notification(notification))?;
+        Ok(())
+    }
+
+    /// Sends `message` to the client as a `$/logTrace`, unless it asked for
+    /// no tracing.
+    fn log_trace(&self, message: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if *self.trace.read() != TraceValue::Off {
+            let notification = Notification::new("$/logTrace".into(), LogTraceParams { message, verbose: None });
+            self.connection.sender.send(Message::Notification(notification))?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs ott once and throws away the result, so that the OS has it
+    /// loaded by the time the first document is checked.
+    fn prewarm(&self) {
+        let config = self.config.read().clone();
+        let started = Instant::now();
+        let message = match check::prewarm(&config) {
+            Ok(status) => format!("prewarmed `{}` in {:?} ({status})", config.ott_path(), started.elapsed()),
+            Err(e) => format!("failed to prewarm `{}`: {e}", config.ott_path()),
+        };
+
+        self.trace(message);
+    }
+
+    /// Sends `message` to the client as a `$/logTrace`, as
+    /// [`Server::log_trace()`] does, reporting failure to do so.
+    fn trace(&self, message: String) {
+        if let Err(e) = self.log_trace(message) {
+            eprintln!("failed to log trace: {e}");
+        }
+    }
+
+    /// Queues a check of every ott file in the workspace that isn't open, if
+    /// `check_on_initialize` is set, so that their diagnostics are published
+    /// without the user opening each one. Clients that pull diagnostics ask
+    /// for the workspace's themselves.
+    fn check_workspace<'s>(&'s self, scope: &'s Scope<'s, '_>) {
+        self.await_config();
+        let config = self.config.read().clone();
+        if !config.check_on_initialize {
+            return;
+        }
+
+        let open: Vec<_> = self.documents.uris().iter().map(uri_path).collect();
+        for path in self.roots.iter().flat_map(|root| workspace::ott_files(root, config.respect_gitignore())) {
+            if open.iter().any(|o| theory::same_file(o, &path)) {
+                continue;
+            }
+
+            if let Some(uri) = path_uri(&path) {
+                self.schedule_check(scope, uri);
+            }
+        }
+    }
+
+    /// Tells the user about any settings in effect that we don't know, which
+    /// are otherwise ignored without a word, and about a temp directory that
+    /// can't be written to, which would fail every check of an unsaved
+    /// document. The temp directory is only probed at first, given no
+    /// `previous` settings, and when it or how ott runs has changed since.
+    fn warn_about_settings(&self, previous: Option<&Config>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let config = self.config.read().clone();
+        let unknown: Vec<_> = config.unknown.keys().map(|key| format!("`{key}`")).collect();
+        if !unknown.is_empty() {
+            let message = format!("ignoring unknown ott-lsp settings: {}", unknown.join(", "));
+            self.show_message(MessageType::WARNING, message)?;
+        }
+
+        let dir = config.temp_dir();
+        let probe = previous.is_none_or(|old| old.runs_ott_differently(&config) || old.temp_dir != config.temp_dir);
+        if probe && let Err(e) = check::TempFile::write(&dir, "probe".as_ref(), "", false) {
+            let message = format!("cannot write temp files to {}: {e}; set `tempDir` to another directory", dir.display());
+            self.show_message(MessageType::WARNING, message)?;
+        }
+
+        if let Some(Err(e)) = config.identifier_pattern.as_deref().map(Regex::new) {
+            let message = format!("invalid `identifierPattern`, using the default instead: {e}");
+            self.show_message(MessageType::WARNING, message)?;
+        }
+
+        let unknown: Vec<_> = config.lints.keys()
+            .filter(|id| !lint::LINTS.contains(&id.as_str()))
+            .map(|id| format!("`{id}`"))
+            .collect();
+
+        if !unknown.is_empty() {
+            let known: Vec<_> = lint::LINTS.iter().map(|id| format!("`{id}`")).collect();
+            let message = format!("ignoring unknown lints {}; known: {}", unknown.join(", "), known.join(", "));
+            self.show_message(MessageType::WARNING, message)?;
+        }
+
+        if let Some(name) = config.format_profile.as_deref()
+            && name != "auto"
+            && !check::PROFILES.iter().any(|profile| profile.name == name)
+        {
+            let names: Vec<_> = check::PROFILES.iter().map(|profile| format!("`{}`", profile.name)).collect();
+            let message = format!("unknown `formatProfile` `{name}`, choosing by ott's version; known: {}", names.join(", "));
+            self.show_message(MessageType::WARNING, message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `message` to the client's log, reporting failure to do so.
+    fn log_message(&self, typ: MessageType, message: String) {
+        let notification = Notification::new("window/logMessage".into(), LogMessageParams { typ, message });
+        if let Err(e) = self.connection.sender.send(Message::Notification(notification)) {
+            eprintln!("failed to log message: {e}");
+        }
+    }
+
+    /// Begins reporting progress on `title` with the client's `token` or, if
+    /// it gave none but can show progress, a token of our own. Returns the
+    /// token to report the rest of the progress with, if any.
+    fn begin_progress(&self, token: Option<ProgressToken>, title: &str) -> Option<ProgressToken> {
+        let token = match token {
+            Some(token) => token,
+            None if self.can_report_progress => {
+                let id = self.next_request_id.load(Ordering::Relaxed);
+                let token = ProgressToken::String(format!("ott-lsp/{id}"));
+                let params = WorkDoneProgressCreateParams { token: token.clone() };
+                if let Err(e) = self.send_request("window/workDoneProgress/create", params) {
+                    eprintln!("failed to create progress: {e}");
+                    return None;
+                }
+
+                token
+            }
+            None => return None,
+        };
+
+        let begin = WorkDoneProgressBegin { title: title.into(), percentage: Some(0), ..Default::default() };
+        self.progress(&token, WorkDoneProgress::Begin(begin));
+        Some(token)
+    }
+
+    fn progress(&self, token: &ProgressToken, progress: WorkDoneProgress) {
+        let params = ProgressParams { token: token.clone(), value: ProgressParamsValue::WorkDone(progress) };
+        let notification = Notification::new("$/progress".into(), params);
+        if let Err(e) = self.connection.sender.send(Message::Notification(notification)) {
+            eprintln!("failed to report progress: {e}");
+        }
+    }
+
+    fn send_request<P: Serialize>(&self, method: &str, params: P) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let request = Request::new(id.into(), method.into(), params);
+        self.connection.sender.send(Message::Request(request))?;
+        Ok(())
+    }
+
+    /// Publishes the diagnostics a check of `primary` found in the `others` of
+    /// its `files`, and in any other files ott read, for those that aren't
+    /// open and so have no checks of their own, and clears any published
+    /// before that are gone now.
+    fn publish_theory(
+        &self,
+        config: &Config,
+        files: &[PathBuf],
+        primary: &Path,
+        mut others: HashMap<PathBuf, Vec<Diagnostic>>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // The files outside the theory this check last found something in
+        // are cleared if it doesn't now, like the theory's own.
+        let elsewhere: Vec<_> = others.keys().filter(|file| !files.contains(file)).cloned().collect();
+        let before = self.elsewhere_published.lock().insert(primary.to_path_buf(), elsewhere.clone());
+        let gone = before.into_iter().flatten().filter(|file| !elsewhere.contains(file));
+        let files: Vec<_> = files.iter().cloned().chain(elsewhere.iter().cloned()).chain(gone).collect();
+
+        let open: Vec<_> = self.documents.uris().iter().map(uri_path).collect();
+        for file in &files {
+            let skip = theory::same_file(file, primary) || open.iter().any(|o| theory::same_file(o, file));
+            let Some(uri) = path_uri(file).filter(|_| !skip) else { continue };
+            let diagnostics = others.remove(file).unwrap_or_default();
+            let published = match diagnostics.is_empty() {
+                true => self.theory_published.lock().remove(&uri),
+                false => {
+                    self.theory_published.lock().insert(uri.clone());
+                    true
+                }
+            };
+
+            if published {
+                let text = std::fs::read_to_string(file).ok();
+                let diagnostics = check::finalize_diagnostics(config, text.as_deref(), diagnostics);
+                self.tally.record(&uri, &diagnostics);
+                let diagnostics = self.debug_data(config, None, diagnostics);
+                publish_diagnostics(uri, None, diagnostics, &self.connection)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `diagnostics`, about to be published for `version` of their document,
+    /// with `debugDiagnosticData`, with that version and the number of this
+    /// publish, counting from 1, as the `data` of those without any.
+    fn debug_data(&self, config: &Config, version: Option<i32>, mut diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        if !config.debug_diagnostic_data {
+            return diagnostics;
+        }
+
+        let run = self.publishes.fetch_add(1, Ordering::SeqCst) + 1;
+        for diagnostic in diagnostics.iter_mut().filter(|d| d.data.is_none()) {
+            diagnostic.data = Some(serde_json::json!({ "version": version, "run": run }));
+        }
+
+        diagnostics
+    }
+
+    /// Sends the diagnostics for `version` of `uri` to the client: in
+    /// response to the pulls waiting on them or, for clients that don't pull,
+    /// as a push.
+    fn report_diagnostics(
+        &self,
+        uri: Uri,
+        version: Option<i32>,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.documents.get(&uri).is_some() {
+            self.reported.write().insert(uri.clone(), diagnostics.clone());
+        }
+
+        self.tally.record(&uri, &diagnostics);
+        let diagnostics = self.debug_data(&self.config.read(), version, diagnostics);
+
+        if !self.pull_diagnostics {
+            return publish_diagnostics(uri, version, diagnostics, &self.connection);
+        }
+
+        // Nothing asked for this check, so something the client doesn't know
+        // about changed, like a file in the theory. Have it ask again.
+        let pulls = self.pulls.lock().remove(&uri).unwrap_or_default();
+        if pulls.is_empty() && self.can_refresh_diagnostics {
+            return self.send_request("workspace/diagnostic/refresh", ());
+        }
+
+        let result_id = result_id(&diagnostics);
+        for (id, previous) in pulls {
+            let report = match previous == Some(result_id.clone()) {
+                true => DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                        result_id: result_id.clone(),
+                    },
+                }),
+                false => DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                    related_documents: None,
+                    full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                        result_id: Some(result_id.clone()),
+                        items: diagnostics.clone(),
+                    },
+                }),
+            };
+
+            respond(&self.connection, id, DocumentDiagnosticReportResult::Report(report))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks every ott file in the workspace that isn't open. Clients pull
+    /// the diagnostics for open documents one at a time instead.
+    fn workspace_diagnostics(
+        &self,
+        params: &WorkspaceDiagnosticParams,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<WorkspaceDiagnosticReportResult> {
+        let config = self.config.read().clone();
+        let theory = self.theory();
+        let open: Vec<_> = self.documents.uris().iter().map(uri_path).collect();
+        let mut items = vec![];
+        for path in self.roots.iter().flat_map(|root| workspace::ott_files(root, config.respect_gitignore())) {
+            if cancelled() {
+                return None;
+            }
+
+            if open.iter().any(|o| theory::same_file(o, &path)) {
+                continue;
+            }
+
+            let Some(uri) = path_uri(&path) else { continue };
+            let files = theory::files_for(&theory, &path);
+            let mut diagnostics = match check::check_ott_file(&config, &self.cache, &path, &files, None, &uri, &check::Subprocess(&|message| self.trace(message)), &|_| None, &|_, _| {}) {
+                Ok(diagnostics) => diagnostics.primary,
+                Err(e) => {
+                    eprintln!("failed to check {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            let text = std::fs::read_to_string(&path).ok();
+            if let Some(text) = &text {
+                let doc = parse::Document::parse(text);
+                check::relate_unused(&mut diagnostics, &doc, &uri);
+                self.relate_duplicates(&config, &mut diagnostics);
+                diagnostics.extend(lint::lint(&config, &doc, text, &uri));
+            }
+
+            let diagnostics = check::finalize_diagnostics(&config, text.as_deref(), diagnostics);
+            self.tally.record(&uri, &diagnostics);
+            let result_id = result_id(&diagnostics);
+            let previous = params.previous_result_ids.iter().find(|p| p.uri == uri);
+            let report = match previous.is_some_and(|p| p.value == result_id) {
+                true => WorkspaceDocumentDiagnosticReport::Unchanged(WorkspaceUnchangedDocumentDiagnosticReport {
+                    uri,
+                    version: None,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
+                }),
+                false => WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                    uri,
+                    version: None,
+                    full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                        result_id: Some(result_id),
+                        items: diagnostics,
+                    },
+                }),
+            };
+
+            items.push(report);
+        }
+
+        Some(WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items }))
+    }
+
+    /// Drops the results of checks still running under the previous config
+    /// and rechecks every open document. Cached results are kept: they're
+    /// keyed by the settings they were checked with.
+    fn restart_checks<'s>(&'s self, scope: &'s Scope<'s, '_>) {
+        self.ott_generation.fetch_add(1, Ordering::SeqCst);
+        let mut uris = self.documents.uris();
+        uris.extend(self.pulls.lock().keys().cloned());
+        for uri in uris {
+            self.schedule_check(scope, uri);
+        }
+    }
+
+    /// Answers `id` on a worker thread with what `f` returns. `f` is passed a
+    /// function that returns `true` once the client cancels the request, and
+    /// should return `None` if it stopped early because of that.
+    fn spawn_request<'s, T: Serialize>(
+        &'s self,
+        scope: &'s Scope<'s, '_>,
+        id: RequestId,
+        f: impl FnOnce(&dyn Fn() -> bool) -> Option<T> + Send + 's,
+    ) {
+        self.in_flight.lock().insert(id.clone(), false);
+        scope.spawn(move || {
+            let cancelled = || self.in_flight.lock().get(&id).copied().unwrap_or(false);
+            let result = f(&cancelled);
+            self.in_flight.lock().remove(&id);
+            let sent = match result {
+                Some(result) => respond(&self.connection, id, result),
+                None => respond_err(&self.connection, id, ErrorCode::RequestCanceled, "cancelled".into()),
+            };
+
+            if let Err(e) = sent {
+                eprintln!("failed to respond: {e}");
+            }
+        });
+    }
+
+    /// Rechecks every open document, bypassing the cache, and answers `id`
+    /// with the totals once they've all been checked.
+    fn check_all<'s>(&'s self, scope: &'s Scope<'s, '_>, id: RequestId) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // `Uri` is a false positive: its interior mutability never affects its hash.
+        #[allow(clippy::mutable_key_type)]
+        let remaining: HashSet<_> = self.documents.uris().into_iter().collect();
+        if remaining.is_empty() {
+            return respond(&self.connection, id, commands::CheckAllResult::default());
+        }
+
+        let uris: Vec<_> = remaining.iter().cloned().collect();
+        self.check_alls.lock().push(CheckAll { id, remaining, summary: Default::default() });
+        self.cache.write().clear();
+        for uri in uris {
+            self.schedule_check(scope, uri);
+        }
+
+        Ok(())
+    }
+
+    /// Counts the check of `uri` towards every `checkAll` waiting on it,
+    /// answering those that aren't waiting on anything else.
+    fn count_check(&self, uri: &Uri, diagnostics: &[Diagnostic]) {
+        let count = |severity| diagnostics.iter().filter(|d| d.severity == Some(severity)).count();
+        self.check_alls.lock().retain_mut(|pending| {
+            if pending.remaining.remove(uri) {
+                pending.summary.files += 1;
+                pending.summary.errors += count(DiagnosticSeverity::ERROR);
+                pending.summary.warnings += count(DiagnosticSeverity::WARNING);
+            }
+
+            if !pending.remaining.is_empty() {
+                return true;
+            }
+
+            let _ = respond(&self.connection, pending.id.clone(), &pending.summary);
+            false
+        });
+    }
+
+    /// Whether the open document `uri` is as it is on disk, so that ott
+    /// reading the file reads what's in the editor.
+    fn is_saved(&self, uri: &Uri) -> bool {
+        let saved = std::fs::read(uri_path(uri)).ok();
+        self.documents.with(uri, |doc| saved.as_deref() == Some(doc.text.as_bytes())).unwrap_or(false)
+    }
+
+    /// Has the running check of another file of `uri`'s theory report on
+    /// `uri` too, if theory opens are batched and `uri` is open and saved, so
+    /// that one ott run serves both. Returns whether it will.
+    fn ride_along(&self, uri: &Uri) -> bool {
+        let config = self.config.read().clone();
+        let path = uri_path(uri);
+        let checked = self.documents.with(uri, |doc| config.checks_language(&doc.language_id));
+        let theory = self.theory();
+        if !config.batches_theory_opens()
+            || checked != Some(true)
+            || !config.checks_path(&path, &self.roots)
+            || theory.len() < 2
+            || !theory.iter().any(|file| theory::same_file(file, &path))
+            || !self.is_saved(uri)
+        {
+            return false;
+        }
+
+        let mut riders = self.riders.lock();
+        let Some(host) = riders.iter_mut().find(|(host, _)| *host != uri).map(|(_, riders)| riders) else {
+            return false;
+        };
+
+        if !host.contains(uri) {
+            host.push(uri.clone());
+        }
+
+        true
+    }
+
+    /// Reports the diagnostics the check of another file of its theory found
+    /// in the open document `uri`, out of `others`, along with its lints.
+    /// Does nothing if `uri` has since changed or closed: a check of its own
+    /// is on its way, if it needs one.
+    fn report_rider(
+        &self,
+        config: &Config,
+        uri: &Uri,
+        others: &mut HashMap<PathBuf, Vec<Diagnostic>>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(open) = self.documents.get(uri).filter(|_| self.is_saved(uri)) else { return Ok(()) };
+        let path = uri_path(uri);
+        let file = others.keys().find(|file| theory::same_file(file, &path)).cloned();
+        let mut diagnostics = file.and_then(|file| others.remove(&file)).unwrap_or_default();
+
+        let doc = open.parsed();
+        check::relate_unused(&mut diagnostics, &doc, uri);
+        self.relate_duplicates(config, &mut diagnostics);
+        diagnostics.extend(lint::lint(config, &doc, &open.text, uri));
+        let diagnostics = check::finalize_diagnostics(config, Some(&open.text), diagnostics);
+        self.count_check(uri, &diagnostics);
+        self.report_diagnostics(uri.clone(), Some(open.version), diagnostics)
+    }
+
+    /// Waits out what's left of the grace period after startup, or until the
+    /// client sends settings, whichever is first, so that a client opening a
+    /// document before sending them doesn't have it checked with defaults.
+    fn await_config(&self) {
+        let deadline = self.started + self.config.read().config_grace();
+        while !self.configured.load(Ordering::SeqCst) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Queues a check of `uri` once it's gone `debounceMs` without changing,
+    /// so that typing doesn't run ott on every keystroke.
+    fn debounce_check<'s>(&'s self, scope: &'s Scope<'s, '_>, uri: Uri) {
+        if self.changes.push(uri, self.config.read().debounce()) {
+            scope.spawn(move || {
+                while let Some(uri) = self.changes.next() {
+                    self.schedule_check(scope, uri);
+                }
+            });
+        }
+    }
+
+    /// Runs one of the `SLOW_COMMANDS` and answers `id` with its result.
+    fn run_command(&self, id: RequestId, params: ExecuteCommandParams) {
+        let result = match params.command.as_str() {
+            commands::GENERATE => self.generate(&params).map(|result| serde_json::json!(result)),
+            commands::GENERATE_ALL => self.generate_all(&params).map(|result| serde_json::json!(result)),
+            commands::PREVIEW_BLOCK => self.preview_block(&params).map(|result| serde_json::json!(result)),
+            commands::PREVIEW_BACKEND => self.preview_backend(&params).map(|result| serde_json::json!(result)),
+            commands::TEST_PRODUCTION => self.test_production(&params).map(|result| serde_json::json!(result)),
+            commands::BUILD_THEORY => self.build_theory().map(|result| serde_json::json!(result)),
+            commands::CHECK_BLOCK => self.check_block(&params).map(|result| serde_json::json!(result)),
+            _ => self.project_diagnostics().map(|result| serde_json::json!(result)),
+        };
+
+        let sent = match result {
+            Ok(result) => respond(&self.connection, id, result),
+            Err(e) => respond_err(&self.connection, id, ErrorCode::InvalidParams, e),
+        };
+
+        if let Err(e) = sent {
+            eprintln!("failed to report result of {}: {e}", params.command);
+        }
+    }
+
+    /// Queues a check of `uri`, to be run on a worker thread once fewer than
+    /// `max_parallel_checks` checks are running.
+    fn schedule_check<'s>(&'s self, scope: &'s Scope<'s, '_>, uri: Uri) {
+        if self.ride_along(&uri) {
+            return;
+        }
+
+        self.schedule(scope, Job::Check(uri));
+    }
+
+    /// Queues `job`, to be run on a worker thread once fewer than
+    /// `max_parallel_checks` jobs, each running ott, are running.
+    fn schedule<'s>(&'s self, scope: &'s Scope<'s, '_>, job: Job) {
+        if self.jobs.push(job, self.config.read().max_parallel_checks()) {
+            scope.spawn(move || {
+                self.await_config();
+                while let Some(job) = self.jobs.next() {
+                    match job {
+                        Job::Check(uri) => self.check(scope, uri),
+                        Job::Command(id, params) => self.run_command(id, params),
+                    }
+                }
+            });
+        }
+    }
+
+    /// Checks `uri` and reports what was found.
+    fn check<'s>(&'s self, scope: &'s Scope<'s, '_>, uri: Uri) {
+        // Read the generation first: if the config changes after
+        // this, the result is dropped even if it was read in time.
+        let generation = self.ott_generation.load(Ordering::SeqCst);
+        let config = self.config.read().clone();
+        let path = uri_path(&uri);
+        let files = theory::files_for(&self.theory(), &path);
+        let open = self.documents.get(&uri);
+        let buffer = open.as_ref().map(|doc| doc.text.as_str());
+
+        // Documents in other languages, or outside the workspace
+        // when those aren't checked, are reported clean, so that
+        // any pulls and `checkAll`s waiting on them finish.
+        let checked = open.as_ref().is_none_or(|doc| config.checks_language(&doc.language_id))
+            && config.checks_path(&path, &self.roots);
+
+        if checked && files.len() > 1 && config.batches_theory_opens() {
+            self.riders.lock().entry(uri.clone()).or_default();
+        }
+
+        let started = Instant::now();
+        let result = match checked {
+            true => {
+                let log = |typ, message| self.log_message(typ, message);
+                let trace = |message| self.trace(message);
+                let resolve = |file: &str| self.workspace_file(file);
+                check::check_ott_file(&config, &self.cache, &path, &files, buffer, &uri, &check::Subprocess(&trace), &resolve, &log)
+            }
+            false => Ok(check::Diagnostics::default()),
+        };
+
+        // Whatever joined the check while ott ran is reported
+        // from its results, or failing that, checked on its own.
+        let mut riders = self.riders.lock().remove(&uri).unwrap_or_default();
+        let result = result
+            .and_then(|check::Diagnostics { primary: mut diagnostics, mut others }| {
+                if checked && let Some(open) = &open {
+                    // When ott can't say where it failed, the
+                    // last edit is the likeliest culprit.
+                    if let Some(edit) = open.last_edit {
+                        check::place_unlocated(&mut diagnostics, edit);
+                    }
+
+                    check::place_lineless(&config, &mut diagnostics, Some(&open.text), open.last_edit);
+
+                    let doc = open.parsed();
+                    check::relate_unused(&mut diagnostics, &doc, &uri);
+                    self.relate_duplicates(&config, &mut diagnostics);
+                    diagnostics.extend(lint::lint(&config, &doc, &open.text, &uri));
+                }
+
+                // A fresh check of `uri` is already queued.
+                if self.ott_generation.load(Ordering::SeqCst) != generation {
+                    return Ok(());
+                }
+
+                // So is one of its newer text, if it's changed
+                // since: don't report on what's been typed over.
+                let version = open.as_ref().map(|open| open.version);
+                let changed = self.documents.with(&uri, |now| Some(now.version) != version);
+                if !self.pull_diagnostics && changed == Some(true) {
+                    return Ok(());
+                }
+
+                for rider in std::mem::take(&mut riders) {
+                    self.report_rider(&config, &rider, &mut others)?;
+                }
+
+                if !self.pull_diagnostics {
+                    self.publish_theory(&config, &files, &path, others)?;
+                }
+
+                let diagnostics = check::finalize_diagnostics(&config, buffer, diagnostics);
+                self.log.timed("check", started.elapsed(), serde_json::json!({
+                    "uri": uri.as_str(),
+                    "files": files.len(),
+                    "diagnostics": diagnostics.len(),
+                }));
+
+                self.count_check(&uri, &diagnostics);
+                self.report_diagnostics(uri.clone(), open.as_ref().map(|doc| doc.version), diagnostics)
+            });
+
+        for rider in riders {
+            self.schedule_check(scope, rider);
+        }
+
+        if let Err(e) = result {
+            eprintln!("failed to check {}: {e}", uri.as_str());
+            self.log.write("error", "check", serde_json::json!({
+                "uri": uri.as_str(),
+                "error": e.to_string(),
+            }));
+
+            self.count_check(&uri, &[]);
+            for (id, _) in self.pulls.lock().remove(&uri).unwrap_or_default() {
+                let _ = respond_err(&self.connection, id, ErrorCode::InternalError, e.to_string());
+            }
+        }
+    }
+}
+
+fn respond<T: Serialize>(
+    connection: &Connection,
+    id: RequestId,
+    result: T,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    connection.sender.send(Message::Response(Response::new_ok(id, result)))?;
+    Ok(())
+}
+
+fn respond_err(
+    connection: &Connection,
+    id: RequestId,
+    code: ErrorCode,
+    message: String,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    connection.sender.send(Message::Response(Response::new_err(id, code as i32, message)))?;
+    Ok(())
+}
+
+
+fn publish_diagnostics(
+    uri: Uri,
+    version: Option<i32>,
+    diagnostics: Vec<Diagnostic>,
+    connection: &Connection,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let params = PublishDiagnosticsParams { uri, diagnostics, version };
+    let notification = Notification::new("textDocument/publishDiagnostics".to_string(), params);
+    connection.sender.send(Message::Notification(notification))?;
+    Ok(())
+}
+
+/// An id for `diagnostics` that changes when they do, so that clients that
+/// pull them can be told when theirs are still current.
+fn result_id(diagnostics: &[Diagnostic]) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(diagnostics).expect("diagnostics serialize").hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// How long to wait for the server to say something before giving up.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The client's end of a connection to a running server.
+    struct Client {
+        connection: Connection,
+        next_id: i32,
+        /// What the server sent that wasn't yet asked for, in order.
+        unread: Vec<Message>,
+    }
+
+    impl Client {
+        fn request(&mut self, method: &str, params: Value) -> Value {
+            let id = self.send(method, params);
+            self.response(id)
+        }
+
+        /// Sends a request without waiting for the response.
+        fn send(&mut self, method: &str, params: Value) -> RequestId {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), method.into(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            id
+        }
+
+        /// Waits for the response to the request `id`, which must succeed.
+        fn response(&mut self, id: RequestId) -> Value {
+            let response = self.receive(|message| match message {
+                Message::Response(response) if response.id == id => Some(response.clone()),
+                _ => None,
+            });
+
+            assert_eq!(response.error.map(|e| e.message), None);
+            response.result.unwrap_or_default()
+        }
+
+        fn notify(&mut self, method: &str, params: Value) {
+            let notification = Notification::new(method.into(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        /// Waits for the server to send something `wanted` picks out.
+        fn receive<T>(&mut self, wanted: impl Fn(&Message) -> Option<T>) -> T {
+            if let Some((i, found)) = self.unread.iter().enumerate().find_map(|(i, m)| Some((i, wanted(m)?))) {
+                self.unread.remove(i);
+                return found;
+            }
+
+            loop {
+                let message = self.connection.receiver.recv_timeout(TIMEOUT).expect("the server said nothing");
+                match wanted(&message) {
+                    Some(found) => return found,
+                    None => self.unread.push(message),
+                }
+            }
+        }
+
+        /// The next diagnostics the server publishes for `uri`.
+        fn published(&mut self, uri: &Uri) -> Vec<Diagnostic> {
+            self.receive(|message| match message {
+                Message::Notification(n) if n.method == "textDocument/publishDiagnostics" => {
+                    let params: PublishDiagnosticsParams = from_value(n.params.clone()).ok()?;
+                    (params.uri == *uri).then_some(params.diagnostics)
+                }
+                _ => None,
+            })
+        }
+
+        fn open(&mut self, path: &Path, text: &str) -> Uri {
+            let uri = path_uri(path).unwrap();
+            let document = json!({ "uri": uri, "languageId": "ott", "version": 1, "text": text });
+            self.notify("textDocument/didOpen", json!({ "textDocument": document }));
+            uri
+        }
+    }
+
+    /// Starts a server with the initialization options `settings`, and runs
+    /// `test` against it and a client of it, shutting the server down after.
+    fn serve(settings: Value, test: impl FnOnce(&Server, &mut Client)) {
+        serve_with(json!({ "capabilities": {}, "initializationOptions": settings }), test)
+    }
+
+    /// As [`serve()`], initializing the server with `params`.
+    fn serve_with(params: Value, test: impl FnOnce(&Server, &mut Client)) {
+        let (connection, client) = Connection::memory();
+        let mut client = Client { connection: client, next_id: 0, unread: vec![] };
+        let server = std::thread::scope(|scope| {
+            let server = scope.spawn(|| Server::start(connection, log::Log::default()).unwrap());
+            client.request("initialize", params);
+            client.notify("initialized", json!({}));
+            server.join().unwrap()
+        });
+
+        std::thread::scope(|scope| {
+            // A failed test still has to shut the server down, or the scope
+            // would wait on it forever.
+            let running = scope.spawn(|| server.run(scope).unwrap());
+            let tested = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(&server, &mut client)));
+            client.request("shutdown", Value::Null);
+            client.notify("exit", Value::Null);
+            running.join().unwrap();
+            if let Err(panic) = tested {
+                std::panic::resume_unwind(panic);
+            }
+        });
+    }
+
+    /// A directory of its own for a test to write to, removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = std::env::temp_dir().join(format!("ott-lsp-test-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Scratch(dir.canonicalize().unwrap())
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+
+        /// A stand-in for ott that prints what's in the file `output` here,
+        /// once there's no file `hold` here, noting each run in `runs`. Asked
+        /// its version, it says it's the newest.
+        #[cfg(unix)]
+        fn ott(&self) -> String {
+            use std::os::unix::fs::PermissionsExt;
+
+            let (output, hold, runs) = (self.write("output", ""), self.0.join("hold"), self.0.join("runs"));
+            let script = format!(
+                "#!/bin/sh\n[ \"$1\" = -version ] && echo 'Ott version 0.33' && exit\necho run >> '{}'\nwhile [ -e '{}' ]; do sleep 0.01; done\ncat '{}'\n",
+                runs.display(), hold.display(), output.display(),
+            );
+
+            let ott = self.write("ott", &script);
+            std::fs::set_permissions(&ott, std::fs::Permissions::from_mode(0o755)).unwrap();
+            ott.display().to_string()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const GRAMMAR: &str = "metavar termvar, x ::=\n  {{ com term variables }}\ngrammar\nt :: 't_' ::=\n  | x :: :: var\n";
+
+    #[test]
+    #[cfg(unix)]
+    fn fixing_an_error_publishes_and_caches_no_diagnostics() {
+        let scratch = Scratch::new("fix");
+        let settings = json!({ "ottPath": scratch.ott() });
+        let path = scratch.write("x.ott", GRAMMAR);
+        scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+        serve(settings, |server, client| {
+            let uri = client.open(&path, GRAMMAR);
+            let diagnostics = client.published(&uri);
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, "no parses");
+
+            let fixed = GRAMMAR.replace("| x", "| termvar");
+            std::fs::write(&path, &fixed).unwrap();
+            scratch.write("output", "");
+            client.notify("textDocument/didSave", json!({ "textDocument": { "uri": uri } }));
+
+            assert_eq!(client.published(&uri), vec![]);
+            let cache = server.cache.read();
+            assert!(cache.get(&uri).is_some_and(|cached| cached.diagnostics.primary.is_empty()));
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_block_answers_from_a_worker() {
+        let scratch = Scratch::new("check-block");
+        let settings = json!({ "ottPath": scratch.ott() });
+        let path = scratch.write("x.ott", GRAMMAR);
+        scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+        serve(settings, |_, client| {
+            let uri = client.open(&path, GRAMMAR);
+            client.published(&uri);
+
+            scratch.write("hold", "");
+            let position = json!({ "textDocument": { "uri": uri }, "position": { "line": 4, "character": 4 } });
+            let arguments = json!({ "command": commands::CHECK_BLOCK, "arguments": [position] });
+            let check = client.send("workspace/executeCommand", arguments);
+
+            // The message loop isn't held up by the check.
+            let symbols = client.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": uri } }));
+            assert!(!symbols.as_array().unwrap().is_empty());
+
+            std::fs::remove_file(scratch.0.join("hold")).unwrap();
+            let result = client.response(check);
+            assert_eq!(result["complete"], true);
+            let diagnostics: Vec<Diagnostic> = from_value(result["diagnostics"].clone()).unwrap();
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, "no parses");
+            assert_eq!(diagnostics[0].range.start, Position::new(4, 4));
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn commands_that_run_ott_wait_for_a_worker() {
+        let scratch = Scratch::new("slow-commands");
+        let settings = json!({ "ottPath": scratch.ott(), "maxParallelChecks": 1 });
+        let path = scratch.write("x.ott", GRAMMAR);
+
+        serve(settings, |_, client| {
+            let uri = client.open(&path, GRAMMAR);
+            client.published(&uri);
+
+            let runs = scratch.write("runs", "");
+            let count = || std::fs::read_to_string(&runs).unwrap().lines().count();
+            scratch.write("hold", "");
+            let position = json!({ "textDocument": { "uri": uri }, "position": { "line": 4, "character": 4 } });
+            let arguments = json!({ "command": commands::CHECK_BLOCK, "arguments": [position] });
+            let checks = [client.send("workspace/executeCommand", arguments.clone()), client.send("workspace/executeCommand", arguments)];
+
+            // Only one ott runs until the first lets go.
+            while count() == 0 {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            std::thread::sleep(Duration::from_millis(200));
+            assert_eq!(count(), 1);
+
+            std::fs::remove_file(scratch.0.join("hold")).unwrap();
+            for check in checks {
+                assert_eq!(client.response(check)["complete"], true);
+            }
+
+            assert_eq!(count(), 2);
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn retries_of_failed_runs_are_traced_when_asked() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // An ott that's there but can't be run, as while it's being installed.
+        let scratch = Scratch::new("retries");
+        let ott = scratch.write("ott", "#!/bin/sh\n");
+        std::fs::set_permissions(&ott, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let path = scratch.write("x.ott", GRAMMAR);
+
+        serve(json!({ "ottPath": ott }), |_, client| {
+            let traced = |message: &Message| match message {
+                Message::Notification(n) if n.method == "$/logTrace" => {
+                    from_value::<LogTraceParams>(n.params.clone()).ok().map(|params| params.message)
+                }
+                _ => None,
+            };
+
+            client.notify("$/setTrace", json!({ "value": "messages" }));
+            let uri = client.open(&path, GRAMMAR);
+            let retry = client.receive(traced);
+            assert!(retry.starts_with(&format!("failed to run {} (attempt 1): ", ott.display())), "{retry}");
+            client.published(&uri);
+
+            // The second attempt's retry is all that's traced once the client
+            // asks for no tracing.
+            client.notify("$/setTrace", json!({ "value": "off" }));
+            client.request("workspace/executeCommand", json!({ "command": commands::CHECK_ALL }));
+            let retries: Vec<_> = client.unread.iter().filter_map(traced).collect();
+            assert_eq!(retries.len(), 1);
+            assert!(retries[0].contains("(attempt 2)"), "{}", retries[0]);
+        });
+    }
+
+    #[test]
+    fn document_symbols_are_answered_from_a_worker() {
+        serve(json!({}), |server, client| {
+            let uri = client.open(Path::new("/x.ott"), GRAMMAR);
+            let symbols = client.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": uri } }));
+            let symbols: Vec<DocumentSymbol> = from_value(symbols).unwrap();
+
+            let names: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+            assert_eq!(names, [("termvar", SymbolKind::CONSTANT), ("x", SymbolKind::CONSTANT), ("t", SymbolKind::CLASS)]);
+            assert_eq!(symbols[2].children.as_ref().unwrap()[0].name, "var");
+            assert!(server.in_flight.lock().is_empty());
+        });
+    }
+
+    #[test]
+    fn inlay_hints_name_rules_unless_turned_off() {
+        let rules = "defns\nJop :: '' ::=\n\ndefn\nt done :: :: done :: D_ by\n\n----- :: var\nx done\n";
+        let hints = |settings| {
+            let mut hints = vec![];
+            serve(settings, |_, client| {
+                let uri = client.open(Path::new("/rules.ott"), rules);
+                let range = json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 8, "character": 0 } });
+                let params = json!({ "textDocument": { "uri": uri }, "range": range });
+                hints = from_value::<Vec<InlayHint>>(client.request("textDocument/inlayHint", params)).unwrap();
+            });
+
+            hints.into_iter()
+                .map(|hint| match hint.label {
+                    InlayHintLabel::String(label) => (hint.position, label),
+                    InlayHintLabel::LabelParts(_) => panic!("a string label"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(hints(json!({})), [(Position::new(6, 0), "D_var".to_string())]);
+        assert_eq!(hints(json!({ "inlayHints": false })), []);
+    }
+
+    #[test]
+    fn definitions_are_found_in_the_theorys_other_files() {
+        let scratch = Scratch::new("definition");
+        let grammar = scratch.write("grammar.ott", GRAMMAR);
+        let rules = "defns\nJop :: '' ::=\n\ndefn\nt done :: :: done :: D_ by\n\n----- :: var\nx done\n";
+        let path = scratch.write("rules.ott", rules);
+        let settings = json!({ "ottPath": "ott-lsp-test-no-ott", "theory": [grammar, path] });
+
+        serve(settings, |_, client| {
+            let uri = client.open(&path, rules);
+            let definition = |client: &mut Client, line, character| {
+                let position = json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } });
+                from_value::<Option<GotoDefinitionResponse>>(client.request("textDocument/definition", position)).unwrap()
+            };
+
+            // The grammar needn't be open.
+            let location = |range| GotoDefinitionResponse::Scalar(Location::new(path_uri(&grammar).unwrap(), range));
+            assert_eq!(definition(client, 4, 0), Some(location(Range::new(Position::new(3, 0), Position::new(3, 1)))));
+            assert_eq!(definition(client, 7, 0), Some(location(Range::new(Position::new(0, 17), Position::new(0, 18)))));
+            assert_eq!(definition(client, 4, 2), None);
+
+            // If it is, it's as it is in the editor.
+            client.open(&grammar, &format!("\n{GRAMMAR}"));
+            assert_eq!(definition(client, 4, 0), Some(location(Range::new(Position::new(4, 0), Position::new(4, 1)))));
+        });
+    }
+
+    #[test]
+    fn renames_reach_the_theorys_unopened_files() {
+        let scratch = Scratch::new("rename");
+        let grammar = scratch.write("grammar.ott", GRAMMAR);
+        let rules = "defns\nJop :: '' ::=\n\ndefn\nt done :: :: done :: D_ by\n\n----- :: var\nx done\n";
+        let path = scratch.write("rules.ott", rules);
+        let settings = json!({ "ottPath": "ott-lsp-test-no-ott", "theory": [grammar, path] });
+
+        serve(settings, |_, client| {
+            let uri = client.open(&path, rules);
+            let rename = |name: &str| json!({
+                "textDocument": { "uri": uri }, "position": { "line": 4, "character": 0 }, "newName": name,
+            });
+
+            let edit: WorkspaceEdit = from_value(client.request("textDocument/rename", rename("ty"))).unwrap();
+            let renamed = |start, end| TextEdit::new(Range::new(start, end), "ty".into());
+            let changes = |uri: &Uri| &edit.changes.as_ref().unwrap()[uri];
+            assert_eq!(edit.changes.as_ref().map(|changes| changes.len()), Some(2));
+            assert_eq!(changes(&uri), &[renamed(Position::new(4, 0), Position::new(4, 1))]);
+            assert_eq!(changes(&path_uri(&grammar).unwrap()), &[
+                renamed(Position::new(3, 0), Position::new(3, 1)),
+                renamed(Position::new(3, 6), Position::new(3, 7)),
+            ]);
+
+            // The new name mustn't be declared in any of them, opened or not.
+            let id = client.send("textDocument/rename", rename("termvar"));
+            let error = client.receive(|message| match message {
+                Message::Response(response) if response.id == id => response.error.clone(),
+                _ => None,
+            });
+            assert_eq!(error.message, "cannot rename `t` to `termvar`: `termvar` is already declared");
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn initialization_options_configure_the_first_check() {
+        let (first, second) = (Scratch::new("init-first"), Scratch::new("init-second"));
+        let (settings, later) = (json!({ "ottPath": first.ott() }), json!({ "ottPath": second.ott() }));
+        let path = first.write("x.ott", GRAMMAR);
+        first.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+        second.write("output", "File \"x.ott\" on line 4, column 1 - 1:\nError: undefined nonterminal t\n");
+
+        serve(settings, |_, client| {
+            let uri = client.open(&path, GRAMMAR);
+            let diagnostics = client.published(&uri);
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, "no parses");
+
+            // Settings sent later replace them.
+            client.notify("workspace/didChangeConfiguration", json!({ "settings": later }));
+            let diagnostics = client.published(&uri);
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, "undefined nonterminal t");
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn theory_files_opened_together_are_checked_in_one_run() {
+        let scratch = Scratch::new("batch");
+        let ott = scratch.ott();
+        let (first, second) = (scratch.write("a.ott", GRAMMAR), scratch.write("b.ott", GRAMMAR));
+        let settings = json!({ "ottPath": ott, "theory": [first, second] });
+        let output = "File \"a.ott\" on line 5, column 5 - 6:\nError: no parses\n\nFile \"b.ott\" on line 4, column 1 - 1:\nError: undefined nonterminal t\n";
+        scratch.write("output", output);
+
+        serve(settings, |server, client| {
+            scratch.write("hold", "");
+            let first = client.open(&first, GRAMMAR);
+            let deadline = Instant::now() + TIMEOUT;
+            while server.riders.lock().is_empty() {
+                assert!(Instant::now() < deadline, "the first check never started");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let second = client.open(&second, GRAMMAR);
+            std::fs::remove_file(scratch.0.join("hold")).unwrap();
+            let messages = |diagnostics: Vec<Diagnostic>| diagnostics.into_iter().map(|d| d.message).collect::<Vec<_>>();
+            assert_eq!(messages(client.published(&first)), ["no parses"]);
+            assert_eq!(messages(client.published(&second)), ["undefined nonterminal t"]);
+            assert_eq!(std::fs::read_to_string(scratch.0.join("runs")).unwrap(), "run\n");
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn documents_outside_the_workspace_can_be_left_unchecked() {
+        let (inside, outside) = (Scratch::new("inside"), Scratch::new("outside"));
+        let settings = json!({ "ottPath": inside.ott(), "checkOutsideWorkspace": false });
+        let (path, other) = (inside.write("x.ott", GRAMMAR), outside.write("x.ott", GRAMMAR));
+        inside.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+        let params = json!({ "capabilities": {}, "rootUri": path_uri(&inside.0), "initializationOptions": settings });
+        serve_with(params, |server, client| {
+            let uri = client.open(&other, GRAMMAR);
+            assert_eq!(client.published(&uri), vec![]);
+            assert!(server.cache.read().get(&uri).is_none(), "ott never ran");
+
+            let uri = client.open(&path, GRAMMAR);
+            assert_eq!(client.published(&uri).len(), 1);
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn documents_opened_before_settings_arrive_are_checked_with_them() {
+        let scratch = Scratch::new("grace");
+        let path = scratch.write("x.ott", GRAMMAR);
+        let ott = scratch.ott();
+        scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+        let settings = json!({ "ottPath": "ott-lsp-test-no-ott", "configGraceMs": 5000 });
+        serve(settings, |server, client| {
+            let uri = client.open(&path, GRAMMAR);
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(server.cache.read().get(&uri).is_none(), "the check waits for settings");
+
+            client.notify("workspace/didChangeConfiguration", json!({ "settings": { "ottPath": ott } }));
+            let messages: Vec<_> = client.published(&uri).into_iter().map(|d| d.message).collect();
+            assert_eq!(messages, ["no parses"]);
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn errors_in_included_files_are_published_under_their_own_uris() {
+        let scratch = Scratch::new("included");
+        let settings = json!({ "ottPath": scratch.ott() });
+        let (path, included) = (scratch.write("x.ott", GRAMMAR), scratch.write("included.ott", GRAMMAR));
+        scratch.write("output", "File \"included.ott\" on line 5, column 5 - 6:\nError: no parses\n\
+            File \"missing.ott\" on line 2, column 1 - 3:\nError: undefined nonterminal t\n");
+
+        let params = json!({ "capabilities": {}, "rootUri": path_uri(&scratch.0), "initializationOptions": settings });
+        serve_with(params, |_, client| {
+            let uri = client.open(&path, GRAMMAR);
+            let elsewhere = client.published(&path_uri(&included).unwrap());
+            assert_eq!(elsewhere.len(), 1);
+            assert_eq!(elsewhere[0].message, "no parses");
+            assert_eq!(elsewhere[0].range, Range::new(Position::new(4, 4), Position::new(4, 6)));
+
+            // A file that isn't in the workspace has its errors reported in
+            // the file checked.
+            let here: Vec<_> = client.published(&uri).into_iter().map(|d| d.message).collect();
+            assert_eq!(here, ["undefined nonterminal t"]);
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unopened_files_are_checked_at_startup_when_asked() {
+        let scratch = Scratch::new("initialize");
+        let settings = json!({ "ottPath": scratch.ott(), "checkOnInitialize": true });
+        let (first, second) = (scratch.write("a.ott", GRAMMAR), scratch.write("b.ott", GRAMMAR));
+        scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+        let params = json!({ "capabilities": {}, "rootUri": path_uri(&scratch.0), "initializationOptions": settings });
+        serve_with(params, |_, client| {
+            for path in [first, second] {
+                let diagnostics = client.published(&path_uri(&path).unwrap());
+                assert_eq!(diagnostics.len(), 1);
+                assert_eq!(diagnostics[0].message, "no parses");
+                assert_eq!(diagnostics[0].range, Range::new(Position::new(4, 4), Position::new(4, 6)));
+            }
+        });
+
+        // Otherwise ott waits for a file to be opened.
+        let scratch = Scratch::new("no-initialize");
+        let settings = json!({ "ottPath": scratch.ott() });
+        scratch.write("a.ott", GRAMMAR);
+        let params = json!({ "capabilities": {}, "rootUri": path_uri(&scratch.0), "initializationOptions": settings });
+        serve_with(params, |_, _| std::thread::sleep(Duration::from_millis(200)));
+        assert!(!scratch.0.join("runs").exists(), "ott never ran");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn strict_checks_fail_on_warnings() {
+        let scratch = Scratch::new("strict");
+        let path = scratch.write("x.ott", GRAMMAR);
+        let ott = scratch.ott();
+        scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nWarning: unused production\n");
+
+        let check = |strict: &[&str]| {
+            let args = ["--check", path.to_str().unwrap(), "--ott-path", &ott];
+            batch_check(args.iter().chain(strict).map(|arg| arg.to_string()).collect()).unwrap()
+        };
+
+        assert_eq!(check(&[]), ExitCode::SUCCESS);
+        assert_eq!(check(&["--strict"]), ExitCode::FAILURE);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn saves_check_again_where_edits_reuse_the_last_check() {
+        for force in [true, false] {
+            let scratch = Scratch::new(&format!("save-{force}"));
+            let settings = json!({ "ottPath": scratch.ott(), "debounceMs": 0, "forceCheckOnSave": force });
+            let path = scratch.write("x.ott", GRAMMAR);
+            scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+            let runs = || std::fs::read_to_string(scratch.0.join("runs")).unwrap().lines().count();
+
+            serve(settings, |_, client| {
+                let uri = client.open(&path, GRAMMAR);
+                assert_eq!(client.published(&uri).len(), 1);
+                assert_eq!(runs(), 1);
+
+                let document = json!({ "uri": uri, "version": 2 });
+                let changes = json!([{ "text": GRAMMAR }]);
+                client.notify("textDocument/didChange", json!({ "textDocument": document, "contentChanges": changes }));
+                assert_eq!(client.published(&uri).len(), 1);
+                assert_eq!(runs(), 1, "an edit that changes nothing reuses the last check");
+
+                client.notify("textDocument/didSave", json!({ "textDocument": { "uri": uri } }));
+                assert_eq!(client.published(&uri).len(), 1);
+                assert_eq!(runs(), if force { 2 } else { 1 });
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn diagnostics_can_carry_the_version_and_run_they_came_from() {
+        for debug in [true, false] {
+            let scratch = Scratch::new(&format!("debug-data-{debug}"));
+            let settings = json!({ "ottPath": scratch.ott(), "debounceMs": 0, "debugDiagnosticData": debug });
+            let path = scratch.write("x.ott", GRAMMAR);
+            scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+            serve(settings, |_, client| {
+                let uri = client.open(&path, GRAMMAR);
+                let data = client.published(&uri).remove(0).data;
+                assert_eq!(data, debug.then(|| json!({ "version": 1, "run": 1 })));
+
+                let document = json!({ "uri": uri, "version": 2 });
+                let changes = json!([{ "text": format!("{GRAMMAR}\n") }]);
+                client.notify("textDocument/didChange", json!({ "textDocument": document, "contentChanges": changes }));
+                let data = client.published(&uri).remove(0).data;
+                assert_eq!(data, debug.then(|| json!({ "version": 2, "run": 2 })));
+            });
+        }
+    }
+
+    #[test]
+    fn a_missing_ott_is_reported_without_stopping_the_server() {
+        let scratch = Scratch::new("missing-ott");
+        let (first, second) = (scratch.write("a.ott", GRAMMAR), scratch.write("b.ott", GRAMMAR));
+        let settings = json!({ "ottPath": scratch.0.join("no-ott") });
+        serve(settings, |_, client| {
+            let message = format!("`{}` not found: check `ottPath`", scratch.0.join("no-ott").display());
+            for path in [first, second] {
+                let uri = client.open(&path, GRAMMAR);
+                let diagnostics = client.published(&uri);
+                assert_eq!(diagnostics.len(), 1);
+                assert_eq!(diagnostics[0].message, message);
+                assert_eq!(diagnostics[0].range, Range::default());
+                assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+            }
+        });
+    }
+
+    #[test]
+    fn unwritable_temp_dirs_are_warned_about_once_each() {
+        let scratch = Scratch::new("temp-dir");
+        let (missing, other) = (scratch.0.join("missing"), scratch.0.join("other"));
+        serve(json!({}), |_, client| {
+            // The next message shown after changing the settings to `settings`.
+            let mut configure = |settings| {
+                client.notify("workspace/didChangeConfiguration", json!({ "settings": settings }));
+                client.receive(|message| match message {
+                    Message::Notification(n) if n.method == "window/showMessage" => {
+                        from_value::<ShowMessageParams>(n.params.clone()).ok().map(|params| params.message)
+                    }
+                    _ => None,
+                })
+            };
+
+            let cannot = |dir: &Path| format!("cannot write temp files to {}", dir.display());
+            assert!(configure(json!({ "tempDir": missing })).starts_with(&cannot(&missing)));
+
+            // Settings that leave it and ott alone don't probe it again.
+            let warning = configure(json!({ "tempDir": missing, "debounce": 10 }));
+            assert_eq!(warning, "ignoring unknown ott-lsp settings: `debounce`");
+
+            assert!(configure(json!({ "tempDir": other })).starts_with(&cannot(&other)));
+        });
+    }
+
+    #[test]
+    fn unknown_settings_are_warned_about_without_losing_the_rest() {
+        serve(json!({}), |server, client| {
+            let settings = json!({ "ottPath": "ott-lsp-test-no-ott", "ottFlag": ["-merge"], "debounce": 10, "debounceMs": 10 });
+            client.notify("workspace/didChangeConfiguration", json!({ "settings": settings }));
+            let message = client.receive(|message| match message {
+                Message::Notification(n) if n.method == "window/showMessage" => from_value::<ShowMessageParams>(n.params.clone()).ok(),
+                _ => None,
+            });
+
+            assert_eq!(message.typ, MessageType::WARNING);
+            assert_eq!(message.message, "ignoring unknown ott-lsp settings: `debounce`, `ottFlag`");
+            let config = server.config.read();
+            assert_eq!(config.ott_path.as_deref(), Some("ott-lsp-test-no-ott"));
+            assert_eq!(config.debounce_ms, Some(10));
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn documents_in_other_languages_arent_checked() {
+        let scratch = Scratch::new("language");
+        let settings = json!({ "ottPath": scratch.ott() });
+        let (tex, ott) = (scratch.write("x.mng", GRAMMAR), scratch.write("x.ott", GRAMMAR));
+        scratch.write("output", "File \"x.ott\" on line 5, column 5 - 6:\nError: no parses\n");
+
+        serve(settings, |server, client| {
+            let uri = path_uri(&tex).unwrap();
+            let document = json!({ "uri": uri, "languageId": "latex", "version": 1, "text": GRAMMAR });
+            client.notify("textDocument/didOpen", json!({ "textDocument": document }));
+            assert_eq!(client.published(&uri), vec![]);
+            assert!(server.cache.read().get(&uri).is_none(), "ott never ran");
+
+            let uri = client.open(&ott, GRAMMAR);
+            assert_eq!(client.published(&uri).len(), 1);
+        });
+    }
 }
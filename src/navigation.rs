@@ -0,0 +1,378 @@
+//! Hover and go-to-definition over the parsed document model.
+
+use lsp_types::*;
+use regex::Regex;
+
+use crate::parse::{self, Defn, Document, Fun, Name};
+
+fn contains(range: &Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+/// Returns the id of the production named at `position`, whether by its
+/// declaration's label or by a reference in a `parsing` section.
+fn production_at(doc: &Document, position: Position) -> Option<&str> {
+    let declared = doc.grammars.iter()
+        .flat_map(|rule| &rule.productions)
+        .find(|p| contains(&p.label.range, position))
+        .map(|p| p.id.as_str());
+
+    declared.or_else(|| precedence_reference_at(doc, position).map(|name| name.text.as_str()))
+}
+
+fn precedence_reference_at(doc: &Document, position: Position) -> Option<&Name> {
+    doc.precedences.iter()
+        .flat_map(|p| [&p.left, &p.right])
+        .find(|name| contains(&name.range, position))
+}
+
+fn markdown(value: String) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+        range: None,
+    }
+}
+
+/// Describes the scope of the binder at `position`, whether it's the binder
+/// of a `bind` or one of its occurrences in the production's body.
+fn binder_hover(doc: &Document, position: Position) -> Option<Hover> {
+    let (production, binder) = doc.grammars.iter()
+        .flat_map(|rule| &rule.productions)
+        .find_map(|p| {
+            let binders = p.binds.iter().map(|b| &b.binder);
+            let name = binders.chain(&p.body).find(|n| contains(&n.range, position))?;
+            p.binds.iter().any(|b| b.binder.text == name.text).then_some((p, name))
+        })?;
+
+    let scopes: Vec<_> = production.binds.iter()
+        .filter(|b| b.binder.text == binder.text)
+        .map(|b| format!("`{}`", b.scope.text))
+        .collect();
+
+    let label = &production.label.text;
+    Some(markdown(format!("`{}`: binder in `{label}`, bound in {}", binder.text, scopes.join(", "))))
+}
+
+/// The judgement's display name, if it has one, alongside its name.
+fn judgement(defn: &Defn) -> String {
+    match &defn.display {
+        Some(display) => format!("{display} (`{}`)", defn.name.text),
+        None => format!("`{}`", defn.name.text),
+    }
+}
+
+/// Describes the judgement or inference rule named at `position`.
+fn judgement_hover(doc: &Document, position: Position) -> Option<Hover> {
+    for (defns, defn) in doc.defns.iter().flat_map(|d| d.defns.iter().map(move |defn| (d, defn))) {
+        if contains(&defn.name.range, position) {
+            return Some(markdown(format!("{}: judgement in `{}`", judgement(defn), defns.name.text)));
+        }
+
+        if let Some(rule) = defn.rules.iter().find(|r| contains(&r.name.range, position)) {
+            return Some(markdown(format!("`{}`: rule of {}", rule.id, judgement(defn))));
+        }
+    }
+
+    None
+}
+
+/// Explains the diagnostics in `diagnostics` that cover `position`: what each
+/// says, and what it says about the other places it involves.
+pub fn diagnostic_hover(diagnostics: &[Diagnostic], position: Position) -> Option<Hover> {
+    let explanations: Vec<_> = diagnostics.iter()
+        .filter(|d| contains(&d.range, position))
+        .map(|d| {
+            let source = d.source.as_deref().map(|s| format!("{s}: ")).unwrap_or_default();
+            let mut value = format!("{source}{}", d.message);
+            for related in d.related_information.iter().flatten() {
+                value.push_str(&format!("\n\n- line {}: {}", related.location.range.start.line + 1, related.message));
+            }
+
+            value
+        })
+        .collect();
+
+    (!explanations.is_empty()).then(|| markdown(explanations.join("\n\n---\n\n")))
+}
+
+pub fn hover(doc: &Document, position: Position) -> Option<Hover> {
+    if let Some(hover) = binder_hover(doc, position).or_else(|| judgement_hover(doc, position)) {
+        return Some(hover);
+    }
+
+    let id = production_at(doc, position)?;
+    let mut value = match doc.production(id) {
+        Some((rule, _)) => {
+            let rule = rule.names.first().map_or("", |n| n.text.as_str());
+            format!("`{id}`: production of `{rule}`")
+        }
+        None => format!("`{id}`: undeclared production"),
+    };
+
+    if let Some(level) = doc.precedence_level(id) {
+        value.push_str(&format!("\n\nPrecedence level {level}:\n"));
+        for p in doc.precedences_of(id) {
+            let keyword = p.relation.keyword();
+            value.push_str(&format!("\n- `{} {keyword} {}`", p.left.text, p.right.text));
+        }
+    }
+
+    Some(markdown(value))
+}
+
+/// Describes the function named at `position` in `doc`, whose text is `text`
+/// and whose identifiers match `identifier`, whether by its definition or an
+/// invocation of it: its form, what it returns, and its clauses.
+pub fn function_hover(doc: &Document, text: &str, position: Position, identifier: &Regex) -> Option<Hover> {
+    let fun = doc.function(&parse::name_at(text, position, identifier)?.text)?;
+    let form: Vec<_> = fun.form.iter().map(|word| word.text.as_str()).collect();
+    let result = fun.result.as_ref().map(|r| format!(" returning `{}`", r.text)).unwrap_or_default();
+    let mut value = format!("`{}`: function `{}`{result}", fun.name.text, form.join(" "));
+    if !fun.clauses.is_empty() {
+        value.push_str(&format!("\n\n```ott\n{}\n```", fun.clauses.join("\n")));
+    }
+
+    Some(markdown(value))
+}
+
+/// Whether `word` is a use of `root`: `root` itself or `root` with one of
+/// ott's suffixes, like `t'` or `x1` or `x_2`.
+pub fn is_use(word: &str, root: &str) -> bool {
+    word.strip_prefix(root)
+        .is_some_and(|suffix| suffix.chars().all(|c| c.is_ascii_digit() || c == '\'' || c == '_'))
+}
+
+/// The declaration of the metavar or nonterminal `word` is a use of, if
+/// `doc` declares it.
+pub fn declaration<'d>(doc: &'d Document, word: &str) -> Option<&'d Name> {
+    let metavars = doc.metavars.iter().flat_map(|m| &m.names);
+    let nonterminals = doc.grammars.iter().flat_map(|rule| &rule.names);
+    metavars.chain(nonterminals)
+        .filter(|name| is_use(word, &name.text))
+        .max_by_key(|name| name.text.len())
+}
+
+/// The declaration of the production, function, metavar, or nonterminal at
+/// `position` in `doc`, whose text is `text` and whose identifiers match
+/// `identifier`, or of the judgement of the rule named there.
+pub fn definition(
+    doc: &Document,
+    text: &str,
+    uri: &Uri,
+    position: Position,
+    identifier: &Regex,
+) -> Option<GotoDefinitionResponse> {
+    let judgement = doc.defns.iter()
+        .flat_map(|block| &block.defns)
+        .find(|defn| defn.rules.iter().any(|rule| contains(&rule.name.range, position)));
+
+    let range = match (precedence_reference_at(doc, position), judgement) {
+        (Some(reference), _) => doc.production(&reference.text)?.1.label.range,
+        (None, Some(defn)) => defn.name.range,
+        (None, None) => {
+            let name = parse::name_at(text, position, identifier)?;
+            match doc.function(&name.text) {
+                Some(fun) => fun.name.range,
+                None => declaration(doc, &name.text)?.range,
+            }
+        }
+    };
+
+    Some(GotoDefinitionResponse::Scalar(Location::new(uri.clone(), range)))
+}
+
+/// The judgements of `doc` whose form the premise at `position` has, as
+/// compared by their terminals alone, as the judgement lint compares them.
+/// For a name in the premise, this is what to go to once [`definition()`]
+/// and the rest of the theory have no declaration of it.
+pub fn premise_judgement(doc: &Document, uri: &Uri, position: Position) -> Option<GotoDefinitionResponse> {
+    let judgements = || doc.defns.iter().flat_map(|block| &block.defns);
+    let terminals = |words: &[Name]| -> Vec<String> {
+        words.iter()
+            .filter(|word| declaration(doc, &word.text).is_none())
+            .map(|word| word.text.clone())
+            .collect()
+    };
+
+    let premise = judgements()
+        .flat_map(|defn| &defn.rules)
+        .flat_map(|rule| &rule.premises)
+        .find(|premise| premise.iter().any(|word| contains(&word.range, position)))?;
+
+    let used = terminals(premise);
+    let mut locations: Vec<_> = judgements()
+        .filter(|defn| terminals(&defn.form) == used)
+        .map(|defn| Location::new(uri.clone(), defn.name.range))
+        .collect();
+
+    match locations.len() {
+        0 => None,
+        1 => locations.pop().map(GotoDefinitionResponse::Scalar),
+        _ => Some(GotoDefinitionResponse::Array(locations)),
+    }
+}
+
+/// The metavariables, nonterminals, productions, and functions declared in
+/// `doc` whose names contain `query`, ignoring case.
+pub fn workspace_symbols(doc: &Document, uri: &Uri, query: &str) -> Vec<WorkspaceSymbol> {
+    let query = query.to_lowercase();
+    let metavars = doc.metavars.iter()
+        .flat_map(|m| &m.names)
+        .map(|name| (name, SymbolKind::CONSTANT, None));
+
+    let rules = doc.grammars.iter().flat_map(|rule| {
+        let container = rule.names.first().map(|n| n.text.clone());
+        let names = rule.names.iter().map(|name| (name, SymbolKind::CLASS, None));
+        let productions = rule.productions.iter()
+            .map(move |p| (&p.label, SymbolKind::CONSTRUCTOR, container.clone()));
+
+        names.chain(productions)
+    });
+
+    let funs = doc.funs.iter().flat_map(|funs| {
+        funs.funs.iter().map(|fun| (&fun.name, SymbolKind::FUNCTION, Some(funs.name.text.clone())))
+    });
+
+    metavars.chain(rules)
+        .chain(funs)
+        .filter(|(name, ..)| name.text.to_lowercase().contains(&query))
+        .map(|(name, kind, container_name)| WorkspaceSymbol {
+            name: name.text.clone(),
+            kind,
+            tags: None,
+            container_name,
+            location: OneOf::Left(Location::new(uri.clone(), name.range)),
+            data: None,
+        })
+        .collect()
+}
+
+/// A symbol for `name`, spanning from it to the end of the last of its
+/// `children`, if any.
+#[allow(deprecated)]
+fn symbol(name: &Name, kind: SymbolKind, detail: Option<String>, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+    let end = children.iter().map(|child| child.range.end).fold(name.range.end, Position::max);
+    DocumentSymbol {
+        name: name.text.clone(),
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: Range::new(name.range.start, end),
+        selection_range: name.range,
+        children: (!children.is_empty()).then_some(children),
+    }
+}
+
+fn function_symbol(fun: &Fun) -> DocumentSymbol {
+    let form: Vec<_> = fun.form.iter().map(|word| word.text.as_str()).collect();
+    symbol(&fun.name, SymbolKind::FUNCTION, Some(form.join(" ")), vec![])
+}
+
+/// The outline of `doc`: its metavars, its nonterminals with their
+/// productions, its judgements with their rules, and its functions.
+pub fn document_symbols(doc: &Document) -> Vec<DocumentSymbol> {
+    let metavars = doc.metavars.iter()
+        .flat_map(|m| &m.names)
+        .map(|name| symbol(name, SymbolKind::CONSTANT, None, vec![]));
+
+    let rules = doc.grammars.iter().filter(|rule| !rule.names.is_empty()).map(|rule| {
+        let productions = rule.productions.iter()
+            .map(|p| DocumentSymbol { range: p.range, ..symbol(&p.label, SymbolKind::CONSTRUCTOR, None, vec![]) })
+            .collect();
+
+        let synonyms: Vec<_> = rule.names[1..].iter().map(|name| name.text.as_str()).collect();
+        let detail = (!synonyms.is_empty()).then(|| synonyms.join(", "));
+        symbol(&rule.names[0], SymbolKind::CLASS, detail, productions)
+    });
+
+    let defns = doc.defns.iter().map(|defns| {
+        let judgements = defns.defns.iter()
+            .map(|defn| {
+                // A rule spans its premises too, which are above its name.
+                let rules = defn.rules.iter()
+                    .map(|rule| {
+                        let symbol = symbol(&rule.name, SymbolKind::METHOD, Some(rule.conclusion.clone()), vec![]);
+                        let start = rule.premises.iter().flatten()
+                            .map(|word| word.range.start)
+                            .fold(symbol.range.start, Position::min);
+                        DocumentSymbol { range: Range::new(start, symbol.range.end), ..symbol }
+                    })
+                    .collect();
+
+                symbol(&defn.name, SymbolKind::STRUCT, defn.display.clone(), rules)
+            })
+            .collect();
+
+        symbol(&defns.name, SymbolKind::NAMESPACE, None, judgements)
+    });
+
+    let funs = doc.funs.iter().map(|funs| {
+        symbol(&funs.name, SymbolKind::NAMESPACE, None, funs.funs.iter().map(function_symbol).collect())
+    });
+
+    metavars.chain(rules).chain(defns).chain(funs).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A theory with a function, and a rule that invokes it.
+    const FUNS: &str = r"metavar termvar, x ::=
+grammar
+t :: 't_' ::=
+  | x :: :: var
+  | t t' :: :: app
+
+funs
+Fv :: '' ::=
+
+fun
+fv ( t ) :: termvars :: fv by
+fv ( x ) === x
+fv ( t t' ) === fv ( t ) fv ( t' )
+
+defns
+Jop :: '' ::=
+
+defn
+t closed :: :: closed :: C_ by
+
+x notin fv ( t )
+----- :: closed
+t closed
+";
+
+    fn identifier() -> Regex {
+        Regex::new(parse::IDENTIFIER).unwrap()
+    }
+
+    #[test]
+    fn functions_are_in_the_outline_under_their_block() {
+        let symbols = document_symbols(&Document::parse(FUNS));
+        let funs = symbols.last().unwrap();
+        assert_eq!((funs.name.as_str(), funs.kind), ("Fv", SymbolKind::NAMESPACE));
+
+        let [fv] = funs.children.as_deref().unwrap() else { panic!("one function: {funs:?}") };
+        assert_eq!((fv.name.as_str(), fv.kind, fv.detail.as_deref()), ("fv", SymbolKind::FUNCTION, Some("fv ( t )")));
+        assert_eq!(fv.selection_range, Range::new(Position::new(10, 24), Position::new(10, 26)));
+    }
+
+    #[test]
+    fn invocations_of_functions_go_to_and_describe_them() {
+        let (doc, uri) = (Document::parse(FUNS), "file:///x.ott".parse().unwrap());
+        let definition = Location::new(Uri::clone(&uri), Range::new(Position::new(10, 24), Position::new(10, 26)));
+        for invocation in [Position::new(20, 9), Position::new(12, 17)] {
+            let found = super::definition(&doc, FUNS, &uri, invocation, &identifier());
+            assert_eq!(found, Some(GotoDefinitionResponse::Scalar(definition.clone())));
+        }
+
+        let Some(Hover { contents: HoverContents::Markup(hover), .. }) = function_hover(&doc, FUNS, Position::new(20, 9), &identifier()) else {
+            panic!("a hover");
+        };
+
+        assert_eq!(hover.value, "`fv`: function `fv ( t )` returning `termvars`\n\n```ott\nfv ( x ) === x\nfv ( t t' ) === fv ( t ) fv ( t' )\n```");
+        assert!(function_hover(&doc, FUNS, Position::new(20, 0), &identifier()).is_none());
+    }
+}
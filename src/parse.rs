@@ -0,0 +1,1603 @@
+//! A lightweight, error-tolerant parser for the structure of ott files.
+//!
+//! This doesn't attempt to understand ott the way ott does. It only recovers
+//! enough structure (declarations, their names, and where they are) to power
+//! navigation features. Anything it doesn't recognize is skipped.
+
+use std::sync::Arc;
+
+use lsp_types::{Position, Range};
+use regex::Regex;
+use serde::Serialize;
+
+/// The keywords that begin a top-level section when they start a line.
+pub const SECTION_KEYWORDS: &[&str] = &[
+    "metavar", "indexvar", "grammar", "embed", "subrules", "contextrules",
+    "substitutions", "freevars", "defns", "funs", "parsing", "homs",
+];
+
+/// Words that mean something to ott wherever they appear in a declaration.
+pub const KEYWORDS: &[&str] = &["defn", "by", "bind", "in"];
+
+/// What an identifier looks like by default: a letter or underscore,
+/// followed by any letters, digits, primes, and underscores, like `t1'`.
+pub const IDENTIFIER: &str = r"[A-Za-z_][A-Za-z0-9_']*";
+
+/// Whether `word` is an ott keyword rather than a name.
+pub fn is_keyword(word: &str) -> bool {
+    SECTION_KEYWORDS.contains(&word) || KEYWORDS.contains(&word)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A maximal run of non-whitespace characters.
+    Word,
+    /// A `{{ ... }}` hom or embed, possibly spanning lines.
+    Hom,
+    /// A `%` comment, running to the end of the line.
+    Comment,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub range: Range,
+    /// Whether this is the first token on its line.
+    pub line_start: bool,
+}
+
+impl Token<'_> {
+    pub fn is(&self, word: &str) -> bool {
+        self.kind == TokenKind::Word && self.text == word
+    }
+}
+
+/// A name as written in the source, along with where it was written.
+#[derive(Debug, Clone, Serialize)]
+pub struct Name {
+    pub text: String,
+    pub range: Range,
+}
+
+/// A metavariable declaration: `metavar termvar , x ::= ...`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Metavar {
+    pub names: Vec<Name>,
+    /// The `%` comment just above the declaration, if any.
+    pub doc: Option<String>,
+}
+
+/// A grammar rule: `t , u :: 't_' ::= | ... `.
+#[derive(Debug, Clone, Serialize)]
+pub struct GrammarRule {
+    pub names: Vec<Name>,
+    pub prefix: String,
+    pub productions: Vec<Production>,
+    /// The `%` comment just above the rule's header, if any.
+    pub doc: Option<String>,
+}
+
+/// A single `| body :: flags :: Label` production of a grammar rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct Production {
+    /// The constructor label, as written after the second `::`.
+    pub label: Name,
+    /// The label prefixed with its rule's prefix, e.g. `t_App`. This is the
+    /// name the `parsing` section refers to the production by.
+    pub id: String,
+    /// The words of the production's body, between the `|` and first `::`.
+    pub body: Vec<Name>,
+    /// The production's `(+ bind x in t +)` binding specifications.
+    pub binds: Vec<Bind>,
+    /// The production's `{{ target ... }}` homs, in order.
+    pub homs: Vec<Hom>,
+    /// The range of the whole production, from the `|` to its last token.
+    pub range: Range,
+}
+
+/// A `{{ target ... }}` hom attached to a declaration.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hom {
+    /// The hom's target: `tex`, `coq`, `com`, and so on.
+    pub target: String,
+    /// The range of the target alone.
+    pub target_range: Range,
+    pub range: Range,
+    /// The range of the hom's body, after its target and before its `}}`.
+    pub content: Range,
+    /// The text of the hom's body.
+    pub body: String,
+    /// Where the token before the hom ends.
+    pub preceding: Position,
+}
+
+impl Hom {
+    fn shift(&mut self, lines: i64) {
+        for range in [&mut self.target_range, &mut self.range, &mut self.content] {
+            shift_range(range, lines);
+        }
+
+        shift_position(&mut self.preceding, lines);
+    }
+}
+
+/// Collects the homs in `tokens`. The first token is never a hom.
+fn homs(tokens: &[Token<'_>]) -> Vec<Hom> {
+    tokens.windows(2)
+        .filter(|pair| pair[1].kind == TokenKind::Hom)
+        .filter_map(|pair| hom(&pair[1], pair[0].range.end))
+        .collect()
+}
+
+/// Every hom in `text`, whatever it's attached to, but not those nested in
+/// others.
+pub fn all_homs(text: &str) -> Vec<Hom> {
+    homs(&tokenize(text))
+}
+
+/// The hom `token`, which follows a token ending at `preceding`.
+fn hom(token: &Token<'_>, preceding: Position) -> Option<Hom> {
+    let target = token.text.trim_start_matches('{').split_whitespace().next()?;
+    let target = target.trim_end_matches('}');
+
+    // Offset the target's and body's bounds from the start of the hom token.
+    let target_start = token.text.find(target)?;
+    let target_end = target_start + target.len();
+    let start = target_end + (token.text[target_end..].len() - token.text[target_end..].trim_start().len());
+    let body = token.text.strip_suffix("}}").unwrap_or(token.text).trim_end();
+    let end = body.len().max(start);
+    let mut cursor = Cursor { text: token.text, offset: 0, position: token.range.start };
+    cursor.bump_str(&token.text[..target_start]);
+    let target_range_start = cursor.position;
+    cursor.bump_str(target);
+    let target_range = Range::new(target_range_start, cursor.position);
+    cursor.bump_str(&token.text[target_end..start]);
+    let content_start = cursor.position;
+    cursor.bump_str(&token.text[start..end]);
+
+    Some(Hom {
+        target: target.into(),
+        target_range,
+        range: token.range,
+        content: Range::new(content_start, cursor.position),
+        body: token.text[start..end].into(),
+        preceding,
+    })
+}
+
+/// The hom or embed a position is in, as [`embed_context_at()`] finds it.
+#[derive(Debug, Clone)]
+pub struct EmbedContext {
+    /// The hom's target, like `tex` or `coq`: the language of its body.
+    pub target: String,
+    /// The whole `{{ ... }}`, including any homs nested in it.
+    pub range: Range,
+    /// The body, after the target and before the closing `}}`.
+    pub content: Range,
+    /// The `[[ ... ]]` interpolation the position is in, if any, where the
+    /// body is ott again rather than the target's language.
+    pub interpolation: Option<Range>,
+    /// If the position is in the target, just after it, or where one would
+    /// go in a hom with none yet, the range from the target's start to the
+    /// position: the part of the target that's been typed.
+    pub typed_target: Option<Range>,
+}
+
+/// The outermost hom or embed `position` is strictly inside of in `text`, if
+/// any. Homs nest, and `}}` and `[[` are only delimiters outside of
+/// interpolations, as [`tokenize()`] has it, so a position can't be found by
+/// looking for the nearest braces. A hom that's never closed runs to the end
+/// of the text, and a position at its very end is inside it.
+pub fn embed_context_at(text: &str, position: Position) -> Option<EmbedContext> {
+    let tokens = tokenize(text);
+    let i = tokens.iter().position(|t| {
+        let open = !t.text.ends_with("}}");
+        t.kind == TokenKind::Hom && t.range.start < position && (position < t.range.end || open && position == t.range.end)
+    })?;
+
+    let token = &tokens[i];
+    let mut cursor = Cursor { text: token.text, offset: 0, position: token.range.start };
+    while cursor.position < position && cursor.bump().is_some() {}
+
+    // Up to the position, the hom is its `{{`, any whitespace, and the
+    // target's first characters, if any.
+    let before = &token.text[..cursor.offset];
+    let typed = before.trim_start_matches('{').trim_start();
+    let typed_target = (before.starts_with("{{") && !typed.contains(|c: char| c.is_whitespace() || c == '}'))
+        .then(|| Range::new(Position::new(position.line, position.character - utf16_len(typed)), position));
+
+    // A hom with no target yet is still a hom, with nothing in it.
+    let preceding = i.checked_sub(1).map_or(token.range.start, |i| tokens[i].range.end);
+    let Some(hom) = hom(token, preceding) else {
+        let end = Range::new(token.range.end, token.range.end);
+        return typed_target.map(|_| EmbedContext {
+            target: String::new(),
+            range: token.range,
+            content: end,
+            interpolation: None,
+            typed_target,
+        });
+    };
+
+    let interpolation = interpolations(token).into_iter()
+        .find(|range| range.start < position && (position < range.end || position == hom.range.end));
+
+    Some(EmbedContext {
+        target: hom.target,
+        range: hom.range,
+        content: hom.content,
+        interpolation,
+        typed_target,
+    })
+}
+
+/// The ranges of the `[[ ... ]]` interpolations in the hom `token`, each
+/// from its `[[` to its `]]`, or to the end of the hom if it's never closed.
+fn interpolations(token: &Token<'_>) -> Vec<Range> {
+    let mut ranges = vec![];
+    let mut cursor = Cursor { text: token.text, offset: 0, position: token.range.start };
+    while !cursor.rest().is_empty() {
+        if cursor.rest().starts_with("[[") {
+            let start = cursor.position;
+            cursor.eat_until("]]");
+            ranges.push(Range::new(start, cursor.position));
+        } else {
+            cursor.bump();
+        }
+    }
+
+    ranges
+}
+
+/// A `bind binder in scope` binding specification.
+#[derive(Debug, Clone, Serialize)]
+pub struct Bind {
+    /// What's bound: a metavar, or an auxiliary like `binders(p)`.
+    pub binder: Name,
+    /// The nonterminal the binder is in scope in.
+    pub scope: Name,
+}
+
+/// A `defns Jop :: '' ::=` block of judgement definitions.
+#[derive(Debug, Clone, Serialize)]
+pub struct Defns {
+    pub name: Name,
+    pub defns: Vec<Defn>,
+}
+
+/// A `defn form :: :: name :: prefix by` judgement and its rules.
+#[derive(Debug, Clone, Serialize)]
+pub struct Defn {
+    /// The judgement's name, as written after the second `::`.
+    pub name: Name,
+    /// The prefix of its rules' names, e.g. `GtT_`.
+    pub prefix: String,
+    /// The human-readable name given by the judgement's `{{ com ... }}` hom.
+    pub display: Option<String>,
+    /// The words of the judgement's form, before the first `::`.
+    pub form: Vec<Name>,
+    pub rules: Vec<InferenceRule>,
+}
+
+/// A `funs Name ::=` block of function definitions.
+#[derive(Debug, Clone, Serialize)]
+pub struct Funs {
+    pub name: Name,
+    pub funs: Vec<Fun>,
+}
+
+/// A `fun form :: result :: name by` function and its clauses.
+#[derive(Debug, Clone, Serialize)]
+pub struct Fun {
+    /// The function's name, as written after the second `::`.
+    pub name: Name,
+    /// The words of the function's form, like `fv ( t )`, before the first
+    /// `::`.
+    pub form: Vec<Name>,
+    /// What the function returns, as written after the first `::`.
+    pub result: Option<Name>,
+    /// Each `lhs === rhs` clause after the `by`, with its words separated by
+    /// single spaces.
+    pub clauses: Vec<String>,
+}
+
+/// An inference rule in a `defn`: premises, a `---- :: name` line, and a
+/// conclusion.
+#[derive(Debug, Clone, Serialize)]
+pub struct InferenceRule {
+    /// The rule's name, as written after the `::` on its line of dashes.
+    pub name: Name,
+    /// The name prefixed with its judgement's prefix, e.g. `GtT_var`.
+    pub id: String,
+    /// The line after the dashes, with its words separated by single spaces.
+    pub conclusion: String,
+    /// The words of each line above the dashes, one premise per line.
+    pub premises: Vec<Vec<Name>>,
+    /// The `%` comment just above the rule's first line, if any.
+    pub doc: Option<String>,
+}
+
+/// A `single t x :: tsubst` or `multiple t x :: tsubst` declaration in a
+/// `substitutions` section: substitute `t`s for `x`s with `tsubst`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Substitution {
+    pub nonterminal: Name,
+    pub metavar: Name,
+    pub function: Name,
+}
+
+/// A `v <:: e` declaration in a `subrules` section: nonterminal `v` is a
+/// subgrammar of `e`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Subrule {
+    pub sub: Name,
+    pub sup: Name,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relation {
+    /// `a left b`: `a` and `b` associate to the left.
+    Left,
+    /// `a right b`: `a` and `b` associate to the right.
+    Right,
+    /// `a non b`: `a` and `b` don't associate.
+    Non,
+    /// `a <= b`: `a` binds no tighter than `b`.
+    LessEq,
+}
+
+impl Relation {
+    fn from_keyword(keyword: &str) -> Option<Relation> {
+        match keyword {
+            "left" => Some(Relation::Left),
+            "right" => Some(Relation::Right),
+            "non" => Some(Relation::Non),
+            "<=" => Some(Relation::LessEq),
+            _ => None,
+        }
+    }
+
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Relation::Left => "left",
+            Relation::Right => "right",
+            Relation::Non => "non",
+            Relation::LessEq => "<=",
+        }
+    }
+}
+
+/// A declaration in a `parsing` section relating two productions by id.
+#[derive(Debug, Clone, Serialize)]
+pub struct Precedence {
+    pub left: Name,
+    pub relation: Relation,
+    pub right: Name,
+}
+
+/// A document's parse, kept section by section as well as whole, so that
+/// an edit within one section can be reparsed alone.
+#[derive(Debug, Default, Clone)]
+pub struct Parse {
+    pub document: Arc<Document>,
+    sections: Vec<Section>,
+}
+
+/// The parse of the tokens of one [`Span`], and where they are, as in the
+/// span.
+#[derive(Debug, Clone)]
+struct Section {
+    lines: std::ops::Range<u32>,
+    keyword: u32,
+    document: Arc<Document>,
+}
+
+impl Parse {
+    /// Parses `text`, guarded against the parser panicking as in
+    /// [`Document::parse()`].
+    pub fn new(text: &str) -> Parse {
+        std::panic::catch_unwind(|| Parse::new_unguarded(text)).unwrap_or_default()
+    }
+
+    fn new_unguarded(text: &str) -> Parse {
+        let tokens = tokenize(text);
+        let sections: Vec<_> = spans(&tokens).into_iter()
+            .map(|span| Section {
+                document: Arc::new(Document::parse_span(&tokens[span.tokens])),
+                lines: span.lines,
+                keyword: span.keyword,
+            })
+            .collect();
+
+        Parse::join(sections)
+    }
+
+    fn join(sections: Vec<Section>) -> Parse {
+        let mut document = Document::default();
+        for section in &sections {
+            document.append(Document::clone(&section.document));
+        }
+
+        Parse { document: Arc::new(document), sections }
+    }
+
+    /// The parse of `new`, which is `old`, the text of this parse, edited,
+    /// made by reparsing the one section the edit is in and moving those
+    /// after it to where they now are. That's the same as parsing `new`
+    /// from scratch, so long as the edit leaves the sections where they
+    /// were: if it touches the lines of more than one, or a section's
+    /// keyword or the comments above it, or might change where the next
+    /// one begins, there's `None`, and `new` is best parsed whole.
+    pub fn edit(&self, old: &str, new: &str) -> Option<Parse> {
+        std::panic::catch_unwind(|| self.edit_unguarded(old, new)).ok().flatten()
+    }
+
+    fn edit_unguarded(&self, old: &str, new: &str) -> Option<Parse> {
+        let (old_lines, new_lines): (Vec<_>, Vec<_>) = (old.split('\n').collect(), new.split('\n').collect());
+        let prefix = old_lines.iter().zip(&new_lines).take_while(|(a, b)| a == b).count();
+        if prefix == old_lines.len() && prefix == new_lines.len() {
+            return Some(self.clone());
+        }
+
+        let most = old_lines.len().min(new_lines.len()) - prefix;
+        let suffix = old_lines.iter().rev().zip(new_lines.iter().rev()).take(most).take_while(|(a, b)| a == b).count();
+
+        // The lines `prefix..end` of `old` were replaced, adding `delta`.
+        let (first, end) = (prefix as u32, (old_lines.len() - suffix) as u32);
+        let delta = new_lines.len() as i64 - old_lines.len() as i64;
+        let i = self.sections.iter().position(|section| section.lines.contains(&first))?;
+        let section = &self.sections[i];
+        if first <= section.keyword || end > section.lines.end {
+            return None;
+        }
+
+        let last = i + 1 == self.sections.len();
+        let lines = match last {
+            true => section.lines.start..u32::MAX,
+            false => section.lines.start..(section.lines.end as i64 + delta) as u32,
+        };
+
+        // The text of the span as edited, which starts and ends on a line
+        // boundary, so it's tokenized just as it is in the whole of `new`.
+        let offset = |line: u32| new_lines.iter().take(line as usize).map(|line| line.len() + 1).sum::<usize>();
+        let text = &new[offset(lines.start)..offset(lines.end).min(new.len())];
+        let tokens = tokenize(text);
+        if tokens.iter().filter(|t| is_section_start(t)).count() != 1 {
+            return None;
+        }
+
+        // Unless it's the last, the span mustn't end in a hom left open,
+        // which would run into the next, or on a comment line, which the
+        // next would take for one of its own.
+        if !last {
+            let open = tokens.iter().any(|t| {
+                let mut cursor = Cursor { text: t.text, offset: 0, position: t.range.start };
+                t.kind == TokenKind::Hom && !cursor.eat_hom()
+            });
+
+            let height = lines.end - lines.start;
+            let commented = tokens.last().is_some_and(|t| {
+                t.kind == TokenKind::Comment && t.line_start && t.range.start.line + 1 == height
+            });
+
+            if open || commented {
+                return None;
+            }
+        }
+
+        let mut document = Document::parse_span(&tokens);
+        document.shift(lines.start as i64);
+        let edited = Section { lines, keyword: section.keyword, document: Arc::new(document) };
+
+        let mut sections = self.sections[..i].to_vec();
+        sections.push(edited);
+        sections.extend(self.sections[i + 1..].iter().cloned().map(|mut section| {
+            if delta != 0 {
+                let shift = |line: u32| (line as i64 + delta) as u32;
+                section.lines = shift(section.lines.start)..match section.lines.end {
+                    u32::MAX => u32::MAX,
+                    end => shift(end),
+                };
+
+                section.keyword = shift(section.keyword);
+                Arc::make_mut(&mut section.document).shift(delta);
+            }
+
+            section
+        }));
+
+        Some(Parse::join(sections))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Document {
+    pub metavars: Vec<Metavar>,
+    pub grammars: Vec<GrammarRule>,
+    pub precedences: Vec<Precedence>,
+    pub substitutions: Vec<Substitution>,
+    pub subrules: Vec<Subrule>,
+    pub defns: Vec<Defns>,
+    pub funs: Vec<Funs>,
+    /// The `{{ target ... }}` blocks of every `embed` section, in order.
+    pub embeds: Vec<Hom>,
+}
+
+/// Parses the structure of the ott file `text`.
+pub fn parse_document(text: &str) -> Document {
+    Document::parse(text)
+}
+
+impl Document {
+    /// Parses `text`. Should the parser panic on something it doesn't expect,
+    /// the panic is caught and the document is parsed as empty, so that a
+    /// bug in the parser costs the features built on it, not the process.
+    pub fn parse(text: &str) -> Document {
+        std::panic::catch_unwind(|| Document::parse_unguarded(text)).unwrap_or_default()
+    }
+
+    fn parse_unguarded(text: &str) -> Document {
+        let tokens = tokenize(text);
+        let mut document = Document::default();
+        for span in spans(&tokens) {
+            document.append(Document::parse_span(&tokens[span.tokens]));
+        }
+
+        document
+    }
+
+    /// Parses the tokens of one [`Span`]: a section, along with the comments
+    /// just above it. Each is parsed on its own, knowing nothing of the
+    /// others, so that it can be reparsed on its own.
+    fn parse_span(tokens: &[Token<'_>]) -> Document {
+        let (comments, tokens): (Vec<_>, Vec<_>) = tokens.iter().cloned()
+            .partition(|t| t.kind == TokenKind::Comment);
+
+        let mut document = Document::default();
+        if let Some(section) = sections(&tokens).first() {
+            match section[0].text {
+                "metavar" | "indexvar" => document.parse_metavar(section),
+                "grammar" => document.parse_grammar(&section[1..]),
+                "parsing" => document.parse_parsing(&section[1..]),
+                "substitutions" => document.parse_substitutions(&section[1..]),
+                "subrules" => document.parse_subrules(&section[1..]),
+                "defns" => document.parse_defns(&section[1..]),
+                "funs" => document.parse_funs(&section[1..]),
+                "embed" => document.embeds.extend(homs(section)),
+                _ => {}
+            }
+        }
+
+        document.attach_docs(&comments);
+        document
+    }
+
+    /// Adds the declarations of `other`, which follows this document, to it.
+    fn append(&mut self, other: Document) {
+        self.metavars.extend(other.metavars);
+        self.grammars.extend(other.grammars);
+        self.precedences.extend(other.precedences);
+        self.substitutions.extend(other.substitutions);
+        self.subrules.extend(other.subrules);
+        self.defns.extend(other.defns);
+        self.funs.extend(other.funs);
+        self.embeds.extend(other.embeds);
+    }
+
+    /// Moves every range in the document down `lines` lines, or up if
+    /// negative.
+    fn shift(&mut self, lines: i64) {
+        let names = self.metavars.iter_mut().flat_map(|m| &mut m.names)
+            .chain(self.grammars.iter_mut().flat_map(|rule| &mut rule.names))
+            .chain(self.precedences.iter_mut().flat_map(|p| [&mut p.left, &mut p.right]))
+            .chain(self.substitutions.iter_mut().flat_map(|s| [&mut s.nonterminal, &mut s.metavar, &mut s.function]))
+            .chain(self.subrules.iter_mut().flat_map(|s| [&mut s.sub, &mut s.sup]));
+
+        for name in names {
+            shift_range(&mut name.range, lines);
+        }
+
+        for production in self.grammars.iter_mut().flat_map(|rule| &mut rule.productions) {
+            shift_range(&mut production.range, lines);
+            let binds = production.binds.iter_mut().flat_map(|b| [&mut b.binder, &mut b.scope]);
+            for name in std::iter::once(&mut production.label).chain(&mut production.body).chain(binds) {
+                shift_range(&mut name.range, lines);
+            }
+
+            for hom in &mut production.homs {
+                hom.shift(lines);
+            }
+        }
+
+        for defns in &mut self.defns {
+            shift_range(&mut defns.name.range, lines);
+            for defn in &mut defns.defns {
+                let rules = defn.rules.iter_mut().flat_map(|rule| {
+                    std::iter::once(&mut rule.name).chain(rule.premises.iter_mut().flatten())
+                });
+
+                for name in std::iter::once(&mut defn.name).chain(&mut defn.form).chain(rules) {
+                    shift_range(&mut name.range, lines);
+                }
+            }
+        }
+
+        for funs in &mut self.funs {
+            shift_range(&mut funs.name.range, lines);
+            for fun in &mut funs.funs {
+                for name in std::iter::once(&mut fun.name).chain(&mut fun.form).chain(&mut fun.result) {
+                    shift_range(&mut name.range, lines);
+                }
+            }
+        }
+
+        for hom in &mut self.embeds {
+            hom.shift(lines);
+        }
+    }
+
+    /// Gives each metavar, grammar rule, and inference rule the comment just
+    /// above it out of `comments`.
+    fn attach_docs(&mut self, comments: &[Token<'_>]) {
+        for metavar in &mut self.metavars {
+            metavar.doc = metavar.names.first().and_then(|name| leading_comment(comments, name.range.start.line));
+        }
+
+        for rule in &mut self.grammars {
+            rule.doc = rule.names.first().and_then(|name| leading_comment(comments, name.range.start.line));
+        }
+
+        for rule in self.defns.iter_mut().flat_map(|d| &mut d.defns).flat_map(|d| &mut d.rules) {
+            let first = rule.premises.first().and_then(|premise| premise.first()).unwrap_or(&rule.name);
+            rule.doc = leading_comment(comments, first.range.start.line);
+        }
+    }
+
+    fn parse_metavar(&mut self, tokens: &[Token<'_>]) {
+        let Some(end) = tokens.iter().position(|t| t.is("::=")) else { return };
+        let names = tokens[1..end].iter().flat_map(split_commas).collect();
+        self.metavars.push(Metavar { names, doc: None });
+    }
+
+    fn parse_grammar(&mut self, tokens: &[Token<'_>]) {
+        for line in lines(tokens) {
+            if line[0].is("|") {
+                let Some(rule) = self.grammars.last_mut() else { continue };
+                let seps: Vec<_> = line.iter().enumerate()
+                    .filter(|(_, t)| t.is("::"))
+                    .map(|(i, _)| i)
+                    .collect();
+
+                let Some(label) = seps.get(1).and_then(|&i| line.get(i + 1)) else { continue };
+                if label.kind != TokenKind::Word {
+                    continue;
+                }
+
+                let body = line[1..seps[0]].iter()
+                    .filter(|t| t.kind == TokenKind::Word)
+                    .map(Name::from)
+                    .collect();
+
+                let end = line.last().map_or(label.range.end, |t| t.range.end);
+                rule.productions.push(Production {
+                    label: Name::from(label),
+                    id: format!("{}{}", rule.prefix, label.text),
+                    body,
+                    binds: binds(line),
+                    homs: homs(line),
+                    range: Range::new(line[0].range.start, end),
+                });
+            } else if let Some(i) = line.iter().position(|t| t.is("::=")) {
+                let header = &line[..i];
+                let sep = header.iter().position(|t| t.is("::")).unwrap_or(header.len());
+                let names = header[..sep].iter()
+                    .flat_map(split_commas)
+                    .collect();
+
+                let prefix = header.get(sep + 1)
+                    .map(|t| t.text.trim_matches('\'').to_string())
+                    .unwrap_or_default();
+
+                self.grammars.push(GrammarRule { names, prefix, productions: vec![], doc: None });
+            }
+        }
+    }
+
+    fn parse_parsing(&mut self, tokens: &[Token<'_>]) {
+        for line in lines(tokens) {
+            let [left, relation, right, ..] = line else { continue };
+            let Some(relation) = Relation::from_keyword(relation.text) else { continue };
+            self.precedences.push(Precedence {
+                left: Name::from(left),
+                relation,
+                right: Name::from(right),
+            });
+        }
+    }
+
+    fn parse_substitutions(&mut self, tokens: &[Token<'_>]) {
+        for line in lines(tokens) {
+            let [kind, nonterminal, metavar, sep, function, ..] = line else { continue };
+            if !(kind.is("single") || kind.is("multiple")) || !sep.is("::") {
+                continue;
+            }
+
+            self.substitutions.push(Substitution {
+                nonterminal: Name::from(nonterminal),
+                metavar: Name::from(metavar),
+                function: Name::from(function),
+            });
+        }
+    }
+
+    fn parse_subrules(&mut self, tokens: &[Token<'_>]) {
+        for line in lines(tokens) {
+            let [sub, sep, sup, ..] = line else { continue };
+            if sep.is("<::") {
+                self.subrules.push(Subrule { sub: Name::from(sub), sup: Name::from(sup) });
+            }
+        }
+    }
+
+    fn parse_defns(&mut self, tokens: &[Token<'_>]) {
+        // A `defn`'s judgement form is on the keyword's line or the next one.
+        let mut after_defn = false;
+        let mut after_rule = false;
+        let mut premises = vec![];
+        for line in lines(tokens) {
+            let is_conclusion = std::mem::take(&mut after_rule);
+            let (is_defn, line) = match line[0].is("defn") {
+                true => (true, &line[1..]),
+                false => (after_defn, line),
+            };
+
+            after_defn = is_defn && line.is_empty();
+            let seps: Vec<_> = line.iter().enumerate()
+                .filter(|(_, t)| t.is("::"))
+                .map(|(i, _)| i)
+                .collect();
+
+            if is_defn {
+                let Some(defns) = self.defns.last_mut() else { continue };
+                let Some(name) = seps.get(1).and_then(|&i| line.get(i + 1)) else { continue };
+                let prefix = seps.get(2)
+                    .and_then(|&i| line.get(i + 1))
+                    .filter(|t| t.kind == TokenKind::Word)
+                    .map(|t| t.text.trim_matches('\'').to_string())
+                    .unwrap_or_default();
+
+                let display = homs(line).into_iter()
+                    .find(|hom| hom.target == "com" && !hom.body.is_empty())
+                    .map(|hom| hom.body);
+
+                let form = line[..seps[0]].iter()
+                    .filter(|t| t.kind == TokenKind::Word)
+                    .map(Name::from)
+                    .collect();
+
+                premises.clear();
+                defns.defns.push(Defn { name: Name::from(name), prefix, display, form, rules: vec![] });
+            } else if line[0].text.starts_with("---") && line[0].text.chars().all(|c| c == '-') {
+                let Some(defn) = self.defns.last_mut().and_then(|d| d.defns.last_mut()) else { continue };
+                let Some(name) = seps.first().and_then(|&i| line.get(i + 1)) else { continue };
+                defn.rules.push(InferenceRule {
+                    name: Name::from(name),
+                    id: format!("{}{}", defn.prefix, name.text),
+                    conclusion: String::new(),
+                    premises: std::mem::take(&mut premises),
+                    doc: None,
+                });
+
+                after_rule = true;
+            } else if is_conclusion {
+                let Some(rule) = self.defns.last_mut()
+                    .and_then(|d| d.defns.last_mut())
+                    .and_then(|d| d.rules.last_mut())
+                else {
+                    continue;
+                };
+
+                let words: Vec<_> = line.iter().filter(|t| t.kind == TokenKind::Word).map(|t| t.text).collect();
+                rule.conclusion = words.join(" ");
+            } else if line.iter().any(|t| t.is("::=")) {
+                premises.clear();
+                self.defns.push(Defns { name: Name::from(&line[0]), defns: vec![] });
+            } else {
+                let words: Vec<_> = line.iter().filter(|t| t.kind == TokenKind::Word).map(Name::from).collect();
+                if !words.is_empty() {
+                    premises.push(words);
+                }
+            }
+        }
+    }
+
+    fn parse_funs(&mut self, tokens: &[Token<'_>]) {
+        // Like a `defn`'s judgement form, a `fun`'s form is on the keyword's
+        // line or the next one.
+        let mut after_fun = false;
+        for line in lines(tokens) {
+            let (is_fun, line) = match line[0].is("fun") {
+                true => (true, &line[1..]),
+                false => (after_fun, line),
+            };
+
+            after_fun = is_fun && line.is_empty();
+            let words = || line.iter().filter(|t| t.kind == TokenKind::Word);
+            if is_fun {
+                let Some(funs) = self.funs.last_mut() else { continue };
+                let seps: Vec<_> = line.iter().enumerate().filter(|(_, t)| t.is("::")).map(|(i, _)| i).collect();
+                let Some(name) = seps.get(1).and_then(|&i| line.get(i + 1)) else { continue };
+                let result = line.get(seps[0] + 1).filter(|t| !t.is("::")).map(Name::from);
+                let form = line[..seps[0]].iter().filter(|t| t.kind == TokenKind::Word).map(Name::from).collect();
+                funs.funs.push(Fun { name: Name::from(name), form, result, clauses: vec![] });
+            } else if line.iter().any(|t| t.is("===")) {
+                let Some(fun) = self.funs.last_mut().and_then(|f| f.funs.last_mut()) else { continue };
+                fun.clauses.push(words().map(|t| t.text).collect::<Vec<_>>().join(" "));
+            } else if line.iter().any(|t| t.is("::=")) {
+                self.funs.push(Funs { name: Name::from(&line[0]), funs: vec![] });
+            }
+        }
+    }
+
+    /// The function named `name`, if the document defines one.
+    pub fn function(&self, name: &str) -> Option<&Fun> {
+        self.funs.iter().flat_map(|f| &f.funs).find(|fun| fun.name.text == name)
+    }
+
+    /// Whether `name` is declared as a metavar.
+    pub fn is_metavar(&self, name: &str) -> bool {
+        self.metavars.iter().flat_map(|m| &m.names).any(|n| n.text == name)
+    }
+
+    /// Returns the production with id `id` along with the rule it belongs to.
+    pub fn production(&self, id: &str) -> Option<(&GrammarRule, &Production)> {
+        self.grammars.iter()
+            .flat_map(|rule| rule.productions.iter().map(move |p| (rule, p)))
+            .find(|(_, p)| p.id == id)
+    }
+
+    /// All of the `parsing` declarations that mention the production `id`.
+    pub fn precedences_of<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a Precedence> + 'a {
+        self.precedences.iter().filter(move |p| p.left.text == id || p.right.text == id)
+    }
+
+    /// The precedence level of the production `id`, if it's mentioned in any
+    /// `parsing` declaration. Level 0 binds loosest; each `a <= b` places `b`
+    /// at least one level above `a`. Cycles are cut rather than followed.
+    pub fn precedence_level(&self, id: &str) -> Option<usize> {
+        fn level<'a>(doc: &'a Document, id: &'a str, visiting: &mut Vec<&'a str>) -> usize {
+            if visiting.contains(&id) {
+                return 0;
+            }
+
+            visiting.push(id);
+            let level = doc.precedences.iter()
+                .filter(|p| p.relation == Relation::LessEq && p.right.text == id)
+                .map(|p| level(doc, &p.left.text, visiting) + 1)
+                .max()
+                .unwrap_or(0);
+
+            visiting.pop();
+            level
+        }
+
+        self.precedences_of(id).next()?;
+        Some(level(self, id, &mut vec![]))
+    }
+}
+
+impl From<&Token<'_>> for Name {
+    fn from(token: &Token<'_>) -> Self {
+        Name { text: token.text.into(), range: token.range }
+    }
+}
+
+/// Finds the `(+ bind binder in scope +)` specifications in `tokens`.
+fn binds(tokens: &[Token<'_>]) -> Vec<Bind> {
+    let mut binds = vec![];
+    let mut in_spec = false;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is("(+") || token.is("+)") {
+            in_spec = token.is("(+");
+        } else if in_spec && token.is("bind")
+            && let [binder, keyword, scope, ..] = &tokens[i + 1..]
+            && keyword.is("in")
+        {
+            binds.push(Bind { binder: Name::from(binder), scope: Name::from(scope) });
+        }
+    }
+
+    binds
+}
+
+/// The text of the `%` comments in `comments` that have lines of their own,
+/// one after another, ending on the line before `line`, without their `%`s.
+fn leading_comment(comments: &[Token<'_>], line: u32) -> Option<String> {
+    let mut lines = vec![];
+    while let Some(above) = (line as usize).checked_sub(lines.len() + 1) {
+        let Some(comment) = comments.iter().find(|c| c.line_start && c.range.start.line as usize == above) else {
+            break;
+        };
+
+        let text = comment.text.trim_start_matches('%');
+        lines.push(text.strip_prefix(' ').unwrap_or(text).trim_end());
+    }
+
+    lines.reverse();
+    let doc = lines.join("\n");
+    (!doc.trim().is_empty()).then_some(doc)
+}
+
+/// Splits a run of header tokens like `t,` or `t,u` into comma-free names.
+pub fn split_commas(token: &Token<'_>) -> Vec<Name> {
+    let mut names = vec![];
+    let mut offset = 0;
+    for part in token.text.split(',') {
+        let start = token.range.start.character + offset;
+        offset += utf16_len(part) + 1;
+        if !part.is_empty() {
+            let start = Position::new(token.range.start.line, start);
+            let end = Position::new(start.line, start.character + utf16_len(part));
+            names.push(Name { text: part.into(), range: Range::new(start, end) });
+        }
+    }
+
+    names
+}
+
+/// The identifiers in the word `token`, as `pattern` matches them, like the
+/// `x` and `t` of `\x.t`.
+pub fn identifiers(token: &Token<'_>, pattern: &Regex) -> Vec<Name> {
+    pattern.find_iter(token.text)
+        .filter(|m| !m.is_empty())
+        .map(|m| {
+            let start = token.range.start.character + utf16_len(&token.text[..m.start()]);
+            let start = Position::new(token.range.start.line, start);
+            let end = Position::new(start.line, start.character + utf16_len(m.as_str()));
+            Name { text: m.as_str().into(), range: Range::new(start, end) }
+        })
+        .collect()
+}
+
+/// The words of the `[[...]]` interpolations in the hom `token`: the ott
+/// terms it embeds, as opposed to the text it passes through as is. Words of
+/// an interpolation that's never closed are left out.
+pub fn interpolated_words<'a>(token: &Token<'a>) -> Vec<Token<'a>> {
+    let text = token.text;
+    let mut words = vec![];
+    let mut pending = vec![];
+    let mut word: Option<(usize, Position)> = None;
+    let mut inside = false;
+    let mut position = token.range.start;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let bracket = text[i..].starts_with(if inside { "]]" } else { "[[" });
+        if (bracket || c.is_whitespace()) && let Some((start, from)) = word.take() {
+            let range = Range::new(from, position);
+            pending.push(Token { kind: TokenKind::Word, text: &text[start..i], range, line_start: false });
+        }
+
+        if bracket {
+            inside = !inside;
+            words.append(&mut pending);
+        } else if inside && !c.is_whitespace() && word.is_none() {
+            word = Some((i, position));
+        }
+
+        let len = if bracket { 2 } else { c.len_utf8() };
+        for c in text[i..i + len].chars() {
+            match c {
+                '\n' => position = Position::new(position.line + 1, 0),
+                _ => position.character += c.len_utf16() as u32,
+            }
+        }
+
+        i += len;
+    }
+
+    words
+}
+
+/// Splits `tokens` into sections, each beginning with its keyword. Tokens
+/// before the first section keyword are dropped.
+fn sections<'a, 'b>(tokens: &'b [Token<'a>]) -> Vec<&'b [Token<'a>]> {
+    let starts: Vec<_> = tokens.iter().enumerate()
+        .filter(|(_, t)| is_section_start(t))
+        .map(|(i, _)| i)
+        .collect();
+
+    starts.iter().enumerate()
+        .map(|(n, &i)| &tokens[i..starts.get(n + 1).copied().unwrap_or(tokens.len())])
+        .collect()
+}
+
+/// The tokens of a top-level section and of the comment lines just above
+/// it, any of which may be the doc comment of its first declaration, and
+/// the lines they span.
+#[derive(Debug, Clone)]
+struct Span {
+    tokens: std::ops::Range<usize>,
+    /// The first of the comment lines, or the keyword's line if there are
+    /// none, and the line the next span begins on.
+    lines: std::ops::Range<u32>,
+    /// The line of the section's keyword.
+    keyword: u32,
+}
+
+/// Splits `tokens` into spans, one for each section. Tokens before the
+/// first span are dropped.
+fn spans(tokens: &[Token<'_>]) -> Vec<Span> {
+    let mut starts: Vec<(usize, u32, u32)> = vec![];
+    for (i, token) in tokens.iter().enumerate().filter(|(_, t)| is_section_start(t)) {
+        let keyword = token.range.start.line;
+        let mut first = (i, keyword);
+        while let Some(comment) = first.0.checked_sub(1).map(|j| &tokens[j])
+            && comment.kind == TokenKind::Comment
+            && comment.line_start
+            && comment.range.start.line + 1 == first.1
+        {
+            first = (first.0 - 1, comment.range.start.line);
+        }
+
+        starts.push((first.0, first.1, keyword));
+    }
+
+    starts.iter().enumerate()
+        .map(|(n, &(start, line, keyword))| {
+            let next = starts.get(n + 1);
+            Span {
+                tokens: start..next.map_or(tokens.len(), |&(next, ..)| next),
+                lines: line..next.map_or(u32::MAX, |&(_, next, _)| next),
+                keyword,
+            }
+        })
+        .collect()
+}
+
+/// A top-level section of an ott file and the lines it spans.
+#[derive(Debug, Clone)]
+pub struct Block<'a> {
+    /// The keyword the section begins with: `grammar`, `defns`, and so on.
+    pub keyword: &'a str,
+    /// The section's first line, and the line the next section begins on.
+    pub lines: std::ops::Range<u32>,
+}
+
+/// Splits `text` into its top-level sections. Anything before the first
+/// section belongs to none of them.
+pub fn blocks(text: &str) -> Vec<Block<'_>> {
+    let starts: Vec<_> = tokenize(text).into_iter()
+        .filter(is_section_start)
+        .map(|t| (t.text, t.range.start.line))
+        .collect();
+
+    starts.iter().enumerate()
+        .map(|(n, &(keyword, line))| Block {
+            keyword,
+            lines: line..starts.get(n + 1).map_or(u32::MAX, |&(_, next)| next),
+        })
+        .collect()
+}
+
+fn is_section_start(token: &Token<'_>) -> bool {
+    token.kind == TokenKind::Word && token.line_start && SECTION_KEYWORDS.contains(&token.text)
+}
+
+/// Splits `tokens` into lines. A multi-line hom belongs to the line it starts on.
+fn lines<'a, 'b>(tokens: &'b [Token<'a>]) -> impl Iterator<Item = &'b [Token<'a>]> {
+    let mut rest = tokens;
+    std::iter::from_fn(move || {
+        let tail = rest.get(1..)?;
+        let len = tail.iter().position(|t| t.line_start).map_or(rest.len(), |i| i + 1);
+        let (line, tail) = rest.split_at(len);
+        rest = tail;
+        Some(line)
+    })
+}
+
+fn shift_position(position: &mut Position, lines: i64) {
+    position.line = (position.line as i64 + lines).clamp(0, u32::MAX as i64) as u32;
+}
+
+fn shift_range(range: &mut Range, lines: i64) {
+    shift_position(&mut range.start, lines);
+    shift_position(&mut range.end, lines);
+}
+
+pub fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+/// Walks `text` a character at a time, tracking the LSP position.
+struct Cursor<'a> {
+    text: &'a str,
+    offset: usize,
+    position: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.position = Position::new(self.position.line + 1, 0);
+        } else {
+            self.position.character += c.len_utf16() as u32;
+        }
+
+        Some(c)
+    }
+
+    fn bump_str(&mut self, s: &str) {
+        for _ in s.chars() {
+            self.bump();
+        }
+    }
+
+    fn eat_until(&mut self, end: &str) {
+        while !self.rest().is_empty() && !self.rest().starts_with(end) {
+            self.bump();
+        }
+
+        self.bump_str(end);
+    }
+
+    /// Eats a `{{ ... }}` block, accounting for nested `{{ }}`, `[[ ]]`
+    /// interpolations, and backslash-escaped braces. Unterminated homs run to
+    /// the end of the text, as they do in ott. Returns whether the hom was
+    /// terminated.
+    fn eat_hom(&mut self) -> bool {
+        let mut depth = 0;
+        while !self.rest().is_empty() {
+            let rest = self.rest();
+            if rest.starts_with("{{") {
+                depth += 1;
+                self.bump_str("{{");
+            } else if rest.starts_with("}}") {
+                depth -= 1;
+                self.bump_str("}}");
+                if depth == 0 {
+                    return true;
+                }
+            } else if rest.starts_with("[[") {
+                self.eat_until("]]");
+            } else if rest.starts_with("\\{") || rest.starts_with("\\}") {
+                self.bump();
+                self.bump();
+            } else {
+                self.bump();
+            }
+        }
+
+        false
+    }
+
+    fn eat_word(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || self.rest().starts_with("{{") {
+                break;
+            }
+
+            self.bump();
+        }
+    }
+}
+
+/// A `{{` and the `}}` that closes it. Either is `None` when unmatched.
+#[derive(Debug, Clone, Copy)]
+pub struct DelimiterPair {
+    pub open: Option<Range>,
+    pub close: Option<Range>,
+}
+
+/// Finds every `{{`/`}}` hom delimiter in `text` and pairs them up by
+/// nesting. Delimiters in comments, in `[[ ]]` interpolations, or formed with
+/// a backslash-escaped brace (`\{{`, `\}}`) don't count. Pairs are returned
+/// in the order they're closed; unmatched opens come last.
+pub fn delimiter_pairs(text: &str) -> Vec<DelimiterPair> {
+    let mut pairs = vec![];
+    let mut open: Vec<Range> = vec![];
+    let mut cursor = Cursor { text, offset: 0, position: Position::default() };
+    while !cursor.rest().is_empty() {
+        let rest = cursor.rest();
+        let start = cursor.position;
+        if rest.starts_with("{{") {
+            cursor.bump_str("{{");
+            open.push(Range::new(start, cursor.position));
+        } else if rest.starts_with("}}") {
+            cursor.bump_str("}}");
+            let close = Some(Range::new(start, cursor.position));
+            pairs.push(DelimiterPair { open: open.pop(), close });
+        } else if open.is_empty() && rest.starts_with('%') {
+            cursor.eat_until("\n");
+        } else if !open.is_empty() && rest.starts_with("[[") {
+            cursor.eat_until("]]");
+        } else if !open.is_empty() && (rest.starts_with("\\{") || rest.starts_with("\\}")) {
+            cursor.bump();
+            cursor.bump();
+        } else {
+            cursor.bump();
+        }
+    }
+
+    pairs.extend(open.into_iter().map(|open| DelimiterPair { open: Some(open), close: None }));
+    pairs
+}
+
+/// The token in `tokens` that `position` is in or at the end of.
+pub fn token_at<'a, 'b>(tokens: &'b [Token<'a>], position: Position) -> Option<&'b Token<'a>> {
+    tokens.iter().find(|t| t.range.start <= position && position <= t.range.end)
+}
+
+/// The identifier, as `pattern` matches them, at `position` in `text`,
+/// unless it's in a hom or comment.
+pub fn name_at(text: &str, position: Position, pattern: &Regex) -> Option<Name> {
+    let tokens = tokenize(text);
+    let token = token_at(&tokens, position).filter(|t| t.kind == TokenKind::Word)?;
+    identifiers(token, pattern).into_iter()
+        .find(|name| name.range.start <= position && position <= name.range.end)
+}
+
+/// Splits `text` into words, homs, and comments.
+pub fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut cursor = Cursor { text, offset: 0, position: Position::default() };
+    let mut line_start = true;
+    while let Some(c) = cursor.peek() {
+        if c == '\n' {
+            line_start = true;
+            cursor.bump();
+            continue;
+        } else if c.is_whitespace() {
+            cursor.bump();
+            continue;
+        }
+
+        let (start_offset, start) = (cursor.offset, cursor.position);
+        let kind = if c == '%' {
+            cursor.eat_until("\n");
+            TokenKind::Comment
+        } else if cursor.rest().starts_with("{{") {
+            cursor.eat_hom();
+            TokenKind::Hom
+        } else {
+            cursor.eat_word();
+            TokenKind::Word
+        };
+
+        // A comment's trailing newline isn't part of it.
+        let text = match kind {
+            TokenKind::Comment => text[start_offset..cursor.offset].trim_end_matches('\n'),
+            _ => &text[start_offset..cursor.offset],
+        };
+
+        let end = match text.len() < cursor.offset - start_offset {
+            true => Position::new(start.line, start.character + utf16_len(text)),
+            false => cursor.position,
+        };
+
+        tokens.push(Token { kind, text, range: Range::new(start, end), line_start });
+        line_start = kind == TokenKind::Comment;
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range};
+
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    /// The text and range of each of `names`.
+    fn names(names: &[Name]) -> Vec<(&str, Range)> {
+        names.iter().map(|name| (name.text.as_str(), name.range)).collect()
+    }
+
+    /// A theory with one of each of the sections the parser reads.
+    const THEORY: &str = r"% Term variables.
+metavar termvar, x ::= {{ com a variable }} {{ coq nat }}
+indexvar n ::= {{ coq nat }}
+
+grammar
+% Terms.
+t :: 't_' ::=
+  | x :: :: var
+  | \ x . t :: :: lam (+ bind x in t +) {{ com abstraction }}
+  | t t' :: :: app
+
+defns
+Jop :: '' ::=
+
+defn
+t --> t' :: :: reduce :: E_ {{ com reduction }} by
+
+% Beta.
+v value
+---------------- :: beta
+(\x.t) v --> t
+
+embed {{ coq
+Require Import Arith.
+}}
+";
+
+    #[test]
+    fn metavars_and_indexvars_are_read_with_their_docs() {
+        let document = Document::parse_unguarded(THEORY);
+        assert_eq!(document.metavars.len(), 2);
+        assert_eq!(names(&document.metavars[0].names), [
+            ("termvar", range((1, 8), (1, 15))),
+            ("x", range((1, 17), (1, 18))),
+        ]);
+
+        assert_eq!(document.metavars[0].doc.as_deref(), Some("Term variables."));
+        assert_eq!(names(&document.metavars[1].names), [("n", range((2, 9), (2, 10)))]);
+        assert_eq!(document.metavars[1].doc, None);
+        assert!(document.is_metavar("x") && document.is_metavar("n") && !document.is_metavar("t"));
+    }
+
+    #[test]
+    fn grammar_rules_are_read_with_their_productions() {
+        let document = Document::parse_unguarded(THEORY);
+        let [rule] = &document.grammars[..] else { panic!("one rule: {:?}", document.grammars) };
+        assert_eq!(names(&rule.names), [("t", range((6, 0), (6, 1)))]);
+        assert_eq!(rule.prefix, "t_");
+        assert_eq!(rule.doc.as_deref(), Some("Terms."));
+
+        let ids: Vec<_> = rule.productions.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["t_var", "t_lam", "t_app"]);
+
+        let var = &rule.productions[0];
+        assert_eq!(var.label.range, range((7, 12), (7, 15)));
+        assert_eq!(var.range, range((7, 2), (7, 15)));
+        assert_eq!(names(&var.body), [("x", range((7, 4), (7, 5)))]);
+
+        let lam = &rule.productions[1];
+        let body: Vec<_> = lam.body.iter().map(|name| name.text.as_str()).collect();
+        assert_eq!(body, ["\\", "x", ".", "t"]);
+        assert_eq!(lam.range, range((8, 2), (8, 61)));
+
+        let [bind] = &lam.binds[..] else { panic!("one bind: {:?}", lam.binds) };
+        assert_eq!((bind.binder.text.as_str(), bind.binder.range), ("x", range((8, 30), (8, 31))));
+        assert_eq!((bind.scope.text.as_str(), bind.scope.range), ("t", range((8, 35), (8, 36))));
+        assert!(document.production("t_app").is_some_and(|(rule, _)| rule.prefix == "t_"));
+    }
+
+    #[test]
+    fn homs_are_read_with_their_target_and_body() {
+        let document = Document::parse_unguarded(THEORY);
+        let [hom] = &document.grammars[0].productions[1].homs[..] else { panic!("one hom") };
+        assert_eq!(hom.target, "com");
+        assert_eq!(hom.body, "abstraction");
+        assert_eq!(hom.range, range((8, 40), (8, 61)));
+        assert_eq!(hom.target_range, range((8, 43), (8, 46)));
+        assert_eq!(hom.content, range((8, 47), (8, 58)));
+        assert_eq!(hom.preceding, Position::new(8, 39));
+
+        // An empty hom's body is empty, and sits just after its target.
+        let [token] = &tokenize("{{ tex }}")[..] else { panic!("one token") };
+        let hom = super::hom(token, Position::default()).unwrap();
+        assert_eq!((hom.target.as_str(), hom.body.as_str()), ("tex", ""));
+        assert_eq!(hom.content, range((0, 7), (0, 7)));
+    }
+
+    #[test]
+    fn defns_are_read_with_their_rules() {
+        let document = Document::parse_unguarded(THEORY);
+        let [defns] = &document.defns[..] else { panic!("one defns: {:?}", document.defns) };
+        assert_eq!((defns.name.text.as_str(), defns.name.range), ("Jop", range((12, 0), (12, 3))));
+
+        // The form is on the line after the `defn`.
+        let [defn] = &defns.defns[..] else { panic!("one defn") };
+        assert_eq!((defn.name.text.as_str(), defn.name.range), ("reduce", range((15, 15), (15, 21))));
+        assert_eq!(defn.prefix, "E_");
+        assert_eq!(defn.display.as_deref(), Some("reduction"));
+        assert_eq!(names(&defn.form).iter().map(|(text, _)| *text).collect::<Vec<_>>(), ["t", "-->", "t'"]);
+
+        let [rule] = &defn.rules[..] else { panic!("one rule") };
+        assert_eq!((rule.name.text.as_str(), rule.name.range), ("beta", range((19, 20), (19, 24))));
+        assert_eq!(rule.id, "E_beta");
+        assert_eq!(rule.conclusion, "(\\x.t) v --> t");
+        assert_eq!(rule.premises.len(), 1);
+        assert_eq!(names(&rule.premises[0]), [("v", range((18, 0), (18, 1))), ("value", range((18, 2), (18, 7)))]);
+        assert_eq!(rule.doc.as_deref(), Some("Beta."));
+    }
+
+    #[test]
+    fn embeds_are_read_as_homs() {
+        let document = Document::parse_unguarded(THEORY);
+        let [embed] = &document.embeds[..] else { panic!("one embed: {:?}", document.embeds) };
+        assert_eq!(embed.target, "coq");
+        assert_eq!(embed.body, "Require Import Arith.");
+        assert_eq!(embed.range, range((22, 6), (24, 2)));
+        assert_eq!(embed.content, range((23, 0), (23, 21)));
+    }
+
+    #[test]
+    fn comments_hide_what_they_say() {
+        let text = "% grammar {{\ngrammar % t :: t_ ::=\nt :: 't_' ::= % | x :: :: hidden\n  | x :: :: var {{ com % kept }}\n";
+        let tokens = tokenize(text);
+        let kinds: Vec<_> = tokens.iter().map(|t| (t.kind, t.text)).collect();
+        assert_eq!(kinds[..3], [
+            (TokenKind::Comment, "% grammar {{"),
+            (TokenKind::Word, "grammar"),
+            (TokenKind::Comment, "% t :: t_ ::="),
+        ]);
+
+        // The comment's newline isn't part of it.
+        assert_eq!(tokens[0].range, range((0, 0), (0, 12)));
+
+        let document = Document::parse_unguarded(text);
+        let [rule] = &document.grammars[..] else { panic!("one rule: {:?}", document.grammars) };
+        let ids: Vec<_> = rule.productions.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["t_var"]);
+        assert_eq!(rule.productions[0].homs[0].body, "% kept");
+        assert_eq!(delimiter_pairs(text).len(), 1);
+    }
+
+    #[test]
+    fn unterminated_homs_run_to_the_end() {
+        let text = "metavar x ::= {{ coq nat\n\ngrammar\nt :: t_ ::=\n  | x :: :: var\n";
+        let document = Document::parse_unguarded(text);
+        assert_eq!(names(&document.metavars[0].names), [("x", range((0, 8), (0, 9)))]);
+        assert!(document.grammars.is_empty());
+
+        let hom = tokenize(text).pop().unwrap();
+        assert_eq!(hom.kind, TokenKind::Hom);
+        assert_eq!(hom.range, range((0, 14), (5, 0)));
+        assert!(blocks(text).iter().all(|block| block.keyword == "metavar"));
+
+        let [pair] = &delimiter_pairs(text)[..] else { panic!("one pair") };
+        assert_eq!((pair.open, pair.close), (Some(range((0, 14), (0, 16))), None));
+
+        // An interpolation left open runs to the end too, and its words with
+        // it.
+        let [token] = &tokenize("{{ tex [[ t }}")[..] else { panic!("one token") };
+        assert!(interpolated_words(token).is_empty());
+    }
+
+    #[test]
+    fn embed_contexts_are_found_in_nested_and_adjacent_homs() {
+        let text = "metavar x ::= {{ tex a {{ com b }} c }}{{ coq nat [[x]] }}\n{{ isa\n";
+        let at = |line, character| embed_context_at(text, Position::new(line, character)).map(|embed| {
+            (embed.target, embed.range, embed.content, embed.interpolation, embed.typed_target)
+        });
+
+        // Only the outermost of nested homs.
+        let tex = ("tex".to_string(), range((0, 14), (0, 39)), range((0, 21), (0, 36)), None, None);
+        assert_eq!(at(0, 30), Some(tex.clone()));
+        assert_eq!(at(0, 36), Some(tex));
+
+        // Neither of two adjacent homs, between them.
+        assert_eq!(at(0, 14), None);
+        assert_eq!(at(0, 39), None);
+        let coq = |interpolation, typed| ("coq".to_string(), range((0, 39), (0, 58)), range((0, 46), (0, 55)), interpolation, typed);
+        assert_eq!(at(0, 41), Some(coq(None, Some(range((0, 41), (0, 41))))));
+        assert_eq!(at(0, 44), Some(coq(None, Some(range((0, 42), (0, 44))))));
+        assert_eq!(at(0, 47), Some(coq(None, None)));
+        assert_eq!(at(0, 52), Some(coq(Some(range((0, 50), (0, 55))), None)));
+        assert_eq!(at(0, 58), None);
+
+        // A hom that's never closed, through the very end of the text.
+        let isa = |typed| ("isa".to_string(), range((1, 0), (2, 0)), range((2, 0), (2, 0)), None, typed);
+        assert_eq!(at(1, 6), Some(isa(Some(range((1, 3), (1, 6))))));
+        assert_eq!(at(2, 0), Some(isa(None)));
+    }
+
+    #[test]
+    fn sections_are_read_wherever_they_are() {
+        let [metavar, indexvar, grammar, defns, embed] = &blocks(THEORY)[..] else { panic!("five blocks") };
+        assert_eq!((metavar.keyword, metavar.lines.clone()), ("metavar", 1..2));
+        assert_eq!((indexvar.keyword, indexvar.lines.clone()), ("indexvar", 2..4));
+        assert_eq!((grammar.keyword, grammar.lines.clone()), ("grammar", 4..11));
+        assert_eq!((defns.keyword, defns.lines.clone()), ("defns", 11..22));
+        assert_eq!((embed.keyword, embed.lines.clone()), ("embed", 22..u32::MAX));
+
+        // Keywords only begin a section at the start of a line, and what's
+        // before the first is in none.
+        let text = "t :: t_ ::=\n  | x :: :: var\n  | y :: :: grammar\n";
+        let document = Document::parse_unguarded(text);
+        assert!(blocks(text).is_empty());
+        assert!(document.grammars.is_empty());
+    }
+
+    #[test]
+    fn malformed_theories_are_parsed_without_panicking() {
+        let fragments = [
+            "grammar\n::=\n  | :: ::\n", "grammar\nt :: 't_ ::=\n  | x ::", "defns\n::=\ndefn\nby\n----\n",
+            "defn\n:: :: :: by\n---- ::\n", "metavar ::=", "metavar , ::= {{", "embed", "embed }} {{",
+            "{{ [[ ]] }} ]] [[", "grammar\nt :: t_ ::=\n  | x :: :: var (+ bind in +) (+", "grammar\n∀ :: '𝔸' ::=\n  | ∀ :: :: ∀\n",
+        ];
+
+        // Every prefix of a theory is a theory cut off somewhere.
+        let prefixes = THEORY.char_indices().map(|(i, _)| &THEORY[..i]);
+        for text in prefixes.chain(fragments) {
+            // Unguarded, so that a panic fails the test.
+            let document = Document::parse_unguarded(text);
+            let json = serde_json::to_value(&document).unwrap();
+            assert_eq!(serde_json::to_value(Document::parse(text)).unwrap(), json, "parsing {text:?}");
+            assert_eq!(serde_json::to_value(&*Parse::new_unguarded(text).document).unwrap(), json, "parsing {text:?}");
+        }
+
+        // What's before the cut is still read.
+        let cut = &THEORY[..THEORY.find("  | t t'").unwrap()];
+        let document = Document::parse_unguarded(cut);
+        assert_eq!(document.metavars.len(), 2);
+        let ids: Vec<_> = document.grammars[0].productions.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["t_var", "t_lam"]);
+    }
+
+    /// Edits `old` into `new` in place, if that can be done, checking that
+    /// it parses as `new` does whole, sections and all. Neither is guarded,
+    /// so that the parser panicking fails the test.
+    fn edited(old: &str, new: &str) -> bool {
+        let Some(edited) = Parse::new_unguarded(old).edit_unguarded(old, new) else { return false };
+        let whole = Parse::new_unguarded(new);
+        let json = |parse: &Parse| serde_json::to_value(&*parse.document).unwrap();
+        assert_eq!(json(&edited), json(&whole), "editing {old:?} into {new:?}");
+
+        let sections = |parse: &Parse| parse.sections.iter().map(|s| (s.lines.clone(), s.keyword)).collect::<Vec<_>>();
+        assert_eq!(sections(&edited), sections(&whole), "editing {old:?} into {new:?}");
+        true
+    }
+
+    #[test]
+    fn edits_within_a_section_are_reparsed_in_place() {
+        assert!(edited(THEORY, &THEORY.replace(":: app", ":: apply")));
+        assert!(edited(THEORY, &THEORY.replace("  | t t' :: :: app\n", "")));
+        assert!(edited(THEORY, &THEORY.replace("  | x :: :: var\n", "  | x :: :: var\n  | ( t ) :: S :: paren\n\n")));
+        assert!(edited(THEORY, &THEORY.replace(":: app\n\ndefns", ":: app\ndefns")));
+        assert!(edited(THEORY, &THEORY.replace("% Terms.\nt", "% Terms.\n\nt")));
+        assert!(edited(THEORY, &THEORY.replace("v value\n", "")));
+        assert!(edited(THEORY, &THEORY.replace("Arith.", "Arith.\nRequire Import List.")));
+        assert!(edited(THEORY, THEORY));
+    }
+
+    #[test]
+    fn edits_across_sections_are_reparsed_whole() {
+        // Across the boundary between the grammar and the defns.
+        assert!(!edited(THEORY, &THEORY.replace("t' :: :: app\n\ndefns\nJop", "t'' :: :: app\n\ndefns\nJoq")));
+        assert!(!edited(THEORY, &THEORY.replace(":: app\n\ndefns\n", ":: app\n\n")));
+
+        // Deleting a section, or starting one.
+        assert!(!edited(THEORY, &THEORY.replace("indexvar n ::= {{ coq nat }}\n", "")));
+        assert!(!edited(THEORY, &THEORY.replace("  | t t' :: :: app\n", "  | t t' :: :: app\nsubrules\n")));
+
+        // Its keyword, or the comments above it, or a comment line the next
+        // would take for one of its own.
+        assert!(!edited(THEORY, &THEORY.replace("grammar\n", "grammar % rules\n")));
+        assert!(!edited(THEORY, &THEORY.replace("% Term variables.", "% Variables.")));
+        assert!(!edited(THEORY, &THEORY.replace(":: app\n\ndefns", ":: app\n% Judgements.\ndefns")));
+
+        // A hom left open, which runs into the next section.
+        assert!(!edited(THEORY, &THEORY.replace("{{ com abstraction }}", "{{ com abstraction")));
+    }
+
+    #[test]
+    fn edits_of_multi_byte_text_keep_utf16_ranges() {
+        let old = THEORY.replace("abstraction", "λ-abstraction");
+        assert!(edited(&old, &old.replace("λ-abstraction", "𝔸 abstraction")));
+        assert!(edited(&old, &old.replace(":: app", ":: app {{ tex [[t]] 𝔸 [[t']] }}")));
+        assert!(edited(&old, &old.replace("v value\n", "v value\nv′ ≠ v\n")));
+
+        let new = old.replace("  | x :: :: var\n", "  | x :: :: var {{ com 𝔸𝔹 }}\n  | 𝔸 x :: :: wide\n");
+        assert!(edited(&old, &new));
+        let wide = Parse::new_unguarded(&new).document.production("t_wide").unwrap().1.range;
+        assert_eq!(wide, range((8, 2), (8, 19)));
+    }
+}
@@ -0,0 +1,73 @@
+//! A queue of pending jobs drained by a bounded number of worker threads.
+//!
+//! The pool doesn't own any threads itself. Instead, [`Pool::push()`] tells
+//! the caller when it should spawn a new worker, and workers pull jobs with
+//! [`Pool::next()`] until it returns `None`. This keeps the running worker
+//! count at or below whatever the limit was when each job was pushed, so the
+//! limit can change at runtime.
+
+use std::collections::VecDeque;
+
+use parking_lot::{Condvar, Mutex};
+
+pub struct Pool<T> {
+    state: Mutex<State<T>>,
+    idle: Condvar,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    workers: usize,
+    shutdown: bool,
+}
+
+impl<T: PartialEq> Pool<T> {
+    pub fn new() -> Self {
+        Pool {
+            state: Mutex::new(State { queue: VecDeque::new(), workers: 0, shutdown: false }),
+            idle: Condvar::new(),
+        }
+    }
+
+    /// Queues `job` unless an equal job is already waiting. Returns `true` if
+    /// there are fewer than `limit` workers, in which case the caller must
+    /// spawn a new worker that drains the pool via [`Pool::next()`].
+    pub fn push(&self, job: T, limit: usize) -> bool {
+        let mut state = self.state.lock();
+        if state.shutdown {
+            return false;
+        }
+
+        if !state.queue.contains(&job) {
+            state.queue.push_back(job);
+        }
+
+        let spawn = state.workers < limit.max(1);
+        state.workers += spawn as usize;
+        spawn
+    }
+
+    /// Returns the next job for the calling worker. A `None` means the worker
+    /// has been retired and must exit.
+    pub fn next(&self) -> Option<T> {
+        let mut state = self.state.lock();
+        let job = state.queue.pop_front();
+        if job.is_none() {
+            state.workers -= 1;
+            self.idle.notify_all();
+        }
+
+        job
+    }
+
+    /// Cancels every queued job, refuses new ones, and waits for the jobs
+    /// already running to finish.
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock();
+        state.shutdown = true;
+        state.queue.clear();
+        while state.workers > 0 {
+            self.idle.wait(&mut state);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Renaming metavars and nonterminals throughout a document and the other
+//! files of its theory.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use lsp_types::*;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{NameCase, Naming};
+use crate::navigation;
+use crate::parse::{self, Document, Name, TokenKind};
+
+/// A file to rename in: its URI, its text, and its parse.
+pub type File = (Uri, String, Arc<Document>);
+
+/// A document declaring every metavar and nonterminal any of `files` does.
+fn declarations(files: &[File]) -> Document {
+    Document {
+        metavars: files.iter().flat_map(|(_, _, doc)| doc.metavars.clone()).collect(),
+        grammars: files.iter().flat_map(|(_, _, doc)| doc.grammars.clone()).collect(),
+        ..Default::default()
+    }
+}
+
+/// The metavar or nonterminal named by `word`, if it names one.
+fn root<'d>(doc: &'d Document, word: &str) -> Option<&'d str> {
+    navigation::declaration(doc, word).map(|name| name.text.as_str())
+}
+
+/// The identifier under `position`, as `identifier` matches identifiers, and
+/// the metavar or nonterminal it uses, or why there isn't one to rename.
+/// `Ok(None)` means there's nothing renamable.
+fn target<'d>(
+    text: &str,
+    doc: &'d Document,
+    position: Position,
+    identifier: &Regex,
+) -> Result<Option<(Name, &'d str)>, String> {
+    if parse::embed_context_at(text, position).is_some_and(|embed| embed.interpolation.is_none()) {
+        return Err("cannot rename inside a hom or embed, except in a `[[...]]`".into());
+    }
+
+    let tokens = parse::tokenize(text);
+    let Some(token) = parse::token_at(&tokens, position) else { return Ok(None) };
+    let token = match token.kind {
+        TokenKind::Hom => {
+            let word = parse::interpolated_words(token).into_iter()
+                .find(|word| word.range.start <= position && position <= word.range.end);
+
+            let Some(word) = word else { return Ok(None) };
+            word
+        }
+        TokenKind::Comment => return Err("cannot rename inside a comment".into()),
+        TokenKind::Word if parse::is_keyword(token.text) => {
+            return Err(format!("cannot rename ott keyword `{}`", token.text));
+        }
+        TokenKind::Word => token.clone(),
+    };
+
+    let name = parse::identifiers(&token, identifier).into_iter()
+        .find(|name| name.range.start <= position && position <= name.range.end);
+
+    Ok(name.and_then(|name| root(doc, &name.text).map(|root| (name, root))))
+}
+
+/// The range of the `root` at the start of `name`.
+fn root_range(name: &Name, root: &str) -> Range {
+    let end = Position::new(name.range.start.line, name.range.start.character + parse::utf16_len(root));
+    Range::new(name.range.start, end)
+}
+
+/// What would be renamed at `position` in the first of `files`, which may
+/// be declared in any of them.
+pub fn prepare_rename(
+    files: &[File],
+    position: Position,
+    identifier: &Regex,
+) -> Result<Option<PrepareRenameResponse>, String> {
+    let Some((_, text, _)) = files.first() else { return Ok(None) };
+    Ok(target(text, &declarations(files), position, identifier)?.map(|(name, root)| {
+        PrepareRenameResponse::RangeWithPlaceholder {
+            range: root_range(&name, root),
+            placeholder: root.into(),
+        }
+    }))
+}
+
+/// Whether `new_name` can name a declaration at all.
+fn is_valid(new_name: &str, identifier: &Regex) -> bool {
+    let whole = identifier.find(new_name).is_some_and(|m| m.range() == (0..new_name.len()));
+    !new_name.is_empty() && whole && !new_name.contains(',') && !parse::is_keyword(new_name)
+}
+
+/// The edit renaming, in every use outside of comments in any of `files`,
+/// each declaration whose root `renames` has to what it maps it to.
+fn renames(files: &[File], declarations: &Document, renames: &HashMap<&str, String>, identifier: &Regex) -> WorkspaceEdit {
+    // `Uri` is a false positive: its interior mutability never affects its hash.
+    #[allow(clippy::mutable_key_type)]
+    let mut changes = HashMap::new();
+    for (uri, text, _) in files {
+        let edits: Vec<_> = parse::tokenize(text).iter()
+            .flat_map(|token| match token.kind {
+                TokenKind::Word => vec![token.clone()],
+                TokenKind::Hom => parse::interpolated_words(token),
+                TokenKind::Comment => vec![],
+            })
+            .flat_map(|token| parse::identifiers(&token, identifier))
+            .filter_map(|name| {
+                let root = self::root(declarations, &name.text)?;
+                Some(TextEdit::new(root_range(&name, root), renames.get(root)?.clone()))
+            })
+            .collect();
+
+        if !edits.is_empty() {
+            changes.insert(uri.clone(), edits);
+        }
+    }
+
+    WorkspaceEdit { changes: Some(changes), ..Default::default() }
+}
+
+/// Renames the metavar or nonterminal at `position` in the first of `files`
+/// to `new_name`, in every declaration and use outside of comments in any of
+/// `files`, so long as none of them declares `new_name` already. In homs and
+/// embeds, only the uses in `[[...]]` interpolations are renamed, never the
+/// text around them. Uses are found as `identifier` matches identifiers,
+/// which `new_name` must be one of.
+pub fn rename(
+    files: &[File],
+    position: Position,
+    new_name: &str,
+    identifier: &Regex,
+) -> Result<Option<WorkspaceEdit>, String> {
+    if !is_valid(new_name, identifier) {
+        return Err(format!("`{new_name}` is not a valid name"));
+    }
+
+    let declarations = declarations(files);
+    let Some((_, text, _)) = files.first() else { return Ok(None) };
+    let Some((_, root)) = target(text, &declarations, position, identifier)? else { return Ok(None) };
+
+    // Were `new_name` a use of another declaration, its uses would change
+    // meaning along with the renamed ones.
+    if let Some(existing) = self::root(&declarations, new_name).filter(|&existing| existing != root) {
+        return Err(match existing == new_name {
+            true => format!("cannot rename `{root}` to `{new_name}`: `{new_name}` is already declared"),
+            false => format!("cannot rename `{root}` to `{new_name}`: it reads as a use of `{existing}`"),
+        });
+    }
+
+    let renames = HashMap::from([(root, new_name.to_string())]);
+    Ok(Some(self::renames(files, &declarations, &renames, identifier)))
+}
+
+/// `name` in `case`, its words told apart by underscores and by capitals
+/// that follow lowercase letters or digits.
+fn cased(name: &str, case: NameCase) -> String {
+    let mut words: Vec<String> = vec![];
+    let mut previous = None;
+    for c in name.chars() {
+        let boundary = c.is_uppercase() && previous.is_some_and(|p: char| p.is_lowercase() || p.is_ascii_digit());
+        match words.last_mut() {
+            Some(word) if c != '_' && !boundary => word.push(c),
+            _ if c == '_' => words.push(String::new()),
+            _ => words.push(c.to_string()),
+        }
+
+        previous = Some(c);
+    }
+
+    let words = words.into_iter().filter(|word| !word.is_empty());
+    match case {
+        NameCase::Lower => name.to_lowercase(),
+        NameCase::Upper => name.to_uppercase(),
+        NameCase::Snake => words.map(|word| word.to_lowercase()).collect::<Vec<_>>().join("_"),
+        NameCase::Camel => words
+            .map(|word| {
+                let mut chars = word.chars();
+                let first = chars.next().map(|c| c.to_uppercase().to_string()).unwrap_or_default();
+                first + &chars.as_str().to_lowercase()
+            })
+            .collect(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Skipped {
+    pub name: String,
+    pub to: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NormalizeResult {
+    /// The renames, applied together.
+    pub edit: WorkspaceEdit,
+    /// The names renamed, each to what it's renamed to.
+    pub renamed: Vec<(String, String)>,
+    /// The names that don't follow their convention but were left alone.
+    pub skipped: Vec<Skipped>,
+}
+
+/// Renames each metavar and nonterminal the first of `files` declares that
+/// doesn't follow its `naming` convention, as [`rename()`] renames one, all
+/// in one edit. A name is skipped if its conventional spelling isn't a valid
+/// name, or would read as a use of another declaration or of another name
+/// renamed along with it.
+pub fn normalize(files: &[File], naming: Naming, identifier: &Regex) -> NormalizeResult {
+    let declarations = declarations(files);
+    let (mut renamed, mut skipped) = (vec![], vec![]);
+    let Some((_, _, doc)) = files.first() else {
+        return NormalizeResult { edit: WorkspaceEdit::default(), renamed, skipped };
+    };
+
+    let metavars = doc.metavars.iter().flat_map(|m| &m.names).map(|name| (name, naming.metavars));
+    let nonterminals = doc.grammars.iter().flat_map(|rule| &rule.names).map(|name| (name, naming.nonterminals));
+    let mut renames: HashMap<&str, String> = HashMap::new();
+    for (name, case) in metavars.chain(nonterminals) {
+        let Some(case) = case else { continue };
+        let to = cased(&name.text, case);
+        if to == name.text {
+            continue;
+        }
+
+        let overlaps = |other: &str| navigation::is_use(&to, other) || navigation::is_use(other, &to);
+        let reason = if !is_valid(&to, identifier) {
+            Some(format!("`{to}` is not a valid name"))
+        } else if let Some(existing) = self::root(&declarations, &to).filter(|&root| root != name.text) {
+            Some(format!("`{to}` reads as a use of `{existing}`"))
+        } else if let Some((other, new)) = renames.iter().find(|(_, new)| overlaps(new)) {
+            Some(format!("`{to}` overlaps `{new}`, which `{other}` is renamed to"))
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => skipped.push(Skipped { name: name.text.clone(), to, reason }),
+            None => {
+                renamed.push((name.text.clone(), to.clone()));
+                renames.insert(&name.text, to);
+            }
+        }
+    }
+
+    let edit = self::renames(files, &declarations, &renames, identifier);
+    NormalizeResult { edit, renamed, skipped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    fn file(uri: &str, text: &str) -> File {
+        (uri.parse().unwrap(), text.into(), Arc::new(Document::parse(text)))
+    }
+
+    const GRAMMAR: &str = concat!(
+        "metavar termvar, e ::= {{ tex \\mathit{e} }}\n",
+        "grammar\n",
+        "t :: 't_' ::=\n",
+        "  | e :: :: var {{ tex [[e]] }}\n",
+        "  | \\ e . t :: :: lam {{ tex \\lambda [[e]] . [[t]] }}\n",
+    );
+
+    /// The ranges `edit` replaces in `uri`, each with what it's replaced with.
+    fn edits<'e>(edit: &'e WorkspaceEdit, uri: &str) -> Vec<(Range, &'e str)> {
+        let edits = &edit.changes.as_ref().unwrap()[&uri.parse().unwrap()];
+        edits.iter().map(|edit| (edit.range, edit.new_text.as_str())).collect()
+    }
+
+    #[test]
+    fn renames_interpolations_but_not_the_text_around_them() {
+        let identifier = Regex::new(parse::IDENTIFIER).unwrap();
+        let files = [file("file:///x.ott", GRAMMAR), file("file:///y.ott", "defns\nJ :: '' ::=\n")];
+        let edit = rename(&files, Position::new(3, 4), "x", &identifier).unwrap().unwrap();
+        assert_eq!(edits(&edit, "file:///x.ott"), [
+            (range((0, 17), (0, 18)), "x"),
+            (range((3, 4), (3, 5)), "x"),
+            (range((3, 25), (3, 26)), "x"),
+            (range((4, 6), (4, 7)), "x"),
+            (range((4, 39), (4, 40)), "x"),
+        ]);
+
+        // `y.ott` has no use of `e` to edit.
+        assert_eq!(edit.changes.as_ref().unwrap().len(), 1);
+
+        // From an interpolation too, but not the hom's text.
+        let edit = rename(&files, Position::new(4, 39), "x", &identifier).unwrap().unwrap();
+        assert_eq!(edits(&edit, "file:///x.ott").len(), 5);
+        assert!(rename(&files, Position::new(4, 31), "x", &identifier).is_err());
+    }
+
+    #[test]
+    fn renames_uses_in_the_theorys_other_files() {
+        let identifier = Regex::new(parse::IDENTIFIER).unwrap();
+        let rules = "defns\nJ :: '' ::=\n\ndefn\nt done :: :: done :: D_ {{ tex [[t]] }} by\n\n----- :: var\ne1 done\n";
+        let files = [file("file:///y.ott", rules), file("file:///x.ott", GRAMMAR)];
+        let edit = rename(&files, Position::new(7, 0), "x", &identifier).unwrap().unwrap();
+        assert_eq!(edits(&edit, "file:///y.ott"), [(range((7, 0), (7, 1)), "x")]);
+        assert_eq!(edits(&edit, "file:///x.ott").len(), 5);
+
+        let error = rename(&files, Position::new(4, 0), "e", &identifier).unwrap_err();
+        assert_eq!(error, "cannot rename `t` to `e`: `e` is already declared");
+    }
+}
@@ -0,0 +1,92 @@
+//! The totals of the diagnostics last reported for each file, for clients to
+//! show the health of the whole project at a glance.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lsp_types::*;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// The method of the request for the summary.
+pub const SUMMARY: &str = "ott-lsp/summary";
+
+/// How many diagnostics there are of each severity.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Counts {
+    pub errors: usize,
+    pub warnings: usize,
+    pub information: usize,
+    pub hints: usize,
+}
+
+impl Counts {
+    pub fn of(diagnostics: &[Diagnostic]) -> Counts {
+        let mut counts = Counts::default();
+        for diagnostic in diagnostics {
+            match diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR) {
+                DiagnosticSeverity::WARNING => counts.warnings += 1,
+                DiagnosticSeverity::INFORMATION => counts.information += 1,
+                DiagnosticSeverity::HINT => counts.hints += 1,
+                _ => counts.errors += 1,
+            }
+        }
+
+        counts
+    }
+
+    fn add(&mut self, other: Counts) {
+        self.errors += other.errors;
+        self.warnings += other.warnings;
+        self.information += other.information;
+        self.hints += other.hints;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub uri: Uri,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    /// Every file diagnostics have been reported for, even if none, by URI.
+    pub files: Vec<FileSummary>,
+    pub totals: Counts,
+    /// When diagnostics were last reported, in milliseconds since the Unix
+    /// epoch, if they have been.
+    pub last_check: Option<u64>,
+}
+
+/// The counts of the diagnostics last reported for each file, and when they
+/// last were for any.
+#[derive(Default)]
+pub struct Tally(Mutex<(HashMap<Uri, Counts>, Option<SystemTime>)>);
+
+impl Tally {
+    /// Records that `diagnostics` were just reported for `uri`.
+    pub fn record(&self, uri: &Uri, diagnostics: &[Diagnostic]) {
+        let mut tally = self.0.lock();
+        tally.0.insert(uri.clone(), Counts::of(diagnostics));
+        tally.1 = Some(SystemTime::now());
+    }
+
+    pub fn summary(&self) -> Summary {
+        let tally = self.0.lock();
+        let mut files: Vec<_> = tally.0.iter()
+            .map(|(uri, &counts)| FileSummary { uri: uri.clone(), counts })
+            .collect();
+
+        files.sort_by(|a, b| a.uri.as_str().cmp(b.uri.as_str()));
+        let mut totals = Counts::default();
+        for file in &files {
+            totals.add(file.counts);
+        }
+
+        let last_check = tally.1.map(|time| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+        Summary { files, totals, last_check }
+    }
+}
@@ -0,0 +1,220 @@
+//! Which production a term written in a theory's syntax is built by, as far
+//! as can be told from the grammar alone, without running ott.
+
+use std::collections::HashMap;
+
+use lsp_types::*;
+use serde::Serialize;
+
+use crate::navigation::is_use;
+use crate::parse::{Document, GrammarRule, Name, Production};
+
+/// A production a term may have been built by.
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    /// The first name of the production's rule.
+    pub nonterminal: String,
+    pub label: String,
+    pub id: String,
+    /// Where the production's label is.
+    pub location: Location,
+}
+
+/// What a word of a production's body stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot<'d> {
+    Terminal(&'d str),
+    Metavar,
+    /// A use of the nonterminal at this index in `Grammar::rules`.
+    Nonterminal(usize),
+}
+
+/// Words that write out lists, like `</ t // i />` or `t1 .. tn`, which
+/// aren't matched.
+const LIST_FORMS: &[&str] = &["</", "//", "/>", "..", "...", "...."];
+
+/// A grammar rule, with where it is and its productions, as the slots of
+/// their bodies.
+struct Rule<'d> {
+    rule: &'d GrammarRule,
+    uri: &'d Uri,
+    productions: Vec<(&'d Production, Vec<Slot<'d>>)>,
+}
+
+struct Grammar<'d> {
+    rules: Vec<Rule<'d>>,
+    /// Every terminal of every production, longest first.
+    terminals: Vec<&'d str>,
+}
+
+impl<'d> Grammar<'d> {
+    fn new(docs: &[(&'d Uri, &'d Document)]) -> Self {
+        let metavars: Vec<&Name> = docs.iter().flat_map(|(_, doc)| &doc.metavars).flat_map(|m| &m.names).collect();
+        let rules: Vec<_> = docs.iter()
+            .flat_map(|&(uri, doc)| doc.grammars.iter().map(move |rule| (uri, rule)))
+            .filter(|(_, rule)| !rule.names.is_empty())
+            .collect();
+
+        // Which a word is a use of, by its longest name, as in
+        // `navigation::declaration`.
+        let slot = |word: &'d Name| -> Slot<'d> {
+            let metavar = metavars.iter().filter(|m| is_use(&word.text, &m.text)).map(|m| m.text.len()).max();
+            let rule = rules.iter().enumerate()
+                .flat_map(|(i, (_, rule))| rule.names.iter().map(move |name| (i, name)))
+                .filter(|(_, name)| is_use(&word.text, &name.text))
+                .max_by_key(|(_, name)| name.text.len());
+
+            match (metavar, rule) {
+                (Some(m), Some((i, name))) if name.text.len() > m => Slot::Nonterminal(i),
+                (Some(_), _) => Slot::Metavar,
+                (None, Some((i, _))) => Slot::Nonterminal(i),
+                (None, None) => Slot::Terminal(&word.text),
+            }
+        };
+
+        let rules: Vec<_> = rules.iter()
+            .map(|&(uri, rule)| {
+                let productions = rule.productions.iter()
+                    .filter(|p| !p.body.is_empty() && !p.body.iter().any(|w| LIST_FORMS.contains(&w.text.as_str())))
+                    .map(|p| (p, p.body.iter().map(slot).collect()))
+                    .collect();
+
+                Rule { rule, uri, productions }
+            })
+            .collect();
+
+        let mut terminals: Vec<&str> = rules.iter()
+            .flat_map(|rule| rule.productions.iter().flat_map(|(_, slots)| slots))
+            .filter_map(|slot| match *slot {
+                Slot::Terminal(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+
+        terminals.sort_by_key(|t| std::cmp::Reverse(t.len()));
+        terminals.dedup();
+        Grammar { rules, terminals }
+    }
+
+    /// The tokens of `term`: the terminals, and runs of the characters of
+    /// names, in each of its whitespace-separated words.
+    fn tokens<'t>(&self, term: &'t str) -> Vec<&'t str> {
+        let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+        let mut tokens = vec![];
+        for mut word in term.split_whitespace() {
+            while let Some(c) = word.chars().next() {
+                let symbol = self.terminals.iter().find(|t| !t.chars().all(is_name) && word.starts_with(**t));
+                let len = match symbol {
+                    Some(terminal) => terminal.len(),
+                    None if is_name(c) => word.find(|c| !is_name(c)).unwrap_or(word.len()),
+                    None => c.len_utf8(),
+                };
+
+                tokens.push(&word[..len]);
+                word = &word[len..];
+            }
+        }
+
+        tokens
+    }
+}
+
+/// Matches tokens against the grammar, remembering which spans parse as
+/// which rules.
+struct Matcher<'g, 'd, 't> {
+    grammar: &'g Grammar<'d>,
+    tokens: &'g [&'t str],
+    /// Whether `tokens[i..j]` parses as rule `r`, by `(r, i, j)`, or `None`
+    /// while that's being worked out, so that a rule reaching itself
+    /// without consuming anything doesn't recurse forever.
+    parses: HashMap<(usize, usize, usize), Option<bool>>,
+}
+
+impl Matcher<'_, '_, '_> {
+    fn rule(&mut self, rule: usize, i: usize, j: usize) -> bool {
+        match self.parses.get(&(rule, i, j)) {
+            Some(parses) => return parses.unwrap_or(false),
+            None => self.parses.insert((rule, i, j), None),
+        };
+
+        let grammar = self.grammar;
+        let parses = grammar.rules[rule].productions.iter().any(|(_, slots)| self.production(slots, i, j));
+        self.parses.insert((rule, i, j), Some(parses));
+        parses
+    }
+
+    /// Whether `tokens[i..j]` is written as `slots` are.
+    fn production(&mut self, slots: &[Slot<'_>], i: usize, j: usize) -> bool {
+        let Some((first, rest)) = slots.split_first() else {
+            return i == j;
+        };
+
+        match *first {
+            Slot::Terminal(text) => i < j && self.tokens[i] == text && self.production(rest, i + 1, j),
+            Slot::Metavar => i < j && self.is_name(self.tokens[i]) && self.production(rest, i + 1, j),
+            // Every slot after this one takes at least a token.
+            Slot::Nonterminal(rule) => (i + 1..=j - rest.len().min(j - i))
+                .any(|k| self.production(rest, k, j) && self.rule(rule, i, k)),
+        }
+    }
+
+    /// Whether `token` could be a metavar: a name that isn't a terminal.
+    fn is_name(&self, token: &str) -> bool {
+        let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+        token.chars().all(is_name) && !self.grammar.terminals.contains(&token)
+    }
+}
+
+/// The productions of the grammar in `docs`, or with `nonterminal`, of its
+/// rule alone, that `term` parses as at its top level, those with the most
+/// terminals first, as they're the most telling. There can be more than one
+/// if `term` is ambiguous. `Err` saying why if there are none.
+pub fn which_production(
+    docs: &[(&Uri, &Document)],
+    term: &str,
+    nonterminal: Option<&str>,
+) -> Result<Vec<Candidate>, String> {
+    let grammar = Grammar::new(docs);
+    let tokens = grammar.tokens(term);
+    if tokens.is_empty() {
+        return Err("the term is empty".into());
+    }
+
+    let rules: Vec<_> = (0..grammar.rules.len())
+        .filter(|&i| nonterminal.is_none_or(|nt| grammar.rules[i].rule.names.iter().any(|name| name.text == nt)))
+        .collect();
+
+    if let Some(nonterminal) = nonterminal && rules.is_empty() {
+        return Err(format!("no nonterminal `{nonterminal}` is declared"));
+    }
+
+    let mut matcher = Matcher { grammar: &grammar, tokens: &tokens, parses: HashMap::new() };
+    let mut candidates = vec![];
+    for rule in rules {
+        let Rule { rule, uri, productions } = &grammar.rules[rule];
+        for (production, slots) in productions {
+            if matcher.production(slots, 0, tokens.len()) {
+                let terminals = slots.iter().filter(|slot| matches!(slot, Slot::Terminal(_))).count();
+                let candidate = Candidate {
+                    nonterminal: rule.names[0].text.clone(),
+                    label: production.label.text.clone(),
+                    id: production.id.clone(),
+                    location: Location::new((*uri).clone(), production.label.range),
+                };
+
+                candidates.push((terminals, candidate));
+            }
+        }
+    }
+
+    if candidates.is_empty() {
+        let tokens = tokens.join(" ");
+        return Err(match nonterminal {
+            Some(nonterminal) => format!("`{tokens}` doesn't parse as any production of `{nonterminal}`"),
+            None => format!("`{tokens}` doesn't parse as any production"),
+        });
+    }
+
+    candidates.sort_by_key(|(terminals, _)| std::cmp::Reverse(*terminals));
+    Ok(candidates.into_iter().map(|(_, candidate)| candidate).collect())
+}
@@ -0,0 +1,219 @@
+//! Multi-file theories: ordered sets of ott files that are checked together.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use lsp_types::Uri;
+
+use crate::navigation::declaration;
+use crate::parse::Document;
+
+/// Resolves the configured theory files against the workspace `root`.
+pub fn resolve(theory: &[String], root: Option<&Path>) -> Vec<PathBuf> {
+    theory.iter()
+        .map(|file| root.map_or_else(|| PathBuf::from(file), |root| root.join(file)))
+        .collect()
+}
+
+/// ott's flag for one more file to read, given in `ottFlags`.
+const INCLUDE: &str = "-i";
+
+/// `flags` with the file after each `-i` resolved against the workspace
+/// `root`, as the theory's files are, so that ott finds it wherever it's run
+/// from.
+pub fn resolve_includes(flags: &[String], root: Option<&Path>) -> Vec<String> {
+    let mut resolved = flags.to_vec();
+    let Some(root) = root else { return resolved };
+    for i in 1..resolved.len() {
+        if flags[i - 1] == INCLUDE && Path::new(&flags[i]).is_relative() {
+            resolved[i] = root.join(&flags[i]).display().to_string();
+        }
+    }
+
+    resolved
+}
+
+/// The files `flags` tell ott to read with `-i`.
+pub fn includes(flags: &[String]) -> Vec<PathBuf> {
+    flags.windows(2)
+        .filter(|pair| pair[0] == INCLUDE)
+        .map(|pair| PathBuf::from(&pair[1]))
+        .collect()
+}
+
+/// Whether `a` and `b` name the same file.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The files to pass to ott, in order, to check `path`: the whole theory if
+/// `path` is part of it, otherwise just `path`.
+pub fn files_for(theory: &[PathBuf], path: &Path) -> Vec<PathBuf> {
+    match theory.iter().any(|file| same_file(file, path)) {
+        true => theory.to_vec(),
+        false => vec![path.to_path_buf()],
+    }
+}
+
+/// `files` with `path` replaced by `replacement`, in the same place, as when a
+/// copy of an unsaved buffer stands in for the file on disk. The other files
+/// are left as they are.
+pub fn substitute(files: &[PathBuf], path: &Path, replacement: &Path) -> Vec<PathBuf> {
+    files.iter()
+        .map(|file| match same_file(file, path) {
+            true => replacement.to_path_buf(),
+            false => file.clone(),
+        })
+        .collect()
+}
+
+/// Maps each file on disk to the open documents whose checks depend on it.
+#[derive(Default)]
+pub struct Dependents(HashMap<PathBuf, Vec<Uri>>);
+
+impl Dependents {
+    /// Builds the map for the `open` documents, given their paths, each of
+    /// which is checked along with the files ott is told to read with `-i`,
+    /// its `includes`.
+    pub fn build<'a>(
+        theory: &[PathBuf],
+        includes: &[PathBuf],
+        open: impl Iterator<Item = (&'a Uri, PathBuf)>,
+    ) -> Self {
+        let mut map: HashMap<PathBuf, Vec<Uri>> = HashMap::new();
+        for (uri, path) in open {
+            for file in files_for(theory, &path).into_iter().chain(includes.iter().cloned()) {
+                if !same_file(&file, &path) {
+                    map.entry(file).or_default().push(uri.clone());
+                }
+            }
+        }
+
+        Dependents(map)
+    }
+
+    /// The open documents that depend on the file at `path`.
+    pub fn of(&self, path: &Path) -> &[Uri] {
+        self.0.iter()
+            .find(|(file, _)| same_file(file, path))
+            .map_or(&[], |(_, uris)| uris.as_slice())
+    }
+
+    /// Every file something depends on, sorted.
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files: Vec<_> = self.0.keys().map(|path| path.as_path()).collect();
+        files.sort();
+        files
+    }
+}
+
+/// The words `doc` uses that may name a metavar or nonterminal: those of its
+/// productions, judgements' forms, rules, subrules, and substitutions.
+fn uses(doc: &Document) -> impl Iterator<Item = &str> {
+    let productions = doc.grammars.iter().flat_map(|rule| &rule.productions).flat_map(|p| &p.body);
+    let defns = doc.defns.iter().flat_map(|d| &d.defns);
+    let forms = defns.clone().flat_map(|defn| &defn.form);
+    let premises = defns.clone().flat_map(|defn| &defn.rules).flat_map(|rule| rule.premises.iter().flatten());
+    let subrules = doc.subrules.iter().flat_map(|s| [&s.sub, &s.sup]);
+    let substitutions = doc.substitutions.iter().flat_map(|s| [&s.nonterminal, &s.metavar]);
+    let conclusions = defns.flat_map(|defn| &defn.rules).flat_map(|rule| rule.conclusion.split_whitespace());
+    productions.chain(forms).chain(premises).chain(subrules).chain(substitutions)
+        .map(|name| name.text.as_str())
+        .chain(conclusions)
+}
+
+/// The indices of the other `docs` that declare something `docs[i]` uses
+/// but doesn't declare itself.
+fn dependencies(docs: &[Document], i: usize) -> Vec<usize> {
+    let mut found = vec![];
+    for word in uses(&docs[i]).filter(|word| declaration(&docs[i], word).is_none()) {
+        let declaring = (0..docs.len()).find(|&j| j != i && declaration(&docs[j], word).is_some());
+        if let Some(j) = declaring && !found.contains(&j) {
+            found.push(j);
+        }
+    }
+
+    found
+}
+
+/// Orders `docs`, the parsed files of a theory, so that each comes after the
+/// files declaring what it uses, keeping their order otherwise. Returns each
+/// file's index along with the indices of every file it depends on, however
+/// indirectly, in the order. `Err` with the indices of the files in a cycle,
+/// in order, the first repeated at the end, if they depend on each other.
+pub fn build_order(docs: &[Document]) -> Result<Vec<(usize, Vec<usize>)>, Vec<usize>> {
+    let direct: Vec<_> = (0..docs.len()).map(|i| dependencies(docs, i)).collect();
+    let mut order: Vec<usize> = vec![];
+    while order.len() < docs.len() {
+        let ready = (0..docs.len())
+            .find(|i| !order.contains(i) && direct[*i].iter().all(|j| order.contains(j)));
+
+        match ready {
+            Some(i) => order.push(i),
+            None => {
+                // Every file left waits on another left, so following what
+                // each waits on must come back around.
+                let mut cycle = vec![(0..docs.len()).find(|i| !order.contains(i)).expect("a file is left")];
+                loop {
+                    let last = cycle[cycle.len() - 1];
+                    let next = *direct[last].iter().find(|j| !order.contains(j)).expect("a file left waits");
+                    if let Some(start) = cycle.iter().position(|&i| i == next) {
+                        cycle.drain(..start);
+                        cycle.push(next);
+                        return Err(cycle);
+                    }
+
+                    cycle.push(next);
+                }
+            }
+        }
+    }
+
+    let mut built: Vec<(usize, Vec<usize>)> = vec![];
+    for &i in &order {
+        let mut needed: Vec<usize> = direct[i].iter()
+            .flat_map(|&j| {
+                let (_, indirect) = built.iter().find(|(k, _)| *k == j).expect("built in order");
+                indirect.iter().copied().chain([j])
+            })
+            .collect();
+
+        needed.sort_by_key(|j| order.iter().position(|k| k == j));
+        needed.dedup();
+        built.push((i, needed));
+    }
+
+    Ok(built)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    #[test]
+    fn relative_includes_are_resolved_against_the_root() {
+        let root = Path::new("/work");
+        let flags = strings(&["-i", "base.ott", "-i", "/abs/other.ott", "-merge", "true", "base.ott"]);
+        let resolved = resolve_includes(&flags, Some(root));
+        assert_eq!(resolved, strings(&["-i", "/work/base.ott", "-i", "/abs/other.ott", "-merge", "true", "base.ott"]));
+        assert_eq!(resolve_includes(&flags, None), flags);
+        assert_eq!(includes(&resolved), [Path::new("/work/base.ott"), Path::new("/abs/other.ott")]);
+    }
+
+    #[test]
+    fn open_documents_depend_on_their_includes() {
+        let uri = crate::path_uri(Path::new("/work/x.ott")).unwrap();
+        let includes = [PathBuf::from("/work/base.ott")];
+        let dependents = Dependents::build(&[], &includes, [(&uri, PathBuf::from("/work/x.ott"))].into_iter());
+        assert_eq!(dependents.files(), [Path::new("/work/base.ott")]);
+        assert_eq!(dependents.of(Path::new("/work/base.ott")), [uri]);
+        assert_eq!(dependents.of(Path::new("/work/x.ott")), []);
+    }
+}
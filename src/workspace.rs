@@ -0,0 +1,176 @@
+//! An index of the ott files in the workspace, kept fresh in the background.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex, RwLock};
+
+use crate::parse::Document;
+
+/// The parsed contents of every ott file found in the workspace.
+#[derive(Default)]
+pub struct Index {
+    files: RwLock<HashMap<PathBuf, Document>>,
+}
+
+impl Index {
+    /// Rescans `roots` and reparses every ott file in them, as [`ott_files()`]
+    /// finds them, replacing the index wholesale. Stops early, leaving the
+    /// index untouched, and returns `false` if `cancelled` returns `true`
+    /// between files.
+    pub fn rebuild(&self, roots: &[PathBuf], respect_ignores: bool, cancelled: impl Fn() -> bool) -> bool {
+        let mut files = HashMap::new();
+        for path in roots.iter().flat_map(|root| ott_files(root, respect_ignores)) {
+            if cancelled() {
+                return false;
+            }
+
+            if let Ok(text) = std::fs::read_to_string(&path) {
+                files.insert(path, Document::parse(&text));
+            }
+        }
+
+        *self.files.write() = files;
+        true
+    }
+
+    /// Calls `f` with every indexed file and its parsed contents.
+    pub fn for_each(&self, mut f: impl FnMut(&Path, &Document)) {
+        for (path, doc) in self.files.read().iter() {
+            f(path, doc);
+        }
+    }
+}
+
+/// Finds every `.ott` file under `dir`, skipping hidden directories and, if
+/// `respect_ignores`, whatever `.gitignore` and `.ignore` files ignore, as
+/// git would, whether or not `dir` is in a repository.
+pub fn ott_files(dir: &Path, respect_ignores: bool) -> Vec<PathBuf> {
+    let walk = ignore::WalkBuilder::new(dir)
+        .standard_filters(respect_ignores)
+        .hidden(true)
+        .require_git(false)
+        .build();
+
+    let mut files: Vec<_> = walk.flatten()
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(ignore::DirEntry::into_path)
+        .filter(|path| path.extension().is_some_and(|e| e == "ott"))
+        .collect();
+
+    files.sort();
+    files
+}
+
+/// Tracks client activity so background work can wait for a quiet period
+/// and notice when it's interrupted.
+pub struct Idle {
+    state: Mutex<IdleState>,
+    wake: Condvar,
+}
+
+struct IdleState {
+    last_activity: Instant,
+    /// Bumped on every bit of activity.
+    generation: u64,
+    /// The generation the last completed quiet-period job ran in.
+    finished: Option<u64>,
+    shutdown: bool,
+}
+
+impl Idle {
+    pub fn new() -> Self {
+        let state = IdleState {
+            last_activity: Instant::now(),
+            generation: 0,
+            finished: None,
+            shutdown: false,
+        };
+
+        Idle { state: Mutex::new(state), wake: Condvar::new() }
+    }
+
+    /// Records activity, interrupting any job in progress.
+    pub fn touch(&self) {
+        let mut state = self.state.lock();
+        state.last_activity = Instant::now();
+        state.generation += 1;
+        self.wake.notify_all();
+    }
+
+    pub fn shutdown(&self) {
+        self.state.lock().shutdown = true;
+        self.wake.notify_all();
+    }
+
+    /// Runs `job` each time there's been no activity for `delay()`, once
+    /// per quiet period, until shutdown. A `None` delay disables the job.
+    /// `job` is passed a function that returns `true` once activity resumes;
+    /// it should return `false` if it stopped early because of that.
+    pub fn run(&self, delay: impl Fn() -> Option<Duration>, job: impl Fn(&dyn Fn() -> bool) -> bool) {
+        let mut state = self.state.lock();
+        while !state.shutdown {
+            let generation = state.generation;
+            let Some(delay) = delay().filter(|_| state.finished != Some(generation)) else {
+                self.wake.wait(&mut state);
+                continue;
+            };
+
+            let deadline = state.last_activity + delay;
+            if Instant::now() < deadline {
+                self.wake.wait_until(&mut state, deadline);
+                continue;
+            }
+
+            drop(state);
+            let interrupted = || self.state.lock().generation != generation;
+            let finished = job(&interrupted);
+            state = self.state.lock();
+            if finished {
+                state.finished = Some(generation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignored_files_are_left_out_of_the_index_unless_told_otherwise() {
+        let dir = std::env::temp_dir().join(format!("ott-lsp-test-{}-ignored", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for sub in ["build", "vendor", "src", ".hidden"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+
+        std::fs::write(dir.join(".gitignore"), "build/\n").unwrap();
+        std::fs::write(dir.join(".ignore"), "vendor/*.ott\n").unwrap();
+        for file in ["build/generated.ott", "vendor/other.ott", "src/x.ott", ".hidden/y.ott", "z.ott"] {
+            std::fs::write(dir.join(file), "metavar x ::=\n").unwrap();
+        }
+
+        let relative = |respect| -> Vec<_> {
+            ott_files(&dir, respect).into_iter().map(|p| p.strip_prefix(&dir).unwrap().to_path_buf()).collect()
+        };
+
+        assert_eq!(relative(true), [Path::new("src/x.ott"), Path::new("z.ott")]);
+        assert_eq!(relative(false), [
+            Path::new("build/generated.ott"),
+            Path::new("src/x.ott"),
+            Path::new("vendor/other.ott"),
+            Path::new("z.ott"),
+        ]);
+
+        let index = Index::default();
+        assert!(index.rebuild(std::slice::from_ref(&dir), true, || false));
+        let mut indexed = vec![];
+        index.for_each(|path, _| indexed.push(path.to_path_buf()));
+        indexed.sort();
+        assert_eq!(indexed, [dir.join("src/x.ott"), dir.join("z.ott")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}